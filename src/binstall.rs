@@ -0,0 +1,121 @@
+use log::*;
+use owo_colors::OwoColorize;
+use std::path::Path;
+
+use crate::{
+    BinstallArgs,
+    cargo::{UNIVERSAL_MACOS_ARTIFACT, UNIVERSAL_MACOS_TARGETS},
+    load_config,
+};
+
+/// The `[package.metadata.binstall]` table that lets `cargo binstall` find our release
+/// assets: one flat `<target>.tar.xz` per target triple, attached to the
+/// `<tag_prefix>v<version>` tag. With `universal_macos`, both macOS targets get the
+/// universal archive.
+fn metadata_snippet(
+    org: &str,
+    name: &str,
+    tag_prefix: Option<&str>,
+    universal_macos: bool,
+) -> String {
+    let prefix = tag_prefix.unwrap_or_default();
+    let release =
+        format!("https://github.com/{org}/{name}/releases/download/{prefix}v{{ version }}");
+    let mut snippet = format!(
+        r#"[package.metadata.binstall]
+pkg-url = "{release}/{{ target }}.tar.xz"
+bin-dir = "{{ bin }}{{ binary-ext }}"
+pkg-fmt = "txz"
+"#
+    );
+    if universal_macos {
+        for target in UNIVERSAL_MACOS_TARGETS {
+            snippet.push_str(&format!(
+                "\n[package.metadata.binstall.overrides.{target}]\n\
+                 pkg-url = \"{release}/{UNIVERSAL_MACOS_ARTIFACT}.tar.xz\"\n"
+            ));
+        }
+    }
+    snippet
+}
+
+pub(crate) fn binstall(args: BinstallArgs, config_path: Option<&Path>) -> eyre::Result<()> {
+    let config = load_config(config_path)?;
+    let universal_macos = config
+        .cargo
+        .as_ref()
+        .is_some_and(|cargo| cargo.universal_macos);
+    let snippet = metadata_snippet(
+        &config.org,
+        &config.name,
+        config.tag_prefix.as_deref(),
+        universal_macos,
+    );
+
+    if !args.write {
+        print!("{}", snippet);
+        return Ok(());
+    }
+
+    let manifest = fs_err::read_to_string("Cargo.toml")?;
+    if manifest.contains("[package.metadata.binstall]") {
+        warn!(
+            "{} already has a {} table, leaving it alone",
+            "Cargo.toml".cyan(),
+            "[package.metadata.binstall]".yellow()
+        );
+        return Ok(());
+    }
+
+    if std::env::var("DRY_RUN").is_ok() {
+        info!(
+            "Dry run: Would add {} to {}",
+            "[package.metadata.binstall]".yellow(),
+            "Cargo.toml".cyan()
+        );
+        return Ok(());
+    }
+
+    let separator = if manifest.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    fs_err::write("Cargo.toml", format!("{manifest}{separator}{snippet}"))?;
+    info!(
+        "Added {} to {}",
+        "[package.metadata.binstall]".yellow(),
+        "Cargo.toml".cyan()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_snippet() {
+        let snippet = metadata_snippet("bearcove", "beardist", None, false);
+        assert!(snippet.contains(
+            "pkg-url = \"https://github.com/bearcove/beardist/releases/download/v{ version }/{ target }.tar.xz\"\n"
+        ));
+        assert!(snippet.contains("bin-dir = \"{ bin }{ binary-ext }\"\n"));
+        assert!(snippet.contains("pkg-fmt = \"txz\"\n"));
+        assert!(!snippet.contains("overrides"));
+
+        let snippet = metadata_snippet("bearcove", "beardist", None, true);
+        assert!(snippet.contains(
+            "[package.metadata.binstall.overrides.aarch64-apple-darwin]\npkg-url = \"https://github.com/bearcove/beardist/releases/download/v{ version }/universal-apple-darwin.tar.xz\"\n"
+        ));
+        assert!(snippet.contains("[package.metadata.binstall.overrides.x86_64-apple-darwin]"));
+    }
+
+    #[test]
+    fn test_metadata_snippet_with_tag_prefix() {
+        let snippet = metadata_snippet("bearcove", "monorepo", Some("foo-"), false);
+        assert!(snippet.contains(
+            "pkg-url = \"https://github.com/bearcove/monorepo/releases/download/foo-v{ version }/{ target }.tar.xz\"\n"
+        ));
+    }
+}
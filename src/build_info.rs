@@ -0,0 +1,134 @@
+//! Build metadata handed to the compiler, so released binaries can report which build
+//! produced them: as environment variables for `env!`, and as a Rust file of constants
+//! for `include!`.
+
+use camino::Utf8Path;
+use indexmap::IndexMap;
+
+use crate::{command, environment::Environment};
+
+/// What a binary gets to know about the build that produced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BuildInfo {
+    /// The tag being built, e.g. "v1.2.3"
+    pub(crate) tag: String,
+
+    /// The commit being built, or "unknown" outside of CI and git
+    pub(crate) commit: String,
+
+    /// When the build happened, in seconds since the Unix epoch. `SOURCE_DATE_EPOCH`
+    /// wins, for reproducible builds.
+    pub(crate) timestamp: u64,
+}
+
+impl BuildInfo {
+    pub(crate) fn collect(env: &dyn Environment, tag: &str, source_dir: &Utf8Path) -> Self {
+        let commit = env
+            .ci_var("SHA")
+            .map(|(_, sha)| sha)
+            .or_else(|| {
+                command::get_trimmed_cmd_stdout(
+                    "git",
+                    &["-C", source_dir.as_str(), "rev-parse", "HEAD"],
+                    None,
+                )
+                .ok()
+            })
+            .unwrap_or_else(|| "unknown".to_string());
+        let timestamp = env
+            .var("SOURCE_DATE_EPOCH")
+            .and_then(|epoch| epoch.parse().ok())
+            .unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            });
+        Self {
+            tag: tag.to_string(),
+            commit,
+            timestamp,
+        }
+    }
+
+    /// Variables to set for the compiler. `BEARDIST_BUILD_INFO` is the path of the file
+    /// written by [`BuildInfo::write`].
+    pub(crate) fn env_vars(&self, info_file: &Utf8Path) -> IndexMap<String, String> {
+        IndexMap::from([
+            ("BEARDIST_TAG".to_string(), self.tag.clone()),
+            ("BEARDIST_COMMIT".to_string(), self.commit.clone()),
+            (
+                "BEARDIST_BUILD_TIMESTAMP".to_string(),
+                self.timestamp.to_string(),
+            ),
+            ("BEARDIST_BUILD_INFO".to_string(), info_file.to_string()),
+        ])
+    }
+
+    /// The constants, as Rust source
+    fn rust_source(&self) -> String {
+        format!(
+            "// Generated by beardist, for `include!(env!(\"BEARDIST_BUILD_INFO\"))`\n\
+             \n\
+             /// The tag this binary was built for\n\
+             pub const TAG: &str = {:?};\n\
+             /// The commit this binary was built from\n\
+             pub const COMMIT: &str = {:?};\n\
+             /// When this binary was built, in seconds since the Unix epoch\n\
+             pub const BUILD_TIMESTAMP: u64 = {};\n",
+            self.tag, self.commit, self.timestamp
+        )
+    }
+
+    /// Writes the constants to `path`, leaving it alone if they haven't changed, so
+    /// cargo doesn't rebuild for nothing
+    pub(crate) fn write(&self, path: &Utf8Path) -> eyre::Result<()> {
+        let source = self.rust_source();
+        if fs_err::read_to_string(path).ok().as_deref() != Some(source.as_str()) {
+            if let Some(parent) = path.parent() {
+                fs_err::create_dir_all(parent)?;
+            }
+            fs_err::write(path, source)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::FakeEnvironment;
+
+    #[test]
+    fn test_build_info() {
+        let env = FakeEnvironment {
+            vars: [
+                ("GITHUB_SHA", "0123abcd"),
+                ("SOURCE_DATE_EPOCH", "1700000000"),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+            ..Default::default()
+        };
+        let info = BuildInfo::collect(&env, "v1.2.3", Utf8Path::new("."));
+        assert_eq!(
+            info,
+            BuildInfo {
+                tag: "v1.2.3".to_string(),
+                commit: "0123abcd".to_string(),
+                timestamp: 1700000000,
+            }
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8Path::from_path(dir.path())
+            .unwrap()
+            .join("build_info.rs");
+        info.write(&path).unwrap();
+        let source = fs_err::read_to_string(&path).unwrap();
+        assert!(source.contains("pub const TAG: &str = \"v1.2.3\";"));
+        assert!(source.contains("pub const BUILD_TIMESTAMP: u64 = 1700000000;"));
+        assert_eq!(info.env_vars(&path)["BEARDIST_COMMIT"], "0123abcd");
+    }
+}
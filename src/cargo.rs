@@ -1,16 +1,130 @@
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use indexmap::IndexMap;
-use log::{debug, error, info, warn};
-use owo_colors::{OwoColorize, Style};
+use log::{debug, info, log_enabled, warn};
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-
-use crate::{BuildContext, PackagedFile, PackagedFileKind, TargetSpec, command};
-
-#[derive(Debug, Serialize, Deserialize)]
+use std::collections::BTreeMap;
+
+use crate::{
+    BuildContext, PackagedFile, PackagedFileKind, TargetSpec,
+    build_info::BuildInfo,
+    command,
+    error::{Classify, ErrorKind},
+    incremental::{self, CacheStats, SweepConfig, TimelordConfig},
+    objfile::{self, ObjectInfo},
+    remote_cache::{RemoteCache, RemoteCacheConfig, Restored},
+    retry::RetryPolicy,
+};
+
+/// Targets merged into universal macOS binaries
+pub(crate) const UNIVERSAL_MACOS_TARGETS: [&str; 2] =
+    ["aarch64-apple-darwin", "x86_64-apple-darwin"];
+
+/// Written in target directories, with the `rustc --version` that last built there
+const TOOLCHAIN_MARKER: &str = ".beardist-toolchain";
+
+/// Name of the archive with universal macOS binaries
+pub(crate) const UNIVERSAL_MACOS_ARTIFACT: &str = "universal-apple-darwin";
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct CargoConfig {
     /// Name of binaries we should pack
     pub(crate) bins: Vec<String>,
+
+    /// Parallel build jobs. Defaults to what the CPU and memory limits allow.
+    #[serde(default)]
+    pub(crate) jobs: Option<usize>,
+
+    /// Codegen units for the release profile. Defaults to fewer when memory is tight,
+    /// unless `Cargo.toml` sets them.
+    #[serde(default)]
+    pub(crate) codegen_units: Option<usize>,
+
+    /// On macOS, build for both Apple Silicon and Intel, and merge the results into
+    /// universal binaries, published as `universal-apple-darwin`
+    #[serde(default)]
+    pub(crate) universal_macos: bool,
+
+    /// Restoring file timestamps between CI builds, so incremental builds work
+    #[serde(default)]
+    pub(crate) timelord: TimelordConfig,
+
+    /// How many target directories to keep. There's one per toolchain and lockfile, and
+    /// the least recently used ones beyond this are deleted. Default: 2
+    #[serde(default = "default_keep_target_dirs")]
+    pub(crate) keep_target_dirs: usize,
+
+    /// Cleaning up the target directory with `cargo sweep` after builds
+    #[serde(default)]
+    pub(crate) sweep: SweepConfig,
+
+    /// Restore the target directory from (and save it to) a remote cache, when it's
+    /// not already there
+    #[serde(default)]
+    pub(crate) remote_cache: Option<RemoteCacheConfig>,
+
+    /// Pass the tag, commit and build time to the compiler, as `BEARDIST_TAG`,
+    /// `BEARDIST_COMMIT` and `BEARDIST_BUILD_TIMESTAMP` (for `env!`), and as constants in
+    /// the file at `BEARDIST_BUILD_INFO` (for `include!`)
+    #[serde(default)]
+    pub(crate) build_info: bool,
+}
+
+fn default_keep_target_dirs() -> usize {
+    2
+}
+
+/// `Cargo.lock`, or `Cargo.toml` when there's none, to key caches on
+fn read_lockfile(source_dir: &Utf8Path) -> eyre::Result<Vec<u8>> {
+    match fs_err::read(source_dir.join("Cargo.lock")) {
+        Ok(lockfile) => Ok(lockfile),
+        Err(_) => {
+            warn!("No Cargo.lock, keying caches on Cargo.toml instead");
+            Ok(fs_err::read(source_dir.join("Cargo.toml"))?)
+        }
+    }
+}
+
+/// Fills the cache with the toolchain and dependencies `config` needs, without building
+pub(crate) fn warm(
+    cache_dir: &Utf8Path,
+    source_dir: &Utf8Path,
+    config: &CargoConfig,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    let env = BuildEnv {
+        cache_dir: cache_dir.to_owned(),
+    }
+    .get_env();
+    install_pinned_toolchain(source_dir, &env, dry_run)?;
+
+    if config.universal_macos && cfg!(target_os = "macos") {
+        for target in UNIVERSAL_MACOS_TARGETS {
+            crate::warm::fill("rustup", &["target", "add", target], &env, dry_run)?;
+        }
+    }
+
+    info!("📦 Fetching crate dependencies");
+    crate::warm::fill("cargo", &["fetch"], &env, dry_run)
+}
+
+/// rustup would install a pinned toolchain on first use anyway, but explicitly
+/// installing it lets us retry if the download fails
+fn install_pinned_toolchain(
+    source_dir: &Utf8Path,
+    env: &IndexMap<String, String>,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    if ["rust-toolchain.toml", "rust-toolchain"]
+        .iter()
+        .any(|file| source_dir.join(file).exists())
+    {
+        info!("📦 Installing the toolchain pinned by the project");
+        crate::warm::fill("rustup", &["toolchain", "install"], env, dry_run)?;
+    }
+    Ok(())
 }
 
 /// builds values for RUSTUP_HOME, CARGO_HOME, etc.
@@ -41,8 +155,11 @@ impl BuildEnv {
             "PNPM_CACHE_FOLDER".to_string(),
             self.pnpm_cache_folder().to_string(),
         );
-        env.insert("CLICOLOR_FORCE".to_string(), "1".to_string());
-        env.insert("FORCE_COLOR".to_string(), "1".to_string());
+        env.extend(crate::warm::js_cache_env(&self.cache_dir));
+        if crate::color::enabled() {
+            env.insert("CLICOLOR_FORCE".to_string(), "1".to_string());
+            env.insert("FORCE_COLOR".to_string(), "1".to_string());
+        }
         env.insert("RUSTC_BOOTSTRAP".to_string(), "1".to_string());
         env.insert("RUSTFLAGS".to_string(), "-Z remap-cwd-prefix=.".to_string());
         env
@@ -61,6 +178,22 @@ pub(crate) struct CargoBuildContext<'a> {
 
     /// the configuration for this build
     config: CargoConfig,
+
+    /// `--target` to pass to cargo, when not building for the host
+    cargo_target: Option<String>,
+
+    /// Contents of the lockfile, see [`read_lockfile`]
+    lockfile: Vec<u8>,
+
+    /// Which of the project's target directories to build in, see
+    /// [`incremental::target_dir_key`]
+    target_key: String,
+
+    /// tool name → `--version` output, for build reports
+    pub(crate) toolchain: BTreeMap<String, String>,
+
+    /// How well incremental compilation worked, once built
+    pub(crate) cache_stats: Option<CacheStats>,
 }
 
 impl<'a> CargoBuildContext<'a> {
@@ -69,6 +202,7 @@ impl<'a> CargoBuildContext<'a> {
             cache_dir: parent.cache_dir.clone(),
         };
 
+        let group = crate::gha::group("Environment and toolchain");
         info!("{}", "🌍 Environment:".yellow());
         let env = build_env.get_env();
         let max_key_len = env.keys().map(|k| k.len()).max().unwrap_or(0);
@@ -88,6 +222,9 @@ impl<'a> CargoBuildContext<'a> {
             );
         }
 
+        // Dry-run builds still build
+        install_pinned_toolchain(&parent.source_dir, &env, false)?;
+
         let rustc_version =
             command::get_trimmed_cmd_stdout("rustc", &["--version"], Some(env.clone()))?;
         let cargo_version =
@@ -101,28 +238,219 @@ impl<'a> CargoBuildContext<'a> {
             cargo_sweep_version.blue()
         );
 
-        let json_output = command::get_trimmed_cmd_stdout(
+        let version_output =
+            command::get_trimmed_cmd_stdout("rustc", &["-vV"], Some(build_env.get_env()))?;
+        let triple = version_output
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .ok_or_else(|| eyre::eyre!("No host triple in `rustc -vV` output"))?;
+        let mut target_spec = command::get_trimmed_cmd_stdout(
             "rustc",
             &["-Z", "unstable-options", "--print", "target-spec-json"],
             Some(build_env.get_env()),
-        )?;
-        let target_spec = TargetSpec::from_json(&json_output)?;
+        )
+        .and_then(|json_output| TargetSpec::from_json(&json_output))
+        .or_else(|e| {
+            warn!(
+                "Couldn't get the target spec ({}), deriving it from {} instead",
+                e,
+                "rustc --print cfg".cyan()
+            );
+            let cfg_output = command::get_trimmed_cmd_stdout(
+                "rustc",
+                &["--print", "cfg"],
+                Some(build_env.get_env()),
+            )?;
+            TargetSpec::from_cfg(&cfg_output, triple)
+        })?;
+        target_spec.triple = Some(triple.to_string());
         target_spec.print_info();
+        drop(group);
+
+        let lockfile = read_lockfile(&parent.source_dir)?;
+        let target_key = incremental::target_dir_key(&version_output, &lockfile);
+
+        let toolchain = BTreeMap::from([
+            ("rustc".to_string(), rustc_version),
+            ("cargo".to_string(), cargo_version),
+            ("cargo-sweep".to_string(), cargo_sweep_version),
+        ]);
 
         Ok(Self {
             parent,
             config,
             build_env,
             target_spec,
+            toolchain,
+            cargo_target: None,
+            lockfile,
+            target_key,
+            cache_stats: None,
         })
     }
 
-    pub(crate) fn build(&self, files_to_package: &mut Vec<PackagedFile>) -> eyre::Result<()> {
-        self.run_timelord()?;
+    pub(crate) fn build(&mut self, files_to_package: &mut Vec<PackagedFile>) -> eyre::Result<()> {
+        let remote_cache = self.remote_cache()?;
+        let restored = remote_cache
+            .as_ref()
+            .map(|cache| cache.restore(&self.cargo_target_dir(), self.scratch_dir()));
+        incremental::prune_target_dirs(
+            &self.target_dirs(),
+            &self.target_key,
+            self.config.keep_target_dirs.max(1),
+        )?;
+
+        let timelord = self
+            .config
+            .timelord
+            .is_enabled(self.parent.env.as_ref())
+            .then(|| {
+                incremental::run_timelord(
+                    &self.config.timelord,
+                    &self.parent.source_dir,
+                    &self.cargo_target_dir(),
+                )
+            });
+
+        let universal = self.config.universal_macos && self.target_spec.os == "macos";
+        let out_dirs: Vec<Utf8PathBuf> = if universal {
+            UNIVERSAL_MACOS_TARGETS
+                .iter()
+                .map(|target| self.cargo_target_dir().join(target).join("release"))
+                .collect()
+        } else {
+            vec![self.cargo_out_dir()]
+        };
+        let warm = incremental::has_fingerprints(&out_dirs);
+
+        let build_start = std::time::SystemTime::now();
+        let build_timer = std::time::Instant::now();
+        if universal {
+            self.build_universal(files_to_package)?;
+        } else {
+            self.build_target(files_to_package)?;
+        }
+
+        let cache_stats = CacheStats {
+            timelord,
+            units: incremental::fingerprint_stats(&out_dirs, build_start),
+            warm,
+            build_ms: build_timer.elapsed().as_millis() as u64,
+        };
+        info!("📊 Incremental build: {}", cache_stats.describe());
+        if cache_stats.is_ineffective() {
+            warn!(
+                "{}",
+                "⚠️ The target directory had a previous build, but every crate was rebuilt: \
+                 timelord or the cache mount may be broken"
+                    .yellow()
+            );
+        }
+        self.cache_stats = Some(cache_stats);
+
+        if let Some(cache) = &remote_cache {
+            if restored == Some(Restored::Exact) {
+                info!("🗄️ Remote cache is up to date for this lockfile");
+            } else if self.parent.is_dry_run {
+                info!("🗄️ Dry run: not saving the target directory to the remote cache");
+            } else {
+                cache.save(&self.cargo_target_dir(), self.scratch_dir());
+            }
+        }
+        Ok(())
+    }
+
+    /// The remote cache to use, if one is configured and the target directory isn't
+    /// already here
+    fn remote_cache(&self) -> eyre::Result<Option<RemoteCache>> {
+        let Some(config) = &self.config.remote_cache else {
+            return Ok(None);
+        };
+        if self.cargo_target_dir().exists() {
+            info!(
+                "🗄️ {} already exists, not using the remote cache",
+                self.cargo_target_dir().to_string().cyan()
+            );
+            return Ok(None);
+        }
+
+        let project = format!("{}/{}", self.parent.config.org, self.parent.config.name);
+        Ok(Some(RemoteCache::new(
+            config,
+            &project,
+            &self.target_spec.full_name(),
+            &self.lockfile,
+        )))
+    }
+
+    /// Where to put archives while restoring or saving the remote cache
+    fn scratch_dir(&self) -> &Utf8Path {
+        Utf8Path::from_path(self.parent.temp_dir.path()).unwrap()
+    }
+
+    /// Builds every target of a universal binary, and merges them with `lipo`
+    fn build_universal(&self, files_to_package: &mut Vec<PackagedFile>) -> eyre::Result<()> {
+        let mut by_name: IndexMap<String, (PackagedFileKind, Vec<Utf8PathBuf>)> = IndexMap::new();
+        for target in UNIVERSAL_MACOS_TARGETS {
+            info!("🍎 Building for {}", target.cyan());
+            command::run_command_with_retry(
+                "rustup",
+                &["target", "add", target],
+                Some(self.get_env()),
+                RetryPolicy::default(),
+            )?;
+            let target_cx = CargoBuildContext {
+                parent: self.parent,
+                build_env: BuildEnv {
+                    cache_dir: self.build_env.cache_dir.clone(),
+                },
+                target_spec: self.target_spec.clone(),
+                config: self.config.clone(),
+                cargo_target: Some(target.to_string()),
+                lockfile: self.lockfile.clone(),
+                target_key: self.target_key.clone(),
+                toolchain: BTreeMap::new(),
+                cache_stats: None,
+            };
+            let mut target_files = Vec::new();
+            target_cx.build_target(&mut target_files)?;
+            for file in target_files {
+                let name = file.path.file_name().unwrap().to_string();
+                by_name
+                    .entry(name)
+                    .or_insert_with(|| (file.kind, Vec::new()))
+                    .1
+                    .push(file.path);
+            }
+        }
+
+        let out_dir = self.cargo_out_dir();
+        fs_err::create_dir_all(&out_dir)?;
+        for (name, (kind, paths)) in by_name {
+            let dest = out_dir.join(&name);
+            let _ = fs_err::remove_file(&dest);
+            // libstd's file name is hashed differently for each target: each slice of the
+            // binaries links to its own, so they're shipped side by side
+            if paths.len() == 1 {
+                fs_err::copy(&paths[0], &dest)?;
+            } else {
+                let mut args = vec!["-create", "-output", dest.as_str()];
+                args.extend(paths.iter().map(|path| path.as_str()));
+                command::run_command("lipo", &args, None)?;
+                info!("🍎 Created universal {}", dest.to_string().cyan());
+            }
+            files_to_package.push(PackagedFile { kind, path: dest });
+        }
+
+        self.check_version()
+    }
+
+    /// Builds for `cargo_target` (or the host), and collects the files to package
+    fn build_target(&self, files_to_package: &mut Vec<PackagedFile>) -> eyre::Result<()> {
         self.build_project()?;
 
         for bin in &self.config.bins {
-            let binary_path = self.cargo_out_dir().join(bin);
+            let binary_path = self.bin_path(bin);
             if binary_path.exists() {
                 let binary_size = fs_err::metadata(&binary_path)?.len();
                 info!(
@@ -135,41 +463,98 @@ impl<'a> CargoBuildContext<'a> {
                     path: binary_path,
                 })
             } else {
-                error!(
-                    "❌ Binary file does not exist at path: {}",
+                return Err(eyre::eyre!(
+                    "Binary file does not exist at path: {}",
                     binary_path.to_string().red()
-                );
-                panic!();
+                ))
+                .classify(ErrorKind::Build);
             }
         }
 
-        let mut highlight_patterns = Vec::new();
-        highlight_patterns.push((
-            regex::Regex::new(r"(?i)(\.dylib|\.so|LC_RPATH|@rpath|@executable_path|\$ORIGIN)")
-                .unwrap(),
-            Style::new().blue(),
-        ));
-        for bin in &self.config.bins {
-            highlight_patterns.push((
-                regex::Regex::new(&format!(r"(?i)({})", regex::escape(bin))).unwrap(),
-                Style::new().green(),
-            ));
+        let cargo_out_dir = self.cargo_out_dir();
+
+        // Copy libstd next to the binary
+        if self.target_spec.os == "linux" || self.target_spec.os == "macos" {
+            self.copy_libstd(&cargo_out_dir)?;
+        } else {
+            warn!(
+                "Skipping libstd copy for unsupported OS: {}",
+                self.target_spec.os
+            );
+        }
+
+        // Add other libraries
+        for entry in fs_err::read_dir(&cargo_out_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let file_path: Utf8PathBuf = entry.path().try_into().unwrap();
+            let kind = objfile::kind_of(&file_path)?;
+            debug!(
+                "Examining file: {} ({:?})",
+                file_path.file_name().unwrap(),
+                kind
+            );
+            if matches!(kind, PackagedFileKind::Lib) {
+                files_to_package.push(PackagedFile {
+                    kind,
+                    path: file_path,
+                });
+            }
+        }
+
+        self.fix_install_names()?;
+
+        // Purely informational, so don't even parse them in quiet mode
+        if log_enabled!(log::Level::Info) {
+            self.show_linkage(files_to_package)?;
+        }
+
+        // Binaries for other targets may not run here
+        if self.cargo_target.is_none() {
+            self.check_version()?;
         }
-        highlight_patterns.push((
-            regex::Regex::new(&format!(
-                r"(?i)({})",
-                regex::escape(&self.target_spec.full_name())
-            ))
-            .unwrap(),
-            Style::new().yellow(),
-        ));
 
+        Ok(())
+    }
+
+    /// Shows what the binaries and libraries we're shipping link against
+    fn show_linkage(&self, files: &[PackagedFile]) -> eyre::Result<()> {
+        let _group = crate::gha::group("linkage");
+        let ours: Vec<&str> = files
+            .iter()
+            .filter_map(|file| file.path.file_name())
+            .collect();
+        let mut infos = Vec::new();
+        for file in files
+            .iter()
+            .filter(|f| matches!(f.kind, PackagedFileKind::Bin | PackagedFileKind::Lib))
+        {
+            match ObjectInfo::read(&file.path)? {
+                Some(info) => {
+                    info.print(&file.path, &ours);
+                    infos.push((file.path.as_path(), info));
+                }
+                None => warn!("{} isn't an object file", file.path.to_string().red()),
+            }
+        }
+        if self.target_spec.os == "windows" {
+            crate::pe::check_imports(&infos, &self.target_spec.arch);
+        }
+        Ok(())
+    }
+
+    /// Copies the toolchain's libstd to `cargo_out_dir`, for binaries that link to it
+    /// dynamically
+    fn copy_libstd(&self, cargo_out_dir: &Utf8Path) -> eyre::Result<()> {
         debug!("📊 Running {} on rustc...", "target-libdir".dimmed());
-        let target_libdir = command::get_trimmed_cmd_stdout(
-            "rustc",
-            &["--print", "target-libdir"],
-            Some(self.get_env()),
-        )?;
+        let mut libdir_args = vec!["--print", "target-libdir"];
+        if let Some(target) = &self.cargo_target {
+            libdir_args.extend(["--target", target.as_str()]);
+        }
+        let target_libdir =
+            command::get_trimmed_cmd_stdout("rustc", &libdir_args, Some(self.get_env()))?;
         debug!("📊 Target libdir: {}", target_libdir.cyan());
 
         let libstd_pattern =
@@ -194,102 +579,25 @@ impl<'a> CargoBuildContext<'a> {
 
         let libstd_path: Utf8PathBuf = libstd_path.path().try_into().unwrap();
 
-        let cargo_out_dir = self.cargo_out_dir();
-
-        // Copy libstd next to the binary
+        // Remove any pre-existing symlinks or files, ignoring any errors
         let libstd_copy_path = cargo_out_dir.join(libstd_path.file_name().unwrap());
-        if self.target_spec.os == "linux" || self.target_spec.os == "macos" {
-            // Remove any pre-existing symlinks or files, ignoring any errors
-            let _ = fs_err::remove_file(&libstd_copy_path);
+        let _ = fs_err::remove_file(&libstd_copy_path);
 
-            // Copy the file
-            fs_err::copy(&libstd_path, &libstd_copy_path)?;
-            info!("📄 Copied libstd: {}", libstd_copy_path.to_string().cyan());
-        } else {
-            warn!(
-                "Skipping libstd copy for unsupported OS: {}",
-                self.target_spec.os
-            );
-        }
-
-        // Add other libraries
-        let dll_suffix = self.target_spec.dll_suffix.as_str();
-        for entry in fs_err::read_dir(&cargo_out_dir)? {
-            let entry = entry?;
-            let file_name = entry.file_name().into_string().unwrap();
-            debug!("Examining file: {file_name} (our dll_suffix is {dll_suffix})");
-            if file_name.starts_with("lib") && file_name.ends_with(dll_suffix) {
-                let file_path = entry.path();
-                files_to_package.push(PackagedFile {
-                    kind: PackagedFileKind::Lib,
-                    path: file_path.try_into().unwrap(),
-                });
-            }
-        }
-
-        self.fix_install_names()?;
-
-        if self.target_spec.os == "linux" {
-            for file in files_to_package
-                .iter()
-                .filter(|f| matches!(f.kind, PackagedFileKind::Bin | PackagedFileKind::Lib))
-            {
-                show_fyi(
-                    "ldd",
-                    &[file.path.as_str()],
-                    Some(self.get_env()),
-                    &highlight_patterns,
-                )?;
-
-                show_fyi(
-                    "readelf",
-                    &["-d", file.path.as_str()],
-                    Some(self.get_env()),
-                    &highlight_patterns,
-                )?;
-            }
-        } else if self.target_spec.os == "macos" {
-            for file in files_to_package
-                .iter()
-                .filter(|f| matches!(f.kind, PackagedFileKind::Bin | PackagedFileKind::Lib))
-            {
-                show_fyi(
-                    "otool",
-                    &["-L", file.path.as_str()],
-                    Some(self.get_env()),
-                    &highlight_patterns,
-                )?;
-            }
-
-            show_fyi(
-                "bash",
-                &[
-                    "-c",
-                    &format!(
-                        "otool -l {}",
-                        self.cargo_out_dir().join(&self.config.bins[0])
-                    ),
-                ],
-                Some(self.get_env()),
-                &highlight_patterns,
-            )?;
-        } else {
-            warn!(
-                "Skipping binary dependency check for unsupported OS: {}",
-                self.target_spec.os
-            );
-        }
+        // Copy the file
+        fs_err::copy(&libstd_path, &libstd_copy_path)?;
+        info!("📄 Copied libstd: {}", libstd_copy_path.to_string().cyan());
+        Ok(())
+    }
 
+    /// Makes sure the first binary runs, at least far enough to print its version
+    fn check_version(&self) -> eyre::Result<()> {
         info!(
             "📊 Running {} on {}...",
             "--version".dimmed(),
-            self.cargo_out_dir()
-                .join(&self.config.bins[0])
-                .to_string()
-                .cyan()
+            self.bin_path(&self.config.bins[0]).to_string().cyan()
         );
         crate::run_command(
-            self.cargo_out_dir().join(&self.config.bins[0]).as_str(),
+            self.bin_path(&self.config.bins[0]).as_str(),
             &["--version"],
             Some(self.get_env()),
         )?;
@@ -297,22 +605,6 @@ impl<'a> CargoBuildContext<'a> {
         Ok(())
     }
 
-    fn run_timelord(&self) -> eyre::Result<()> {
-        // Detect if we're in CI
-        if std::env::var("CI").is_ok() {
-            if std::env::var("SKIP_TIMELORD").is_ok() {
-                info!("Skipping timelord ($SKIP_TIMELORD is set)");
-            } else {
-                // this manipulates timestamps on files to ensure that incremental builds work correctly
-                timelord::sync(self.parent.source_dir.clone(), self.cargo_target_dir());
-                info!("🕰️ Timelord sync completed in CI environment");
-            }
-        } else {
-            info!("🏠 Not in CI environment, skipping Timelord sync");
-        }
-        Ok(())
-    }
-
     fn get_env(&self) -> IndexMap<String, String> {
         let mut env = self.build_env.get_env();
         env.insert(
@@ -322,7 +614,8 @@ impl<'a> CargoBuildContext<'a> {
         env
     }
 
-    fn cargo_target_dir(&self) -> Utf8PathBuf {
+    /// Where the project's target directories for this target live, one per key
+    fn target_dirs(&self) -> Utf8PathBuf {
         self.build_env
             .cache_dir
             .join("target")
@@ -331,18 +624,104 @@ impl<'a> CargoBuildContext<'a> {
             .join(self.target_spec.full_name())
     }
 
-    /// ${TARGET}/${PROFILE}
+    fn cargo_target_dir(&self) -> Utf8PathBuf {
+        self.target_dirs().join(&self.target_key)
+    }
+
+    /// Path to a binary cargo built, with `.exe` on Windows
+    fn bin_path(&self, bin: &str) -> Utf8PathBuf {
+        match self.target_spec.os.as_str() {
+            "windows" => self.cargo_out_dir().join(format!("{}.exe", bin)),
+            _ => self.cargo_out_dir().join(bin),
+        }
+    }
+
+    /// ${TARGET}/${PROFILE}, with cargo's `--target` (or the universal binaries) in
+    /// between when needed
     fn cargo_out_dir(&self) -> Utf8PathBuf {
-        self.cargo_target_dir().join("release")
+        let target_dir = self.cargo_target_dir();
+        match &self.cargo_target {
+            Some(target) => target_dir.join(target).join("release"),
+            None if self.config.universal_macos && self.target_spec.os == "macos" => {
+                target_dir.join(UNIVERSAL_MACOS_ARTIFACT).join("release")
+            }
+            None => target_dir.join("release"),
+        }
     }
 
     fn build_project(&self) -> eyre::Result<()> {
         info!("{}", "🔨 Building the project...".yellow());
-        let env = self.get_env();
-        crate::run_command("cargo", &["build", "--verbose", "--release"], Some(env))?;
+        let mut env = self.get_env();
+        self.tune_parallelism(&mut env);
+        if self.config.build_info {
+            let parent = self.parent;
+            let info = BuildInfo::collect(parent.env.as_ref(), &parent.tag, &parent.source_dir);
+            let info_file = self
+                .cargo_target_dir()
+                .join("beardist")
+                .join("build_info.rs");
+            info.write(&info_file)?;
+            info!(
+                "🏷️ Embedding build info: {} at {}",
+                info.tag.cyan(),
+                info.commit.yellow()
+            );
+            env.extend(info.env_vars(&info_file));
+        }
+        let mut args = vec!["build", "--verbose", "--release"];
+        if let Some(target) = &self.cargo_target {
+            args.extend(["--target", target.as_str()]);
+        }
+        crate::run_command("cargo", &args, Some(env))?;
         Ok(())
     }
 
+    /// Sets `CARGO_BUILD_JOBS` and codegen units from the config, or to fit the CPU and
+    /// memory limits we run under. Variables set by the user are left alone.
+    fn tune_parallelism(&self, env: &mut IndexMap<String, String>) {
+        let tuning = crate::system::build_tuning();
+        // The project may have chosen its codegen units for performance reasons
+        let project_codegen_units =
+            fs_err::read_to_string(self.parent.source_dir.join("Cargo.toml"))
+                .ok()
+                .and_then(|manifest| manifest.parse::<toml::Table>().ok())
+                .is_some_and(|manifest| {
+                    manifest
+                        .get("profile")
+                        .and_then(|profile| profile.get("release"))
+                        .and_then(|release| release.get("codegen-units"))
+                        .is_some()
+                });
+
+        let settings = [
+            ("CARGO_BUILD_JOBS", self.config.jobs, tuning.jobs, "jobs"),
+            (
+                "CARGO_PROFILE_RELEASE_CODEGEN_UNITS",
+                self.config.codegen_units,
+                tuning.codegen_units.filter(|_| !project_codegen_units),
+                "codegen units",
+            ),
+        ];
+        for (var, configured, tuned, what) in settings {
+            if self.parent.env.var(var).is_some() {
+                continue;
+            }
+            let value = match (configured, tuned) {
+                (Some(configured), _) => configured,
+                (None, Some(tuned)) => {
+                    info!(
+                        "⚖️ Building with {} {} to fit the container's resources",
+                        tuned.to_string().cyan(),
+                        what
+                    );
+                    tuned
+                }
+                (None, None) => continue,
+            };
+            env.insert(var.to_string(), value.to_string());
+        }
+    }
+
     fn fix_install_names(&self) -> eyre::Result<()> {
         if self.target_spec.os != "macos" {
             return Ok(());
@@ -416,7 +795,7 @@ impl<'a> CargoBuildContext<'a> {
             let dylib_path_str = dylib_path.to_string_lossy();
             debug!("🔍 Inspecting dependencies for: {}", file_name.cyan());
 
-            let dependencies = get_dependencies(dylib_path_str.as_ref())?;
+            let dependencies = get_dependencies(&dylib_path)?;
 
             // Formulate a plan: change only our libraries to use @rpath
             for dep in dependencies {
@@ -447,7 +826,7 @@ impl<'a> CargoBuildContext<'a> {
             }
 
             // Verify changes
-            let verify_deps = get_dependencies(dylib_path_str.as_ref())?;
+            let verify_deps = get_dependencies(&dylib_path)?;
             debug!(
                 "✅ Verification output for {}:\n{}",
                 file_name.cyan(),
@@ -457,35 +836,41 @@ impl<'a> CargoBuildContext<'a> {
         Ok(())
     }
 
+    /// Sweeps the target directory, if it's grown big enough to bother, or the
+    /// toolchain changed since it was last built in
     pub(crate) fn sweep(&self) -> eyre::Result<()> {
-        debug!("🧹 Running cargo sweep...");
+        let target_dir = self.cargo_target_dir();
+        let marker = target_dir.join(TOOLCHAIN_MARKER);
+        let rustc = self.toolchain.get("rustc").cloned().unwrap_or_default();
+        let toolchain_changed =
+            fs_err::read_to_string(&marker).is_ok_and(|previous| previous.trim() != rustc);
+        let size = incremental::dir_size(&target_dir);
+
+        let commands = incremental::sweep_commands(&self.config.sweep, size, toolchain_changed);
+        if commands.is_empty() {
+            debug!(
+                "🧹 Target directory is {}, not sweeping",
+                crate::format_bytes(size)
+            );
+        }
         let env = self.get_env();
-        crate::run_command("cargo", &["sweep", "--time", "30"], Some(env))?;
+        for args in &commands {
+            info!("🧹 Running {}", format!("cargo {}", args.join(" ")).cyan());
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            crate::run_command("cargo", &args, Some(env.clone()))?;
+        }
+        fs_err::write(&marker, rustc)?;
         Ok(())
     }
 }
 
-// Helper function to run otool -l and collect all dependencies
-fn get_dependencies(path: &str) -> eyre::Result<Vec<String>> {
-    let output = command::get_cmd_stdout("otool", &["-l", path], None)?;
-    let mut dependencies = Vec::new();
-    let mut in_load_dylib = false;
-    let mut current_dependency;
-
-    for line in output.lines() {
-        if line.trim().starts_with("cmd LC_LOAD_DYLIB") {
-            in_load_dylib = true;
-        } else if in_load_dylib && line.trim().starts_with("name") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() > 1 {
-                current_dependency = parts[1].to_string();
-                dependencies.push(current_dependency.clone());
-            }
-            in_load_dylib = false;
-        }
-    }
-
-    Ok(dependencies)
+/// The libraries a Mach-O file loads
+fn get_dependencies(path: &std::path::Path) -> eyre::Result<Vec<String>> {
+    let path =
+        Utf8Path::from_path(path).ok_or_else(|| eyre::eyre!("Non-UTF-8 path: {:?}", path))?;
+    Ok(ObjectInfo::read(path)?
+        .map(|info| info.needed)
+        .unwrap_or_default())
 }
 
 // Helper function to run install_name_tool and verify the change
@@ -497,26 +882,3 @@ fn change_dep(path: &str, old_dep: &str, new_dep: &str) -> eyre::Result<()> {
     )?;
     Ok(())
 }
-
-fn show_fyi(
-    command: &str,
-    args: &[&str],
-    env: Option<IndexMap<String, String>>,
-    highlight_patterns: &[(regex::Regex, Style)],
-) -> eyre::Result<()> {
-    let cmd_str = format!("{} {}", command, args.join(" "));
-    info!("💅 FYI, {}", cmd_str.magenta());
-    let output = command::get_cmd_stdout(command, args, env)?;
-    for line in output.lines() {
-        let mut highlighted_line = line.to_string();
-        for (pattern, style) in highlight_patterns {
-            highlighted_line = pattern
-                .replace_all(&highlighted_line, |caps: &regex::Captures| {
-                    caps[0].to_string().style(*style).to_string()
-                })
-                .to_string();
-        }
-        info!("    {}", highlighted_line);
-    }
-    Ok(())
-}
@@ -0,0 +1,295 @@
+//! `beardist changelog`: renders the history between two tags as Markdown, one line per
+//! commit (or pull request, for merge and squash commits), grouped by conventional
+//! commit type when the project uses them.
+
+use log::*;
+use owo_colors::OwoColorize;
+use std::path::Path;
+
+use crate::{
+    ChangelogArgs, command,
+    environment::{Environment, ProcessEnvironment},
+    find_config,
+    forge::ForgeKind,
+};
+
+/// Separates the fields of a commit in our `git log` format
+const FIELD: char = '\u{1f}';
+/// Separates commits in our `git log` format
+const RECORD: char = '\u{1e}';
+
+/// A line of the changelog
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    /// Abbreviated commit hash
+    sha: String,
+
+    /// The commit's subject, or for merges of a pull request, the pull request's title
+    summary: String,
+
+    /// Pull request the change came from, when the subject says
+    pr: Option<u64>,
+}
+
+impl Entry {
+    /// Which section the entry goes in, going by conventional commit prefixes
+    /// (`feat: ...`, `fix(parser)!: ...`)
+    fn section(&self) -> Option<&'static str> {
+        let (kind, _) = self.summary.split_once(':')?;
+        let kind = kind.trim_end_matches('!');
+        let kind = kind.split_once('(').map_or(kind, |(kind, _)| kind);
+        Some(match kind {
+            "feat" => "Features",
+            "fix" => "Fixes",
+            "perf" => "Performance",
+            "docs" => "Documentation",
+            _ if kind.chars().all(|c| c.is_ascii_lowercase()) => "Other changes",
+            _ => return None,
+        })
+    }
+}
+
+/// Parses `git log --first-parent --format=%h%x1f%s%x1f%b%x1e` output
+fn parse_log(output: &str) -> Vec<Entry> {
+    output
+        .split(RECORD)
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').split(FIELD);
+            let sha = fields.next().filter(|sha| !sha.is_empty())?.to_string();
+            let subject = fields.next().unwrap_or_default();
+            let body = fields.next().unwrap_or_default();
+
+            // GitHub and Forgejo merge commits: "Merge pull request #12 from fork/branch",
+            // with the pull request's title as the body
+            if let Some(rest) = subject.strip_prefix("Merge pull request #") {
+                let number = rest.split_whitespace().next()?.parse().ok();
+                let title = body.lines().find(|line| !line.trim().is_empty());
+                return Some(Entry {
+                    sha,
+                    summary: title.unwrap_or(subject).trim().to_string(),
+                    pr: number,
+                });
+            }
+            // Squash merges: "Title (#12)"
+            let squashed = subject
+                .strip_suffix(')')
+                .and_then(|rest| rest.rsplit_once(" (#"))
+                .and_then(|(title, number)| Some((title, number.parse().ok()?)));
+            Some(match squashed {
+                Some((title, number)) => Entry {
+                    sha,
+                    summary: title.to_string(),
+                    pr: Some(number),
+                },
+                None => Entry {
+                    sha,
+                    summary: subject.to_string(),
+                    pr: None,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Where to link pull requests and commits to
+struct Links {
+    /// e.g. "https://github.com/bearcove/beardist"
+    repo_url: String,
+    forge: ForgeKind,
+}
+
+impl Links {
+    fn pull_request(&self, number: u64) -> String {
+        match self.forge {
+            ForgeKind::Github => format!("{}/pull/{}", self.repo_url, number),
+            ForgeKind::Forgejo => format!("{}/pulls/{}", self.repo_url, number),
+        }
+    }
+
+    fn commit(&self, sha: &str) -> String {
+        format!("{}/commit/{}", self.repo_url, sha)
+    }
+}
+
+/// Renders the changelog, with links if we know where the repository is
+fn render(title: &str, entries: &[Entry], links: Option<&Links>) -> String {
+    let line = |entry: &Entry| {
+        let reference = match (entry.pr, links) {
+            (Some(pr), Some(links)) => format!("[#{}]({})", pr, links.pull_request(pr)),
+            (Some(pr), None) => format!("#{}", pr),
+            (None, Some(links)) => format!("[{}]({})", entry.sha, links.commit(&entry.sha)),
+            (None, None) => entry.sha.clone(),
+        };
+        format!("- {} ({})\n", entry.summary, reference)
+    };
+
+    let mut out = format!("## {}\n\n", title);
+    if entries.is_empty() {
+        out.push_str("No changes.\n");
+        return out;
+    }
+    if entries.iter().all(|entry| entry.section().is_none()) {
+        out.extend(entries.iter().map(line));
+        return out;
+    }
+    let sections = [
+        "Features",
+        "Fixes",
+        "Performance",
+        "Documentation",
+        "Other changes",
+    ];
+    for section in sections {
+        let lines: Vec<String> = entries
+            .iter()
+            .filter(|entry| entry.section().unwrap_or("Other changes") == section)
+            .map(line)
+            .collect();
+        if !lines.is_empty() {
+            out.push_str(&format!("### {}\n\n", section));
+            out.extend(lines);
+            out.push('\n');
+        }
+    }
+    out.truncate(out.trim_end().len() + 1);
+    out
+}
+
+/// The tag before `to`, among the ones starting with `tag_prefix`. For `HEAD`, that's
+/// the latest tag, even if it's on `HEAD` itself.
+fn previous_tag(to: &str, tag_prefix: &str) -> eyre::Result<String> {
+    let pattern = format!("{}*", tag_prefix);
+    let before = if to == "HEAD" {
+        to.to_string()
+    } else {
+        format!("{}^", to)
+    };
+    command::get_trimmed_cmd_stdout(
+        "git",
+        &[
+            "describe",
+            "--tags",
+            "--abbrev=0",
+            "--match",
+            &pattern,
+            &before,
+        ],
+        None,
+    )
+    .map_err(|e| eyre::eyre!("Couldn't find the tag before {}, pass --from: {}", to, e))
+}
+
+pub(crate) fn changelog(args: ChangelogArgs, config_path: Option<&Path>) -> eyre::Result<()> {
+    // Without a config, we can still render, just without links
+    let config: Option<serde_json::Value> = match find_config(config_path)? {
+        Some(path) => Some(crate::config_file::load(&path, "config")?),
+        None => None,
+    };
+    let tag_prefix = config
+        .as_ref()
+        .and_then(|config| config["tag_prefix"].as_str())
+        .unwrap_or_default();
+    let links = config.as_ref().and_then(|config| {
+        let server_url = ProcessEnvironment
+            .ci_var("SERVER_URL")
+            .map_or_else(|| "https://github.com".to_string(), |(_, url)| url);
+        Some(Links {
+            repo_url: format!(
+                "{}/{}/{}",
+                server_url,
+                config["org"].as_str()?,
+                config["name"].as_str()?
+            ),
+            forge: serde_json::from_value(config["forge"].clone()).unwrap_or_default(),
+        })
+    });
+
+    let from = match args.from {
+        Some(from) => from,
+        None => previous_tag(&args.to, tag_prefix)?,
+    };
+    info!("📝 Changes from {} to {}", from.cyan(), args.to.cyan());
+    let log = command::get_cmd_stdout(
+        "git",
+        &[
+            "log",
+            "--first-parent",
+            "--format=%h%x1f%s%x1f%b%x1e",
+            &format!("{}..{}", from, args.to),
+        ],
+        None,
+    )?;
+    let title = if args.to == "HEAD" {
+        "Unreleased"
+    } else {
+        &args.to
+    };
+    let markdown = render(title, &parse_log(&log), links.as_ref());
+
+    match &args.output {
+        Some(path) if std::env::var("DRY_RUN").is_ok() => {
+            info!(
+                "Dry run: Would write the changelog to {}",
+                path.display().cyan()
+            );
+            print!("{}", markdown);
+        }
+        Some(path) => {
+            fs_err::write(path, &markdown)?;
+            info!("✅ Wrote the changelog to {}", path.display().cyan());
+        }
+        None => print!("{}", markdown),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_changelog() {
+        let log = "\
+a1b2c3d\u{1f}Merge pull request #12 from fork/branch\u{1f}feat: add a changelog command\n\u{1e}
+d4e5f6a\u{1f}fix(upload): retry on 502 (#11)\u{1f}\u{1e}
+0a1b2c3\u{1f}Bump dependencies\u{1f}\u{1e}
+";
+        let entries = parse_log(log);
+        assert_eq!(
+            entries[0],
+            Entry {
+                sha: "a1b2c3d".to_string(),
+                summary: "feat: add a changelog command".to_string(),
+                pr: Some(12),
+            }
+        );
+        assert_eq!(entries[1].pr, Some(11));
+        assert_eq!(entries[2].section(), None);
+
+        let links = Links {
+            repo_url: "https://github.com/bearcove/beardist".to_string(),
+            forge: ForgeKind::Github,
+        };
+        assert_eq!(
+            render("v1.2.0", &entries, Some(&links)),
+            "## v1.2.0
+
+### Features
+
+- feat: add a changelog command ([#12](https://github.com/bearcove/beardist/pull/12))
+
+### Fixes
+
+- fix(upload): retry on 502 ([#11](https://github.com/bearcove/beardist/pull/11))
+
+### Other changes
+
+- Bump dependencies ([0a1b2c3](https://github.com/bearcove/beardist/commit/0a1b2c3))
+"
+        );
+        assert_eq!(
+            render("Unreleased", &entries[2..], None),
+            "## Unreleased\n\n- Bump dependencies (0a1b2c3)\n"
+        );
+    }
+}
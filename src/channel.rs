@@ -0,0 +1,73 @@
+//! Channel builds: builds of a branch rather than a tag, published to a rolling release
+//! (e.g. "nightly") whose tag follows the branch, so downstream users can test HEAD.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ChannelConfig {
+    /// Name of the rolling release, and of its tag, e.g. "nightly"
+    pub(crate) name: String,
+
+    /// Name of the archive, without `.tar.xz`: `{target}` is replaced with the artifact
+    /// name (e.g. "x86_64-unknown-linux-gnu") and `{channel}` with the channel's name
+    #[serde(default = "default_artifact_template")]
+    pub(crate) artifact_template: String,
+}
+
+fn default_artifact_template() -> String {
+    "{target}-{channel}".to_string()
+}
+
+/// The channel a build publishes to, instead of a tag's release
+#[derive(Debug, Clone)]
+pub(crate) struct Channel {
+    pub(crate) name: String,
+
+    /// The commit being built, which the channel's tag gets moved to
+    pub(crate) sha: String,
+
+    artifact_template: String,
+}
+
+impl Channel {
+    pub(crate) fn new(config: &ChannelConfig, sha: String) -> Self {
+        Self {
+            name: config.name.clone(),
+            sha,
+            artifact_template: config.artifact_template.clone(),
+        }
+    }
+
+    /// The archive name (without `.tar.xz`) for a build of `target`
+    pub(crate) fn artifact_name(&self, target: &str) -> String {
+        self.artifact_template
+            .replace("{target}", target)
+            .replace("{channel}", &self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifact_name() {
+        let config: ChannelConfig = serde_json::from_str(r#"{"name": "nightly"}"#).unwrap();
+        let channel = Channel::new(&config, "abc123".to_string());
+        assert_eq!(
+            channel.artifact_name("aarch64-apple-darwin"),
+            "aarch64-apple-darwin-nightly"
+        );
+
+        let config = ChannelConfig {
+            artifact_template: "{channel}-{target}".to_string(),
+            ..config
+        };
+        assert_eq!(
+            Channel::new(&config, "abc123".to_string()).artifact_name("x86_64-pc-windows-msvc"),
+            "nightly-x86_64-pc-windows-msvc"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use log::*;
+use owo_colors::OwoColorize;
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    download_cache::{self, DownloadCache},
+    http,
+};
+
+/// File name → lowercase hex SHA-256, as published in a release's `SHA256SUMS`
+pub(crate) type Sha256Sums = HashMap<String, String>;
+
+/// Downloads `url` and hashes it as it comes in
+pub(crate) async fn fetch_and_hash(url: &str, dry_run: bool) -> eyre::Result<String> {
+    info!("Fetching binary from {}...", url.cyan());
+    if dry_run {
+        info!("Dry run: Would fetch {}", "binary".bright_yellow());
+        use sha2::{Digest, Sha256};
+        let mut hasher = Sha256::new();
+        hasher.update(url);
+        let sha256 = format!("{:x}", hasher.finalize());
+        return Ok(sha256);
+    }
+
+    fetch_and_hash_cached(url, DownloadCache::from_env().as_ref()).await
+}
+
+/// Like [`fetch_and_hash`], skipping the download when `cache` has the hash of what's
+/// still at `url`
+async fn fetch_and_hash_cached(url: &str, cache: Option<&DownloadCache>) -> eyre::Result<String> {
+    let cached = cache.and_then(|cache| cache.get(url));
+    let response = http::send("Fetching binary", || {
+        let request = http::client().get(url);
+        match &cached {
+            Some(entry) => request.header(reqwest::header::IF_NONE_MATCH, &entry.etag),
+            None => request,
+        }
+    })
+    .await?;
+    let status = response.status();
+    if let (304, Some(entry)) = (status.as_u16(), &cached) {
+        info!(
+            "Binary unchanged since last time, SHA256 {} from the download cache",
+            entry.sha256.green()
+        );
+        return Ok(entry.sha256.clone());
+    }
+    if status != 200 {
+        let error_text = response.text().await?;
+        error!(
+            "Failed to fetch binary: HTTP status {}, Response: {}",
+            status.to_string().red(),
+            error_text.red()
+        );
+        return Err(eyre::eyre!(
+            "Failed to fetch binary: HTTP status {}",
+            status
+        ));
+    }
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(String::from);
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    let byte_count = http::read_body(
+        response,
+        format!("Fetching {}", url.rsplit('/').next().unwrap_or(url)),
+        |chunk| hasher.update(chunk),
+    )
+    .await?;
+    let sha256 = format!("{:x}", hasher.finalize());
+    info!(
+        "Binary fetched ({} bytes) and SHA256 {}",
+        byte_count.to_string().green(),
+        "computed".green()
+    );
+    if let (Some(cache), Some(etag)) = (cache, etag) {
+        cache.put(&download_cache::Entry {
+            url: url.to_string(),
+            etag,
+            sha256: sha256.clone(),
+            size: byte_count,
+        });
+    }
+    Ok(sha256)
+}
+
+/// Lowercase hex SHA-256 of some bytes
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Passes writes through to `inner`, hashing and counting them along the way, so
+/// archives don't need to be read back to be checksummed
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: sha2::Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        use sha2::Digest;
+        Self {
+            inner,
+            hasher: sha2::Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Flushes `inner`, and returns the lowercase hex SHA-256 and size of what was written
+    pub(crate) fn finish(mut self) -> std::io::Result<(String, u64)> {
+        use sha2::Digest;
+        self.inner.flush()?;
+        Ok((format!("{:x}", self.hasher.finalize()), self.size))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Serializes checksums in `sha256sum` format, sorted by file name
+pub(crate) fn format_sha256sums(sums: &Sha256Sums) -> String {
+    let mut entries: Vec<_> = sums.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+        .into_iter()
+        .map(|(file_name, hash)| format!("{}  {}\n", hash, file_name))
+        .collect()
+}
+
+/// Fetches and parses a `SHA256SUMS` file. Returns `None` if there isn't one, so callers
+/// can fall back to hashing the artifacts themselves.
+pub(crate) async fn fetch_sha256sums(url: &str, dry_run: bool) -> eyre::Result<Option<Sha256Sums>> {
+    if dry_run {
+        return Ok(None);
+    }
+
+    info!("Looking for published checksums at {}...", url.cyan());
+    let response = http::send("Fetching SHA256SUMS", || http::client().get(url)).await?;
+    let status = response.status();
+    if status == 404 {
+        info!(
+            "No {} on this release, will hash artifacts",
+            "SHA256SUMS".cyan()
+        );
+        return Ok(None);
+    }
+    if !status.is_success() {
+        warn!(
+            "Failed to fetch SHA256SUMS (HTTP status {}), will hash artifacts",
+            status.to_string().yellow()
+        );
+        return Ok(None);
+    }
+    Ok(Some(parse_sha256sums(&response.text().await?)))
+}
+
+/// Parses `sha256sum`-style output: `<hex digest>  <file name>` per line. A leading `*`
+/// on the file name (binary mode) is ignored.
+pub(crate) fn parse_sha256sums(contents: &str) -> Sha256Sums {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (hash, file_name) = line.trim().split_once(char::is_whitespace)?;
+            let file_name = file_name.trim_start().trim_start_matches('*');
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            Some((file_name.to_string(), hash.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sha256sums() {
+        let hash_a = "a".repeat(64);
+        let hash_b = "B".repeat(64);
+        let contents = format!(
+            "{hash_a}  x86_64-unknown-linux-gnu.tar.xz\n{hash_b} *aarch64-apple-darwin.tar.xz\n\nnot a checksum line\n"
+        );
+        let sums = parse_sha256sums(&contents);
+        assert_eq!(sums.len(), 2);
+        assert_eq!(sums["x86_64-unknown-linux-gnu.tar.xz"], hash_a);
+        assert_eq!(sums["aarch64-apple-darwin.tar.xz"], "b".repeat(64));
+
+        let formatted = format_sha256sums(&sums);
+        assert_eq!(
+            formatted,
+            format!(
+                "{}  aarch64-apple-darwin.tar.xz\n{hash_a}  x86_64-unknown-linux-gnu.tar.xz\n",
+                "b".repeat(64)
+            )
+        );
+        assert_eq!(parse_sha256sums(&formatted), sums);
+    }
+
+    #[test]
+    fn test_fetch_and_hash_revalidates_cached_hashes() {
+        use crate::mock_forge::{MockForge, Response};
+
+        let forge = MockForge::start();
+        forge.mock_with("GET", "/x.tar.xz", |request| {
+            match request.header("If-None-Match") {
+                Some("\"v1\"") => Response::status(304),
+                _ => Response::status(200)
+                    .header("ETag", "\"v1\"")
+                    .body("archive"),
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DownloadCache::new(
+            camino::Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap(),
+        );
+        let url = format!("{}/x.tar.xz", forge.url());
+
+        let fetch = || http::block_on(fetch_and_hash_cached(&url, Some(&cache))).unwrap();
+        assert_eq!(fetch(), sha256_hex(b"archive"));
+        assert_eq!(fetch(), sha256_hex(b"archive"));
+        let requests = forge.requests_to("GET", "/x.tar.xz");
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].header("If-None-Match"), None);
+        assert_eq!(requests[1].header("If-None-Match"), Some("\"v1\""));
+    }
+
+    #[test]
+    fn test_hashing_writer() {
+        let mut writer = HashingWriter::new(Vec::new());
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+        let (hash, size) = writer.finish().unwrap();
+        assert_eq!(hash, sha256_hex(b"hello world"));
+        assert_eq!(size, 11);
+    }
+}
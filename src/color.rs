@@ -0,0 +1,70 @@
+//! Whether we emit ANSI colors, and tell child processes to. Call sites color their output
+//! unconditionally with `owo_colors`: when colors are off, escape codes are stripped as
+//! log lines are written.
+
+use std::{
+    borrow::Cow,
+    io::IsTerminal,
+    sync::{LazyLock, OnceLock},
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub(crate) enum ColorChoice {
+    /// Colors on a terminal or in GitHub Actions logs, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Resolves the choice, and sets the environment variables child processes (cargo, xz,
+/// custom steps...) look at to match it
+pub(crate) fn init(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && (std::io::stderr().is_terminal() || crate::gha::enabled())
+        }
+    };
+    ENABLED.set(enabled).ok();
+
+    unsafe {
+        if enabled {
+            std::env::remove_var("NO_COLOR");
+            std::env::set_var("CLICOLOR_FORCE", "1");
+            std::env::set_var("FORCE_COLOR", "1");
+            std::env::set_var("CARGO_TERM_COLOR", "always");
+        } else {
+            std::env::set_var("NO_COLOR", "1");
+            std::env::remove_var("CLICOLOR_FORCE");
+            std::env::remove_var("FORCE_COLOR");
+            std::env::set_var("CARGO_TERM_COLOR", "never");
+        }
+    }
+}
+
+pub(crate) fn enabled() -> bool {
+    *ENABLED.get().unwrap_or(&true)
+}
+
+pub(crate) fn strip_ansi(s: &str) -> Cow<'_, str> {
+    static ANSI: LazyLock<regex::Regex> =
+        LazyLock::new(|| regex::Regex::new("\x1b\\[[0-9;]*m").unwrap());
+    ANSI.replace_all(s, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi() {
+        use owo_colors::OwoColorize;
+        let colored = format!("{} and {}", "cyan".cyan(), "bold red".bold().red());
+        assert_eq!(strip_ansi(&colored), "cyan and bold red");
+    }
+}
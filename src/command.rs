@@ -1,18 +1,252 @@
+use crate::retry::{Failure, RetryPolicy};
 use color_eyre::owo_colors::OwoColorize;
 use eyre::Context;
 use indexmap::IndexMap;
 use log::*;
 use owo_colors::Style;
-use std::process::{Command, Stdio};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many of the last lines of each stream of a streamed command end up in its error
+const CAPTURED_LINES: usize = 200;
+
+/// Where to write the full output of each streamed command, if anywhere
+static LOG_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Logs older than this are removed when the log dir is set, so it doesn't grow forever
+const LOG_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Tees the full output of commands run with [`run_command`] to files in `dir`
+pub(crate) fn set_log_dir(dir: PathBuf) {
+    if let Ok(entries) = fs_err::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let expired = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified.elapsed().unwrap_or_default() > LOG_RETENTION);
+            if expired {
+                fs_err::remove_file(entry.path()).ok();
+            }
+        }
+    }
+    *LOG_DIR.lock().unwrap() = Some(dir);
+}
+
+/// Timeout for commands that aren't given one explicitly, from the config
+static DEFAULT_TIMEOUT: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Sets the timeout of commands that aren't given one explicitly
+pub(crate) fn set_default_timeout(timeout: Option<Duration>) {
+    *DEFAULT_TIMEOUT.lock().unwrap() = timeout;
+}
+
+fn default_timeout() -> Option<Duration> {
+    *DEFAULT_TIMEOUT.lock().unwrap()
+}
+
+/// A command that ran, but didn't succeed
+#[derive(Debug)]
+pub(crate) struct CommandError {
+    /// The command line, e.g. "cargo build --release"
+    pub(crate) command: String,
+    pub(crate) status: ExitStatus,
+    /// Set if we killed the command because it ran for longer than this
+    pub(crate) timed_out: Option<Duration>,
+    /// Captured output (only its last lines, if it was streamed)
+    pub(crate) stdout: Option<String>,
+    pub(crate) stderr: Option<String>,
+    /// Where the full output was written
+    pub(crate) log_file: Option<PathBuf>,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.timed_out {
+            Some(timeout) => write!(
+                f,
+                "Command '{}' timed out after {:.0?}",
+                self.command, timeout
+            )?,
+            None => write!(f, "Command '{}' failed with {}", self.command, self.status)?,
+        }
+        for (name, output) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if let Some(output) = output.as_deref().map(str::trim).filter(|o| !o.is_empty()) {
+                write!(f, "\n{}:\n{}", name.to_uppercase(), output)?;
+            }
+        }
+        if let Some(log_file) = &self.log_file {
+            write!(f, "\nFull output in {}", log_file.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Spawns the command, in its own process group if it may have to be killed along with
+/// its children (`cargo` spawns `rustc`, which may spawn linkers etc.)
+fn spawn(cmd: &mut Command, timeout: Option<Duration>) -> std::io::Result<Child> {
+    #[cfg(unix)]
+    if timeout.is_some() {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = timeout;
+    cmd.spawn()
+}
+
+/// Waits for the child to exit, killing it (and its process group) once `timeout` has
+/// elapsed. Returns its status, and whether it timed out.
+fn wait(child: &mut Child, timeout: Option<Duration>) -> std::io::Result<(ExitStatus, bool)> {
+    let Some(timeout) = timeout else {
+        return Ok((child.wait()?, false));
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false));
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    warn!(
+        "⏱️ Command timed out after {:.0?}, killing it",
+        timeout.yellow()
+    );
+    #[cfg(unix)]
+    // SAFETY: `kill` has no memory safety requirements. The child hasn't been reaped
+    // yet, so its pid (and process group id) can't have been reused.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    child.kill()?;
+    Ok((child.wait()?, true))
+}
+
+/// Opens a pseudo-terminal, returning its controller end (which we read from) and its
+/// terminal end (which the child writes to)
+#[cfg(unix)]
+fn open_pty() -> std::io::Result<(std::fs::File, std::os::fd::OwnedFd)> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let columns = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(120);
+    let mut size = libc::winsize {
+        ws_row: 40,
+        ws_col: columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let (mut controller, mut terminal) = (0, 0);
+    // SAFETY: the pointers are valid for the duration of the call, and on success we're
+    // the sole owners of the two file descriptors
+    unsafe {
+        if libc::openpty(
+            &mut controller,
+            &mut terminal,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &raw mut size,
+        ) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok((
+            OwnedFd::from_raw_fd(controller).into(),
+            OwnedFd::from_raw_fd(terminal),
+        ))
+    }
+}
+
+/// Creates the file to tee a command's output to, if there's a log dir. Failing to do so
+/// only costs us the log, so it's not an error.
+fn create_log_file(command: &str) -> Option<(PathBuf, fs_err::File)> {
+    let dir = LOG_DIR.lock().unwrap().clone()?;
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let name = std::path::Path::new(command)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| command.to_string());
+    let path = dir.join(format!("{}-{}.log", millis, name));
+    let file = fs_err::create_dir_all(&dir)
+        .and_then(|_| fs_err::File::create(&path))
+        .inspect_err(|e| warn!("Not logging output of {}: {}", command, e))
+        .ok()?;
+    Some((path, file))
+}
+
+/// Copies a child's output line by line to ours, prefixed with `prefix`, and to the log
+/// file if any. The last lines are kept in `tail` for error messages.
+fn stream_lines(
+    pipe: impl Read,
+    prefix: &str,
+    to_stderr: bool,
+    log: Option<&Mutex<fs_err::File>>,
+    tail: &Mutex<VecDeque<String>>,
+) {
+    for line in BufReader::new(pipe).split(b'\n') {
+        let Ok(line) = line else { break };
+        let line = String::from_utf8_lossy(&line);
+        // Progress bars redraw with carriage returns: keep what was drawn last
+        let line = line.trim_end_matches('\r');
+        let line = line.rsplit('\r').next().unwrap_or_default();
+        if to_stderr {
+            eprintln!("{} {}", prefix, line);
+        } else {
+            println!("{} {}", prefix, line);
+        }
+        if let Some(log) = log {
+            writeln!(log.lock().unwrap(), "{}", line).ok();
+        }
+        let mut tail = tail.lock().unwrap();
+        if tail.len() == CAPTURED_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(crate::color::strip_ansi(line).into_owned());
+    }
+}
 
 pub(crate) fn run_command(
     command: &str,
     args: &[&str],
     env: Option<IndexMap<String, String>>,
 ) -> eyre::Result<()> {
-    use std::process::{Command, Stdio};
-    use std::time::Instant;
+    run_command_with(command, args, env, RunOptions::default())
+}
 
+/// How to run a command with [`run_command_with`]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RunOptions {
+    /// Kill the command if it runs for longer than this. Defaults to the config's timeout.
+    pub(crate) timeout: Option<Duration>,
+
+    /// Give the command a pseudo-terminal for its stdout and stderr (which end up merged
+    /// into our stdout), for tools that only use colors and progress bars on a terminal
+    pub(crate) pty: bool,
+}
+
+/// Like [`run_command`], with options
+pub(crate) fn run_command_with(
+    command: &str,
+    args: &[&str],
+    env: Option<IndexMap<String, String>>,
+    options: RunOptions,
+) -> eyre::Result<()> {
+    let timeout = options.timeout.or_else(default_timeout);
     debug!(
         "🚀 Running command: {} {}",
         command.cyan(),
@@ -20,17 +254,81 @@ pub(crate) fn run_command(
     );
 
     let mut cmd = Command::new(command);
-    cmd.args(args)
-        .stdin(Stdio::null())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+    cmd.args(args).stdin(Stdio::null());
+    let mut pty = None;
+    if options.pty {
+        #[cfg(unix)]
+        {
+            let (controller, terminal) = open_pty().wrap_err("while opening a pseudo-terminal")?;
+            cmd.stdout(terminal.try_clone()?).stderr(terminal);
+            pty = Some(Box::new(controller) as Box<dyn Read + Send>);
+        }
+        #[cfg(not(unix))]
+        warn!("Pseudo-terminals are only supported on Unix, piping output instead");
+    }
+    if pty.is_none() {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
 
     if let Some(env_vars) = env {
         cmd.envs(env_vars);
     }
 
+    let name = std::path::Path::new(command)
+        .file_name()
+        .map_or(command.into(), |name| name.to_string_lossy());
+    let prefix = format!("[{}]", name);
+    let prefix = if crate::color::enabled() {
+        prefix.dimmed().to_string()
+    } else {
+        prefix
+    };
+    let (log_file, log) = match create_log_file(command) {
+        Some((path, file)) => (Some(path), Some(Arc::new(Mutex::new(file)))),
+        None => (None, None),
+    };
+
     let start_time = Instant::now();
-    let status = cmd.status()?;
+    let mut child = spawn(&mut cmd, timeout)?;
+    // The command holds on to our copies of the terminal end: it has to go for reads from
+    // the controller end to end once the child exits
+    drop(cmd);
+    let (done_tx, done_rx) = mpsc::channel();
+    let tails = [
+        pty.or_else(|| {
+            child
+                .stdout
+                .take()
+                .map(|out| Box::new(out) as Box<dyn Read + Send>)
+        }),
+        child
+            .stderr
+            .take()
+            .map(|err| Box::new(err) as Box<dyn Read + Send>),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(index, pipe)| {
+        let tail = Arc::new(Mutex::new(VecDeque::new()));
+        let (prefix, log, thread_tail, done_tx) =
+            (prefix.clone(), log.clone(), tail.clone(), done_tx.clone());
+        std::thread::spawn(move || {
+            if let Some(pipe) = pipe {
+                stream_lines(pipe, &prefix, index == 1, log.as_deref(), &thread_tail);
+            }
+            done_tx.send(()).ok();
+        });
+        tail
+    })
+    .collect::<Vec<_>>();
+    let (status, timed_out) = wait(&mut child, timeout)?;
+    // Daemons spawned by the command (build servers etc.) may hold on to its pipes, so
+    // only give the output a moment to drain once the command itself has exited
+    for _ in &tails {
+        if done_rx.recv_timeout(Duration::from_secs(2)).is_err() {
+            break;
+        }
+    }
     let duration = start_time.elapsed();
 
     let status_icon = if status.success() { "✅" } else { "❌" };
@@ -61,17 +359,58 @@ pub(crate) fn run_command(
         error!("{}", log_message);
     }
     if !status.success() {
-        error!("We really needed that command to work, so we're going to bail out now. Buh-bye.",);
-        std::process::exit(status.code().unwrap_or(-1));
+        return Err(CommandError {
+            command: format!("{} {}", command, args.join(" ")),
+            status,
+            timed_out: timeout.filter(|_| timed_out),
+            stdout: Some(tails[0].lock().unwrap().make_contiguous().join("\n")),
+            stderr: Some(tails[1].lock().unwrap().make_contiguous().join("\n")),
+            log_file,
+        }
+        .into());
     }
 
     Ok(())
 }
 
+/// Like [`run_command`], but tries again as `policy` says if the command fails, for
+/// commands that go over the network (`git push`, toolchain installs...)
+pub(crate) fn run_command_with_retry(
+    command: &str,
+    args: &[&str],
+    env: Option<IndexMap<String, String>>,
+    policy: RetryPolicy,
+) -> eyre::Result<()> {
+    let what = match args.first() {
+        Some(subcommand) => format!("{} {}", command, subcommand),
+        None => command.to_string(),
+    };
+    policy.run(&what, || {
+        run_command(command, args, env.clone()).map_err(|e| {
+            // Failing to even start the command won't get better by trying again
+            if e.is::<CommandError>() {
+                Failure::Transient(e)
+            } else {
+                Failure::Permanent(e)
+            }
+        })
+    })
+}
+
 pub(crate) fn get_cmd_stdout(
     command: &str,
     args: &[&str],
     env: Option<IndexMap<String, String>>,
+) -> eyre::Result<String> {
+    get_cmd_stdout_with_timeout(command, args, env, default_timeout())
+}
+
+/// Like [`get_cmd_stdout`], but kills the command if it runs for longer than `timeout`
+pub(crate) fn get_cmd_stdout_with_timeout(
+    command: &str,
+    args: &[&str],
+    env: Option<IndexMap<String, String>>,
+    timeout: Option<Duration>,
 ) -> eyre::Result<String> {
     debug!(
         "🚀 Running command: {} {}",
@@ -89,22 +428,44 @@ pub(crate) fn get_cmd_stdout(
         cmd.envs(env_vars);
     }
 
-    let output = cmd
-        .output()
-        .wrap_err_with(|| format!("while running {} {}", command.cyan(), args.join(" ").cyan()))?;
+    let wrap = || format!("while running {} {}", command.cyan(), args.join(" ").cyan());
+    let mut child = spawn(&mut cmd, timeout).wrap_err_with(wrap)?;
+    // Drain both pipes while we wait, so a chatty child can't block on a full pipe
+    let readers = [
+        child
+            .stdout
+            .take()
+            .map(|out| Box::new(out) as Box<dyn Read + Send>),
+        child
+            .stderr
+            .take()
+            .map(|err| Box::new(err) as Box<dyn Read + Send>),
+    ]
+    .map(|pipe| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                pipe.read_to_end(&mut buf).ok();
+            }
+            buf
+        })
+    });
+    let (status, timed_out) = wait(&mut child, timeout).wrap_err_with(wrap)?;
+    let [stdout, stderr] = readers.map(|reader| reader.join().unwrap_or_default());
 
-    if !output.status.success() {
-        error!(
-            "Command failed with exit code {}",
-            output.status.code().unwrap_or(-1)
-        );
-        eprintln!("STDOUT: {}", String::from_utf8_lossy(&output.stdout));
-        eprintln!("STDERR: {}", String::from_utf8_lossy(&output.stderr));
-        std::process::exit(output.status.code().unwrap_or(-1));
+    if !status.success() {
+        return Err(CommandError {
+            command: format!("{} {}", command, args.join(" ")),
+            status,
+            timed_out: timeout.filter(|_| timed_out),
+            stdout: Some(String::from_utf8_lossy(&stdout).into_owned()),
+            stderr: Some(String::from_utf8_lossy(&stderr).into_owned()),
+            log_file: None,
+        }
+        .into());
     }
 
-    let stdout = String::from_utf8(output.stdout)?;
-    Ok(stdout)
+    Ok(String::from_utf8(stdout)?)
 }
 
 pub(crate) fn get_trimmed_cmd_stdout(
@@ -115,3 +476,97 @@ pub(crate) fn get_trimmed_cmd_stdout(
     let stdout = get_cmd_stdout(command, args, env)?;
     Ok(stdout.trim().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failing_commands_return_errors() {
+        let err = get_cmd_stdout("sh", &["-c", "echo oops >&2; exit 3"], None).unwrap_err();
+        let err = err.downcast_ref::<CommandError>().unwrap();
+        assert_eq!(err.status.code(), Some(3));
+        assert_eq!(err.stderr.as_deref(), Some("oops\n"));
+        assert!(err.to_string().contains("STDERR:\noops"));
+
+        let err = run_command("sh", &["-c", "exit 4"], None).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CommandError>().unwrap().status.code(),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn test_streamed_output_is_captured_and_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        set_log_dir(dir.path().to_path_buf());
+        let err = run_command("sh", &["-c", "seq 1 300; echo boom >&2; exit 1"], None).unwrap_err();
+        let err = err.downcast_ref::<CommandError>().unwrap();
+        let stdout = err.stdout.as_deref().unwrap();
+        assert_eq!(stdout.lines().count(), CAPTURED_LINES);
+        assert!(stdout.ends_with("299\n300"));
+        assert_eq!(err.stderr.as_deref(), Some("boom"));
+
+        let log = fs_err::read_to_string(err.log_file.as_ref().unwrap()).unwrap();
+        assert!(log.starts_with("1\n2\n"));
+        assert!(log.contains("boom\n"));
+    }
+
+    #[test]
+    fn test_pty_mode() {
+        let err = run_command_with(
+            "sh",
+            &[
+                "-c",
+                "[ -t 1 ] && [ -t 2 ] && echo tty; printf 'a\\rb\\n' >&2; exit 1",
+            ],
+            None,
+            RunOptions {
+                pty: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        let err = err.downcast_ref::<CommandError>().unwrap();
+        // Both streams go through the terminal, and only the last redraw of a line is kept
+        assert_eq!(err.stdout.as_deref(), Some("tty\nb"));
+        assert_eq!(err.stderr.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_retries_failing_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("tried");
+        let script = format!("[ -e {0} ] && exit 0; touch {0}; exit 1", marker.display());
+        let policy = RetryPolicy {
+            base_delay: Duration::ZERO,
+            max_jitter: Duration::ZERO,
+            ..Default::default()
+        };
+        run_command_with_retry("sh", &["-c", &script], None, policy).unwrap();
+
+        let err = run_command_with_retry("does-not-exist", &[], None, policy).unwrap_err();
+        assert!(!err.is::<CommandError>());
+    }
+
+    #[test]
+    fn test_timeouts_kill_the_process_group() {
+        let started = Instant::now();
+        let err = get_cmd_stdout_with_timeout(
+            "sh",
+            &["-c", "echo started; sleep 30 & wait"],
+            None,
+            Some(Duration::from_millis(200)),
+        )
+        .unwrap_err();
+        // Returning at all means the backgrounded sleep, which holds stdout open, was killed
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(
+            crate::error::ErrorKind::of(&err),
+            crate::error::ErrorKind::Timeout
+        );
+        let err = err.downcast_ref::<CommandError>().unwrap();
+        assert_eq!(err.stdout.as_deref(), Some("started\n"));
+        assert!(err.to_string().contains("timed out after"));
+    }
+}
@@ -0,0 +1,226 @@
+//! Config files can be written in JSON, TOML or YAML (all with the same shape): the
+//! format is picked from the file's extension.
+
+use eyre::Context;
+use owo_colors::OwoColorize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Classify, ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Extensions we look for, in order of preference
+    const EXTENSIONS: [(&str, Format); 4] = [
+        ("json", Format::Json),
+        ("toml", Format::Toml),
+        ("yaml", Format::Yaml),
+        ("yml", Format::Yaml),
+    ];
+
+    pub(crate) fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        Self::EXTENSIONS
+            .iter()
+            .find(|(ext, _)| *ext == extension)
+            .map(|(_, format)| *format)
+    }
+}
+
+/// Finds `{stem}.json`, `{stem}.toml`, `{stem}.yaml` or `{stem}.yml` in `dir`. Having
+/// more than one is an error, since it wouldn't be obvious which one is used.
+pub(crate) fn find(dir: &Path, stem: &str) -> eyre::Result<Option<PathBuf>> {
+    let found: Vec<PathBuf> = Format::EXTENSIONS
+        .iter()
+        .map(|(ext, _)| dir.join(format!("{}.{}", stem, ext)))
+        .filter(|path| path.is_file())
+        .collect();
+    match found.as_slice() {
+        [] => Ok(None),
+        [path] => Ok(Some(path.clone())),
+        paths => Err(eyre::eyre!(
+            "Found several config files, keep only one of: {}",
+            paths
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Parses a config file's contents, in the given format
+pub(crate) fn from_str<T: DeserializeOwned>(contents: &str, format: Format) -> eyre::Result<T> {
+    Ok(match format {
+        Format::Json => serde_json::from_str(contents)?,
+        Format::Toml => toml::from_str(contents)?,
+        Format::Yaml => serde_yaml_ng::from_str(contents)?,
+    })
+}
+
+/// Serializes a config in the given format
+pub(crate) fn to_string(value: &Value, format: Format) -> eyre::Result<String> {
+    Ok(match format {
+        Format::Json => serde_json::to_string_pretty(value)? + "\n",
+        Format::Toml => toml::to_string_pretty(value)?,
+        Format::Yaml => serde_yaml_ng::to_string(value)?,
+    })
+}
+
+/// Reads and parses a config file, in whichever format its extension says, after
+/// resolving its `extends` chain. `what` is used in error messages, e.g. "tap config".
+pub(crate) fn load<T: DeserializeOwned>(path: &Path, what: &str) -> eyre::Result<T> {
+    let value = load_value(path, what, &mut Vec::new()).classify(ErrorKind::Config)?;
+    serde_json::from_value(value)
+        .wrap_err_with(|| {
+            format!(
+                "Failed to parse {} file at {}",
+                what,
+                path.display().to_string().cyan()
+            )
+        })
+        .classify(ErrorKind::Config)
+}
+
+/// Reads a config file as a JSON value. If it has an `"extends"` key, the file it points
+/// to (relative to this one) is loaded first, and this file's settings are merged on top.
+fn load_value(path: &Path, what: &str, seen: &mut Vec<PathBuf>) -> eyre::Result<Value> {
+    let path = fs_err::canonicalize(path)?;
+    if seen.contains(&path) {
+        return Err(eyre::eyre!(
+            "{} file at {} extends itself",
+            what,
+            path.display().to_string().cyan()
+        ));
+    }
+    seen.push(path.clone());
+
+    let format = Format::from_path(&path).ok_or_else(|| {
+        eyre::eyre!(
+            "Unsupported {} file format (expected .json, .toml or .yaml): {}",
+            what,
+            path.display().to_string().cyan()
+        )
+    })?;
+    let contents = fs_err::read_to_string(&path).wrap_err_with(|| {
+        format!(
+            "Failed to read {} file at {}",
+            what,
+            path.display().to_string().cyan()
+        )
+    })?;
+    let mut value: Value = from_str(&contents, format).wrap_err_with(|| {
+        format!(
+            "Failed to parse {} file at {}",
+            what,
+            path.display().to_string().cyan()
+        )
+    })?;
+
+    let Some(extends) = value.as_object_mut().and_then(|o| o.remove("extends")) else {
+        return Ok(value);
+    };
+    let extends = extends.as_str().ok_or_else(|| {
+        eyre::eyre!(
+            "\"extends\" must be a path, in {}",
+            path.display().to_string().cyan()
+        )
+    })?;
+    let base_path = path.parent().unwrap().join(extends);
+    let mut base = load_value(&base_path, what, seen)
+        .wrap_err_with(|| format!("Failed to load the config extended by {}", path.display()))?;
+    merge(&mut base, value);
+    Ok(base)
+}
+
+/// Merges `overrides` into `base`: objects are merged key by key, anything else
+/// (including arrays) is replaced
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_formats_have_the_same_shape() {
+        let json = r#"{ "version": 3, "bins": ["beardist"] }"#;
+        let toml = "# comments!\nversion = 3\nbins = [\"beardist\"]\n";
+        let yaml = "# comments!\nversion: 3\nbins:\n  - beardist\n";
+
+        let expected: BTreeMap<String, serde_json::Value> = from_str(json, Format::Json).unwrap();
+        for (contents, format) in [(toml, Format::Toml), (yaml, Format::Yaml)] {
+            let parsed: BTreeMap<String, serde_json::Value> = from_str(contents, format).unwrap();
+            assert_eq!(parsed, expected);
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(find(dir.path(), ".beardist").unwrap(), None);
+
+        fs_err::write(dir.path().join(".beardist.yml"), "version: 3\n").unwrap();
+        let found = find(dir.path(), ".beardist").unwrap().unwrap();
+        assert_eq!(Format::from_path(&found), Some(Format::Yaml));
+
+        fs_err::write(dir.path().join(".beardist.json"), "{}").unwrap();
+        assert!(find(dir.path(), ".beardist").is_err());
+    }
+
+    #[test]
+    fn test_extends() {
+        let dir = tempfile::tempdir().unwrap();
+        fs_err::create_dir(dir.path().join("crate")).unwrap();
+        fs_err::write(
+            dir.path().join(".beardist-base.toml"),
+            "version = 3\norg = \"bearcove\"\n[custom]\nsteps = [[\"make\"]]\nfiles = [\"LICENSE\"]\n",
+        )
+        .unwrap();
+        let path = dir.path().join("crate/.beardist.json");
+        fs_err::write(
+            &path,
+            r#"{ "extends": "../.beardist-base.toml", "name": "home", "custom": { "files": [] } }"#,
+        )
+        .unwrap();
+
+        let value: Value = load(&path, "config").unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "version": 3,
+                "org": "bearcove",
+                "name": "home",
+                "custom": { "steps": [["make"]], "files": [] },
+            })
+        );
+
+        fs_err::write(
+            dir.path().join(".beardist-base.toml"),
+            "extends = \"crate/.beardist.json\"\n",
+        )
+        .unwrap();
+        assert!(load::<Value>(&path, "config").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Where tokens come from. Besides the `$VAR` environment variable, they can be read from
+//! the file at `$VAR_FILE` (which is how secrets get mounted in Kubernetes, and files
+//! don't show up in process listings), from a credential helper command configured in
+//! `.beardist.json`, or for GitHub tokens, from the GitHub CLI.
+
+use log::*;
+use owo_colors::OwoColorize;
+use std::sync::Mutex;
+
+use crate::{
+    command::get_trimmed_cmd_stdout,
+    environment::{Environment, ProcessEnvironment},
+    error::{Classify, ErrorKind},
+};
+
+/// Command to ask for tokens, with the variable name appended to its arguments
+static HELPER: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Variables `gh auth token` is an acceptable fallback for. Not `GH_READWRITE_TOKEN`:
+/// builds without it are dry runs, and a developer's own token shouldn't change that.
+const GITHUB_TOKEN_VARS: &[&str] = &["GITHUB_TOKEN"];
+
+pub(crate) fn set_helper(helper: Option<Vec<String>>) {
+    *HELPER.lock().unwrap() = helper.filter(|helper| !helper.is_empty());
+}
+
+/// Looks up a token by the name of the environment variable that would hold it
+pub(crate) fn token(var: &str) -> Option<String> {
+    token_in(&ProcessEnvironment, var)
+}
+
+/// Like [`token`], looking up variables in `env`
+pub(crate) fn token_in(env: &dyn Environment, var: &str) -> Option<String> {
+    if let Some(token) = env.var(var).filter(|token| !token.is_empty()) {
+        return Some(token);
+    }
+
+    let file_var = format!("{}_FILE", var);
+    if let Some(path) = env.var(&file_var) {
+        match fs_err::read_to_string(&path) {
+            Ok(token) => return Some(token.trim().to_string()),
+            Err(e) => warn!("Couldn't read {} from {}: {}", var, file_var.cyan(), e),
+        }
+    }
+
+    let helper = HELPER.lock().unwrap().clone();
+    if let Some(helper) = helper {
+        let mut args: Vec<&str> = helper[1..].iter().map(String::as_str).collect();
+        args.push(var);
+        match get_trimmed_cmd_stdout(&helper[0], &args, None) {
+            Ok(token) if !token.is_empty() => return Some(token),
+            Ok(_) => debug!("Credential helper has no {}", var),
+            Err(e) => warn!("Credential helper failed to provide {}: {}", var, e),
+        }
+    }
+
+    if GITHUB_TOKEN_VARS.contains(&var) {
+        if let Ok(token) = get_trimmed_cmd_stdout("gh", &["auth", "token"], None) {
+            if !token.is_empty() {
+                debug!("Using the GitHub CLI's token for {}", var);
+                return Some(token);
+            }
+        }
+    }
+    None
+}
+
+/// Like [`token`], but it's an error for it to be missing
+pub(crate) fn require(var: &str) -> eyre::Result<String> {
+    token(var)
+        .ok_or_else(|| {
+            eyre::eyre!(
+                "{} is not set (neither is {}_FILE, and no credential helper provided it)",
+                var,
+                var
+            )
+        })
+        .classify(ErrorKind::Config)
+}
+
+/// Like [`require`], for a token with a narrower purpose (e.g. `GITHUB_PUSH_TOKEN`) that
+/// falls back to a general one (e.g. `GITHUB_TOKEN`)
+pub(crate) fn require_or(var: &str, fallback: &str) -> eyre::Result<String> {
+    match token(var) {
+        Some(token) => Ok(token),
+        None => require(fallback)
+            .map_err(|e| eyre::eyre!("Neither {} nor {} is available: {}", var, fallback, e))
+            .classify(ErrorKind::Config),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("token");
+        fs_err::write(&file, "from-file\n").unwrap();
+        // SAFETY: these variables are only used by this test
+        unsafe {
+            std::env::set_var("BEARDIST_TEST_CREDENTIAL_FILE", &file);
+        }
+        assert_eq!(
+            token("BEARDIST_TEST_CREDENTIAL").as_deref(),
+            Some("from-file")
+        );
+
+        unsafe {
+            std::env::set_var("BEARDIST_TEST_CREDENTIAL", "from-env");
+        }
+        assert_eq!(
+            token("BEARDIST_TEST_CREDENTIAL").as_deref(),
+            Some("from-env")
+        );
+
+        assert!(require("BEARDIST_TEST_MISSING_CREDENTIAL").is_err());
+        assert_eq!(
+            require_or(
+                "BEARDIST_TEST_MISSING_CREDENTIAL",
+                "BEARDIST_TEST_CREDENTIAL"
+            )
+            .unwrap(),
+            "from-env"
+        );
+    }
+}
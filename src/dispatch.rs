@@ -0,0 +1,222 @@
+//! `beardist dispatch`: triggers the build workflow for a tag on every configured runner,
+//! waits for the runs to finish, and optionally updates the Homebrew tap, so a release
+//! takes one command instead of three stages.
+
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    DispatchArgs, UpdateTapArgs,
+    error::{Classify, ErrorKind},
+    forge::{self, RunState, WorkflowRun},
+    homebrew, load_config,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DispatchConfig {
+    /// File name of the build workflow, which needs a `workflow_dispatch` trigger, e.g.
+    /// "build.yml"
+    pub(crate) workflow: String,
+
+    /// Runners to build on: the workflow is dispatched once for each, with its name as the
+    /// `runner_input` input, e.g. `["ubuntu-24.04", "macos-15"]`. If empty, it's
+    /// dispatched once, for workflows with a matrix of their own.
+    #[serde(default)]
+    pub(crate) runners: Vec<String>,
+
+    /// Name of the input runners are passed as
+    #[serde(default = "default_runner_input")]
+    pub(crate) runner_input: String,
+
+    /// Other inputs, passed to every run
+    #[serde(default)]
+    pub(crate) inputs: BTreeMap<String, String>,
+}
+
+fn default_runner_input() -> String {
+    "runner".to_string()
+}
+
+impl DispatchConfig {
+    /// The inputs of each run to dispatch
+    fn runs(&self) -> Vec<Map<String, Value>> {
+        let common: Map<String, Value> = self
+            .inputs
+            .iter()
+            .map(|(name, value)| (name.clone(), Value::from(value.as_str())))
+            .collect();
+        if self.runners.is_empty() {
+            return vec![common];
+        }
+        self.runners
+            .iter()
+            .map(|runner| {
+                let mut inputs = common.clone();
+                inputs.insert(self.runner_input.clone(), Value::from(runner.as_str()));
+                inputs
+            })
+            .collect()
+    }
+}
+
+/// Picks the runs we dispatched out of `runs`: the ones that weren't there `before`.
+/// `None` until all `expected` of them have shown up and finished.
+fn finished_runs<'a>(
+    runs: &'a [WorkflowRun],
+    before: &HashSet<u64>,
+    expected: usize,
+) -> Option<Vec<&'a WorkflowRun>> {
+    let ours: Vec<&WorkflowRun> = runs
+        .iter()
+        .filter(|run| !before.contains(&run.id))
+        .collect();
+    let finished = ours.len() >= expected && ours.iter().all(|run| run.state != RunState::Pending);
+    finished.then_some(ours)
+}
+
+pub(crate) fn dispatch(args: DispatchArgs, config_path: Option<&Path>) -> eyre::Result<()> {
+    let config = load_config(config_path)?;
+    let dispatch_config = config
+        .dispatch
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("No `dispatch` section in the config"))
+        .classify(ErrorKind::Config)?;
+    let (org, name, workflow) = (&config.org, &config.name, &dispatch_config.workflow);
+    let runs = dispatch_config.runs();
+
+    if std::env::var("DRY_RUN").is_ok() {
+        for inputs in &runs {
+            info!(
+                "Dry run: Would dispatch {} on {} with inputs {}",
+                workflow.cyan(),
+                args.tag.yellow(),
+                Value::Object(inputs.clone())
+            );
+        }
+        return Ok(());
+    }
+
+    let forge = forge::client_from_env(config.forge)?;
+    let before: HashSet<u64> = forge
+        .list_workflow_runs(org, name, workflow, &args.tag)?
+        .iter()
+        .map(|run| run.id)
+        .collect();
+    for inputs in &runs {
+        forge.dispatch_workflow(org, name, workflow, &args.tag, inputs)?;
+        info!(
+            "🚀 Dispatched {} on {} {}",
+            workflow.cyan(),
+            args.tag.yellow(),
+            Value::Object(inputs.clone()).dimmed()
+        );
+    }
+    if args.no_wait {
+        return Ok(());
+    }
+
+    info!(
+        "⏳ Waiting for {} runs to finish...",
+        runs.len().to_string().cyan()
+    );
+    let deadline = Instant::now() + Duration::from_secs(args.timeout_mins * 60);
+    let finished = loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let all_runs = forge.list_workflow_runs(org, name, workflow, &args.tag)?;
+        if let Some(finished) = finished_runs(&all_runs, &before, runs.len()) {
+            break finished.into_iter().cloned().collect::<Vec<_>>();
+        }
+        if Instant::now() > deadline {
+            return Err(eyre::eyre!(
+                "Runs of {} on {} didn't finish within {} minutes",
+                workflow,
+                args.tag,
+                args.timeout_mins
+            ));
+        }
+    };
+
+    for run in &finished {
+        match run.state {
+            RunState::Succeeded => info!("✅ {}", run.url.green()),
+            _ => error!("❌ {}", run.url.red()),
+        }
+    }
+    let failed = finished
+        .iter()
+        .filter(|run| run.state != RunState::Succeeded)
+        .count();
+    if failed > 0 {
+        return Err(eyre::eyre!(
+            "{} of {} runs of {} on {} failed",
+            failed,
+            finished.len(),
+            workflow,
+            args.tag
+        ))
+        .classify(ErrorKind::Build);
+    }
+
+    if let Some(tap_dir) = &args.update_tap {
+        info!("🍺 Updating the tap in {}", tap_dir.display().cyan());
+        homebrew::update_tap(UpdateTapArgs {
+            pr: false,
+            only: vec![name.clone()],
+            jobs: 1,
+            repos: vec![tap_dir.clone()],
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(id: u64, state: RunState) -> WorkflowRun {
+        WorkflowRun {
+            id,
+            state,
+            url: format!("https://github.com/bearcove/beardist/actions/runs/{}", id),
+        }
+    }
+
+    #[test]
+    fn test_dispatched_runs() {
+        let config: DispatchConfig = serde_json::from_value(serde_json::json!({
+            "workflow": "build.yml",
+            "runners": ["ubuntu-24.04", "macos-15"],
+            "inputs": {"profile": "release"},
+        }))
+        .unwrap();
+        let runs = config.runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1]["runner"], "macos-15");
+        assert_eq!(runs[1]["profile"], "release");
+
+        let before = HashSet::from([1]);
+        let all_runs = [run(3, RunState::Pending), run(1, RunState::Succeeded)];
+        assert!(finished_runs(&all_runs, &before, 2).is_none());
+        let all_runs = [
+            run(3, RunState::Failed),
+            run(2, RunState::Succeeded),
+            run(1, RunState::Pending),
+        ];
+        let finished = finished_runs(&all_runs, &before, 2).unwrap();
+        assert_eq!(
+            finished.iter().map(|run| run.id).collect::<Vec<_>>(),
+            [3, 2]
+        );
+    }
+}
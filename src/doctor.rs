@@ -0,0 +1,274 @@
+use log::info;
+use owo_colors::OwoColorize;
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{USER_AGENT, utils::format_secret};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    status: Status,
+    name: String,
+    detail: String,
+}
+
+#[derive(Default)]
+struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn add(&mut self, status: Status, name: impl Into<String>, detail: impl Into<String>) {
+        self.checks.push(Check {
+            status,
+            name: name.into(),
+            detail: detail.into(),
+        });
+    }
+
+    fn print(&self) {
+        let name_width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for check in &self.checks {
+            let status = match check.status {
+                Status::Pass => "PASS".green().to_string(),
+                Status::Warn => "WARN".yellow().to_string(),
+                Status::Fail => "FAIL".red().to_string(),
+            };
+            info!(
+                "{}  {:<width$}  {}",
+                status,
+                check.name,
+                check.detail,
+                width = name_width
+            );
+        }
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.checks.iter().filter(|c| c.status == status).count()
+    }
+}
+
+/// First line of `<tool> <args>`'s output, if it could be run at all
+fn tool_version(tool: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(tool)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let first_line = stdout
+        .lines()
+        .chain(stderr.lines())
+        .next()
+        .unwrap_or_default();
+    Some(first_line.trim().to_string())
+}
+
+fn check_tool(report: &mut Report, tool: &str, args: &[&str], missing: Status) {
+    match tool_version(tool, args) {
+        Some(version) => report.add(Status::Pass, tool, version),
+        None => report.add(missing, tool, "not found"),
+    }
+}
+
+fn check_env(report: &mut Report, var: &str, secret: bool, missing: Status, why: &str) {
+    let value = match secret {
+        true => crate::credentials::token(var),
+        false => std::env::var(var).ok(),
+    };
+    match value {
+        Some(value) if secret => report.add(Status::Pass, var, format_secret(&value)),
+        Some(value) => report.add(Status::Pass, var, value),
+        None => report.add(missing, var, format!("not set — {}", why)),
+    }
+}
+
+/// Probes the GitHub API, and reports the scopes of classic tokens
+fn check_github(report: &mut Report) {
+    let api_url = std::env::var("GITHUB_SERVER_URL")
+        .map(|url| url.replace("github.com", "api.github.com"))
+        .unwrap_or_else(|_| "https://api.github.com".to_string());
+    let token = crate::credentials::token("GH_READWRITE_TOKEN")
+        .or_else(|| crate::credentials::token("GITHUB_TOKEN"));
+
+    let mut request = reqwest::blocking::Client::new()
+        .get(&api_url)
+        .header("User-Agent", USER_AGENT)
+        .header("Accept", "application/vnd.github+json");
+    if let Some(token) = &token {
+        request = request.header("Authorization", format!("token {}", token));
+    }
+    let response = match request.send() {
+        Ok(response) => response,
+        Err(e) => {
+            report.add(Status::Fail, "GitHub API", format!("unreachable: {}", e));
+            return;
+        }
+    };
+    let status = response.status();
+    if !status.is_success() {
+        report.add(
+            Status::Fail,
+            "GitHub API",
+            format!("{} answered {}", api_url, status),
+        );
+        return;
+    }
+    report.add(
+        Status::Pass,
+        "GitHub API",
+        format!("{} is reachable", api_url),
+    );
+
+    if token.is_none() {
+        return;
+    }
+    match response
+        .headers()
+        .get("X-OAuth-Scopes")
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(scopes)
+            if scopes
+                .split(',')
+                .any(|s| matches!(s.trim(), "repo" | "public_repo")) =>
+        {
+            report.add(Status::Pass, "Token scopes", scopes.to_string())
+        }
+        Some(scopes) => report.add(
+            Status::Warn,
+            "Token scopes",
+            format!(
+                "{:?} — uploading releases needs repo or public_repo",
+                scopes
+            ),
+        ),
+        None => report.add(
+            Status::Warn,
+            "Token scopes",
+            "unknown (fine-grained or Actions token), make sure it can write contents",
+        ),
+    }
+}
+
+/// `beardist doctor`: checks that a build has what it needs before attempting one
+pub(crate) fn doctor(config_path: Option<&Path>) -> eyre::Result<()> {
+    let mut report = Report::default();
+
+    let config = match crate::find_config(config_path) {
+        Ok(Some(path)) => {
+            let name = path.display().to_string();
+            match crate::load_config(Some(&path)) {
+                Ok(config) => {
+                    report.add(
+                        Status::Pass,
+                        name,
+                        format!("{}/{}", config.org, config.name),
+                    );
+                    Some(config)
+                }
+                Err(e) => {
+                    report.add(Status::Fail, name, format!("{}", e));
+                    None
+                }
+            }
+        }
+        Ok(None) => {
+            report.add(
+                Status::Warn,
+                ".beardist.json",
+                "not found in the current directory or its parents",
+            );
+            None
+        }
+        Err(e) => {
+            report.add(Status::Fail, ".beardist.json", format!("{}", e));
+            None
+        }
+    };
+
+    check_tool(&mut report, "bash", &["--version"], Status::Fail);
+    check_tool(&mut report, "tar", &["--version"], Status::Fail);
+    check_tool(&mut report, "xz", &["--version"], Status::Fail);
+    if config.as_ref().is_none_or(|config| config.cargo.is_some()) {
+        check_tool(&mut report, "cargo", &["--version"], Status::Fail);
+        check_tool(&mut report, "rustup", &["--version"], Status::Warn);
+        match tool_version("cargo", &["sweep", "--version"]) {
+            Some(version) if version.starts_with("cargo-sweep") => {
+                report.add(Status::Pass, "cargo sweep", version)
+            }
+            _ => report.add(
+                Status::Fail,
+                "cargo sweep",
+                "not installed (cargo install cargo-sweep)",
+            ),
+        }
+    }
+    if cfg!(target_os = "macos") {
+        check_tool(&mut report, "otool", &["--version"], Status::Fail);
+        check_tool(&mut report, "install_name_tool", &["-h"], Status::Fail);
+    } else if cfg!(target_os = "linux") {
+        check_tool(&mut report, "ldd", &["--version"], Status::Fail);
+    }
+
+    check_env(
+        &mut report,
+        "BEARDIST_CACHE_DIR",
+        false,
+        Status::Fail,
+        "builds need persistent storage for caches",
+    );
+    check_env(
+        &mut report,
+        "BEARDIST_ARTIFACT_NAME",
+        false,
+        Status::Warn,
+        "the host triple will be used",
+    );
+    check_env(
+        &mut report,
+        "GH_READWRITE_TOKEN",
+        true,
+        Status::Warn,
+        "builds will be dry runs",
+    );
+    check_env(
+        &mut report,
+        "GITHUB_REF",
+        false,
+        Status::Warn,
+        "builds will be dry runs",
+    );
+    check_env(
+        &mut report,
+        "GITHUB_TOKEN",
+        true,
+        Status::Warn,
+        "needed by the update-* and k8s commands",
+    );
+
+    check_github(&mut report);
+
+    report.print();
+    let (warnings, failures) = (report.count(Status::Warn), report.count(Status::Fail));
+    info!(
+        "{} passed, {} warnings, {} failures",
+        report.count(Status::Pass).green(),
+        warnings.yellow(),
+        failures.red()
+    );
+    if failures > 0 {
+        return Err(eyre::eyre!("{} checks failed", failures));
+    }
+    Ok(())
+}
@@ -0,0 +1,239 @@
+//! `beardist download <org/repo>`: fetches release assets (picked by target triple or
+//! glob), verifies them against the release's `SHA256SUMS`, and optionally extracts them.
+
+use log::*;
+use owo_colors::OwoColorize;
+use reqwest::blocking::Client;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    DownloadArgs, USER_AGENT, checksums,
+    command::run_command,
+    forge::ReleaseAsset,
+    install::{archive_for, host_triple},
+    progress::Progress,
+};
+
+#[derive(serde::Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+/// Looks up a release (the latest one if `tag` is `None`). `GITHUB_TOKEN` is used if set,
+/// which raises rate limits and gives access to private repositories.
+fn fetch_release(client: &Client, repo: &str, tag: Option<&str>) -> eyre::Result<Release> {
+    let api_url = std::env::var("GITHUB_SERVER_URL")
+        .map(|url| url.replace("github.com", "api.github.com"))
+        .unwrap_or_else(|_| "https://api.github.com".to_string());
+    let url = match tag {
+        Some(tag) => format!("{}/repos/{}/releases/tags/{}", api_url, repo, tag),
+        None => format!("{}/repos/{}/releases/latest", api_url, repo),
+    };
+    let mut request = client
+        .get(&url)
+        .header("Accept", "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", "2022-11-28")
+        .header("User-Agent", USER_AGENT);
+    if let Some(token) = crate::credentials::token("GITHUB_TOKEN") {
+        request = request.header("Authorization", format!("token {}", token));
+    }
+    let response = request.send()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(eyre::eyre!(
+            "Failed to find release {} of {}: HTTP status {}",
+            tag.unwrap_or("latest"),
+            repo,
+            status
+        ));
+    }
+    Ok(response.json()?)
+}
+
+/// Picks the assets to download: those matching `pattern`, or the archive for `target`
+/// (see [`archive_for`])
+fn select_assets<'a>(
+    assets: &'a [ReleaseAsset],
+    pattern: Option<&str>,
+    target: &str,
+) -> eyre::Result<Vec<&'a ReleaseAsset>> {
+    let selected: Vec<&ReleaseAsset> = match pattern {
+        Some(pattern) => {
+            let pattern = glob::Pattern::new(pattern)?;
+            assets
+                .iter()
+                .filter(|asset| pattern.matches(&asset.name))
+                .collect()
+        }
+        None => {
+            let name = archive_for(target, |name| assets.iter().any(|a| a.name == name));
+            assets.iter().filter(|asset| asset.name == name).collect()
+        }
+    };
+    if selected.is_empty() {
+        return Err(eyre::eyre!(
+            "No matching asset, the release has: {}",
+            assets
+                .iter()
+                .map(|asset| asset.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    Ok(selected)
+}
+
+/// Fetches `url`, showing progress along the way
+pub(crate) fn fetch(client: &Client, url: &str, label: &str) -> eyre::Result<Vec<u8>> {
+    let response = client.get(url).header("User-Agent", USER_AGENT).send()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(eyre::eyre!(
+            "Failed to download {}: HTTP status {}",
+            url,
+            status
+        ));
+    }
+    let progress = Progress::bytes(label, response.content_length());
+    Ok(progress.read_to_end(response)?)
+}
+
+/// Downloads `url` to `dest`, and returns the file's contents
+fn download_to(client: &Client, url: &str, dest: &Path) -> eyre::Result<Vec<u8>> {
+    info!("Downloading {} to {}", url.cyan(), dest.display());
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let contents = fetch(client, url, &format!("Downloading {}", name))?;
+    fs_err::write(dest, &contents)?;
+    Ok(contents)
+}
+
+pub(crate) fn download(args: DownloadArgs) -> eyre::Result<()> {
+    let client = Client::new();
+    let release = fetch_release(&client, &args.repo, args.tag.as_deref())?;
+    info!(
+        "Release {} of {}",
+        release.tag_name.cyan(),
+        args.repo.cyan()
+    );
+
+    let target = match &args.target {
+        Some(target) => target.clone(),
+        None => host_triple()?,
+    };
+    let selected = select_assets(&release.assets, args.asset.as_deref(), &target)?;
+
+    let output = args.output.unwrap_or_else(|| PathBuf::from("."));
+    let into_dir = output.is_dir() || selected.len() > 1;
+    if into_dir {
+        fs_err::create_dir_all(&output)?;
+    }
+
+    if std::env::var("DRY_RUN").is_ok() {
+        for asset in &selected {
+            info!(
+                "Dry run: Would download {} to {}",
+                asset.name.cyan(),
+                output.display()
+            );
+        }
+        return Ok(());
+    }
+
+    let sums = match release
+        .assets
+        .iter()
+        .find(|asset| asset.name == "SHA256SUMS")
+    {
+        Some(asset) => {
+            let response = client
+                .get(&asset.browser_download_url)
+                .header("User-Agent", USER_AGENT)
+                .send()?
+                .error_for_status()?;
+            checksums::parse_sha256sums(&response.text()?)
+        }
+        None => Default::default(),
+    };
+
+    for asset in selected {
+        let dest = if into_dir {
+            output.join(&asset.name)
+        } else {
+            output.clone()
+        };
+        let contents = download_to(&client, &asset.browser_download_url, &dest)?;
+
+        match sums.get(&asset.name) {
+            Some(expected) => {
+                let actual = checksums::sha256_hex(&contents);
+                if *expected != actual {
+                    fs_err::remove_file(&dest)?;
+                    return Err(eyre::eyre!(
+                        "Checksum mismatch for {}: expected {}, got {}",
+                        asset.name,
+                        expected,
+                        actual
+                    ));
+                }
+                info!("✅ Checksum verified for {}", asset.name.cyan());
+            }
+            None => warn!("No published checksum for {}, not verified", asset.name),
+        }
+
+        if args.extract && asset.name.contains(".tar") {
+            let dir = dest.parent().unwrap_or(Path::new("."));
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            run_command(
+                "tar",
+                &["-xf", dest.to_str().unwrap(), "-C", dir.to_str().unwrap()],
+                None,
+            )?;
+            fs_err::remove_file(&dest)?;
+            info!("📂 Extracted {} into {}", asset.name.cyan(), dir.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(name: &str) -> ReleaseAsset {
+        ReleaseAsset {
+            id: 0,
+            name: name.to_string(),
+            browser_download_url: format!("https://example.com/{}", name),
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn test_select_assets() {
+        let assets = [
+            asset("x86_64-unknown-linux-gnu.tar.xz"),
+            asset("aarch64-apple-darwin.tar.xz"),
+            asset("SHA256SUMS"),
+        ];
+        let selected = select_assets(&assets, None, "aarch64-apple-darwin").unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name, "aarch64-apple-darwin.tar.xz");
+
+        let selected = select_assets(&assets, Some("*.tar.xz"), "ignored").unwrap();
+        assert_eq!(selected.len(), 2);
+
+        assert!(select_assets(&assets, None, "riscv64gc-unknown-linux-gnu").is_err());
+
+        let assets = [
+            asset("x86_64-unknown-linux-gnu.tar.xz"),
+            asset("universal-apple-darwin.tar.xz"),
+        ];
+        let selected = select_assets(&assets, None, "x86_64-apple-darwin").unwrap();
+        assert_eq!(selected[0].name, "universal-apple-darwin.tar.xz");
+    }
+}
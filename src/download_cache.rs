@@ -0,0 +1,93 @@
+//! Remembers the SHA-256 of what we downloaded, under `$BEARDIST_CACHE_DIR/downloads`, so
+//! hashing the same release archives again (re-runs of `update-tap`, formulas sharing
+//! archives) costs a conditional request instead of a download. Entries are keyed by URL
+//! and validated with the server's `ETag`. Only hashes are kept, not the bytes: that's
+//! all formulas need.
+
+use camino::Utf8PathBuf;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::checksums::sha256_hex;
+
+/// What we know about a URL's contents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub(crate) url: String,
+
+    /// The `ETag` the server sent with the contents
+    pub(crate) etag: String,
+
+    /// Lowercase hex SHA-256 of the contents
+    pub(crate) sha256: String,
+
+    pub(crate) size: u64,
+}
+
+pub(crate) struct DownloadCache {
+    dir: Utf8PathBuf,
+}
+
+impl DownloadCache {
+    pub(crate) fn new(dir: Utf8PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cache in `$BEARDIST_CACHE_DIR`, if it's set
+    pub(crate) fn from_env() -> Option<Self> {
+        let cache_dir = std::env::var("BEARDIST_CACHE_DIR").ok()?;
+        Some(Self::new(Utf8PathBuf::from(cache_dir).join("downloads")))
+    }
+
+    fn entry_path(&self, url: &str) -> Utf8PathBuf {
+        self.dir
+            .join(format!("{}.json", sha256_hex(url.as_bytes())))
+    }
+
+    /// The entry for `url`, if there's a readable one
+    pub(crate) fn get(&self, url: &str) -> Option<Entry> {
+        let contents = fs_err::read_to_string(self.entry_path(url)).ok()?;
+        serde_json::from_str::<Entry>(&contents)
+            .ok()
+            .filter(|entry| entry.url == url)
+    }
+
+    /// Records an entry. The cache is only an optimization: failing to write it is
+    /// logged, not an error.
+    pub(crate) fn put(&self, entry: &Entry) {
+        let path = self.entry_path(&entry.url);
+        let result = fs_err::create_dir_all(&self.dir).and_then(|()| {
+            // Write then rename, so concurrent readers never see half an entry
+            let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+            fs_err::write(&tmp_path, serde_json::to_string(entry)?)?;
+            fs_err::rename(&tmp_path, &path)
+        });
+        if let Err(e) = result {
+            warn!("Couldn't cache the hash of {}: {}", entry.url, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache =
+            DownloadCache::new(Utf8PathBuf::from_path_buf(dir.path().join("downloads")).unwrap());
+        let url = "https://github.com/bearcove/beardist/releases/download/v1.0.0/x.tar.xz";
+        assert_eq!(cache.get(url), None);
+
+        let entry = Entry {
+            url: url.to_string(),
+            etag: "\"abc\"".to_string(),
+            sha256: "a".repeat(64),
+            size: 1234,
+        };
+        cache.put(&entry);
+        assert_eq!(cache.get(url), Some(entry));
+        assert_eq!(cache.get("https://example.com/other.tar.xz"), None);
+    }
+}
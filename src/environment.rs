@@ -0,0 +1,60 @@
+//! Where builds learn about the world they run in: environment variables, the current
+//! directory, whether they're in CI. Behind a trait so tests can make one up.
+
+use camino::Utf8PathBuf;
+
+pub(crate) trait Environment: Send + Sync {
+    /// The value of an environment variable, if it's set to valid UTF-8
+    fn var(&self, name: &str) -> Option<String>;
+
+    /// The directory we were started from, canonicalized
+    fn current_dir(&self) -> eyre::Result<Utf8PathBuf>;
+
+    fn is_ci(&self) -> bool {
+        self.var("CI").is_some()
+    }
+
+    /// Reads a variable set by the CI: GitHub Actions sets `GITHUB_<name>`, Forgejo Actions
+    /// sets that too for compatibility, and also `FORGEJO_<name>`. Returns the variable's
+    /// name along with its value.
+    fn ci_var(&self, name: &str) -> Option<(String, String)> {
+        ["GITHUB", "FORGEJO"].iter().find_map(|prefix| {
+            let var = format!("{}_{}", prefix, name);
+            self.var(&var).map(|value| (var, value))
+        })
+    }
+}
+
+/// The environment of this process
+pub(crate) struct ProcessEnvironment;
+
+impl Environment for ProcessEnvironment {
+    fn var(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+
+    fn current_dir(&self) -> eyre::Result<Utf8PathBuf> {
+        let dir = fs_err::canonicalize(std::env::current_dir()?)?;
+        Utf8PathBuf::from_path_buf(dir)
+            .map_err(|dir| eyre::eyre!("{} isn't valid UTF-8", dir.display()))
+    }
+}
+
+/// A made-up environment, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct FakeEnvironment {
+    pub(crate) vars: std::collections::HashMap<String, String>,
+    pub(crate) current_dir: Utf8PathBuf,
+}
+
+#[cfg(test)]
+impl Environment for FakeEnvironment {
+    fn var(&self, name: &str) -> Option<String> {
+        self.vars.get(name).cloned()
+    }
+
+    fn current_dir(&self) -> eyre::Result<Utf8PathBuf> {
+        Ok(self.current_dir.clone())
+    }
+}
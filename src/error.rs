@@ -0,0 +1,128 @@
+//! Classes of failure, each with its own exit code, so CI pipelines can tell a broken
+//! config from a flaky upload. Errors stay `eyre` reports: the class is attached where
+//! it's known, with [`Classify::classify`], and `main` looks it up to pick the exit code.
+
+use std::fmt;
+
+use crate::command::CommandError;
+
+/// What kind of failure ended the run. The exit codes are part of our interface: don't
+/// change them, only add new ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorKind {
+    /// Anything not classified below (exit code 1)
+    Other,
+    /// The config is missing or invalid, or a credential isn't set (exit code 2)
+    Config,
+    /// Building or packaging failed (exit code 3)
+    Build,
+    /// Publishing the package failed: forge release, registry, generic package (exit code 4)
+    Upload,
+    /// The package breaks a configured rule, e.g. its size bounds (exit code 5)
+    Policy,
+    /// A command we ran took too long and was killed (exit code 124, like `timeout(1)`)
+    Timeout,
+}
+
+impl ErrorKind {
+    pub(crate) fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Config => 2,
+            ErrorKind::Build => 3,
+            ErrorKind::Upload => 4,
+            ErrorKind::Policy => 5,
+            ErrorKind::Timeout => 124,
+        }
+    }
+
+    /// The class of `report`: the innermost one attached, or one inferred from the error
+    /// itself
+    pub(crate) fn of(report: &eyre::Report) -> Self {
+        if let Some(kind) = report.downcast_ref::<ErrorKind>() {
+            return *kind;
+        }
+        match report.downcast_ref::<CommandError>() {
+            Some(command_error) if command_error.timed_out.is_some() => ErrorKind::Timeout,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Attaches this class to `report`, unless it already has a (more specific) one
+    pub(crate) fn attach(self, report: eyre::Report) -> eyre::Report {
+        if report.downcast_ref::<ErrorKind>().is_some() {
+            report
+        } else {
+            report.wrap_err(self)
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorKind::Other => "Failed",
+            ErrorKind::Config => "Configuration error",
+            ErrorKind::Build => "Build failed",
+            ErrorKind::Upload => "Upload failed",
+            ErrorKind::Policy => "Package policy violation",
+            ErrorKind::Timeout => "Timed out",
+        })
+    }
+}
+
+/// The whole chain of `report` on one line, e.g. "Upload failed: while uploading: HTTP
+/// status 502". Plain `Display` would only show the outermost context, which is often
+/// just the class.
+pub(crate) fn message(report: &eyre::Report) -> String {
+    format!("{:#}", report)
+}
+
+pub(crate) trait Classify<T> {
+    /// Marks the error, if any, as being of class `kind` (see [`ErrorKind::attach`])
+    fn classify(self, kind: ErrorKind) -> eyre::Result<T>;
+}
+
+impl<T> Classify<T> for eyre::Result<T> {
+    fn classify(self, kind: ErrorKind) -> eyre::Result<T> {
+        self.map_err(|report| kind.attach(report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eyre::WrapErr;
+
+    #[test]
+    fn test_innermost_class_wins() {
+        let report = Err::<(), _>(eyre::eyre!("package too small"))
+            .classify(ErrorKind::Policy)
+            .wrap_err("while uploading")
+            .classify(ErrorKind::Upload)
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&report), ErrorKind::Policy);
+        assert_eq!(ErrorKind::of(&report).exit_code(), 5);
+
+        let report = eyre::eyre!("something else");
+        assert_eq!(ErrorKind::of(&report), ErrorKind::Other);
+        assert_eq!(
+            ErrorKind::of(&ErrorKind::Config.attach(report)).exit_code(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_message_keeps_the_cause() {
+        let report = Err::<(), _>(eyre::eyre!("HTTP status 502"))
+            .wrap_err("while uploading")
+            .classify(ErrorKind::Upload)
+            .unwrap_err();
+        assert_eq!(
+            message(&report),
+            "Upload failed: while uploading: HTTP status 502"
+        );
+        let annotation = crate::gha::annotation("error", &message(&report));
+        assert!(annotation.contains("HTTP status 502"), "{annotation}");
+    }
+}
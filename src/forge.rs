@@ -0,0 +1,363 @@
+use bytes::Bytes;
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_util::{StreamExt, TryStreamExt, stream, stream::BoxStream};
+use log::info;
+use owo_colors::OwoColorize;
+use reqwest::RequestBuilder;
+use schemars::JsonSchema;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read};
+
+use crate::{
+    checksums, credentials,
+    environment::{Environment, ProcessEnvironment},
+    forgejo::ForgejoClient,
+    github::GitHubClient,
+    http,
+    progress::Progress,
+    throttle,
+};
+
+/// What beardist needs from a code forge: releases, their assets, and container packages
+pub(crate) trait Forge: Send + Sync {
+    /// The version of the latest release of a repository, without the `v` prefix
+    fn get_latest_release_version(&self, owner: &str, repo: &str) -> eyre::Result<Option<String>>;
+
+    /// The semver tags of a container package, newest first
+    fn get_container_versions(&self, owner: &str, package_name: &str)
+    -> eyre::Result<Vec<Version>>;
+
+    /// Create a release if it doesn't exist, and return the release ID. An existing
+    /// release is left as it is, prerelease or not.
+    fn create_release(
+        &self,
+        owner: &str,
+        repo: &str,
+        tag: &str,
+        prerelease: bool,
+    ) -> eyre::Result<u64>;
+
+    /// The body (description) of a release, in Markdown
+    fn release_body(&self, owner: &str, repo: &str, release_id: u64) -> eyre::Result<String>;
+
+    /// Replace the body of a release
+    fn set_release_body(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+        body: &str,
+    ) -> eyre::Result<()>;
+
+    /// Point `tag` at commit `sha`, creating it if needed. Channel builds use this, since
+    /// their release follows a branch.
+    fn move_tag(&self, owner: &str, repo: &str, tag: &str, sha: &str) -> eyre::Result<()>;
+
+    /// Upload a file as a release asset. `label` is what the release page shows instead
+    /// of the file name, on forges that support it (GitHub).
+    fn upload_artifact(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+        file_name: &str,
+        label: Option<&str>,
+        source: UploadSource,
+    ) -> eyre::Result<()>;
+
+    /// List the assets attached to a release
+    fn list_release_assets(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+    ) -> eyre::Result<Vec<ReleaseAsset>>;
+
+    /// Download the contents of a release asset (works for private repositories too)
+    fn download_release_asset(
+        &self,
+        owner: &str,
+        repo: &str,
+        asset: &ReleaseAsset,
+    ) -> eyre::Result<Vec<u8>>;
+
+    /// Delete a release asset, e.g. before replacing it with a new version
+    fn delete_release_asset(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+        asset_id: u64,
+    ) -> eyre::Result<()>;
+
+    /// Every release of a repository, drafts included, with their assets
+    fn list_releases(&self, owner: &str, repo: &str) -> eyre::Result<Vec<Release>>;
+
+    /// Delete a release along with its assets. Its tag is left alone.
+    fn delete_release(&self, owner: &str, repo: &str, release_id: u64) -> eyre::Result<()>;
+
+    /// Trigger a `workflow_dispatch` run of `workflow` (the workflow's file name, e.g.
+    /// "build.yml") on `git_ref`
+    fn dispatch_workflow(
+        &self,
+        owner: &str,
+        repo: &str,
+        workflow: &str,
+        git_ref: &str,
+        inputs: &serde_json::Map<String, serde_json::Value>,
+    ) -> eyre::Result<()>;
+
+    /// The `workflow_dispatch` runs of `workflow` on `git_ref`, most recent ones at least
+    fn list_workflow_runs(
+        &self,
+        owner: &str,
+        repo: &str,
+        workflow: &str,
+        git_ref: &str,
+    ) -> eyre::Result<Vec<WorkflowRun>>;
+}
+
+/// The `Content-Type` of a release asset, going by its name
+pub(crate) fn content_type(file_name: &str) -> &'static str {
+    let (_, extension) = file_name.rsplit_once('.').unwrap_or_default();
+    match extension {
+        "xz" => "application/x-xz",
+        "gz" | "tgz" => "application/gzip",
+        "zst" => "application/zstd",
+        "zip" => "application/zip",
+        "json" => "application/json",
+        "sig" | "asc" => "application/pgp-signature",
+        "sh" => "application/x-sh",
+        _ if file_name == "SHA256SUMS" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// What to upload: generated files come from memory, archives stream from disk
+#[derive(Clone, Copy)]
+pub(crate) enum UploadSource<'a> {
+    Bytes(&'a [u8]),
+    /// A file whose size and hash were computed when it was written
+    File {
+        path: &'a Utf8Path,
+        size: u64,
+        sha256: &'a str,
+    },
+}
+
+impl UploadSource<'_> {
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            Self::Bytes(bytes) => bytes.len() as u64,
+            Self::File { size, .. } => *size,
+        }
+    }
+
+    /// Lowercase hex SHA-256 of the contents
+    pub(crate) fn sha256(&self) -> String {
+        match self {
+            Self::Bytes(bytes) => checksums::sha256_hex(bytes),
+            Self::File { sha256, .. } => sha256.to_string(),
+        }
+    }
+
+    /// A fresh blocking reader over the contents, for every attempt at uploading them.
+    /// Files are only opened once read from, so errors opening them surface as failed
+    /// requests. Reads keep to the upload rate cap.
+    pub(crate) fn reader(&self) -> Box<dyn Read + Send> {
+        match self {
+            Self::Bytes(bytes) => Box::new(Throttled(Cursor::new(bytes.to_vec()))),
+            Self::File { path, .. } => Box::new(Throttled(LazyFile {
+                path: path.to_path_buf(),
+                file: None,
+            })),
+        }
+    }
+
+    /// Sets the contents as the body of `request`, streamed and reporting progress as
+    /// "Uploading <file_name>"
+    pub(crate) fn attach(&self, request: RequestBuilder, file_name: &str) -> RequestBuilder {
+        // a streamed body is sent chunked unless we say how long it is, which forges
+        // don't accept for uploads
+        request
+            .header(reqwest::header::CONTENT_LENGTH, self.len())
+            .body(reqwest::Body::wrap_stream(self.stream(file_name)))
+    }
+
+    /// The contents as a stream of chunks, reporting progress as "Uploading <file_name>".
+    /// Like [`Self::reader`], files are only opened once polled, and chunks keep to the
+    /// upload rate cap.
+    pub(crate) fn stream(&self, file_name: &str) -> BoxStream<'static, std::io::Result<Bytes>> {
+        let progress = Progress::bytes(format!("Uploading {}", file_name), Some(self.len()));
+        let chunks = match self {
+            Self::Bytes(bytes) => {
+                tokio_util::io::ReaderStream::new(Cursor::new(bytes.to_vec())).boxed()
+            }
+            Self::File { path, .. } => stream::once(tokio::fs::File::open(path.to_path_buf()))
+                .map_ok(tokio_util::io::ReaderStream::new)
+                .try_flatten()
+                .boxed(),
+        };
+        let finished = progress.clone();
+        chunks
+            .and_then(|chunk| async move {
+                throttle::upload(chunk.len()).await;
+                Ok(chunk)
+            })
+            .inspect_ok(move |chunk| progress.inc(chunk.len() as u64))
+            .chain(stream::poll_fn(move |_| {
+                finished.finish();
+                std::task::Poll::Ready(None)
+            }))
+            .boxed()
+    }
+}
+
+/// A reader that keeps to the upload rate cap
+struct Throttled<R>(R);
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.0.read(buf)?;
+        throttle::upload_blocking(read);
+        Ok(read)
+    }
+}
+
+struct LazyFile {
+    path: Utf8PathBuf,
+    file: Option<fs_err::File>,
+}
+
+impl Read for LazyFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.file.is_none() {
+            self.file = Some(fs_err::File::open(&self.path)?);
+        }
+        self.file.as_mut().unwrap().read(buf)
+    }
+}
+
+/// An asset attached to a release
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ReleaseAsset {
+    pub(crate) id: u64,
+    pub(crate) name: String,
+    pub(crate) browser_download_url: String,
+
+    /// In bytes
+    #[serde(default)]
+    pub(crate) size: u64,
+}
+
+/// A release, as listed by [`Forge::list_releases`]
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Release {
+    pub(crate) id: u64,
+    pub(crate) tag_name: String,
+
+    #[serde(default)]
+    pub(crate) draft: bool,
+
+    #[serde(default)]
+    pub(crate) prerelease: bool,
+
+    /// RFC 3339, e.g. "2025-01-31T12:00:00Z"
+    pub(crate) created_at: String,
+
+    #[serde(default)]
+    pub(crate) assets: Vec<ReleaseAsset>,
+}
+
+/// A run of a CI workflow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WorkflowRun {
+    pub(crate) id: u64,
+    pub(crate) state: RunState,
+
+    /// Where to look at it in a browser
+    pub(crate) url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RunState {
+    /// Queued or running
+    Pending,
+    Succeeded,
+    /// Failed, cancelled, timed out...
+    Failed,
+}
+
+/// Which kind of forge a repository or registry is hosted on
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ForgeKind {
+    /// GitHub, or GitHub Enterprise
+    #[default]
+    Github,
+    /// Forgejo (or Gitea)
+    Forgejo,
+}
+
+/// A client for the given kind of forge. `server_url` is the web URL, e.g.
+/// `https://github.com` or `https://code.bearcove.cloud`.
+pub(crate) fn client(kind: ForgeKind, server_url: String, token: String) -> Box<dyn Forge> {
+    match kind {
+        ForgeKind::Github => Box::new(GitHubClient::new(server_url, token)),
+        ForgeKind::Forgejo => Box::new(ForgejoClient::new(server_url, token)),
+    }
+}
+
+/// A client for the forge hosting the project, on the server CI says we're on
+/// (`GITHUB_SERVER_URL`, github.com by default). Authenticates with `GH_READWRITE_TOKEN`,
+/// or for Forgejo, `FORGEJO_TOKEN` if it's set.
+pub(crate) fn client_from_env(kind: ForgeKind) -> eyre::Result<Box<dyn Forge>> {
+    let server_url = ProcessEnvironment
+        .ci_var("SERVER_URL")
+        .map(|(_, url)| url)
+        .unwrap_or_else(|| "https://github.com".to_string());
+    let token = match (kind, credentials::token("FORGEJO_TOKEN")) {
+        (ForgeKind::Forgejo, Some(token)) => token,
+        _ => credentials::require("GH_READWRITE_TOKEN")?,
+    };
+    Ok(client(kind, server_url, token))
+}
+
+/// Sends an upload, retrying a few times with jitter on connection and 5xx errors
+pub(crate) async fn upload_with_retries(
+    forge_name: &str,
+    upload_url: &str,
+    build_request: impl Fn() -> RequestBuilder,
+) -> eyre::Result<()> {
+    info!(
+        "📤 Uploading package to {} ({})...",
+        forge_name.yellow(),
+        upload_url.cyan()
+    );
+    let upload_start = std::time::Instant::now();
+
+    let response = http::send("Upload", build_request).await?;
+    let status = response.status();
+    info!("🔢 Response status code: {}", format!("{}", status).blue());
+
+    let response_text = response.text().await?;
+    info!("{}", "----------------------------------------".yellow());
+    info!("📄 {}", "Response Data:".yellow());
+    info!("{}", "----------------------------------------".yellow());
+    info!("{}", response_text);
+    info!("{}", "----------------------------------------".yellow());
+
+    if !status.is_success() {
+        return Err(eyre::eyre!("❌ Upload failed with status code: {}", status));
+    }
+
+    let upload_time = upload_start.elapsed().as_millis() as u64;
+    info!(
+        "✅ Package upload completed ({})",
+        format!("{}ms", upload_time).green()
+    );
+    Ok(())
+}
@@ -0,0 +1,719 @@
+use futures_util::StreamExt;
+use log::{debug, info};
+use owo_colors::OwoColorize;
+use reqwest::{Method, RequestBuilder};
+use schemars::JsonSchema;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    forge::{
+        Forge, Release, ReleaseAsset, RunState, UploadSource, WorkflowRun, content_type,
+        upload_with_retries,
+    },
+    http,
+};
+
+/// Where to upload archives as generic packages, in `.beardist.json`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct GenericPackageConfig {
+    /// Forgejo instance, e.g. "https://code.bearcove.cloud". Defaults to the one the
+    /// repository is hosted on. The token is read from `FORGEJO_TOKEN`, falling back to
+    /// the release upload token.
+    #[serde(default)]
+    pub(crate) server_url: Option<String>,
+
+    /// User or organization owning the package, defaults to the project's org
+    #[serde(default)]
+    pub(crate) owner: Option<String>,
+
+    /// Defaults to the project's name
+    #[serde(default)]
+    pub(crate) package: Option<String>,
+
+    /// Only upload packages, don't create forge releases
+    #[serde(default)]
+    pub(crate) skip_releases: bool,
+}
+
+/// Talks to the API of a Forgejo (or Gitea) instance, through the shared async core
+/// ([`http`])
+pub struct ForgejoClient {
+    /// e.g. "https://code.bearcove.cloud"
+    server_url: String,
+    token: String,
+}
+
+impl ForgejoClient {
+    pub fn new(server_url: String, token: String) -> Self {
+        Self {
+            server_url: server_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    /// Uses `FORGEJO_TOKEN` to authenticate against the given instance
+    pub fn from_env(server_url: String) -> eyre::Result<Self> {
+        let token = crate::credentials::require("FORGEJO_TOKEN")?;
+        Ok(Self::new(server_url, token))
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        format!("{}/api/v1{}", self.server_url, path)
+    }
+
+    /// Where a file of a generic package is uploaded to, and downloaded from
+    pub(crate) fn generic_package_url(
+        &self,
+        owner: &str,
+        package: &str,
+        version: &str,
+        file_name: &str,
+    ) -> String {
+        format!(
+            "{}/api/packages/{}/generic/{}/{}/{}",
+            self.server_url, owner, package, version, file_name
+        )
+    }
+
+    /// The web page of a version of a generic package
+    pub(crate) fn generic_package_page(&self, owner: &str, package: &str, version: &str) -> String {
+        format!(
+            "{}/{}/-/packages/generic/{}/{}",
+            self.server_url, owner, package, version
+        )
+    }
+
+    /// Uploads a file to a generic package, replacing it if it was already uploaded (by an
+    /// earlier run of the same build), and returns its download URL
+    pub(crate) fn upload_generic_package(
+        &self,
+        owner: &str,
+        package: &str,
+        version: &str,
+        file_name: &str,
+        source: UploadSource,
+    ) -> eyre::Result<String> {
+        let url = self.generic_package_url(owner, package, version, file_name);
+
+        http::block_on(async {
+            // Files of a package version can't be overwritten
+            let response = http::send("Deleting package file", || {
+                self.request(Method::DELETE, &url)
+            })
+            .await?;
+            let status = response.status();
+            if status.is_success() {
+                info!("Deleted previously uploaded {}", file_name);
+            } else if status != 404 {
+                return Err(eyre::eyre!(
+                    "Failed to delete existing package file {}: HTTP status {status}",
+                    file_name
+                ));
+            }
+
+            upload_with_retries("Forgejo packages", &url, || {
+                let request = self
+                    .request(Method::PUT, &url)
+                    .header("Content-Type", "application/octet-stream");
+                source.attach(request, file_name)
+            })
+            .await
+        })?;
+        Ok(url)
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        http::client()
+            .request(method, url)
+            .header("Authorization", format!("token {}", self.token))
+            .header("Accept", "application/json")
+    }
+
+    /// The release with the given tag, if it exists
+    async fn get_release_by_tag(
+        &self,
+        owner: &str,
+        repo: &str,
+        tag: &str,
+    ) -> eyre::Result<Option<u64>> {
+        let url = self.api_url(&format!("/repos/{}/{}/releases/tags/{}", owner, repo, tag));
+        info!("Checking if release exists at {}...", url);
+        let response = http::send("Fetching release", || self.request(Method::GET, &url)).await?;
+        let status = response.status();
+        if status == 404 {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(eyre::eyre!("Failed to get release: HTTP status {status}"));
+        }
+        let release: Value = response.json().await?;
+        let id = release["id"]
+            .as_u64()
+            .ok_or_else(|| eyre::eyre!("Invalid release ID"))?;
+        Ok(Some(id))
+    }
+
+    /// The latest release's version, without the leading "v". `None` if there are no
+    /// releases yet.
+    pub(crate) async fn latest_release_version(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> eyre::Result<Option<String>> {
+        let url = self.api_url(&format!("/repos/{}/{}/releases/latest", owner, repo));
+        info!(
+            "Fetching latest release for repository '{}/{}' from '{}'",
+            owner, repo, url
+        );
+
+        let response = http::send("Fetching latest release", || {
+            self.request(Method::GET, &url)
+        })
+        .await?;
+        let status = response.status();
+        // 404 means no releases yet
+        if status == 404 {
+            info!("No releases found for repository '{}/{}'", owner, repo);
+            return Ok(None);
+        }
+        if status != 200 {
+            let body = response.text().await?;
+            debug!("Error response: {}", body);
+            return Err(eyre::eyre!(
+                "Failed to get latest release: HTTP status {status}"
+            ));
+        }
+
+        let release: Value = response.json().await?;
+        let Some(tag_name) = release["tag_name"].as_str() else {
+            info!("Release found but no tag_name present");
+            return Ok(None);
+        };
+        info!("Latest release tag: {}", tag_name);
+        let version_str = tag_name.trim_start_matches('v');
+        match Version::parse(version_str) {
+            Ok(version) => Ok(Some(version.to_string())),
+            Err(_) => Ok(Some(version_str.to_string())),
+        }
+    }
+}
+
+impl Forge for ForgejoClient {
+    fn get_latest_release_version(&self, owner: &str, repo: &str) -> eyre::Result<Option<String>> {
+        http::block_on(self.latest_release_version(owner, repo))
+    }
+
+    fn get_container_versions(
+        &self,
+        owner: &str,
+        package_name: &str,
+    ) -> eyre::Result<Vec<Version>> {
+        let mut versions = Vec::new();
+        // Each tag of a package is listed as its own entry, 50 per page
+        for page in 1..=10 {
+            let url = self.api_url(&format!(
+                "/packages/{}?type=container&q={}&limit=50&page={}",
+                owner, package_name, page
+            ));
+            info!(
+                "Fetching container versions for '{}' from '{}'",
+                package_name, url
+            );
+
+            let start_time = std::time::Instant::now();
+            let (status, body) = http::block_on(async {
+                let response = http::send("Fetching container versions", || {
+                    self.request(Method::GET, &url)
+                })
+                .await?;
+                eyre::Ok((response.status(), response.text().await?))
+            })?;
+            info!(
+                "Request completed in {}ms with status {}",
+                start_time.elapsed().as_millis(),
+                status
+            );
+            if status != 200 {
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to get container versions: HTTP status {status}"
+                ));
+            }
+
+            let packages: Vec<Value> = serde_json::from_str(&body)?;
+            if packages.is_empty() {
+                break;
+            }
+            versions.extend(
+                packages
+                    .iter()
+                    // `q` is a substring match
+                    .filter(|package| package["name"].as_str() == Some(package_name))
+                    .filter_map(|package| package["version"].as_str())
+                    .filter_map(|tag| Version::parse(tag.trim_start_matches('v')).ok()),
+            );
+        }
+        versions.sort_by(|a, b| b.cmp(a));
+        versions.dedup();
+
+        info!("Found {} valid semver tags", versions.len());
+        Ok(versions)
+    }
+
+    fn create_release(
+        &self,
+        owner: &str,
+        repo: &str,
+        tag: &str,
+        prerelease: bool,
+    ) -> eyre::Result<u64> {
+        http::block_on(async {
+            if let Some(id) = self.get_release_by_tag(owner, repo, tag).await? {
+                return Ok(id);
+            }
+
+            info!("Release doesn't exist, creating one...");
+            let url = self.api_url(&format!("/repos/{}/{}/releases", owner, repo));
+            let response = http::send("Creating release", || {
+                self.request(Method::POST, &url).json(&serde_json::json!({
+                    "tag_name": tag,
+                    "name": tag,
+                    "draft": false,
+                    "prerelease": prerelease
+                }))
+            })
+            .await?;
+            // Builds for other targets race us to create it
+            if response.status() == 409 {
+                if let Some(id) = self.get_release_by_tag(owner, repo, tag).await? {
+                    info!("Release was created concurrently, using it");
+                    return Ok(id);
+                }
+            }
+            if !response.status().is_success() {
+                return Err(eyre::eyre!(
+                    "Failed to create release: {}",
+                    response.text().await?
+                ));
+            }
+            let release: Value = response.json().await?;
+            release["id"]
+                .as_u64()
+                .ok_or_else(|| eyre::eyre!("Invalid release ID"))
+        })
+    }
+
+    fn release_body(&self, owner: &str, repo: &str, release_id: u64) -> eyre::Result<String> {
+        let url = self.api_url(&format!(
+            "/repos/{}/{}/releases/{}",
+            owner, repo, release_id
+        ));
+        http::block_on(async {
+            let response =
+                http::send("Fetching release", || self.request(Method::GET, &url)).await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(eyre::eyre!(
+                    "Failed to fetch release {release_id}: HTTP status {status}"
+                ));
+            }
+            let release: Value = response.json().await?;
+            // A release without a body has a null one
+            Ok(release["body"].as_str().unwrap_or_default().to_string())
+        })
+    }
+
+    fn set_release_body(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+        body: &str,
+    ) -> eyre::Result<()> {
+        let url = self.api_url(&format!(
+            "/repos/{}/{}/releases/{}",
+            owner, repo, release_id
+        ));
+        http::block_on(async {
+            let response = http::send("Updating release", || {
+                self.request(Method::PATCH, &url)
+                    .json(&serde_json::json!({ "body": body }))
+            })
+            .await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to update release {release_id}: HTTP status {status}"
+                ));
+            }
+            Ok(())
+        })
+    }
+
+    fn move_tag(&self, owner: &str, repo: &str, tag: &str, sha: &str) -> eyre::Result<()> {
+        let tag_url = self.api_url(&format!("/repos/{}/{}/tags/{}", owner, repo, tag));
+        let tags_url = self.api_url(&format!("/repos/{}/{}/tags", owner, repo));
+        http::block_on(async {
+            let response =
+                http::send("Fetching tag", || self.request(Method::GET, &tag_url)).await?;
+            if response.status().is_success() {
+                let existing: Value = response.json().await?;
+                if existing["commit"]["sha"].as_str() == Some(sha) {
+                    return Ok(());
+                }
+                // Forgejo can't move tags, and won't delete one a release uses: the
+                // release goes too, and gets recreated by whichever build uploads first
+                info!("Moving tag {} to {}", tag.cyan(), sha.yellow());
+                if let Some(release_id) = self.get_release_by_tag(owner, repo, tag).await? {
+                    let release_url = self.api_url(&format!(
+                        "/repos/{}/{}/releases/{}",
+                        owner, repo, release_id
+                    ));
+                    http::send("Deleting release", || {
+                        self.request(Method::DELETE, &release_url)
+                    })
+                    .await?;
+                }
+                http::send("Deleting tag", || self.request(Method::DELETE, &tag_url)).await?;
+            }
+
+            let response = http::send("Creating tag", || {
+                self.request(Method::POST, &tags_url)
+                    .json(&serde_json::json!({ "tag_name": tag, "target": sha }))
+            })
+            .await?;
+            let status = response.status();
+            // 409: another build for the same commit got there first
+            if !status.is_success() && status != 409 {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to point tag {tag} at {sha}: HTTP status {status}"
+                ));
+            }
+            Ok(())
+        })
+    }
+
+    fn upload_artifact(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+        file_name: &str,
+        // Forgejo attachments don't have labels
+        _label: Option<&str>,
+        source: UploadSource,
+    ) -> eyre::Result<()> {
+        let upload_url = self.api_url(&format!(
+            "/repos/{}/{}/releases/{}/assets?name={}",
+            owner, repo, release_id, file_name
+        ));
+
+        // Forgejo wants a multipart form with the file in an `attachment` field
+        let boundary = format!("beardist-{:016x}", rand::random::<u64>());
+        let head = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"attachment\"; filename=\"{file_name}\"\r\nContent-Type: {}\r\n\r\n",
+            content_type(file_name)
+        );
+        let tail = format!("\r\n--{boundary}--\r\n");
+        let len = head.len() as u64 + source.len() + tail.len() as u64;
+
+        http::block_on(upload_with_retries("Forgejo", &upload_url, || {
+            let body = futures_util::stream::iter([Ok(head.clone().into())])
+                .chain(source.stream(file_name))
+                .chain(futures_util::stream::iter([Ok(tail.clone().into())]));
+            self.request(Method::POST, &upload_url)
+                .header(
+                    "Content-Type",
+                    format!("multipart/form-data; boundary={}", boundary),
+                )
+                .header(reqwest::header::CONTENT_LENGTH, len)
+                .body(reqwest::Body::wrap_stream(body))
+        }))
+    }
+
+    fn list_release_assets(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+    ) -> eyre::Result<Vec<ReleaseAsset>> {
+        let url = self.api_url(&format!(
+            "/repos/{}/{}/releases/{}/assets",
+            owner, repo, release_id
+        ));
+        http::block_on(async {
+            let response =
+                http::send("Listing release assets", || self.request(Method::GET, &url)).await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to list release assets: HTTP status {status}"
+                ));
+            }
+            Ok(response.json().await?)
+        })
+    }
+
+    fn download_release_asset(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        asset: &ReleaseAsset,
+    ) -> eyre::Result<Vec<u8>> {
+        http::block_on(async {
+            let response = http::send("Downloading release asset", || {
+                self.request(Method::GET, &asset.browser_download_url)
+            })
+            .await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(eyre::eyre!(
+                    "Failed to download release asset {}: HTTP status {status}",
+                    asset.id
+                ));
+            }
+            Ok(response.bytes().await?.to_vec())
+        })
+    }
+
+    fn delete_release_asset(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+        asset_id: u64,
+    ) -> eyre::Result<()> {
+        let url = self.api_url(&format!(
+            "/repos/{}/{}/releases/{}/assets/{}",
+            owner, repo, release_id, asset_id
+        ));
+        let status = http::block_on(http::send("Deleting release asset", || {
+            self.request(Method::DELETE, &url)
+        }))?
+        .status();
+        if !status.is_success() && status != 404 {
+            return Err(eyre::eyre!(
+                "Failed to delete release asset {asset_id}: HTTP status {status}"
+            ));
+        }
+        Ok(())
+    }
+
+    fn list_releases(&self, owner: &str, repo: &str) -> eyre::Result<Vec<Release>> {
+        const LIMIT: usize = 50;
+        http::block_on(async {
+            let mut releases = Vec::new();
+            for page in 1.. {
+                let url = self.api_url(&format!(
+                    "/repos/{}/{}/releases?limit={}&page={}",
+                    owner, repo, LIMIT, page
+                ));
+                let response =
+                    http::send("Listing releases", || self.request(Method::GET, &url)).await?;
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await?;
+                    debug!("Error response: {}", body);
+                    return Err(eyre::eyre!("Failed to list releases: HTTP status {status}"));
+                }
+                let page: Vec<Release> = response.json().await?;
+                let last_page = page.len() < LIMIT;
+                releases.extend(page);
+                if last_page {
+                    break;
+                }
+            }
+            Ok(releases)
+        })
+    }
+
+    fn delete_release(&self, owner: &str, repo: &str, release_id: u64) -> eyre::Result<()> {
+        let url = self.api_url(&format!(
+            "/repos/{}/{}/releases/{}",
+            owner, repo, release_id
+        ));
+        let status = http::block_on(http::send("Deleting release", || {
+            self.request(Method::DELETE, &url)
+        }))?
+        .status();
+        if !status.is_success() && status != 404 {
+            return Err(eyre::eyre!(
+                "Failed to delete release {release_id}: HTTP status {status}"
+            ));
+        }
+        Ok(())
+    }
+
+    fn dispatch_workflow(
+        &self,
+        owner: &str,
+        repo: &str,
+        workflow: &str,
+        git_ref: &str,
+        inputs: &serde_json::Map<String, Value>,
+    ) -> eyre::Result<()> {
+        let url = self.api_url(&format!(
+            "/repos/{}/{}/actions/workflows/{}/dispatches",
+            owner, repo, workflow
+        ));
+        http::block_on(async {
+            let response = http::send("Dispatching workflow", || {
+                self.request(Method::POST, &url)
+                    .json(&serde_json::json!({ "ref": git_ref, "inputs": inputs }))
+            })
+            .await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to dispatch {workflow} on {git_ref}: HTTP status {status}: {body}"
+                ));
+            }
+            Ok(())
+        })
+    }
+
+    fn list_workflow_runs(
+        &self,
+        owner: &str,
+        repo: &str,
+        workflow: &str,
+        git_ref: &str,
+    ) -> eyre::Result<Vec<WorkflowRun>> {
+        // Forgejo can't filter runs by workflow or ref, so we do
+        let url = self.api_url(&format!(
+            "/repos/{}/{}/actions/runs?event=workflow_dispatch&limit=50",
+            owner, repo
+        ));
+        http::block_on(async {
+            let response =
+                http::send("Listing workflow runs", || self.request(Method::GET, &url)).await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to list workflow runs: HTTP status {status}"
+                ));
+            }
+            let body: Value = response.json().await?;
+            let runs = body["workflow_runs"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            Ok(runs
+                .iter()
+                .filter(|run| run["workflow_id"].as_str() == Some(workflow))
+                .filter(|run| {
+                    let run_ref = run["prettyref"].as_str().unwrap_or_default();
+                    run_ref == git_ref || run_ref.ends_with(&format!("/{}", git_ref))
+                })
+                .filter_map(|run| {
+                    let state = match run["status"].as_str()? {
+                        "success" => RunState::Succeeded,
+                        "failure" | "cancelled" | "skipped" => RunState::Failed,
+                        _ => RunState::Pending,
+                    };
+                    Some(WorkflowRun {
+                        id: run["id"].as_u64()?,
+                        state,
+                        url: run["html_url"].as_str().unwrap_or_default().to_string(),
+                    })
+                })
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_forge::{MockForge, Response};
+    use serde_json::json;
+
+    #[test]
+    fn test_generic_package_urls() {
+        let client = ForgejoClient::new("https://code.bearcove.cloud/".to_string(), String::new());
+        assert_eq!(
+            client.generic_package_url(
+                "bearcove",
+                "beardist",
+                "v1.0.0",
+                "x86_64-unknown-linux-gnu.tar.xz"
+            ),
+            "https://code.bearcove.cloud/api/packages/bearcove/generic/beardist/v1.0.0/x86_64-unknown-linux-gnu.tar.xz"
+        );
+        assert_eq!(
+            client.generic_package_page("bearcove", "beardist", "v1.0.0"),
+            "https://code.bearcove.cloud/bearcove/-/packages/generic/beardist/v1.0.0"
+        );
+    }
+
+    #[test]
+    fn test_release_race_and_multipart_upload() {
+        let forge = MockForge::start();
+        let tag_path = "/api/v1/repos/bearcove/beardist/releases/tags/v1.0.0";
+        // Another build creates the release between our lookup and our creation attempt
+        forge.mock_sequence(
+            "GET",
+            tag_path,
+            vec![
+                Response::status(404),
+                Response::json(200, json!({"id": 12})),
+            ],
+        );
+        forge.mock(
+            "POST",
+            "/api/v1/repos/bearcove/beardist/releases",
+            Response::status(409),
+        );
+        forge.mock_sequence(
+            "POST",
+            "/api/v1/repos/bearcove/beardist/releases/12/assets",
+            vec![Response::status(500), Response::json(201, json!({"id": 3}))],
+        );
+
+        let client = ForgejoClient::new(forge.url().to_string(), "secret".to_string());
+        let release_id = client
+            .create_release("bearcove", "beardist", "v1.0.0", false)
+            .unwrap();
+        assert_eq!(release_id, 12);
+        assert_eq!(forge.requests_to("GET", tag_path).len(), 2);
+
+        client
+            .upload_artifact(
+                "bearcove",
+                "beardist",
+                release_id,
+                "install.sh",
+                None,
+                UploadSource::Bytes(b"#!/bin/sh"),
+            )
+            .unwrap();
+        let uploads =
+            forge.requests_to("POST", "/api/v1/repos/bearcove/beardist/releases/12/assets");
+        assert_eq!(uploads.len(), 2, "the 500 should have been retried");
+        let upload = &uploads[1];
+        let content_type = upload.header("content-type").unwrap();
+        let boundary = content_type.split("boundary=").nth(1).unwrap();
+        let body = String::from_utf8(upload.body.clone()).unwrap();
+        assert!(body.starts_with(&format!("--{boundary}\r\n")));
+        assert!(body.contains("filename=\"install.sh\"\r\nContent-Type: application/x-sh\r\n"));
+        assert!(body.contains("\r\n\r\n#!/bin/sh\r\n"));
+        assert!(body.ends_with(&format!("--{boundary}--\r\n")));
+        assert_eq!(
+            upload.header("content-length"),
+            Some(body.len().to_string().as_str())
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! GitHub Actions integration, when running under `GITHUB_ACTIONS`: workflow commands
+//! (annotations, collapsible log groups) and the Markdown job summary.
+
+use std::io::Write;
+
+use crate::{format_bytes, incremental::CacheStats};
+
+pub(crate) fn enabled() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true")
+}
+
+/// Workflow command for an annotation, e.g. `level` = "error" or "warning". Colors are
+/// stripped, since annotations are shown as plain text.
+pub(crate) fn annotation(level: &str, message: &str) -> String {
+    let message = crate::color::strip_ansi(message);
+    let escaped = message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    format!("::{}::{}", level, escaped)
+}
+
+/// Folds everything logged until it's dropped into a collapsible group
+pub(crate) struct Group {
+    enabled: bool,
+}
+
+pub(crate) fn group(title: &str) -> Group {
+    let enabled = enabled();
+    if enabled {
+        eprintln!("::group::{}", title);
+    }
+    Group { enabled }
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        if self.enabled {
+            eprintln!("::endgroup::");
+        }
+    }
+}
+
+/// What went into a build, for the job summary
+pub(crate) struct BuildSummary<'a> {
+    pub(crate) project: String,
+    pub(crate) archive: &'a str,
+    pub(crate) archive_size: u64,
+    /// (file name, size) of everything in the archive
+    pub(crate) files: Vec<(&'a str, u64)>,
+    /// (step, milliseconds)
+    pub(crate) timings: Vec<(&'a str, u64)>,
+    pub(crate) release_url: Option<String>,
+    pub(crate) cache_stats: Option<CacheStats>,
+}
+
+impl BuildSummary<'_> {
+    fn to_markdown(&self) -> String {
+        let mut md = format!("## 📦 {}\n\n", self.project);
+        md.push_str("| Artifact | Size |\n|---|---|\n");
+        md.push_str(&format!(
+            "| **`{}`** | {} |\n",
+            self.archive,
+            format_bytes(self.archive_size)
+        ));
+        for (name, size) in &self.files {
+            md.push_str(&format!("| `{}` | {} |\n", name, format_bytes(*size)));
+        }
+        md.push_str("\n| Step | Time |\n|---|---|\n");
+        for (step, ms) in &self.timings {
+            md.push_str(&format!("| {} | {}ms |\n", step, ms));
+        }
+        if let Some(cache_stats) = &self.cache_stats {
+            md.push_str(&format!("\n{}\n", cache_stats.describe()));
+        }
+        match &self.release_url {
+            Some(url) => md.push_str(&format!("\nReleased at {}\n", url)),
+            None => md.push_str("\nDry run: nothing was uploaded\n"),
+        }
+        md
+    }
+
+    /// Appends the summary to `$GITHUB_STEP_SUMMARY`, if set
+    pub(crate) fn write(&self) -> eyre::Result<()> {
+        let Ok(path) = std::env::var("GITHUB_STEP_SUMMARY") else {
+            return Ok(());
+        };
+        let mut file = fs_err::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        file.write_all(self.to_markdown().as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotation() {
+        assert_eq!(
+            annotation("warning", "\x1b[36mGITHUB_REF\x1b[39m is 100%\nnot set"),
+            "::warning::GITHUB_REF is 100%25%0Anot set"
+        );
+    }
+
+    #[test]
+    fn test_build_summary() {
+        let summary = BuildSummary {
+            project: "bearcove/beardist".to_string(),
+            archive: "x86_64-unknown-linux-gnu.tar.xz",
+            archive_size: 2048,
+            files: vec![("beardist", 4096)],
+            timings: vec![("Build", 1200), ("Archive", 300)],
+            release_url: Some(
+                "https://github.com/bearcove/beardist/releases/tag/v1.0.0".to_string(),
+            ),
+            cache_stats: None,
+        };
+        let md = summary.to_markdown();
+        assert!(md.starts_with("## 📦 bearcove/beardist\n"));
+        assert!(md.contains("| **`x86_64-unknown-linux-gnu.tar.xz`** |"));
+        assert!(md.contains("| `beardist` |"));
+        assert!(md.contains("| Build | 1200ms |\n"));
+        assert!(
+            md.contains("Released at https://github.com/bearcove/beardist/releases/tag/v1.0.0")
+        );
+    }
+}
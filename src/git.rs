@@ -0,0 +1,404 @@
+use indexmap::IndexMap;
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::path::Path;
+use url::Url;
+
+use crate::{
+    command::{get_trimmed_cmd_stdout, run_command_with_retry},
+    github::GitHubClient,
+    retry::RetryPolicy,
+    run_command,
+};
+
+/// Who automated commits are by, and what they say. The `BEARDIST_GIT_NAME` and
+/// `BEARDIST_GIT_EMAIL` environment variables override the identity, like git's own
+/// `GIT_AUTHOR_NAME` overrides its config.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CommitConfig {
+    /// Author and committer name (default: "beardist")
+    #[serde(default)]
+    name: Option<String>,
+
+    /// Author and committer email (default: "amos@bearcove.eu")
+    #[serde(default)]
+    email: Option<String>,
+
+    /// Commit message, where `{kind}` is what was bumped (e.g. "formulas") and `{bumps}`
+    /// the list of bumps (e.g. "beardist to 1.2.0, home to 0.3.1"). Default:
+    /// "Bump {kind}: {bumps}"
+    #[serde(default)]
+    message: Option<String>,
+}
+
+impl CommitConfig {
+    fn identity(&self) -> (String, String) {
+        let name = std::env::var("BEARDIST_GIT_NAME")
+            .ok()
+            .or_else(|| self.name.clone())
+            .unwrap_or_else(|| "beardist".to_string());
+        let email = std::env::var("BEARDIST_GIT_EMAIL")
+            .ok()
+            .or_else(|| self.email.clone())
+            .unwrap_or_else(|| "amos@bearcove.eu".to_string());
+        (name, email)
+    }
+
+    /// Environment variables that make git use our identity
+    fn identity_env(&self) -> IndexMap<String, String> {
+        let (name, email) = self.identity();
+        indexmap::indexmap! {
+            "GIT_AUTHOR_NAME".to_string() => name.clone(),
+            "GIT_AUTHOR_EMAIL".to_string() => email.clone(),
+            "GIT_COMMITTER_NAME".to_string() => name,
+            "GIT_COMMITTER_EMAIL".to_string() => email,
+        }
+    }
+
+    fn message(&self, kind: &str, bumps: &str) -> String {
+        self.message
+            .as_deref()
+            .unwrap_or("Bump {kind}: {bumps}")
+            .replace("{kind}", kind)
+            .replace("{bumps}", bumps)
+    }
+}
+
+/// How bumps are pushed
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Transport {
+    /// Over HTTPS, authenticated with the push token
+    #[default]
+    Https,
+
+    /// Over SSH, authenticated by the SSH agent, or by the deploy key in
+    /// `BEARDIST_SSH_KEY` (either the key itself, or the path to it)
+    Ssh,
+}
+
+/// The SSH URL of the repository at `remote_url`, e.g. "git@github.com:org/repo.git"
+fn ssh_url(remote_url: &str) -> eyre::Result<String> {
+    if !remote_url.starts_with("https://") && !remote_url.starts_with("http://") {
+        // Already an SSH URL (or a local path), use it as is
+        return Ok(remote_url.to_string());
+    }
+    let url = Url::parse(remote_url)?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| eyre::eyre!("Remote URL {} has no host", remote_url))?;
+    let path = url.path().trim_start_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    Ok(format!("git@{}:{}.git", host, path))
+}
+
+/// Makes git use the deploy key in `BEARDIST_SSH_KEY`, if there's one. Otherwise, ssh
+/// finds keys the usual way (the agent, `~/.ssh`). The returned file holds the key
+/// and must outlive the push.
+fn ssh_env() -> eyre::Result<(IndexMap<String, String>, Option<tempfile::NamedTempFile>)> {
+    let Ok(key) = std::env::var("BEARDIST_SSH_KEY") else {
+        return Ok((IndexMap::new(), None));
+    };
+    let (key_path, key_file) = if key.contains("PRIVATE KEY") {
+        // Temporary files are only readable by us, as ssh insists
+        let mut key_file = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(&mut key_file, format!("{}\n", key.trim()).as_bytes())?;
+        (
+            key_file.path().to_string_lossy().into_owned(),
+            Some(key_file),
+        )
+    } else {
+        (key, None)
+    };
+    let ssh_command = format!(
+        "ssh -i '{}' -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new",
+        key_path
+    );
+    Ok((
+        indexmap::indexmap! { "GIT_SSH_COMMAND".to_string() => ssh_command },
+        key_file,
+    ))
+}
+
+/// An automated bump to commit and push to the repository in the current directory
+/// (a Homebrew tap, a Scoop bucket, ...)
+pub(crate) struct Publish<'a> {
+    /// What was bumped, e.g. "formulas" — used in the commit message
+    pub(crate) kind: &'a str,
+
+    /// `(name, version)` pairs that were bumped
+    pub(crate) bumped: &'a [(String, String)],
+
+    /// Token used to push over HTTPS and to open pull requests
+    pub(crate) github_token: &'a str,
+
+    /// Whether to push over HTTPS or SSH
+    pub(crate) transport: Transport,
+
+    /// Push to a new branch and open a pull request instead of pushing to the base branch
+    pub(crate) pr: bool,
+
+    /// Branch to push to (or to target with the pull request), e.g. "main"
+    pub(crate) base_branch: &'a str,
+
+    /// "owner/repo" to open the pull request against, when pushing to a fork of it
+    pub(crate) upstream: Option<&'a str>,
+
+    pub(crate) dry_run: bool,
+
+    /// Identity and message of the commit
+    pub(crate) commit: &'a CommitConfig,
+}
+
+pub(crate) fn commit_and_push(publish: Publish) -> eyre::Result<()> {
+    let Publish {
+        kind,
+        bumped,
+        github_token,
+        transport,
+        pr,
+        base_branch,
+        upstream,
+        dry_run,
+        commit,
+    } = publish;
+
+    let commit_message = bumped
+        .iter()
+        .map(|(name, version)| format!("{} to {}", name, version))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let full_commit_message = commit.message(kind, &commit_message);
+
+    info!("Committing changes...");
+    if !dry_run {
+        run_command("git", &["add", "."], None)?;
+        run_command(
+            "git",
+            &["commit", "-m", &full_commit_message],
+            Some(commit.identity_env()),
+        )?;
+        info!("Changes committed successfully");
+    } else {
+        info!(
+            "Dry run: Would commit changes with message: {}",
+            full_commit_message.cyan()
+        );
+    }
+
+    info!("Bumped {}:", kind);
+    for (name, version) in bumped {
+        info!("  {} to version {}", name.cyan(), version.green());
+    }
+
+    info!("Pushing changes...");
+    let (remote_url, org_repo) = push_remote(Path::new("."))?;
+
+    info!("Remote URL: {}", remote_url.cyan());
+    info!("Organization/Repo: {}", org_repo.cyan());
+
+    let push_ref = if pr {
+        let slug = bumped
+            .iter()
+            .map(|(name, version)| format!("{}-{}", name, version))
+            .collect::<Vec<_>>()
+            .join("-");
+        format!("beardist/bump-{}", slug)
+    } else {
+        base_branch.to_string()
+    };
+    // Bump branches are rewritten by every run until their pull request is merged
+    let refspec = if pr {
+        format!("+HEAD:{}", push_ref)
+    } else {
+        format!("HEAD:{}", push_ref)
+    };
+
+    match transport {
+        Transport::Https => {
+            let mut push_url = Url::parse(&remote_url)?;
+            push_url.set_username("token").unwrap();
+            push_url.set_password(Some(github_token)).unwrap();
+            if !dry_run {
+                run_command_with_retry(
+                    "git",
+                    &["push", push_url.as_str(), &refspec],
+                    None,
+                    RetryPolicy::default(),
+                )?;
+                info!("Changes pushed successfully");
+            } else {
+                info!("Dry run: Would push changes to remote repository");
+                info!("Push command that would be executed:");
+                let mut redacted_url = push_url.clone();
+                redacted_url.set_password(Some("REDACTED")).unwrap();
+                info!("git push {} {}", redacted_url.to_string().cyan(), refspec);
+            }
+        }
+        Transport::Ssh => {
+            let push_url = ssh_url(&remote_url)?;
+            if !dry_run {
+                let (env, _key_file) = ssh_env()?;
+                run_command_with_retry(
+                    "git",
+                    &["push", &push_url, &refspec],
+                    Some(env),
+                    RetryPolicy::default(),
+                )?;
+                info!("Changes pushed successfully over SSH");
+            } else {
+                info!("Dry run: Would push changes to remote repository over SSH");
+                info!("git push {} {}", push_url.cyan(), refspec);
+            }
+        }
+    }
+
+    if pr {
+        let (fork_owner, _) = org_repo
+            .split_once('/')
+            .ok_or_else(|| eyre::eyre!("Could not determine repository from remote"))?;
+        let (owner, repo) = upstream
+            .unwrap_or(&org_repo)
+            .split_once('/')
+            .ok_or_else(|| eyre::eyre!("Upstream repository must be in 'owner/repo' format"))?;
+        // Cross-repository pull requests name the head as "owner:branch"
+        let head = if owner == fork_owner {
+            push_ref.clone()
+        } else {
+            format!("{}:{}", fork_owner, push_ref)
+        };
+        let body = format!(
+            "Automated {} bump by beardist:\n\n{}",
+            kind,
+            bumped
+                .iter()
+                .map(|(name, version)| format!("- `{}` to {}", name, version))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        if !dry_run {
+            let github_client = GitHubClient::new(
+                "https://api.github.com".to_string(),
+                github_token.to_string(),
+            );
+            let pr_url = github_client.create_pull_request(
+                owner,
+                repo,
+                &head,
+                base_branch,
+                &full_commit_message,
+                &body,
+            )?;
+            info!("Pull request opened: {}", pr_url.bright_green());
+        } else {
+            info!(
+                "Dry run: Would open a pull request from {} to {}/{}:{} with body:\n{}",
+                head.cyan(),
+                owner.cyan(),
+                repo.cyan(),
+                base_branch.cyan(),
+                body
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The HTTPS URL we push the repository in `dir` to, and its "owner/repo"
+pub(crate) fn push_remote(dir: &Path) -> eyre::Result<(String, String)> {
+    let dir = dir.to_string_lossy();
+    let remote_output = get_trimmed_cmd_stdout("git", &["-C", &dir, "remote", "-v"], None)?;
+    let remote_url = remote_output
+        .lines()
+        .find(|line| line.contains("(push)"))
+        .and_then(|line| {
+            let url = line.split_whitespace().nth(1)?;
+            // Convert SSH URLs to HTTPS URLs
+            if url.starts_with("git@github.com:") {
+                let repo_path = url.trim_start_matches("git@github.com:");
+                Some(format!("https://github.com/{}", repo_path))
+            } else {
+                Some(url.to_string())
+            }
+        })
+        .ok_or_else(|| eyre::eyre!("Failed to get remote URL"))?;
+
+    let org_repo = remote_url
+        .trim_start_matches("https://")
+        .trim_end_matches(".git")
+        .split('/')
+        .skip(1)
+        .take(2)
+        .collect::<Vec<&str>>()
+        .join("/");
+    Ok((remote_url, org_repo))
+}
+
+/// Commits some paths with the user's own git identity, then optionally pushes the
+/// current branch to its upstream
+pub(crate) fn commit_paths(paths: &[&str], message: &str, push: bool) -> eyre::Result<()> {
+    info!("Committing {} paths...", paths.len());
+    let mut add_args = vec!["add", "--"];
+    add_args.extend_from_slice(paths);
+    run_command("git", &add_args, None)?;
+    // Only commit these paths, even if other changes are staged
+    let mut commit_args = vec!["commit", "-m", message, "--"];
+    commit_args.extend_from_slice(paths);
+    run_command("git", &commit_args, None)?;
+    info!("Committed: {}", message.cyan());
+
+    if push {
+        info!("Pushing changes...");
+        run_command_with_retry("git", &["push"], None, RetryPolicy::default())?;
+        info!("Changes pushed successfully");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_config() {
+        let config: CommitConfig = serde_json::from_value(serde_json::json!({
+            "name": "Release Bot",
+            "email": "releases@example.com",
+            "message": "chore({kind}): {bumps}",
+        }))
+        .unwrap();
+        let env = config.identity_env();
+        assert_eq!(env["GIT_AUTHOR_NAME"], "Release Bot");
+        assert_eq!(env["GIT_COMMITTER_EMAIL"], "releases@example.com");
+        assert_eq!(
+            config.message("formulas", "beardist to 1.2.0"),
+            "chore(formulas): beardist to 1.2.0"
+        );
+
+        let default = CommitConfig::default();
+        assert_eq!(
+            default.message("formulas", "beardist to 1.2.0"),
+            "Bump formulas: beardist to 1.2.0"
+        );
+    }
+
+    #[test]
+    fn test_ssh_url() {
+        assert_eq!(
+            ssh_url("https://github.com/bearcove/homebrew-tap").unwrap(),
+            "git@github.com:bearcove/homebrew-tap.git"
+        );
+        assert_eq!(
+            ssh_url("https://code.example.com/mirrors/tap.git").unwrap(),
+            "git@code.example.com:mirrors/tap.git"
+        );
+        assert_eq!(
+            ssh_url("git@github.com:bearcove/homebrew-tap.git").unwrap(),
+            "git@github.com:bearcove/homebrew-tap.git"
+        );
+    }
+}
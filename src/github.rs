@@ -1,111 +1,332 @@
 use log::{debug, info};
 use owo_colors::OwoColorize;
-use reqwest::blocking::Client;
+use reqwest::{Method, RequestBuilder};
 use semver::Version;
 use serde_json::Value;
+use std::{collections::HashMap, sync::Mutex};
 
-use crate::USER_AGENT;
+use crate::{
+    forge::{
+        Forge, Release, ReleaseAsset, RunState, UploadSource, WorkflowRun, content_type,
+        upload_with_retries,
+    },
+    http,
+};
 
+/// A commit status, as set by [`GitHubClient::create_commit_status`]
+pub(crate) struct CommitStatus<'a> {
+    pub(crate) state: &'a str,
+    pub(crate) target_url: &'a str,
+    pub(crate) description: &'a str,
+    pub(crate) context: &'a str,
+}
+
+/// What [`GitHubClient::upsert_comment`] comments on
+pub(crate) enum CommentTarget {
+    PullRequest(u64),
+    /// A commit, by SHA
+    Commit(String),
+}
+
+/// Talks to the GitHub API. Requests go through the shared async core ([`http`]); the
+/// [`Forge`] methods block on it, async callers use the inherent async methods.
 pub struct GitHubClient {
-    client: Client,
     server_url: String,
     token: String,
+
+    /// URL → (ETag, body) of responses we can revalidate with `If-None-Match`. A `304 Not
+    /// Modified` doesn't count against the rate limit, which matters when polling.
+    etags: Mutex<HashMap<String, (String, String)>>,
 }
 
 impl GitHubClient {
     pub fn new(server_url: String, token: String) -> Self {
         Self {
-            client: Client::new(),
             server_url,
             token,
+            etags: Default::default(),
         }
     }
 
     pub fn from_env() -> eyre::Result<Self> {
         let server_url = std::env::var("GITHUB_SERVER_URL")
             .unwrap_or_else(|_| "https://api.github.com".to_string());
-        let token = std::env::var("GITHUB_TOKEN")
-            .map_err(|_| eyre::eyre!("GITHUB_TOKEN environment variable not set"))?;
+        let token = crate::credentials::require("GITHUB_TOKEN")?;
         Ok(Self::new(server_url, token))
     }
 
-    /// Get the latest version tag from a GitHub Container Registry (ghcr.io) package
-    pub fn get_latest_container_version(
-        &self,
-        org: &str,
-        package_name: &str,
-    ) -> eyre::Result<Option<String>> {
-        let url = format!(
-            "{}/orgs/{}/packages/container/{}/versions",
-            self.server_url, org, package_name
-        );
-
-        info!(
-            "Fetching latest container version for '{}' from '{}'",
-            package_name, url
-        );
+    /// `https://github.com` → `https://api.github.com`, as used by the release endpoints
+    fn api_base(&self) -> String {
+        self.server_url.replace("github.com", "api.github.com")
+    }
 
-        let start_time = std::time::Instant::now();
-        let response = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("token {}", self.token))
+    /// An authenticated request to the GitHub API
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        http::client()
+            .request(method, url)
             .header("Accept", "application/vnd.github+json")
+            .header("Authorization", format!("token {}", self.token))
             .header("X-GitHub-Api-Version", "2022-11-28")
-            .header("User-Agent", USER_AGENT)
-            .send()?;
+    }
 
-        let status = response.status();
-        let elapsed = start_time.elapsed();
-        info!(
-            "Request completed in {}ms with status {}",
-            elapsed.as_millis(),
-            status
-        );
+    /// Open a pull request and return its URL. If one is already open for `head`
+    /// (e.g. from an earlier run bumping the same version), returns that one's URL.
+    pub fn create_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        head: &str,
+        base: &str,
+        title: &str,
+        body: &str,
+    ) -> eyre::Result<String> {
+        http::block_on(async {
+            let url = format!("{}/repos/{}/{}/pulls", self.server_url, owner, repo);
 
-        if status != 200 {
-            let body = response.text()?;
-            debug!("Error response: {}", body);
+            info!(
+                "Opening pull request {} → {} on '{}/{}'",
+                head.cyan(),
+                base.cyan(),
+                owner,
+                repo
+            );
+
+            let response = http::send("Opening pull request", || {
+                self.request(Method::POST, &url).json(&serde_json::json!({
+                    "title": title,
+                    "head": head,
+                    "base": base,
+                    "body": body,
+                }))
+            })
+            .await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                if status == reqwest::StatusCode::UNPROCESSABLE_ENTITY
+                    && body.contains("A pull request already exists")
+                {
+                    return self.find_pull_request(owner, repo, head, base).await;
+                }
+                return Err(eyre::eyre!(
+                    "Failed to create pull request: HTTP status {status}: {body}"
+                ));
+            }
+
+            let pr: Value = response.json().await?;
+            let html_url = pr["html_url"]
+                .as_str()
+                .ok_or_else(|| eyre::eyre!("Pull request response has no html_url"))?;
+            Ok(html_url.to_string())
+        })
+    }
+
+    /// The URL of the open pull request from `head` into `base`
+    async fn find_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        head: &str,
+        base: &str,
+    ) -> eyre::Result<String> {
+        // The `head` filter wants "owner:branch", even for same-repository branches
+        let head = if head.contains(':') {
+            head.to_string()
+        } else {
+            format!("{}:{}", owner, head)
+        };
+        let url = format!("{}/repos/{}/{}/pulls", self.server_url, owner, repo);
+        let response = http::send("Looking up existing pull request", || {
+            self.request(Method::GET, &url).query(&[
+                ("head", head.as_str()),
+                ("base", base),
+                ("state", "open"),
+            ])
+        })
+        .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
             return Err(eyre::eyre!(
-                "Failed to get container versions: HTTP status {status}"
+                "Failed to look up existing pull request: HTTP status {status}: {body}"
             ));
         }
 
-        let body = response.text()?;
-        debug!("Response body size: {} bytes", body.len());
+        let prs: Vec<Value> = response.json().await?;
+        let html_url = prs
+            .first()
+            .and_then(|pr| pr["html_url"].as_str())
+            .ok_or_else(|| eyre::eyre!("No open pull request found from {} into {}", head, base))?;
+        info!("Pull request already open: {}", html_url.cyan());
+        Ok(html_url.to_string())
+    }
 
-        let versions: Vec<Value> = serde_json::from_str(&body)?;
-        info!("Received {} versions in response", versions.len());
-
-        let valid_versions: Vec<Version> = versions
-            .iter()
-            .filter_map(|version| {
-                // Look for metadata tags with semver format
-                version["metadata"]["container"]["tags"]
-                    .as_array()
-                    .and_then(|tags| {
-                        tags.iter()
-                            .filter_map(|tag| tag.as_str())
-                            .filter_map(|tag| Version::parse(tag.trim_start_matches('v')).ok())
-                            .max()
-                    })
+    /// Whether our token can push to a repository. Works for classic and fine-grained
+    /// tokens alike, unlike checking `X-OAuth-Scopes`.
+    pub(crate) fn can_push(&self, owner: &str, repo: &str) -> eyre::Result<bool> {
+        http::block_on(async {
+            let url = format!("{}/repos/{}/{}", self.server_url, owner, repo);
+            let response = http::send("Checking repository access", || {
+                self.request(Method::GET, &url)
             })
-            .collect();
+            .await?;
+            let status = response.status();
+            // Private repositories the token can't see look like they don't exist
+            if status == 404 || status == 401 || status == 403 {
+                return Ok(false);
+            }
+            if !status.is_success() {
+                return Err(eyre::eyre!(
+                    "Failed to get repository {owner}/{repo}: HTTP status {status}"
+                ));
+            }
+            let repository: Value = response.json().await?;
+            Ok(repository["permissions"]["push"].as_bool().unwrap_or(false))
+        })
+    }
 
-        info!("Found {} valid semver tags", valid_versions.len());
+    /// Sets a status on commit `sha`, shown next to it (and on pull requests that
+    /// contain it). `state` is "success", "failure", "pending" or "error"; statuses with
+    /// the same `context` replace each other.
+    pub(crate) fn create_commit_status(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+        status: &CommitStatus,
+    ) -> eyre::Result<()> {
+        let url = format!(
+            "{}/repos/{}/{}/statuses/{}",
+            self.api_base(),
+            owner,
+            repo,
+            sha
+        );
+        http::block_on(async {
+            let response = http::send("Setting commit status", || {
+                self.request(Method::POST, &url).json(&serde_json::json!({
+                    "state": status.state,
+                    "target_url": status.target_url,
+                    "description": status.description,
+                    "context": status.context,
+                }))
+            })
+            .await?;
+            let code = response.status();
+            if !code.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to set status on {sha}: HTTP status {code}"
+                ));
+            }
+            Ok(())
+        })
+    }
 
-        if valid_versions.is_empty() {
-            info!("No valid versioned tags found for container");
-            return Ok(None);
-        }
+    /// Numbers of the pull requests commit `sha` is part of
+    pub(crate) fn pull_requests_for_commit(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+    ) -> eyre::Result<Vec<u64>> {
+        let url = format!(
+            "{}/repos/{}/{}/commits/{}/pulls",
+            self.api_base(),
+            owner,
+            repo,
+            sha
+        );
+        http::block_on(async {
+            let response = http::send("Listing pull requests of commit", || {
+                self.request(Method::GET, &url)
+            })
+            .await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(eyre::eyre!(
+                    "Failed to list pull requests of {sha}: HTTP status {status}"
+                ));
+            }
+            let pulls: Vec<Value> = response.json().await?;
+            Ok(pulls
+                .iter()
+                .filter_map(|pull| pull["number"].as_u64())
+                .collect())
+        })
+    }
 
-        let latest_version = valid_versions.into_iter().max().unwrap();
-        info!("Latest container version found: {}", latest_version);
-        Ok(Some(latest_version.to_string()))
+    /// Comments `body` on a pull request or commit, editing our previous comment instead
+    /// if there's one containing `marker`
+    pub(crate) fn upsert_comment(
+        &self,
+        owner: &str,
+        repo: &str,
+        target: &CommentTarget,
+        marker: &str,
+        body: &str,
+    ) -> eyre::Result<()> {
+        let repo_url = format!("{}/repos/{}/{}", self.api_base(), owner, repo);
+        let (comments_url, comment_url) = match target {
+            CommentTarget::PullRequest(number) => (
+                format!("{}/issues/{}/comments", repo_url, number),
+                format!("{}/issues/comments", repo_url),
+            ),
+            CommentTarget::Commit(sha) => (
+                format!("{}/commits/{}/comments", repo_url, sha),
+                format!("{}/comments", repo_url),
+            ),
+        };
+        http::block_on(async {
+            let list_url = format!("{}?per_page=100", comments_url);
+            let response =
+                http::send("Listing comments", || self.request(Method::GET, &list_url)).await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(eyre::eyre!("Failed to list comments: HTTP status {status}"));
+            }
+            let comments: Vec<Value> = response.json().await?;
+            let existing = comments.iter().find_map(|comment| {
+                comment["body"]
+                    .as_str()
+                    .is_some_and(|body| body.contains(marker))
+                    .then(|| comment["id"].as_u64())
+                    .flatten()
+            });
+
+            let payload = serde_json::json!({ "body": body });
+            let response = match existing {
+                Some(id) => {
+                    let url = format!("{}/{}", comment_url, id);
+                    http::send("Editing comment", || {
+                        self.request(Method::PATCH, &url).json(&payload)
+                    })
+                    .await?
+                }
+                None => {
+                    http::send("Commenting", || {
+                        self.request(Method::POST, &comments_url).json(&payload)
+                    })
+                    .await?
+                }
+            };
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!("Failed to comment: HTTP status {status}"));
+            }
+            Ok(())
+        })
     }
 
-    /// Get the latest release version from a GitHub repository
-    pub fn get_latest_release_version(
+    /// The latest release's version, without the leading "v". `None` if there are no
+    /// releases yet.
+    pub(crate) async fn latest_release_version(
         &self,
         owner: &str,
         repo: &str,
@@ -121,14 +342,10 @@ impl GitHubClient {
         );
 
         let start_time = std::time::Instant::now();
-        let response = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("token {}", self.token))
-            .header("Accept", "application/vnd.github+json")
-            .header("X-GitHub-Api-Version", "2022-11-28")
-            .header("User-Agent", USER_AGENT)
-            .send()?;
+        let response = http::send("Fetching latest release", || {
+            self.request(Method::GET, &url)
+        })
+        .await?;
 
         let status = response.status();
         let elapsed = start_time.elapsed();
@@ -145,14 +362,14 @@ impl GitHubClient {
         }
 
         if status != 200 {
-            let body = response.text()?;
+            let body = response.text().await?;
             debug!("Error response: {}", body);
             return Err(eyre::eyre!(
                 "Failed to get latest release: HTTP status {status}"
             ));
         }
 
-        let body = response.text()?;
+        let body = response.text().await?;
         debug!("Response body size: {} bytes", body.len());
 
         let release: Value = serde_json::from_str(&body)?;
@@ -172,88 +389,388 @@ impl GitHubClient {
         }
     }
 
-    /// Create a release if it doesn't exist, and return the release ID
-    pub fn create_release(&self, org: &str, name: &str, tag: &str) -> eyre::Result<u64> {
-        let github_api_url = format!(
-            "{}/repos/{}/{}/releases/tags/{}",
-            self.server_url.replace("github.com", "api.github.com"),
-            org,
-            name,
-            tag
+    /// Versions of the latest published (not draft, not prerelease) releases, newest
+    /// first, without the leading "v"
+    pub(crate) async fn release_versions(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> eyre::Result<Vec<String>> {
+        let url = format!(
+            "{}/repos/{}/{}/releases?per_page=100",
+            self.server_url, owner, repo
         );
+        let response = http::send("Listing releases", || self.request(Method::GET, &url)).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            debug!("Error response: {}", body);
+            return Err(eyre::eyre!("Failed to list releases: HTTP status {status}"));
+        }
+        let releases: Vec<Release> = response.json().await?;
+        Ok(releases
+            .into_iter()
+            .filter(|release| !release.draft && !release.prerelease)
+            .map(|release| release.tag_name.trim_start_matches('v').to_string())
+            .collect())
+    }
+}
 
-        info!("Checking if release exists at {}...", github_api_url);
+impl Forge for GitHubClient {
+    fn get_container_versions(&self, org: &str, package_name: &str) -> eyre::Result<Vec<Version>> {
+        http::block_on(async {
+            let url = format!(
+                "{}/orgs/{}/packages/container/{}/versions",
+                self.server_url, org, package_name
+            );
 
-        let release_response = self
-            .client
-            .get(&github_api_url)
-            .header("Accept", "application/vnd.github+json")
-            .header("Authorization", format!("token {}", self.token))
-            .header("X-GitHub-Api-Version", "2022-11-28")
-            .header("User-Agent", USER_AGENT)
-            .send()?;
+            info!(
+                "Fetching latest container version for '{}' from '{}'",
+                package_name, url
+            );
+
+            let cached = self.etags.lock().unwrap().get(&url).cloned();
+            let start_time = std::time::Instant::now();
+            let response = http::send("Fetching container versions", || {
+                let request = self.request(Method::GET, &url);
+                match &cached {
+                    Some((etag, _)) => request.header("If-None-Match", etag),
+                    None => request,
+                }
+            })
+            .await?;
+
+            let status = response.status();
+            let elapsed = start_time.elapsed();
+            info!(
+                "Request completed in {}ms with status {}",
+                elapsed.as_millis(),
+                status
+            );
+
+            let body = match (status.as_u16(), cached) {
+                (304, Some((_, body))) => body,
+                (200, _) => {
+                    let etag = response
+                        .headers()
+                        .get("ETag")
+                        .and_then(|v| v.to_str().ok())
+                        .map(String::from);
+                    let body = response.text().await?;
+                    if let Some(etag) = etag {
+                        self.etags
+                            .lock()
+                            .unwrap()
+                            .insert(url.clone(), (etag, body.clone()));
+                    }
+                    body
+                }
+                _ => {
+                    let body = response.text().await?;
+                    debug!("Error response: {}", body);
+                    return Err(eyre::eyre!(
+                        "Failed to get container versions: HTTP status {status}"
+                    ));
+                }
+            };
+            debug!("Response body size: {} bytes", body.len());
+
+            let versions: Vec<Value> = serde_json::from_str(&body)?;
+            info!("Received {} versions in response", versions.len());
 
-        let release_id = if !release_response.status().is_success() {
-            info!("Release doesn't exist, creating one...");
+            let mut valid_versions: Vec<Version> = versions
+                .iter()
+                .filter_map(|version| {
+                    // Look for metadata tags with semver format
+                    version["metadata"]["container"]["tags"]
+                        .as_array()
+                        .and_then(|tags| {
+                            tags.iter()
+                                .filter_map(|tag| tag.as_str())
+                                .filter_map(|tag| Version::parse(tag.trim_start_matches('v')).ok())
+                                .max()
+                        })
+                })
+                .collect();
+            valid_versions.sort_by(|a, b| b.cmp(a));
 
-            let release_create_url = format!(
-                "{}/repos/{}/{}/releases",
+            info!("Found {} valid semver tags", valid_versions.len());
+            match valid_versions.first() {
+                Some(latest_version) => {
+                    info!("Latest container version found: {}", latest_version)
+                }
+                None => info!("No valid versioned tags found for container"),
+            }
+            Ok(valid_versions)
+        })
+    }
+
+    fn get_latest_release_version(&self, owner: &str, repo: &str) -> eyre::Result<Option<String>> {
+        http::block_on(self.latest_release_version(owner, repo))
+    }
+
+    fn create_release(
+        &self,
+        org: &str,
+        name: &str,
+        tag: &str,
+        prerelease: bool,
+    ) -> eyre::Result<u64> {
+        http::block_on(async {
+            let github_api_url = format!(
+                "{}/repos/{}/{}/releases/tags/{}",
                 self.server_url.replace("github.com", "api.github.com"),
                 org,
-                name
+                name,
+                tag
             );
 
-            let release_create_body = serde_json::json!({
-                "tag_name": tag,
-                "name": tag,
-                "draft": false,
-                "prerelease": false
-            });
+            info!("Checking if release exists at {}...", github_api_url);
+
+            let release_response = http::send("Fetching release", || {
+                self.request(Method::GET, &github_api_url)
+            })
+            .await?;
+
+            let release_id = if !release_response.status().is_success() {
+                info!("Release doesn't exist, creating one...");
 
-            let create_response = self
-                .client
-                .post(&release_create_url)
-                .header("Accept", "application/vnd.github+json")
-                .header("Authorization", format!("token {}", self.token))
-                .header("X-GitHub-Api-Version", "2022-11-28")
-                .header("User-Agent", USER_AGENT)
-                .json(&release_create_body)
-                .send()?;
-
-            if !create_response.status().is_success() {
+                let release_create_url = format!(
+                    "{}/repos/{}/{}/releases",
+                    self.server_url.replace("github.com", "api.github.com"),
+                    org,
+                    name
+                );
+
+                let release_create_body = serde_json::json!({
+                    "tag_name": tag,
+                    "name": tag,
+                    "draft": false,
+                    "prerelease": prerelease
+                });
+
+                let create_response = http::send("Creating release", || {
+                    self.request(Method::POST, &release_create_url)
+                        .json(&release_create_body)
+                })
+                .await?;
+
+                if !create_response.status().is_success() {
+                    return Err(eyre::eyre!(
+                        "Failed to create release: {}",
+                        create_response.text().await?
+                    ));
+                }
+
+                let release_data: serde_json::Value = create_response.json().await?;
+                release_data["id"]
+                    .as_u64()
+                    .ok_or_else(|| eyre::eyre!("Invalid release ID"))?
+            } else {
+                let release_data: serde_json::Value = release_response.json().await?;
+                release_data["id"]
+                    .as_u64()
+                    .ok_or_else(|| eyre::eyre!("Invalid release ID"))?
+            };
+
+            Ok(release_id)
+        })
+    }
+
+    fn release_body(&self, owner: &str, repo: &str, release_id: u64) -> eyre::Result<String> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/{}",
+            self.api_base(),
+            owner,
+            repo,
+            release_id
+        );
+        http::block_on(async {
+            let response =
+                http::send("Fetching release", || self.request(Method::GET, &url)).await?;
+            let status = response.status();
+            if !status.is_success() {
                 return Err(eyre::eyre!(
-                    "Failed to create release: {}",
-                    create_response.text()?
+                    "Failed to fetch release {release_id}: HTTP status {status}"
                 ));
             }
+            let release: Value = response.json().await?;
+            // A release without a body has a null one
+            Ok(release["body"].as_str().unwrap_or_default().to_string())
+        })
+    }
 
-            let release_data: serde_json::Value = create_response.json()?;
-            release_data["id"]
-                .as_u64()
-                .ok_or_else(|| eyre::eyre!("Invalid release ID"))?
-        } else {
-            let release_data: serde_json::Value = release_response.json()?;
-            release_data["id"]
-                .as_u64()
-                .ok_or_else(|| eyre::eyre!("Invalid release ID"))?
-        };
+    fn set_release_body(
+        &self,
+        owner: &str,
+        repo: &str,
+        release_id: u64,
+        body: &str,
+    ) -> eyre::Result<()> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/{}",
+            self.api_base(),
+            owner,
+            repo,
+            release_id
+        );
+        http::block_on(async {
+            let response = http::send("Updating release", || {
+                self.request(Method::PATCH, &url)
+                    .json(&serde_json::json!({ "body": body }))
+            })
+            .await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to update release {release_id}: HTTP status {status}"
+                ));
+            }
+            Ok(())
+        })
+    }
 
-        Ok(release_id)
+    fn move_tag(&self, owner: &str, repo: &str, tag: &str, sha: &str) -> eyre::Result<()> {
+        let ref_url = format!(
+            "{}/repos/{}/{}/git/refs/tags/{}",
+            self.api_base(),
+            owner,
+            repo,
+            tag
+        );
+        let refs_url = format!("{}/repos/{}/{}/git/refs", self.api_base(), owner, repo);
+        info!("Moving tag {} to {}", tag.cyan(), sha.yellow());
+        http::block_on(async {
+            let response = http::send("Moving tag", || {
+                self.request(Method::PATCH, &ref_url)
+                    .json(&serde_json::json!({ "sha": sha, "force": true }))
+            })
+            .await?;
+            // GitHub says 422 ("Reference does not exist") rather than 404
+            let response = match response.status().as_u16() {
+                404 | 422 => {
+                    http::send("Creating tag", || {
+                        self.request(Method::POST, &refs_url)
+                            .json(&serde_json::json!({
+                                "ref": format!("refs/tags/{}", tag),
+                                "sha": sha,
+                            }))
+                    })
+                    .await?
+                }
+                _ => response,
+            };
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to point tag {tag} at {sha}: HTTP status {status}"
+                ));
+            }
+            Ok(())
+        })
     }
 
-    /// Upload an artifact to a GitHub release as a release asset
-    pub fn upload_artifact(
+    fn list_release_assets(
         &self,
         org: &str,
         name: &str,
         release_id: u64,
-        package_file_name: &str,
-        file_content: &[u8],
+    ) -> eyre::Result<Vec<ReleaseAsset>> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/{}/assets?per_page=100",
+            self.api_base(),
+            org,
+            name,
+            release_id
+        );
+        http::block_on(async {
+            let response =
+                http::send("Listing release assets", || self.request(Method::GET, &url)).await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to list release assets: HTTP status {status}"
+                ));
+            }
+            Ok(response.json().await?)
+        })
+    }
+
+    fn download_release_asset(
+        &self,
+        org: &str,
+        name: &str,
+        asset: &ReleaseAsset,
+    ) -> eyre::Result<Vec<u8>> {
+        let asset_id = asset.id;
+        let url = format!(
+            "{}/repos/{}/{}/releases/assets/{}",
+            self.api_base(),
+            org,
+            name,
+            asset_id
+        );
+        http::block_on(async {
+            let response = http::send("Downloading release asset", || {
+                self.request(Method::GET, &url)
+                    .header("Accept", "application/octet-stream")
+            })
+            .await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                return Err(eyre::eyre!(
+                    "Failed to download release asset {asset_id}: HTTP status {status}"
+                ));
+            }
+            Ok(response.bytes().await?.to_vec())
+        })
+    }
+
+    fn delete_release_asset(
+        &self,
+        org: &str,
+        name: &str,
+        _release_id: u64,
+        asset_id: u64,
     ) -> eyre::Result<()> {
-        use log::warn;
+        let url = format!(
+            "{}/repos/{}/{}/releases/assets/{}",
+            self.api_base(),
+            org,
+            name,
+            asset_id
+        );
+        let status = http::block_on(http::send("Deleting release asset", || {
+            self.request(Method::DELETE, &url)
+        }))?
+        .status();
+        if !status.is_success() && status != 404 {
+            return Err(eyre::eyre!(
+                "Failed to delete release asset {asset_id}: HTTP status {status}"
+            ));
+        }
+        Ok(())
+    }
 
+    fn upload_artifact(
+        &self,
+        org: &str,
+        name: &str,
+        release_id: u64,
+        package_file_name: &str,
+        label: Option<&str>,
+        source: UploadSource,
+    ) -> eyre::Result<()> {
         // Assemble the correct uploads.github.com asset endpoint
-        let upload_url = format!(
+        let mut upload_url = format!(
             "{}/repos/{}/{}/releases/{}/assets?name={}",
             self.server_url.replace("github.com", "uploads.github.com"),
             org,
@@ -261,86 +778,343 @@ impl GitHubClient {
             release_id,
             package_file_name
         );
+        if let Some(label) = label {
+            upload_url.push_str("&label=");
+            upload_url.extend(url::form_urlencoded::byte_serialize(label.as_bytes()));
+        }
 
-        info!(
-            "📤 Uploading package to {} ({})...",
-            "GitHub".yellow(),
-            upload_url.cyan()
-        );
-        let upload_start = std::time::Instant::now();
+        http::block_on(upload_with_retries("GitHub", &upload_url, || {
+            let request = self
+                .request(Method::POST, &upload_url)
+                .header("Content-Type", content_type(package_file_name));
+            source.attach(request, package_file_name)
+        }))
+    }
 
-        // Retry logic for upload attempts
-        const MAX_RETRIES: usize = 3;
-        const BASE_RETRY_DELAY_MS: u64 = 2000; // 2 seconds
+    fn list_releases(&self, owner: &str, repo: &str) -> eyre::Result<Vec<Release>> {
+        const PER_PAGE: usize = 100;
+        http::block_on(async {
+            let mut releases = Vec::new();
+            for page in 1.. {
+                let url = format!(
+                    "{}/repos/{}/{}/releases?per_page={}&page={}",
+                    self.api_base(),
+                    owner,
+                    repo,
+                    PER_PAGE,
+                    page
+                );
+                let response =
+                    http::send("Listing releases", || self.request(Method::GET, &url)).await?;
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await?;
+                    debug!("Error response: {}", body);
+                    return Err(eyre::eyre!("Failed to list releases: HTTP status {status}"));
+                }
+                let page: Vec<Release> = response.json().await?;
+                let last_page = page.len() < PER_PAGE;
+                releases.extend(page);
+                if last_page {
+                    break;
+                }
+            }
+            Ok(releases)
+        })
+    }
 
-        let mut attempt = 0;
-        let mut last_error = None;
+    fn delete_release(&self, owner: &str, repo: &str, release_id: u64) -> eyre::Result<()> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/{}",
+            self.api_base(),
+            owner,
+            repo,
+            release_id
+        );
+        let status = http::block_on(http::send("Deleting release", || {
+            self.request(Method::DELETE, &url)
+        }))?
+        .status();
+        if !status.is_success() && status != 404 {
+            return Err(eyre::eyre!(
+                "Failed to delete release {release_id}: HTTP status {status}"
+            ));
+        }
+        Ok(())
+    }
 
-        while attempt < MAX_RETRIES {
-            attempt += 1;
+    fn dispatch_workflow(
+        &self,
+        owner: &str,
+        repo: &str,
+        workflow: &str,
+        git_ref: &str,
+        inputs: &serde_json::Map<String, Value>,
+    ) -> eyre::Result<()> {
+        let url = format!(
+            "{}/repos/{}/{}/actions/workflows/{}/dispatches",
+            self.api_base(),
+            owner,
+            repo,
+            workflow
+        );
+        http::block_on(async {
+            let response = http::send("Dispatching workflow", || {
+                self.request(Method::POST, &url)
+                    .json(&serde_json::json!({ "ref": git_ref, "inputs": inputs }))
+            })
+            .await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to dispatch {workflow} on {git_ref}: HTTP status {status}: {body}"
+                ));
+            }
+            Ok(())
+        })
+    }
 
-            if attempt > 1 {
-                info!("🔄 Retry attempt {} of {}...", attempt, MAX_RETRIES);
-                let jitter = rand::random::<u64>() % 1000; // Random jitter between 0-999ms
-                let delay = BASE_RETRY_DELAY_MS + jitter;
-                std::thread::sleep(std::time::Duration::from_millis(delay));
+    fn list_workflow_runs(
+        &self,
+        owner: &str,
+        repo: &str,
+        workflow: &str,
+        git_ref: &str,
+    ) -> eyre::Result<Vec<WorkflowRun>> {
+        let url = format!(
+            "{}/repos/{}/{}/actions/workflows/{}/runs?event=workflow_dispatch&branch={}&per_page=100",
+            self.api_base(),
+            owner,
+            repo,
+            workflow,
+            git_ref
+        );
+        http::block_on(async {
+            let response =
+                http::send("Listing workflow runs", || self.request(Method::GET, &url)).await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await?;
+                debug!("Error response: {}", body);
+                return Err(eyre::eyre!(
+                    "Failed to list workflow runs: HTTP status {status}"
+                ));
             }
+            let body: Value = response.json().await?;
+            let runs = body["workflow_runs"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            Ok(runs
+                .iter()
+                .filter_map(|run| {
+                    let state = match (run["status"].as_str()?, run["conclusion"].as_str()) {
+                        ("completed", Some("success")) => RunState::Succeeded,
+                        ("completed", _) => RunState::Failed,
+                        _ => RunState::Pending,
+                    };
+                    Some(WorkflowRun {
+                        id: run["id"].as_u64()?,
+                        state,
+                        url: run["html_url"].as_str().unwrap_or_default().to_string(),
+                    })
+                })
+                .collect())
+        })
+    }
+}
 
-            match self
-                .client
-                .post(&upload_url)
-                .header("Accept", "application/vnd.github+json")
-                .header("Authorization", format!("token {}", self.token))
-                .header("X-GitHub-Api-Version", "2022-11-28")
-                .header("User-Agent", USER_AGENT)
-                .header("Content-Type", "application/octet-stream")
-                .body(file_content.to_vec())
-                .send()
-            {
-                Ok(response) => {
-                    info!(
-                        "🔢 Response status code: {}",
-                        format!("{}", response.status()).blue()
-                    );
-
-                    let status = response.status();
-                    let response_text = response.text()?;
-                    info!("{}", "----------------------------------------".yellow());
-                    info!("📄 {}", "Response Data:".yellow());
-                    info!("{}", "----------------------------------------".yellow());
-                    info!("{}", response_text);
-                    info!("{}", "----------------------------------------".yellow());
-
-                    // If successful or not a 5xx error, break out of retry loop
-                    if status.is_success() || !status.is_server_error() {
-                        if !status.is_success() {
-                            return Err(eyre::eyre!(
-                                "❌ Upload failed with status code: {}",
-                                status
-                            ));
-                        }
-
-                        let upload_time = upload_start.elapsed().as_millis() as u64;
-                        info!(
-                            "✅ Package upload completed ({})",
-                            format!("{}ms", upload_time).green()
-                        );
-                        return Ok(());
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_forge::{MockForge, Response};
+    use serde_json::json;
 
-                    // If we get here, it's a 5xx error and we'll retry
-                    last_error = Some(eyre::eyre!("Server error with status code: {}", status));
-                }
-                Err(e) => {
-                    last_error = Some(eyre::eyre!("Request error: {}", e));
-                }
-            }
+    #[test]
+    fn test_can_push() {
+        let forge = MockForge::start();
+        forge.mock(
+            "GET",
+            "/repos/bearcove/homebrew-tap",
+            Response::json(200, json!({"permissions": {"pull": true, "push": true}})),
+        );
+        forge.mock(
+            "GET",
+            "/repos/bearcove/readonly-tap",
+            Response::json(200, json!({"permissions": {"pull": true, "push": false}})),
+        );
+        forge.mock(
+            "GET",
+            "/repos/bearcove/private-tap",
+            Response::json(404, json!({"message": "Not Found"})),
+        );
+
+        let client = GitHubClient::new(forge.url().to_string(), "secret".to_string());
+        assert!(client.can_push("bearcove", "homebrew-tap").unwrap());
+        assert!(!client.can_push("bearcove", "readonly-tap").unwrap());
+        assert!(!client.can_push("bearcove", "private-tap").unwrap());
+        assert_eq!(
+            forge.requests()[0].header("Authorization"),
+            Some("token secret")
+        );
+    }
+
+    #[test]
+    fn test_create_pull_request_reuses_open_one() {
+        let forge = MockForge::start();
+        forge.mock(
+            "POST",
+            "/repos/bearcove/homebrew-tap/pulls",
+            Response::json(
+                422,
+                json!({
+                    "message": "Validation Failed",
+                    "errors": [{"message": "A pull request already exists for bearcove:beardist/bump-foo-1.2.3."}]
+                }),
+            ),
+        );
+        forge.mock(
+            "GET",
+            "/repos/bearcove/homebrew-tap/pulls",
+            Response::json(
+                200,
+                json!([{"html_url": "https://github.com/bearcove/homebrew-tap/pull/7"}]),
+            ),
+        );
+
+        let client = GitHubClient::new(forge.url().to_string(), "secret".to_string());
+        let url = client
+            .create_pull_request(
+                "bearcove",
+                "homebrew-tap",
+                "beardist/bump-foo-1.2.3",
+                "main",
+                "Bump foo",
+                "",
+            )
+            .unwrap();
+        assert_eq!(url, "https://github.com/bearcove/homebrew-tap/pull/7");
+        let lookup = &forge.requests_to("GET", "/repos/bearcove/homebrew-tap/pulls")[0];
+        assert!(
+            lookup
+                .query
+                .as_deref()
+                .unwrap()
+                .contains("head=bearcove%3Abeardist%2Fbump-foo-1.2.3")
+        );
+    }
 
-            warn!("📶 Upload attempt {} failed, retrying...", attempt);
+    #[test]
+    fn test_release_flow_against_mock_forge() {
+        let forge = MockForge::start();
+        forge.mock(
+            "GET",
+            "/repos/bearcove/beardist/releases/tags/v1.0.0",
+            Response::json(404, json!({"message": "Not Found"})),
+        );
+        forge.mock_sequence(
+            "POST",
+            "/repos/bearcove/beardist/releases",
+            vec![
+                Response::status(502),
+                Response::json(201, json!({"id": 42})),
+            ],
+        );
+        forge.mock_sequence(
+            "POST",
+            "/repos/bearcove/beardist/releases/42/assets",
+            vec![
+                Response::status(503),
+                Response::json(
+                    201,
+                    json!({"id": 7, "name": "x86_64-unknown-linux-gnu.tar.xz"}),
+                ),
+            ],
+        );
+
+        let client = GitHubClient::new(forge.url().to_string(), "secret".to_string());
+        let release_id = client
+            .create_release("bearcove", "beardist", "v1.0.0", false)
+            .unwrap();
+        assert_eq!(release_id, 42);
+
+        client
+            .upload_artifact(
+                "bearcove",
+                "beardist",
+                release_id,
+                "x86_64-unknown-linux-gnu.tar.xz",
+                Some("Linux x86_64 (glibc)"),
+                UploadSource::Bytes(b"not really an archive"),
+            )
+            .unwrap();
+
+        let uploads = forge.requests_to("POST", "/repos/bearcove/beardist/releases/42/assets");
+        assert_eq!(uploads.len(), 2, "the 503 should have been retried");
+        for upload in &uploads {
+            assert_eq!(
+                upload.query.as_deref(),
+                Some("name=x86_64-unknown-linux-gnu.tar.xz&label=Linux+x86_64+%28glibc%29")
+            );
+            assert_eq!(upload.header("content-type"), Some("application/x-xz"));
+            assert_eq!(upload.header("authorization"), Some("token secret"));
+            assert_eq!(upload.body, b"not really an archive");
         }
+        let created = forge.requests_to("POST", "/repos/bearcove/beardist/releases");
+        assert_eq!(created.len(), 2);
+        let body: Value = serde_json::from_slice(&created[1].body).unwrap();
+        assert_eq!(body["tag_name"], "v1.0.0");
+
+        // Client errors aren't retried
+        forge.mock(
+            "POST",
+            "/repos/bearcove/beardist/releases/42/assets",
+            Response::status(422),
+        );
+        let result = client.upload_artifact(
+            "bearcove",
+            "beardist",
+            release_id,
+            "SHA256SUMS",
+            None,
+            UploadSource::Bytes(b""),
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            forge
+                .requests_to("POST", "/repos/bearcove/beardist/releases/42/assets")
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_move_tag_creates_missing_tags() {
+        let forge = MockForge::start();
+        forge.mock(
+            "PATCH",
+            "/repos/bearcove/beardist/git/refs/tags/nightly",
+            Response::json(422, json!({"message": "Reference does not exist"})),
+        );
+        forge.mock(
+            "POST",
+            "/repos/bearcove/beardist/git/refs",
+            Response::json(201, json!({"ref": "refs/tags/nightly"})),
+        );
+
+        let client = GitHubClient::new(forge.url().to_string(), "secret".to_string());
+        client
+            .move_tag("bearcove", "beardist", "nightly", "0123abcd")
+            .unwrap();
 
-        // If we get here, all retries failed
-        Err(last_error
-            .unwrap_or_else(|| eyre::eyre!("Upload failed after {} attempts", MAX_RETRIES)))
+        let patch =
+            &forge.requests_to("PATCH", "/repos/bearcove/beardist/git/refs/tags/nightly")[0];
+        let body: Value = serde_json::from_slice(&patch.body).unwrap();
+        assert_eq!(body, json!({"sha": "0123abcd", "force": true}));
+        let create = &forge.requests_to("POST", "/repos/bearcove/beardist/git/refs")[0];
+        let body: Value = serde_json::from_slice(&create.body).unwrap();
+        assert_eq!(body, json!({"ref": "refs/tags/nightly", "sha": "0123abcd"}));
     }
 }
@@ -0,0 +1,288 @@
+//! `beardist helm <tag>`: packages the project's chart from `charts/<name>` with its
+//! `appVersion` set to the release, optionally signs it, and pushes it to an OCI
+//! registry and/or a ChartMuseum-style chart repository (ChartMuseum, Harbor, Forgejo...).
+
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::{HelmArgs, USER_AGENT, command, credentials, load_config};
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HelmConfig {
+    /// Directory of the chart. Default: "charts/<name>"
+    #[serde(default)]
+    pub(crate) chart: Option<PathBuf>,
+
+    /// OCI registry to push the chart to, e.g. "oci://ghcr.io/bearcove/charts". Logs in
+    /// with `HELM_REGISTRY_USERNAME` and `HELM_REGISTRY_PASSWORD` if set.
+    #[serde(default)]
+    pub(crate) oci: Option<String>,
+
+    /// Chart repository with a ChartMuseum-style upload API (`POST <repo>/api/charts`),
+    /// e.g. "https://git.example.com/api/packages/bearcove/helm". Authenticates with
+    /// `HELM_REPO_USERNAME` and `HELM_REPO_PASSWORD` if set.
+    #[serde(default)]
+    pub(crate) repo: Option<String>,
+
+    /// Sign the chart with this GPG key (by name), producing a `.prov` file. The
+    /// passphrase is read from `HELM_KEY_PASSPHRASE`.
+    #[serde(default)]
+    pub(crate) sign_key: Option<String>,
+
+    /// Keyring holding `sign_key`. Default: "~/.gnupg/secring.gpg"
+    #[serde(default)]
+    pub(crate) keyring: Option<PathBuf>,
+}
+
+/// Arguments to `helm package`
+fn package_args(
+    chart_dir: &Path,
+    destination: &Path,
+    app_version: &str,
+    config: &HelmConfig,
+) -> Vec<String> {
+    let mut args = vec![
+        "package".to_string(),
+        chart_dir.display().to_string(),
+        "--destination".to_string(),
+        destination.display().to_string(),
+        "--app-version".to_string(),
+        app_version.to_string(),
+    ];
+    if let Some(key) = &config.sign_key {
+        args.extend(["--sign".to_string(), "--key".to_string(), key.clone()]);
+        if let Some(keyring) = &config.keyring {
+            args.extend(["--keyring".to_string(), keyring.display().to_string()]);
+        }
+    }
+    args
+}
+
+/// The registry host of an `oci://host/path` reference
+fn oci_host(reference: &str) -> eyre::Result<&str> {
+    reference
+        .strip_prefix("oci://")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| {
+            eyre::eyre!(
+                "Invalid OCI reference {:?}, expected e.g. \"oci://ghcr.io/org/charts\"",
+                reference
+            )
+        })
+}
+
+fn push_oci(package: &Path, reference: &str) -> eyre::Result<()> {
+    if let (Some(username), Some(password)) = (
+        credentials::token("HELM_REGISTRY_USERNAME"),
+        credentials::token("HELM_REGISTRY_PASSWORD"),
+    ) {
+        let host = oci_host(reference)?;
+        info!("Logging in to {}...", host.cyan());
+        let mut login = Command::new("helm")
+            .args(["registry", "login", host, "--username", &username])
+            .arg("--password-stdin")
+            .stdin(Stdio::piped())
+            .spawn()?;
+        login.stdin.take().unwrap().write_all(password.as_bytes())?;
+        let status = login.wait()?;
+        if !status.success() {
+            return Err(eyre::eyre!("helm registry login failed with {}", status));
+        }
+    }
+    let package = package.display().to_string();
+    command::run_command("helm", &["push", &package, reference], None)?;
+    info!("✅ Pushed the chart to {}", reference.cyan());
+    Ok(())
+}
+
+/// Uploads the chart (and its provenance file) with the ChartMuseum API
+fn push_repo(package: &Path, provenance: Option<&Path>, repo: &str) -> eyre::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let credentials = credentials::token("HELM_REPO_USERNAME")
+        .map(|username| -> eyre::Result<_> {
+            Ok((username, credentials::require("HELM_REPO_PASSWORD")?))
+        })
+        .transpose()?;
+    let repo = repo.trim_end_matches('/');
+    let uploads = std::iter::once((package, "charts")).chain(provenance.map(|p| (p, "prov")));
+    for (path, endpoint) in uploads {
+        let url = format!("{}/api/{}", repo, endpoint);
+        let mut request = client
+            .post(&url)
+            .header("User-Agent", USER_AGENT)
+            .header("Content-Type", "application/octet-stream")
+            .body(fs_err::read(path)?);
+        if let Some((username, password)) = &credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response = request.send()?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            debug!("Error response: {}", body);
+            return Err(eyre::eyre!(
+                "Uploading {} to {} failed with HTTP status {}",
+                path.display(),
+                url,
+                status
+            ));
+        }
+    }
+    info!("✅ Uploaded the chart to {}", repo.cyan());
+    Ok(())
+}
+
+pub(crate) fn helm(args: HelmArgs, config_path: Option<&Path>) -> eyre::Result<()> {
+    // Before loading the config moves us to the project's root
+    let output = args
+        .output
+        .as_deref()
+        .map(std::path::absolute)
+        .transpose()?;
+    let config = load_config(config_path)?;
+    let mut helm = config.helm.clone().unwrap_or_default();
+    if args.oci.is_some() {
+        helm.oci = args.oci;
+    }
+    if args.repo.is_some() {
+        helm.repo = args.repo;
+    }
+    if args.sign_key.is_some() {
+        helm.sign_key = args.sign_key;
+    }
+    let chart_dir = helm
+        .chart
+        .clone()
+        .unwrap_or_else(|| Path::new("charts").join(&config.name));
+    if !chart_dir.join("Chart.yaml").exists() {
+        return Err(eyre::eyre!(
+            "No chart at {} (set `helm.chart` in the config)",
+            chart_dir.display().to_string().cyan()
+        ));
+    }
+    let app_version = args
+        .tag
+        .strip_prefix(config.tag_prefix.as_deref().unwrap_or_default())
+        .unwrap_or(&args.tag);
+
+    let destination = tempfile::tempdir()?;
+    info!(
+        "📦 Packaging {} with appVersion {}",
+        chart_dir.display().cyan(),
+        app_version.cyan()
+    );
+    let package_args = package_args(&chart_dir, destination.path(), app_version, &helm);
+    let package_args: Vec<&str> = package_args.iter().map(String::as_str).collect();
+    command::run_command("helm", &package_args, None)?;
+
+    let mut package = None;
+    let mut provenance = None;
+    for entry in fs_err::read_dir(destination.path())? {
+        let path = entry?.path();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("tgz") => package = Some(path),
+            Some("prov") => provenance = Some(path),
+            _ => {}
+        }
+    }
+    let package = package.ok_or_else(|| eyre::eyre!("helm package produced no chart"))?;
+    info!("Packaged {}", package.display().cyan());
+    if let Some(output) = &output {
+        fs_err::create_dir_all(output)?;
+        for path in std::iter::once(&package).chain(provenance.as_ref()) {
+            fs_err::copy(path, output.join(path.file_name().unwrap()))?;
+        }
+    }
+
+    if helm.oci.is_none() && helm.repo.is_none() {
+        warn!("Nowhere to push the chart: set `helm.oci` or `helm.repo`");
+        return Ok(());
+    }
+    if std::env::var("DRY_RUN").is_ok() {
+        warn!("Not pushing the chart (dry run)");
+        return Ok(());
+    }
+    if let Some(reference) = &helm.oci {
+        push_oci(&package, reference)?;
+    }
+    if let Some(repo) = &helm.repo {
+        push_repo(&package, provenance.as_deref(), repo)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_forge::{MockForge, Response};
+
+    #[test]
+    fn test_package_and_upload() {
+        let config = HelmConfig {
+            sign_key: Some("releases@bearcove.eu".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            package_args(
+                Path::new("charts/home"),
+                Path::new("/tmp/out"),
+                "v1.2.3",
+                &config
+            ),
+            [
+                "package",
+                "charts/home",
+                "--destination",
+                "/tmp/out",
+                "--app-version",
+                "v1.2.3",
+                "--sign",
+                "--key",
+                "releases@bearcove.eu"
+            ]
+        );
+        assert_eq!(
+            oci_host("oci://ghcr.io/bearcove/charts").unwrap(),
+            "ghcr.io"
+        );
+        assert!(oci_host("ghcr.io/bearcove/charts").is_err());
+
+        let repo = MockForge::start();
+        repo.mock(
+            "POST",
+            "/helm/api/charts",
+            Response::json(201, serde_json::json!({})),
+        );
+        repo.mock(
+            "POST",
+            "/helm/api/prov",
+            Response::json(201, serde_json::json!({})),
+        );
+        let tmp = tempfile::tempdir().unwrap();
+        let package = tmp.path().join("home-0.1.0.tgz");
+        let provenance = tmp.path().join("home-0.1.0.tgz.prov");
+        fs_err::write(&package, "chart").unwrap();
+        fs_err::write(&provenance, "signature").unwrap();
+        push_repo(
+            &package,
+            Some(&provenance),
+            &format!("{}/helm/", repo.url()),
+        )
+        .unwrap();
+        assert_eq!(
+            repo.requests_to("POST", "/helm/api/charts")[0].body,
+            b"chart"
+        );
+        assert_eq!(
+            repo.requests_to("POST", "/helm/api/prov")[0].body,
+            b"signature"
+        );
+    }
+}
@@ -4,23 +4,44 @@ use convert_case::{Case, Casing};
 use eyre::Context;
 use log::*;
 use owo_colors::OwoColorize;
-use reqwest::blocking::Client;
-use std::{path::PathBuf, sync::Arc};
-use url::Url;
-
-use crate::{Indented, command::get_trimmed_cmd_stdout, github::GitHubClient, run_command};
-
+use std::path::{Path, PathBuf};
+
+use crate::{
+    Indented, IndentedWriter, UpdateTapArgs,
+    checksums::{self, Sha256Sums},
+    credentials,
+    error::{Classify, ErrorKind},
+    github::GitHubClient,
+    http,
+};
+
+use schemars::JsonSchema;
 use serde::Deserialize;
 
+mod check;
 #[cfg(test)]
 mod tests;
+mod validate;
 
-#[derive(Deserialize, Debug, Clone)]
-struct TapConfig {
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub(crate) struct TapConfig {
     formulas: Vec<Formula>,
+
+    /// Run `brew style --fix` and `brew audit --strict` on updated formulas, and don't
+    /// push them if they fail. Skipped (with a warning) when brew isn't installed.
+    #[serde(default)]
+    validate: bool,
+
+    /// Identity and message of the commits bumping formulas
+    #[serde(default)]
+    commit: crate::git::CommitConfig,
+
+    /// Push over "https" (the default) or "ssh"
+    #[serde(default)]
+    push: crate::git::Transport,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
 struct Formula {
     repo: String,
     homepage: String,
@@ -30,6 +51,163 @@ struct Formula {
 
     #[serde(default)]
     deps: Vec<String>,
+
+    /// How `brew livecheck` should track upstream versions
+    #[serde(default)]
+    livecheck: Livecheck,
+
+    /// Text shown to the user after installation (`def caveats`)
+    #[serde(default)]
+    caveats: Option<String>,
+
+    /// Formulas this one can't be installed alongside
+    #[serde(default)]
+    conflicts_with: Vec<Conflict>,
+
+    /// Reason for `keg_only`: either a symbol like ":provided_by_macos" or free text
+    #[serde(default)]
+    keg_only: Option<String>,
+
+    /// `service do` block, for formulas that ship a daemon
+    #[serde(default)]
+    service: Option<Service>,
+
+    /// Hold the formula at this version instead of following the latest release
+    #[serde(default)]
+    pin: Option<String>,
+
+    /// Use the `universal-apple-darwin` archive on macOS, which works on Intel Macs too
+    #[serde(default)]
+    universal_macos: bool,
+
+    /// Let people build from the repository with `brew install --HEAD`
+    #[serde(default)]
+    head: Option<Head>,
+
+    /// Known-bad versions, never written into the formula: when the latest release is one
+    /// of them, the newest release that isn't is used instead
+    #[serde(default)]
+    skip_versions: Vec<String>,
+}
+
+/// How to build a formula from its repository, for `head do`
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Head {
+    /// Git URL to clone (default: the formula's repository)
+    #[serde(default)]
+    url: Option<String>,
+
+    /// Branch to build (default: the repository's default branch)
+    #[serde(default)]
+    branch: Option<String>,
+
+    #[serde(default)]
+    build_system: BuildSystem,
+
+    /// With cargo, the directory of the package to install, relative to the repository
+    /// (default: its root)
+    #[serde(default)]
+    path: Option<String>,
+}
+
+/// What builds a formula from source
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum BuildSystem {
+    /// `cargo install`, with Rust as a build dependency
+    #[default]
+    Cargo,
+    /// `make install PREFIX=...`
+    Make,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Conflict {
+    /// Name of the conflicting formula
+    name: String,
+
+    /// Why they conflict (required by `brew audit`)
+    because: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Service {
+    /// Command to run: the first element is one of our bins, the rest are arguments
+    run: Vec<String>,
+
+    #[serde(default)]
+    keep_alive: bool,
+
+    /// Working directory for the service
+    #[serde(default)]
+    working_dir: Option<String>,
+
+    /// Log file name, relative to `var/log`
+    #[serde(default)]
+    log_path: Option<String>,
+
+    /// Error log file name, relative to `var/log`
+    #[serde(default)]
+    error_log_path: Option<String>,
+}
+
+/// Configuration for the `livecheck do` block emitted in the formula
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Livecheck {
+    /// Livecheck strategy, e.g. "github_latest", "github_releases", "git"
+    #[serde(default = "default_livecheck_strategy")]
+    strategy: String,
+
+    /// URL to check: either a symbol like ":stable" / ":homepage", or a literal URL
+    #[serde(default = "default_livecheck_url")]
+    url: String,
+
+    /// Optional regex (without slashes) used to extract the version
+    #[serde(default)]
+    regex: Option<String>,
+
+    /// If set, emit `skip "<reason>"` instead of a strategy
+    #[serde(default)]
+    skip: Option<String>,
+}
+
+impl Default for Livecheck {
+    fn default() -> Self {
+        Self {
+            strategy: default_livecheck_strategy(),
+            url: default_livecheck_url(),
+            regex: None,
+            skip: None,
+        }
+    }
+}
+
+fn default_livecheck_strategy() -> String {
+    "github_latest".to_string()
+}
+
+fn default_livecheck_url() -> String {
+    ":stable".to_string()
+}
+
+/// Where formulas' releases are looked up and downloaded from
+#[derive(Debug, Clone)]
+struct Endpoints {
+    api_url: String,
+    server_url: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            api_url: "https://api.github.com".to_string(),
+            server_url: "https://github.com".to_string(),
+        }
+    }
 }
 
 struct Binaries {
@@ -52,20 +230,58 @@ impl Formula {
         Utf8PathBuf::from(format!("Formula/{}.rb", self.name()))
     }
 
-    fn github_version(
+    async fn github_version(
         &self,
-        _config: &TapConfig,
+        endpoints: &Endpoints,
         github_token: &str,
     ) -> eyre::Result<Option<String>> {
-        let github_client = GitHubClient::new(
-            "https://api.github.com".to_string(),
-            github_token.to_string(),
+        let github_client = GitHubClient::new(endpoints.api_url.clone(), github_token.to_string());
+        let latest = github_client
+            .latest_release_version(self.org(), self.name())
+            .await?;
+        let latest = match latest {
+            Some(latest) if self.skips(&latest) => latest,
+            latest => return Ok(latest),
+        };
+
+        warn!(
+            "Latest release of {} is {}, which is in skip_versions",
+            self.name().cyan(),
+            latest.red()
         );
-        github_client.get_latest_release_version(self.org(), self.name())
+        let fallback = github_client
+            .release_versions(self.org(), self.name())
+            .await?
+            .into_iter()
+            .find(|version| !self.skips(version));
+        match &fallback {
+            Some(version) => warn!(
+                "Using {} for {} instead",
+                version.bright_yellow(),
+                self.name().cyan()
+            ),
+            None => warn!(
+                "No release of {} outside skip_versions, leaving it alone",
+                self.name().cyan()
+            ),
+        }
+        Ok(fallback)
+    }
+
+    /// Whether `version` is one of the versions to skip
+    fn skips(&self, version: &str) -> bool {
+        self.skip_versions
+            .iter()
+            .any(|skipped| skipped.trim_start_matches('v') == version.trim_start_matches('v'))
     }
 
     fn formula_version(&self) -> Option<String> {
-        let disk_path = self.disk_path();
+        self.formula_version_in(Path::new("."))
+    }
+
+    /// The version of the formula in the tap at `tap_dir`, if it's been written
+    fn formula_version_in(&self, tap_dir: &Path) -> Option<String> {
+        let disk_path = tap_dir.join(self.disk_path());
         if !disk_path.exists() {
             return None;
         }
@@ -83,19 +299,18 @@ struct Binary {
     sha256: String,
 }
 
-#[derive(Clone)]
 struct HomebrewContext {
-    client: Arc<Client>,
     dry_run: bool,
     formula: Formula,
     new_version: String,
+    endpoints: Endpoints,
 }
 
 impl HomebrewContext {
     fn new(
-        client: Arc<Client>,
         formula: Formula,
         github_version: String,
+        endpoints: Endpoints,
         dry_run: bool,
     ) -> eyre::Result<Option<Self>> {
         let formula_version = formula.formula_version();
@@ -111,58 +326,92 @@ impl HomebrewContext {
         }
 
         Ok(Some(Self {
-            client,
             dry_run,
             formula,
             new_version: github_version,
+            endpoints,
         }))
     }
 
-    fn get_binary(&self, url: &str) -> eyre::Result<Binary> {
+    async fn get_binary(&self, url: &str) -> eyre::Result<Binary> {
         Ok(Binary {
             url: url.to_string(),
-            sha256: self.fetch_and_hash(url)?,
+            sha256: checksums::fetch_and_hash(url, self.dry_run).await?,
         })
     }
 
+    /// Like `get_binary`, but uses the hash from `SHA256SUMS` if the release published one
+    async fn get_binary_with_sums(
+        &self,
+        url: &str,
+        sums: Option<&Sha256Sums>,
+    ) -> eyre::Result<Binary> {
+        let file_name = url.rsplit('/').next().unwrap_or(url);
+        if let Some(sha256) = sums.and_then(|sums| sums.get(file_name)) {
+            info!(
+                "Using published SHA256 for {}: {}",
+                file_name.cyan(),
+                sha256.green()
+            );
+            return Ok(Binary {
+                url: url.to_string(),
+                sha256: sha256.clone(),
+            });
+        }
+        self.get_binary(url).await
+    }
+
+    /// Fetches and parses the release's `SHA256SUMS` asset, if there is one
+    async fn fetch_sha256sums(&self) -> eyre::Result<Option<Sha256Sums>> {
+        checksums::fetch_sha256sums(&self.package_file_url("SHA256SUMS"), self.dry_run).await
+    }
+
     fn package_artifact_url(&self, arch: &str) -> String {
+        self.package_file_url(&format!("{}.tar.xz", arch))
+    }
+
+    fn package_file_url(&self, file_name: &str) -> String {
         format!(
-            "https://github.com/{}/{}/releases/download/v{}/{}.tar.xz",
+            "{}/{}/{}/releases/download/v{}/{}",
+            self.endpoints.server_url,
             self.formula.org(),
             self.formula.name(),
             self.new_version,
-            arch
+            file_name
         )
     }
 
-    fn update_formula(&self) -> eyre::Result<()> {
-        info!("Updating Homebrew {}...", "formula".bright_yellow());
-
+    /// Downloads (or looks up the hashes of) the archives the formula points to
+    async fn fetch_binaries(&self) -> eyre::Result<Binaries> {
         // Set up URLs for all architectures
-        let mac_url = self.package_artifact_url("aarch64-apple-darwin");
+        let mac_url = self.package_artifact_url(if self.formula.universal_macos {
+            crate::cargo::UNIVERSAL_MACOS_ARTIFACT
+        } else {
+            "aarch64-apple-darwin"
+        });
         let linux_x86_64_url = self.package_artifact_url("x86_64-unknown-linux-gnu");
         let linux_aarch64_url = self.package_artifact_url("aarch64-unknown-linux-gnu");
 
-        // Use threads to fetch binaries in parallel
-        let self_clone1 = self.clone();
-        let mac = std::thread::spawn(move || self_clone1.get_binary(&mac_url));
+        let sums = self.fetch_sha256sums().await?;
 
-        let self_clone2 = self.clone();
-        let linux_x86_64 = std::thread::spawn(move || self_clone2.get_binary(&linux_x86_64_url));
-
-        let self_clone3 = self.clone();
-        let linux_aarch64 = std::thread::spawn(move || self_clone3.get_binary(&linux_aarch64_url));
-
-        let mac = mac.join().unwrap();
-        let linux_x86_64 = linux_x86_64.join().unwrap();
-        let linux_aarch64 = linux_aarch64.join().unwrap();
+        // Fetch binaries concurrently
+        let (mac, linux_x86_64, linux_aarch64) = futures_util::future::try_join3(
+            self.get_binary_with_sums(&mac_url, sums.as_ref()),
+            self.get_binary_with_sums(&linux_x86_64_url, sums.as_ref()),
+            self.get_binary_with_sums(&linux_aarch64_url, sums.as_ref()),
+        )
+        .await?;
 
-        let binaries = Binaries {
-            mac: mac?,
-            linux_x86_64: linux_x86_64?,
-            linux_aarch64: linux_aarch64?,
-        };
+        Ok(Binaries {
+            mac,
+            linux_x86_64,
+            linux_aarch64,
+        })
+    }
 
+    async fn update_formula(&self) -> eyre::Result<()> {
+        info!("Updating Homebrew {}...", "formula".bright_yellow());
+        let binaries = self.fetch_binaries().await?;
         let formula = self.generate_homebrew_formula(binaries)?;
         let formula_path = self.formula.disk_path();
 
@@ -206,6 +455,20 @@ impl HomebrewContext {
             writeln!(w, "version \"{}\"", self.new_version)?;
             writeln!(w, "license \"{}\"", self.formula.license)?;
             writeln!(w)?;
+            if let Some(head) = &self.formula.head {
+                self.write_head(&mut w, head)?;
+                writeln!(w)?;
+            }
+            self.write_livecheck(&mut w)?;
+            writeln!(w)?;
+            if let Some(reason) = &self.formula.keg_only {
+                if reason.starts_with(':') {
+                    writeln!(w, "keg_only {}", reason)?;
+                } else {
+                    writeln!(w, "keg_only \"{}\"", reason)?;
+                }
+                writeln!(w)?;
+            }
             for dep in &self.formula.deps {
                 let parts: Vec<&str> = dep.split('#').collect();
                 let (name, keyword) = match parts.as_slice() {
@@ -230,6 +493,13 @@ impl HomebrewContext {
                     }
                 }
             }
+            for conflict in &self.formula.conflicts_with {
+                writeln!(
+                    w,
+                    "conflicts_with \"{}\", because: \"{}\"",
+                    conflict.name, conflict.because
+                )?;
+            }
             writeln!(w)?;
             writeln!(w, "if OS.mac?")?;
             {
@@ -260,56 +530,166 @@ impl HomebrewContext {
             writeln!(w, "def install")?;
             {
                 let mut w = w.indented();
-                for bin in &self.formula.bins {
-                    writeln!(w, "bin.install \"{}\"", bin)?;
+                match &self.formula.head {
+                    Some(head) => {
+                        writeln!(w, "if build.head?")?;
+                        Self::write_head_install(&mut w.indented(), head)?;
+                        writeln!(w, "else")?;
+                        self.write_binary_install(&mut w.indented())?;
+                        writeln!(w, "end")?;
+                    }
+                    None => self.write_binary_install(&mut w)?,
                 }
-                writeln!(w, "libexec.install Dir[\"lib*.dylib\"] if OS.mac?")?;
-                writeln!(w, "libexec.install Dir[\"lib*.so\"] if OS.linux?")?;
             }
             writeln!(w, "end")?;
+            if let Some(caveats) = &self.formula.caveats {
+                writeln!(w)?;
+                writeln!(w, "def caveats")?;
+                {
+                    let mut w = w.indented();
+                    writeln!(w, "<<~EOS")?;
+                    {
+                        let mut w = w.indented();
+                        for line in caveats.lines() {
+                            writeln!(w, "{}", line)?;
+                        }
+                    }
+                    writeln!(w, "EOS")?;
+                }
+                writeln!(w, "end")?;
+            }
+            if let Some(service) = &self.formula.service {
+                writeln!(w)?;
+                self.write_service(&mut w, service)?;
+            }
         }
         writeln!(w, "end")?;
 
         Ok(w)
     }
 
-    fn fetch_and_hash(&self, url: &str) -> eyre::Result<String> {
-        info!("Fetching binary from {}...", url.cyan());
-        if self.dry_run {
-            info!("Dry run: Would fetch {}", "binary".bright_yellow());
-            use sha2::{Digest, Sha256};
-            let mut hasher = Sha256::new();
-            hasher.update(url);
-            let sha256 = format!("{:x}", hasher.finalize());
-            return Ok(sha256);
+    /// Installs the prebuilt binaries (and the libraries shipped next to them)
+    fn write_binary_install(&self, w: &mut IndentedWriter) -> eyre::Result<()> {
+        use std::fmt::Write;
+
+        for bin in &self.formula.bins {
+            writeln!(w, "bin.install \"{}\"", bin)?;
         }
+        writeln!(w, "libexec.install Dir[\"lib*.dylib\"] if OS.mac?")?;
+        writeln!(w, "libexec.install Dir[\"lib*.so\"] if OS.linux?")?;
+        Ok(())
+    }
 
-        let response = self.client.get(url).send()?;
-        let status = response.status();
-        if status != 200 {
-            let error_text = response.text()?;
-            error!(
-                "Failed to fetch binary: HTTP status {}, Response: {}",
-                status.to_string().red(),
-                error_text.red()
-            );
+    fn write_head(&self, w: &mut IndentedWriter, head: &Head) -> eyre::Result<()> {
+        use std::fmt::Write;
+
+        let url = head
+            .url
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}.git", self.endpoints.server_url, self.formula.repo));
+        writeln!(w, "head do")?;
+        {
+            let mut w = w.indented();
+            match &head.branch {
+                Some(branch) => writeln!(w, "url \"{}\", branch: \"{}\"", url, branch)?,
+                None => writeln!(w, "url \"{}\"", url)?,
+            }
+            if head.build_system == BuildSystem::Cargo {
+                writeln!(w, "depends_on \"rust\" => :build")?;
+            }
+        }
+        writeln!(w, "end")?;
+        Ok(())
+    }
+
+    fn write_head_install(w: &mut IndentedWriter, head: &Head) -> eyre::Result<()> {
+        use std::fmt::Write;
+
+        match (head.build_system, &head.path) {
+            (BuildSystem::Cargo, Some(path)) => writeln!(
+                w,
+                "system \"cargo\", \"install\", *std_cargo_args(path: \"{}\")",
+                path
+            )?,
+            (BuildSystem::Cargo, None) => {
+                writeln!(w, "system \"cargo\", \"install\", *std_cargo_args")?
+            }
+            (BuildSystem::Make, _) => {
+                writeln!(w, "system \"make\", \"install\", \"PREFIX=#{{prefix}}\"")?
+            }
+        }
+        Ok(())
+    }
+
+    fn write_livecheck(&self, w: &mut IndentedWriter) -> eyre::Result<()> {
+        use std::fmt::Write;
+
+        let livecheck = &self.formula.livecheck;
+
+        writeln!(w, "livecheck do")?;
+        {
+            let mut w = w.indented();
+            if let Some(reason) = &livecheck.skip {
+                writeln!(w, "skip \"{}\"", reason)?;
+            } else {
+                if livecheck.url.starts_with(':') {
+                    writeln!(w, "url {}", livecheck.url)?;
+                } else {
+                    writeln!(w, "url \"{}\"", livecheck.url)?;
+                }
+                if let Some(regex) = &livecheck.regex {
+                    writeln!(w, "regex(/{}/i)", regex)?;
+                }
+                writeln!(w, "strategy :{}", livecheck.strategy)?;
+            }
+        }
+        writeln!(w, "end")?;
+        Ok(())
+    }
+
+    fn write_service(&self, w: &mut IndentedWriter, service: &Service) -> eyre::Result<()> {
+        use std::fmt::Write;
+
+        let (bin, args) = service
+            .run
+            .split_first()
+            .ok_or_else(|| eyre::eyre!("Service `run` must name at least a binary"))?;
+        if !self.formula.bins.contains(bin) {
             return Err(eyre::eyre!(
-                "Failed to fetch binary: HTTP status {}",
-                status
+                "Service binary '{}' is not one of the formula's bins: {:?}",
+                bin,
+                self.formula.bins
             ));
         }
-        let bytes = response.bytes()?;
-        let byte_count = bytes.len();
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(&bytes);
-        let sha256 = format!("{:x}", hasher.finalize());
-        info!(
-            "Binary fetched ({} bytes) and SHA256 {}",
-            byte_count.to_string().green(),
-            "computed".green()
-        );
-        Ok(sha256)
+
+        writeln!(w, "service do")?;
+        {
+            let mut w = w.indented();
+            if args.is_empty() {
+                writeln!(w, "run opt_bin/\"{}\"", bin)?;
+            } else {
+                let args = args
+                    .iter()
+                    .map(|arg| format!("\"{}\"", arg))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                writeln!(w, "run [opt_bin/\"{}\", {}]", bin, args)?;
+            }
+            if service.keep_alive {
+                writeln!(w, "keep_alive true")?;
+            }
+            if let Some(working_dir) = &service.working_dir {
+                writeln!(w, "working_dir \"{}\"", working_dir)?;
+            }
+            if let Some(log_path) = &service.log_path {
+                writeln!(w, "log_path var/\"log/{}\"", log_path)?;
+            }
+            if let Some(error_log_path) = &service.error_log_path {
+                writeln!(w, "error_log_path var/\"log/{}\"", error_log_path)?;
+            }
+        }
+        writeln!(w, "end")?;
+        Ok(())
     }
 }
 
@@ -321,150 +701,267 @@ fn load_tap_config() -> eyre::Result<TapConfig> {
             config_path.display().to_string().cyan()
         )
     })?;
-    let config: TapConfig = serde_json::from_str(&config_str).wrap_err_with(|| {
+    let config = parse_tap_config(&config_str).wrap_err_with(|| {
         format!(
-            "Failed to parse config file at {}",
+            "Invalid tap config at {}",
             config_path.display().to_string().cyan()
         )
-    })?;
-    Ok(config)
+    });
+    config.classify(ErrorKind::Config)
 }
 
-pub(crate) fn update_tap() -> eyre::Result<()> {
-    let dry_run = std::env::var("DRY_RUN").is_ok();
-    if dry_run {
-        info!("Dry run {}", "enabled".bright_yellow());
-    }
-    let github_token =
-        std::env::var("GITHUB_TOKEN").expect("GITHUB_TOKEN environment variable not set");
-
-    info!("Loading tap {}...", "configuration".cyan());
-    let config = load_tap_config()?;
-    info!("Tap configuration loaded {}", "successfully".green());
+/// The version the tap at `tap_dir` has of the formula for `repo` (e.g.
+/// "bearcove/beardist"): `None` if the tap has no such formula, or hasn't written it yet
+pub(crate) fn tap_version(tap_dir: &Path, repo: &str) -> eyre::Result<Option<String>> {
+    let config_path = tap_dir.join(".beardist-tap.json");
+    let source = fs_err::read_to_string(&config_path)?;
+    let config = parse_tap_config(&source)
+        .wrap_err_with(|| format!("Invalid tap config at {}", config_path.display()))
+        .classify(ErrorKind::Config)?;
+    Ok(config
+        .formulas
+        .iter()
+        .find(|formula| formula.repo == repo)
+        .and_then(|formula| formula.formula_version_in(tap_dir)))
+}
 
-    let client = Arc::new(Client::new());
+/// Parses the tap config, then checks it: problems are all reported at once
+fn parse_tap_config(source: &str) -> eyre::Result<TapConfig> {
+    let deserializer = &mut serde_json::Deserializer::from_str(source);
+    let config: TapConfig = serde_path_to_error::deserialize(deserializer)
+        .map_err(|e| eyre::eyre!("{}: {}", e.path(), e.inner()))?;
+    let problems = check::check(&config, source);
+    if !problems.is_empty() {
+        return Err(eyre::eyre!(
+            "{} problems:\n{}",
+            problems.len(),
+            problems
+                .iter()
+                .map(|problem| format!("  - {}", problem))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ));
+    }
+    Ok(config)
+}
 
-    info!("Processing {}...", "formulas".bright_yellow());
-    let mut bumped_formulas = Vec::new();
-    for (index, formula) in config.formulas.iter().enumerate() {
+/// Brings a single formula up to date, returning the new version if it was bumped
+async fn process_formula(
+    endpoints: &Endpoints,
+    formula: &Formula,
+    github_token: &str,
+    dry_run: bool,
+) -> eyre::Result<Option<String>> {
+    let github_version = if let Some(pin) = &formula.pin {
         info!(
-            "Processing formula {} of {}: {}",
-            (index + 1).to_string().cyan(),
-            config.formulas.len().to_string().cyan(),
-            formula.name().cyan()
+            "{} is pinned to version {}, not checking GitHub",
+            formula.name().cyan(),
+            pin.bright_yellow()
         );
-
+        pin.clone()
+    } else {
         info!("Fetching GitHub {}...", "version".cyan());
-        let github_version = formula.github_version(&config, &github_token)?;
-        let github_version = match github_version {
+        match formula.github_version(endpoints, github_token).await? {
             Some(version) => version,
             None => {
                 info!("No version found for {}, skipping", formula.name().cyan());
-                continue;
+                return Ok(None);
             }
-        };
+        }
+    };
+
+    info!(
+        "Target version for {}: {}",
+        formula.name().cyan(),
+        github_version.green()
+    );
+
+    let context = HomebrewContext::new(
+        formula.clone(),
+        github_version.clone(),
+        endpoints.clone(),
+        dry_run,
+    )?;
+
+    if let Some(context) = context {
+        info!("Updating formula for {}...", formula.name().bright_yellow());
+        context.update_formula().await?;
+        info!(
+            "Formula update completed for {}",
+            formula.name().bright_green()
+        );
+        Ok(Some(github_version))
+    } else {
+        info!("No update needed for {}", formula.name().bright_blue());
+        Ok(None)
+    }
+}
 
-        info!("GitHub version: {}", github_version.green());
+pub(crate) fn update_tap(args: UpdateTapArgs) -> eyre::Result<()> {
+    let dry_run = std::env::var("DRY_RUN").is_ok();
+    if dry_run {
+        info!("Dry run {}", "enabled".bright_yellow());
+    }
+    // Version checks only need to read; pushing needs write access to the tap
+    let tokens = Tokens {
+        read: credentials::require_or("GITHUB_READ_TOKEN", "GITHUB_TOKEN")?,
+        push: credentials::require_or("GITHUB_PUSH_TOKEN", "GITHUB_TOKEN")?,
+    };
+    let tap_dirs = if args.repos.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        args.repos.clone()
+    };
+    if !dry_run {
+        check_push_access(&tokens.push, &tap_dirs)?;
+    }
 
-        let context = HomebrewContext::new(
-            client.clone(),
-            formula.clone(),
-            github_version.clone(),
-            dry_run,
-        )?;
+    if args.repos.is_empty() {
+        return update_one_tap(&args, &tokens, dry_run);
+    }
 
-        if let Some(context) = context {
-            info!("Updating formula for {}...", formula.name().bright_yellow());
-            context.update_formula()?;
-            info!(
-                "Formula update completed for {}",
-                formula.name().bright_green()
-            );
-            bumped_formulas.push((formula.name().to_string(), github_version));
-        } else {
-            info!("No update needed for {}", formula.name().bright_blue());
+    // Each tap is committed and pushed on its own: one failing doesn't hold back the
+    // others
+    let start_dir = std::env::current_dir()?;
+    let mut failed = Vec::new();
+    for repo in &args.repos {
+        info!("🍺 Updating the tap in {}", repo.display().cyan());
+        let result = std::env::set_current_dir(start_dir.join(repo))
+            .map_err(eyre::Report::from)
+            .and_then(|()| update_one_tap(&args, &tokens, dry_run));
+        if let Err(e) = result {
+            error!("Failed to update the tap in {}: {:?}", repo.display(), e);
+            failed.push(repo.display().to_string());
         }
     }
-    info!("All formulas {}", "processed".bright_green());
+    std::env::set_current_dir(&start_dir)?;
+    if !failed.is_empty() {
+        return Err(eyre::eyre!(
+            "{} of {} taps failed to update: {}",
+            failed.len(),
+            args.repos.len(),
+            failed.join(", ")
+        ));
+    }
+    Ok(())
+}
 
-    if !bumped_formulas.is_empty() {
-        let commit_message = bumped_formulas
-            .iter()
-            .map(|(name, version)| format!("{} to {}", name, version))
-            .collect::<Vec<String>>()
-            .join(", ");
-
-        let full_commit_message = format!("Bump formulas: {}", commit_message);
-
-        info!("Committing changes...");
-        if !dry_run {
-            run_command("git", &["add", "."], None)?;
-            run_command(
-                "git",
-                &["commit", "-m", &full_commit_message],
-                Some(indexmap::indexmap! {
-                    "GIT_AUTHOR_NAME".to_string() => "beardist".to_string(),
-                    "GIT_AUTHOR_EMAIL".to_string() => "amos@bearcove.eu".to_string(),
-                    "GIT_COMMITTER_NAME".to_string() => "beardist".to_string(),
-                    "GIT_COMMITTER_EMAIL".to_string() => "amos@bearcove.eu".to_string(),
-                }),
-            )?;
-            info!("Changes committed successfully");
-        } else {
-            info!(
-                "Dry run: Would commit changes with message: {}",
-                full_commit_message.cyan()
-            );
+/// GitHub tokens for `update-tap`
+struct Tokens {
+    /// For API reads: release versions, archives
+    read: String,
+
+    /// For pushing the bumps, and opening pull requests
+    push: String,
+}
+
+/// Makes sure `push_token` can push to every tap, before any of them gets updated
+fn check_push_access(push_token: &str, tap_dirs: &[PathBuf]) -> eyre::Result<()> {
+    let client = GitHubClient::new(Endpoints::default().api_url, push_token.to_string());
+    for dir in tap_dirs {
+        // Taps pushed over SSH don't push with the token
+        let pushes_over_ssh = fs_err::read_to_string(dir.join(".beardist-tap.json"))
+            .ok()
+            .and_then(|source| serde_json::from_str::<TapConfig>(&source).ok())
+            .is_some_and(|config| config.push == crate::git::Transport::Ssh);
+        if pushes_over_ssh {
+            continue;
+        }
+        let (_, org_repo) = crate::git::push_remote(dir)?;
+        let (owner, repo) = org_repo
+            .split_once('/')
+            .ok_or_else(|| eyre::eyre!("Can't tell which repository {} is", dir.display()))?;
+        if !client.can_push(owner, repo)? {
+            return Err(eyre::eyre!(
+                "The push token (GITHUB_PUSH_TOKEN, or GITHUB_TOKEN) can't push to {}: it needs write access to its contents (or the `repo` scope, for classic tokens)",
+                org_repo
+            ))
+            .classify(ErrorKind::Config);
         }
+        debug!("Push token can push to {}", org_repo.cyan());
+    }
+    Ok(())
+}
 
-        info!("Formulas bumped:");
-        for (name, version) in bumped_formulas {
-            info!("  {} to version {}", name.cyan(), version.green());
+/// Updates the tap in the current directory
+fn update_one_tap(args: &UpdateTapArgs, tokens: &Tokens, dry_run: bool) -> eyre::Result<()> {
+    info!("Loading tap {}...", "configuration".cyan());
+    let config = load_tap_config()?;
+    info!("Tap configuration loaded {}", "successfully".green());
+
+    // With several taps, a formula only has to be in one of them
+    if args.repos.len() <= 1 {
+        for name in &args.only {
+            if !config.formulas.iter().any(|f| f.name() == name) {
+                return Err(eyre::eyre!(
+                    "--only {}: no such formula in the tap config",
+                    name.cyan()
+                ));
+            }
         }
+    }
+    let formulas: Vec<&Formula> = config
+        .formulas
+        .iter()
+        .filter(|f| args.only.is_empty() || args.only.iter().any(|name| name == f.name()))
+        .collect();
+
+    info!(
+        "Processing {} with {} jobs...",
+        "formulas".bright_yellow(),
+        args.jobs.max(1).to_string().cyan()
+    );
+    let total = formulas.len();
+    let endpoints = Endpoints::default();
+    let results = http::block_on(http::bounded(
+        args.jobs,
+        formulas.iter().enumerate().map(|(index, formula)| {
+            let endpoints = &endpoints;
+            async move {
+                info!(
+                    "Processing formula {} of {}: {}",
+                    (index + 1).to_string().cyan(),
+                    total.to_string().cyan(),
+                    formula.name().cyan()
+                );
+                process_formula(endpoints, formula, &tokens.read, dry_run).await
+            }
+        }),
+    ));
 
-        info!("Pushing changes...");
-        let remote_output = get_trimmed_cmd_stdout("git", &["remote", "-v"], None)?;
-        let remote_url = remote_output
-            .lines()
-            .find(|line| line.contains("(push)"))
-            .and_then(|line| {
-                let url = line.split_whitespace().nth(1)?;
-                // Convert SSH URLs to HTTPS URLs
-                if url.starts_with("git@github.com:") {
-                    let repo_path = url.trim_start_matches("git@github.com:");
-                    Some(format!("https://github.com/{}", repo_path))
-                } else {
-                    Some(url.to_string())
-                }
-            })
-            .ok_or_else(|| eyre::eyre!("Failed to get remote URL"))?;
-
-        let org_repo = remote_url
-            .trim_start_matches("https://")
-            .trim_end_matches(".git")
-            .split('/')
-            .skip(1)
-            .take(2)
-            .collect::<Vec<&str>>()
-            .join("/");
-
-        info!("Remote URL: {}", remote_url.cyan());
-        info!("Organization/Repo: {}", org_repo.cyan());
-
-        let mut push_url = Url::parse(&remote_url)?;
-        push_url.set_username("token").unwrap();
-        push_url.set_password(Some(&github_token)).unwrap();
-
-        if !dry_run {
-            run_command("git", &["push", push_url.as_str(), "HEAD:main"], None)?;
-            info!("Changes pushed successfully");
-        } else {
-            info!("Dry run: Would push changes to remote repository");
-            info!("Push command that would be executed:");
-            let mut redacted_url = push_url.clone();
-            redacted_url.set_password(Some("REDACTED")).unwrap();
-            info!("git push {} HEAD:main", redacted_url.to_string().cyan());
+    // Results come back in config order so the commit message is stable
+    let mut bumped_formulas = Vec::new();
+    for (formula, result) in formulas.iter().zip(results) {
+        let version = result
+            .wrap_err_with(|| format!("while processing formula {}", formula.name().cyan()))?;
+        if let Some(version) = version {
+            bumped_formulas.push((formula.name().to_string(), version));
         }
+    }
+    info!("All formulas {}", "processed".bright_green());
+
+    // Dry runs don't write the formulas, so there's nothing to validate
+    if config.validate && !dry_run {
+        let paths: Vec<Utf8PathBuf> = formulas
+            .iter()
+            .filter(|f| bumped_formulas.iter().any(|(name, _)| name == f.name()))
+            .map(|f| f.disk_path())
+            .collect();
+        validate::validate(&paths)?;
+    }
+
+    if !bumped_formulas.is_empty() {
+        crate::git::commit_and_push(crate::git::Publish {
+            kind: "formulas",
+            bumped: &bumped_formulas,
+            github_token: &tokens.push,
+            pr: args.pr,
+            base_branch: "main",
+            upstream: None,
+            dry_run,
+            commit: &config.commit,
+            transport: config.push,
+        })?;
     } else {
         info!("No formulas were bumped");
     }
@@ -0,0 +1,285 @@
+//! Checks on `.beardist-tap.json` beyond its shape, so mistakes show up (all at once)
+//! when the config is loaded, rather than as a broken formula after a release.
+
+use std::{collections::HashMap, fmt};
+
+use super::TapConfig;
+
+/// SPDX identifiers of licenses Homebrew formulas can declare. Not the whole SPDX list:
+/// the licenses software actually ships under, from <https://spdx.org/licenses/>.
+const SPDX_LICENSES: &[&str] = &[
+    "0BSD",
+    "AFL-3.0",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+    "Apache-1.1",
+    "Apache-2.0",
+    "APSL-2.0",
+    "Artistic-1.0",
+    "Artistic-2.0",
+    "BlueOak-1.0.0",
+    "BSD-1-Clause",
+    "BSD-2-Clause",
+    "BSD-2-Clause-Patent",
+    "BSD-3-Clause",
+    "BSD-3-Clause-Clear",
+    "BSD-4-Clause",
+    "BSL-1.0",
+    "BUSL-1.1",
+    "CC-BY-3.0",
+    "CC-BY-4.0",
+    "CC-BY-SA-3.0",
+    "CC-BY-SA-4.0",
+    "CC0-1.0",
+    "CDDL-1.0",
+    "CDDL-1.1",
+    "CECILL-2.1",
+    "ECL-2.0",
+    "EFL-2.0",
+    "Elastic-2.0",
+    "EPL-1.0",
+    "EPL-2.0",
+    "EUPL-1.1",
+    "EUPL-1.2",
+    "FSFAP",
+    "FTL",
+    "GPL-2.0-only",
+    "GPL-2.0-or-later",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "HPND",
+    "ICU",
+    "IJG",
+    "IPL-1.0",
+    "ISC",
+    "LGPL-2.0-only",
+    "LGPL-2.0-or-later",
+    "LGPL-2.1-only",
+    "LGPL-2.1-or-later",
+    "LGPL-3.0-only",
+    "LGPL-3.0-or-later",
+    "LPL-1.02",
+    "LPPL-1.3c",
+    "MIT",
+    "MIT-0",
+    "MIT-CMU",
+    "MPL-1.1",
+    "MPL-2.0",
+    "MPL-2.0-no-copyleft-exception",
+    "MS-PL",
+    "MS-RL",
+    "MulanPSL-2.0",
+    "NCSA",
+    "OFL-1.1",
+    "OpenSSL",
+    "OSL-3.0",
+    "PHP-3.01",
+    "PostgreSQL",
+    "PSF-2.0",
+    "Python-2.0",
+    "Ruby",
+    "SSPL-1.0",
+    "Sleepycat",
+    "Unicode-3.0",
+    "Unicode-DFS-2016",
+    "Unlicense",
+    "UPL-1.0",
+    "Vim",
+    "W3C",
+    "WTFPL",
+    "X11",
+    "Zlib",
+    "zlib-acknowledgement",
+    "ZPL-2.1",
+];
+
+/// Keywords that can follow a dependency's name, as in "openssl#optional"
+const DEPENDENCY_KEYWORDS: &[&str] = &["recommended", "optional"];
+
+/// Something wrong with the tap config
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Problem {
+    /// Line of the formula it's about, when we could find it
+    line: Option<usize>,
+
+    /// Path of the offending field, e.g. "formulas[2].license"
+    field: String,
+
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Lines (1-based) of each formula's `"repo"` key in `source`, in order
+fn formula_lines(source: &str) -> Vec<usize> {
+    let repo_key = regex::Regex::new(r#""repo"\s*:"#).unwrap();
+    repo_key
+        .find_iter(source)
+        .map(|m| source[..m.start()].matches('\n').count() + 1)
+        .collect()
+}
+
+fn valid_repo(repo: &str) -> bool {
+    let valid_part = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    matches!(repo.split('/').collect::<Vec<_>>().as_slice(), [org, name] if valid_part(org) && valid_part(name))
+}
+
+fn check_license(license: &str) -> Option<String> {
+    if SPDX_LICENSES.contains(&license) {
+        return None;
+    }
+    if let Some(id) = SPDX_LICENSES
+        .iter()
+        .find(|id| id.eq_ignore_ascii_case(license))
+    {
+        return Some(format!(
+            "{:?} isn't an SPDX identifier, did you mean {:?}?",
+            license, id
+        ));
+    }
+    if license.contains(['/', ' ']) {
+        return Some(format!(
+            "{:?} isn't a single SPDX identifier: pick one, e.g. {:?}",
+            license,
+            license.split(['/', ' ']).next().unwrap_or_default()
+        ));
+    }
+    Some(format!(
+        "{:?} isn't a known SPDX license identifier (see https://spdx.org/licenses/)",
+        license
+    ))
+}
+
+/// Checks the parsed config, whose JSON is `source`, returning every problem found
+pub(super) fn check(config: &TapConfig, source: &str) -> Vec<Problem> {
+    let lines = formula_lines(source);
+    let mut problems = Vec::new();
+    let mut names: HashMap<&str, usize> = HashMap::new();
+
+    for (index, formula) in config.formulas.iter().enumerate() {
+        let mut problem = |field: &str, message: String| {
+            problems.push(Problem {
+                line: lines.get(index).copied(),
+                field: format!("formulas[{}].{}", index, field),
+                message,
+            })
+        };
+
+        if valid_repo(&formula.repo) {
+            if let Some(first) = names.get(formula.name()) {
+                problem(
+                    "repo",
+                    format!(
+                        "another formula is named {:?} (formulas[{}])",
+                        formula.name(),
+                        first
+                    ),
+                );
+            } else {
+                names.insert(formula.name(), index);
+            }
+        } else {
+            problem(
+                "repo",
+                format!("{:?} should look like \"org/name\"", formula.repo),
+            );
+        }
+        if let Some(message) = check_license(&formula.license) {
+            problem("license", message);
+        }
+        if formula.bins.is_empty() {
+            problem(
+                "bins",
+                "no binaries: there'd be nothing to install".to_string(),
+            );
+        }
+        for (dep_index, dep) in formula.deps.iter().enumerate() {
+            let field = format!("deps[{}]", dep_index);
+            match dep.split('#').collect::<Vec<_>>().as_slice() {
+                [name] | [name, _] if name.is_empty() => {
+                    problem(&field, format!("{:?} has no formula name", dep))
+                }
+                [_] => {}
+                [_, keyword] if DEPENDENCY_KEYWORDS.contains(&keyword.trim()) => {}
+                [_, keyword] => problem(
+                    &field,
+                    format!(
+                        "unknown keyword {:?}, use one of: {}",
+                        keyword,
+                        DEPENDENCY_KEYWORDS.join(", ")
+                    ),
+                ),
+                _ => problem(
+                    &field,
+                    format!("{:?} should be \"name\" or \"name#keyword\"", dep),
+                ),
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reports_every_problem() {
+        let source = r#"{
+  "formulas": [
+    {
+      "repo": "bearcove/beardist",
+      "homepage": "https://github.com/bearcove/beardist",
+      "desc": "Builds and publishes Rust binaries",
+      "license": "Apache-2.0",
+      "bins": ["beardist"],
+      "deps": ["xz", "zstd#optional"]
+    },
+    {
+      "repo": "bearcove/beardist",
+      "homepage": "https://github.com/bearcove/beardist",
+      "desc": "Same again",
+      "license": "mit",
+      "bins": [],
+      "deps": ["openssl#required", "a#b#c"]
+    },
+    {
+      "repo": "home",
+      "homepage": "https://github.com/bearcove/home",
+      "desc": "Not a repo",
+      "license": "Apache-2.0/MIT",
+      "bins": ["home"]
+    }
+  ]
+}"#;
+        let config: TapConfig = serde_json::from_str(source).unwrap();
+        let problems: Vec<String> = check(&config, source)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                "line 12: formulas[1].repo: another formula is named \"beardist\" (formulas[0])",
+                "line 12: formulas[1].license: \"mit\" isn't an SPDX identifier, did you mean \"MIT\"?",
+                "line 12: formulas[1].bins: no binaries: there'd be nothing to install",
+                "line 12: formulas[1].deps[0]: unknown keyword \"required\", use one of: recommended, optional",
+                "line 12: formulas[1].deps[1]: \"a#b#c\" should be \"name\" or \"name#keyword\"",
+                "line 20: formulas[2].repo: \"home\" should look like \"org/name\"",
+                "line 20: formulas[2].license: \"Apache-2.0/MIT\" isn't a single SPDX identifier: pick one, e.g. \"Apache-2.0\"",
+            ]
+        );
+    }
+}
@@ -30,19 +30,32 @@ fn test_generate_and_audit_formula() -> eyre::Result<()> {
             license: "MIT".to_string(),
             bins: vec!["foobar".to_string()],
             deps: vec!["ffmpeg".to_string()],
+            livecheck: Default::default(),
+            caveats: None,
+            conflicts_with: vec![],
+            keg_only: None,
+            service: None,
+            pin: None,
+            universal_macos: false,
+            head: None,
+            skip_versions: vec![],
         }],
+        validate: false,
+        commit: Default::default(),
+        push: Default::default(),
     };
     let dry_run = true;
     info!("Generated sample config");
     let formula = homebrew_config.formulas.first().unwrap().clone();
-    let client = Arc::new(Client::new());
     let github_version = "8.0.0".to_string();
-    let context = HomebrewContext::new(client.clone(), formula, github_version, dry_run)?
+    let context = HomebrewContext::new(formula, github_version, Endpoints::default(), dry_run)?
         .expect("Failed to create HomebrewContext");
 
-    let mac_binary = context.get_binary("https://example.com/mac")?;
-    let linux_x86_64_binary = context.get_binary("https://example.com/linux-x86_64")?;
-    let linux_aarch64_binary = context.get_binary("https://example.com/linux-aarch64")?;
+    let mac_binary = http::block_on(context.get_binary("https://example.com/mac"))?;
+    let linux_x86_64_binary =
+        http::block_on(context.get_binary("https://example.com/linux-x86_64"))?;
+    let linux_aarch64_binary =
+        http::block_on(context.get_binary("https://example.com/linux-aarch64"))?;
 
     let binaries = Binaries {
         linux_x86_64: linux_x86_64_binary,
@@ -108,7 +121,253 @@ fn test_generate_and_audit_formula() -> eyre::Result<()> {
     info!("Brew audit passed successfully");
 
     // Do upload dry run
-    context.update_formula()?;
+    http::block_on(context.update_formula())?;
 
     Ok(())
 }
+
+fn sample_formula() -> Formula {
+    Formula {
+        repo: "testowner/foobar".to_string(),
+        homepage: "https://example.com".to_string(),
+        desc: "Sample generated by beardist".to_string(),
+        license: "MIT".to_string(),
+        bins: vec!["foobar".to_string()],
+        deps: vec![],
+        livecheck: Default::default(),
+        caveats: None,
+        conflicts_with: vec![],
+        keg_only: None,
+        service: None,
+        pin: None,
+        universal_macos: false,
+        head: None,
+        skip_versions: vec![],
+    }
+}
+
+fn generate(formula: Formula) -> eyre::Result<String> {
+    let context = HomebrewContext::new(formula, "8.0.0".to_string(), Endpoints::default(), true)?
+        .expect("Failed to create HomebrewContext");
+    let binaries = http::block_on(async {
+        eyre::Ok(Binaries {
+            mac: context.get_binary("https://example.com/mac").await?,
+            linux_x86_64: context
+                .get_binary("https://example.com/linux-x86_64")
+                .await?,
+            linux_aarch64: context
+                .get_binary("https://example.com/linux-aarch64")
+                .await?,
+        })
+    })?;
+    context.generate_homebrew_formula(binaries)
+}
+
+#[test]
+fn test_livecheck_default_strategy() -> eyre::Result<()> {
+    let content = generate(sample_formula())?;
+    assert!(
+        content.contains("  livecheck do\n    url :stable\n    strategy :github_latest\n  end\n")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_livecheck_custom_and_skip() -> eyre::Result<()> {
+    let mut formula = sample_formula();
+    formula.livecheck = Livecheck {
+        strategy: "github_releases".to_string(),
+        url: "https://github.com/testowner/foobar".to_string(),
+        regex: Some(r"^v?(\d+(?:\.\d+)+)$".to_string()),
+        skip: None,
+    };
+    let content = generate(formula)?;
+    assert!(content.contains("    url \"https://github.com/testowner/foobar\"\n"));
+    assert!(content.contains("    regex(/^v?(\\d+(?:\\.\\d+)+)$/i)\n"));
+    assert!(content.contains("    strategy :github_releases\n"));
+
+    let mut formula = sample_formula();
+    formula.livecheck.skip = Some("Private releases".to_string());
+    let content = generate(formula)?;
+    assert!(content.contains("  livecheck do\n    skip \"Private releases\"\n  end\n"));
+    assert!(!content.contains("strategy :"));
+    Ok(())
+}
+
+#[test]
+fn test_formula_snippets() -> eyre::Result<()> {
+    let mut formula = sample_formula();
+    formula.caveats = Some("Run `foobar init` first.\nThen enjoy.".to_string());
+    formula.conflicts_with = vec![Conflict {
+        name: "foobaz".to_string(),
+        because: "both install a `foobar` binary".to_string(),
+    }];
+    formula.keg_only = Some(":provided_by_macos".to_string());
+    formula.service = Some(Service {
+        run: vec!["foobar".to_string(), "serve".to_string()],
+        keep_alive: true,
+        working_dir: None,
+        log_path: Some("foobar.log".to_string()),
+        error_log_path: None,
+    });
+    let content = generate(formula)?;
+    assert!(content.contains("  keg_only :provided_by_macos\n"));
+    assert!(
+        content
+            .contains("  conflicts_with \"foobaz\", because: \"both install a `foobar` binary\"\n")
+    );
+    assert!(content.contains(
+        "  def caveats\n    <<~EOS\n      Run `foobar init` first.\n      Then enjoy.\n    EOS\n  end\n"
+    ));
+    assert!(content.contains(
+        "  service do\n    run [opt_bin/\"foobar\", \"serve\"]\n    keep_alive true\n    log_path var/\"log/foobar.log\"\n  end\n"
+    ));
+
+    let mut formula = sample_formula();
+    formula.service = Some(Service {
+        run: vec!["notours".to_string()],
+        keep_alive: false,
+        working_dir: None,
+        log_path: None,
+        error_log_path: None,
+    });
+    assert!(generate(formula).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_head_spec() -> eyre::Result<()> {
+    let mut formula = sample_formula();
+    formula.head = Some(Head {
+        url: None,
+        branch: Some("main".to_string()),
+        build_system: BuildSystem::Cargo,
+        path: Some("crates/foobar".to_string()),
+    });
+    let content = generate(formula)?;
+    assert!(content.contains(
+        "  head do\n    url \"https://github.com/testowner/foobar.git\", branch: \"main\"\n    depends_on \"rust\" => :build\n  end\n"
+    ));
+    assert!(content.contains(
+        "  def install\n    if build.head?\n      system \"cargo\", \"install\", *std_cargo_args(path: \"crates/foobar\")\n    else\n      bin.install \"foobar\"\n"
+    ));
+
+    let mut formula = sample_formula();
+    formula.head = Some(Head {
+        url: Some("https://example.com/foobar.git".to_string()),
+        branch: None,
+        build_system: BuildSystem::Make,
+        path: None,
+    });
+    let content = generate(formula)?;
+    assert!(content.contains("  head do\n    url \"https://example.com/foobar.git\"\n  end\n"));
+    assert!(content.contains("      system \"make\", \"install\", \"PREFIX=#{prefix}\"\n"));
+    Ok(())
+}
+
+/// The network half of `update-tap` against a mock forge: latest release lookup (through a
+/// 503), `SHA256SUMS`, and hashing the archive it doesn't cover
+#[test]
+fn test_fetch_binaries_against_mock_forge() -> eyre::Result<()> {
+    use crate::mock_forge::{MockForge, Response};
+
+    let forge = MockForge::start();
+    forge.mock_sequence(
+        "GET",
+        "/repos/testowner/foobar/releases/latest",
+        vec![
+            Response::status(503),
+            Response::json(200, serde_json::json!({"tag_name": "v8.1.0"})),
+        ],
+    );
+    let downloads = "/testowner/foobar/releases/download/v8.1.0";
+    let mac_sha256 = "a".repeat(64);
+    let linux_aarch64_sha256 = "b".repeat(64);
+    forge.mock(
+        "GET",
+        &format!("{downloads}/SHA256SUMS"),
+        Response::status(200).body(format!(
+            "{mac_sha256}  aarch64-apple-darwin.tar.xz\n{linux_aarch64_sha256}  aarch64-unknown-linux-gnu.tar.xz\n"
+        )),
+    );
+    forge.mock(
+        "GET",
+        &format!("{downloads}/x86_64-unknown-linux-gnu.tar.xz"),
+        Response::status(200).body("linux archive"),
+    );
+
+    let endpoints = Endpoints {
+        api_url: forge.url().to_string(),
+        server_url: forge.url().to_string(),
+    };
+    let formula = sample_formula();
+    let version = http::block_on(formula.github_version(&endpoints, "secret"))?;
+    assert_eq!(version.as_deref(), Some("8.1.0"));
+
+    let context = HomebrewContext::new(formula, "8.1.0".to_string(), endpoints, false)?
+        .expect("Failed to create HomebrewContext");
+    let binaries = http::block_on(context.fetch_binaries())?;
+    assert_eq!(binaries.mac.sha256, mac_sha256);
+    assert_eq!(binaries.linux_aarch64.sha256, linux_aarch64_sha256);
+    assert_eq!(
+        binaries.linux_x86_64.sha256,
+        checksums::sha256_hex(b"linux archive")
+    );
+    assert_eq!(
+        binaries.linux_x86_64.url,
+        format!("{}{downloads}/x86_64-unknown-linux-gnu.tar.xz", forge.url())
+    );
+    assert!(
+        forge
+            .requests_to("GET", &format!("{downloads}/aarch64-apple-darwin.tar.xz"))
+            .is_empty(),
+        "archives listed in SHA256SUMS shouldn't be downloaded"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_skip_versions() -> eyre::Result<()> {
+    use crate::mock_forge::{MockForge, Response};
+
+    let forge = MockForge::start();
+    forge.mock(
+        "GET",
+        "/repos/testowner/foobar/releases/latest",
+        Response::json(200, serde_json::json!({"tag_name": "v8.2.0"})),
+    );
+    forge.mock(
+        "GET",
+        "/repos/testowner/foobar/releases",
+        Response::json(
+            200,
+            serde_json::json!([
+                {"id": 4, "tag_name": "v8.2.0", "draft": false, "prerelease": false, "created_at": "2025-03-04T00:00:00Z"},
+                {"id": 3, "tag_name": "v8.2.0-rc.1", "draft": false, "prerelease": true, "created_at": "2025-03-03T00:00:00Z"},
+                {"id": 2, "tag_name": "v8.1.1", "draft": false, "prerelease": false, "created_at": "2025-03-02T00:00:00Z"},
+                {"id": 1, "tag_name": "v8.1.0", "draft": false, "prerelease": false, "created_at": "2025-03-01T00:00:00Z"},
+            ]),
+        ),
+    );
+    let endpoints = Endpoints {
+        api_url: forge.url().to_string(),
+        server_url: forge.url().to_string(),
+    };
+
+    let mut formula = sample_formula();
+    formula.skip_versions = vec!["v8.2.0".to_string(), "8.1.1".to_string()];
+    let version = http::block_on(formula.github_version(&endpoints, "secret"))?;
+    assert_eq!(version.as_deref(), Some("8.1.0"));
+
+    formula.skip_versions = vec!["8.1.0".to_string()];
+    let version = http::block_on(formula.github_version(&endpoints, "secret"))?;
+    assert_eq!(version.as_deref(), Some("8.2.0"));
+    assert_eq!(
+        forge
+            .requests_to("GET", "/repos/testowner/foobar/releases")
+            .len(),
+        1,
+        "releases are only listed when the latest one is skipped"
+    );
+    Ok(())
+}
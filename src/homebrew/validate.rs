@@ -0,0 +1,95 @@
+//! Checks updated formulas with Homebrew's own tools before they're pushed: `brew style`
+//! fixes what it can, and `brew audit --strict` catches the rest. `brew audit` only takes
+//! formulas from a tap, so they're copied to a scratch one first.
+
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::Context;
+use log::*;
+use owo_colors::OwoColorize;
+
+use crate::{
+    command,
+    error::{Classify, ErrorKind},
+};
+
+/// The scratch tap formulas are audited in
+const AUDIT_TAP: &str = "beardist/validate";
+
+/// Names to audit the formulas at `paths` by, once copied to the scratch tap
+fn audit_names(paths: &[Utf8PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .filter_map(|path| path.file_stem())
+        .map(|name| format!("{}/{}", AUDIT_TAP, name))
+        .collect()
+}
+
+/// Runs `brew style --fix`, then `brew audit --strict`, on the formulas at `paths`.
+/// Without `brew`, there's nothing to validate with: that's only a warning.
+pub(super) fn validate(paths: &[Utf8PathBuf]) -> eyre::Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    if command::get_trimmed_cmd_stdout("brew", &["--version"], None).is_err() {
+        warn!(
+            "{} isn't installed, not validating the formulas",
+            "brew".cyan()
+        );
+        return Ok(());
+    }
+
+    info!(
+        "🧐 Validating {} formulas...",
+        paths.len().to_string().cyan()
+    );
+    let mut args = vec!["style", "--fix"];
+    args.extend(paths.iter().map(|path| path.as_str()));
+    command::run_command("brew", &args, None)
+        .wrap_err("brew style found problems it couldn't fix")
+        .classify(ErrorKind::Policy)?;
+
+    // Left over from an interrupted run, maybe
+    let _ = command::get_cmd_stdout("brew", &["untap", "--force", AUDIT_TAP], None);
+    command::run_command("brew", &["tap-new", "--no-git", AUDIT_TAP], None)?;
+    let audited = audit(paths);
+    if let Err(e) = command::get_cmd_stdout("brew", &["untap", "--force", AUDIT_TAP], None) {
+        warn!("Couldn't remove the {} tap: {}", AUDIT_TAP.cyan(), e);
+    }
+    audited
+}
+
+fn audit(paths: &[Utf8PathBuf]) -> eyre::Result<()> {
+    let tap_dir = command::get_trimmed_cmd_stdout("brew", &["--repository", AUDIT_TAP], None)?;
+    let formula_dir = Utf8Path::new(&tap_dir).join("Formula");
+    fs_err::create_dir_all(&formula_dir)?;
+    for path in paths {
+        fs_err::copy(path, formula_dir.join(path.file_name().unwrap_or_default()))?;
+    }
+
+    let names = audit_names(paths);
+    let mut args = vec!["audit", "--strict", "--formula"];
+    args.extend(names.iter().map(String::as_str));
+    command::run_command("brew", &args, None)
+        .wrap_err("brew audit rejected the formulas, not pushing them")
+        .classify(ErrorKind::Policy)?;
+    info!("✅ Formulas passed {}", "brew audit --strict".cyan());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_names() {
+        let paths = [
+            Utf8PathBuf::from("Formula/beardist.rb"),
+            Utf8PathBuf::from("Formula/home.rb"),
+        ];
+        assert_eq!(
+            audit_names(&paths),
+            ["beardist/validate/beardist", "beardist/validate/home"]
+        );
+        assert!(validate(&[]).is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+//! Lifecycle hooks: external commands run at points of a build, configured in the
+//! `hooks` section of `.beardist.json`. Each gets a JSON description of the event on
+//! stdin, so teams can add custom steps (metrics, internal registries...) without
+//! forking beardist.
+
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HooksConfig {
+    /// Before building
+    #[serde(default)]
+    pub(crate) pre_build: Vec<Vec<String>>,
+
+    /// After building, with the files that will be packaged
+    #[serde(default)]
+    pub(crate) post_build: Vec<Vec<String>>,
+
+    /// Once the archive is created, before uploading it
+    #[serde(default)]
+    pub(crate) pre_upload: Vec<Vec<String>>,
+
+    /// After uploading, with where the archive went
+    #[serde(default)]
+    pub(crate) post_upload: Vec<Vec<String>>,
+
+    /// When any step fails, with the error. Failing `on_failure` hooks are only logged.
+    #[serde(default)]
+    pub(crate) on_failure: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HookEvent {
+    PreBuild,
+    PostBuild,
+    PreUpload,
+    PostUpload,
+    OnFailure,
+}
+
+impl HookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::PreBuild => "pre_build",
+            HookEvent::PostBuild => "post_build",
+            HookEvent::PreUpload => "pre_upload",
+            HookEvent::PostUpload => "post_upload",
+            HookEvent::OnFailure => "on_failure",
+        }
+    }
+}
+
+impl HooksConfig {
+    fn commands(&self, event: HookEvent) -> &[Vec<String>] {
+        match event {
+            HookEvent::PreBuild => &self.pre_build,
+            HookEvent::PostBuild => &self.post_build,
+            HookEvent::PreUpload => &self.pre_upload,
+            HookEvent::PostUpload => &self.post_upload,
+            HookEvent::OnFailure => &self.on_failure,
+        }
+    }
+
+    /// Runs the hooks for `event`, in order, with `payload` (plus the event's name) on
+    /// stdin. The first failing hook fails the build, except for `on_failure` hooks.
+    pub(crate) fn run(
+        &self,
+        event: HookEvent,
+        mut payload: Map<String, Value>,
+    ) -> eyre::Result<()> {
+        let commands = self.commands(event);
+        if commands.is_empty() {
+            return Ok(());
+        }
+        payload.insert("event".to_string(), event.name().into());
+        let payload = serde_json::to_vec(&payload)?;
+
+        for command in commands {
+            let Some((program, args)) = command.split_first() else {
+                continue;
+            };
+            info!(
+                "🪝 Running {} hook: {}",
+                event.name().yellow(),
+                command.join(" ").cyan()
+            );
+            let result = run_hook(program, args, event, &payload);
+            match (result, event) {
+                (Ok(()), _) => {}
+                (Err(e), HookEvent::OnFailure) => warn!("{} hook failed: {}", event.name(), e),
+                (Err(e), _) => {
+                    return Err(e.wrap_err(format!("{} hook failed", event.name())));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn run_hook(program: &str, args: &[String], event: HookEvent, payload: &[u8]) -> eyre::Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .env("BEARDIST_HOOK", event.name())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| eyre::eyre!("Failed to run {}: {}", program, e))?;
+    // Hooks don't have to read their stdin, so a broken pipe is fine
+    let _ = child.stdin.take().unwrap().write_all(payload);
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(eyre::eyre!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("payload.json");
+        let hooks = HooksConfig {
+            post_build: vec![vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("cat > {}", out.display()),
+            ]],
+            pre_upload: vec![vec!["false".to_string()]],
+            on_failure: vec![vec!["false".to_string()]],
+            ..Default::default()
+        };
+
+        let mut payload = Map::new();
+        payload.insert("tag".to_string(), "v1.0.0".into());
+        hooks.run(HookEvent::PostBuild, payload).unwrap();
+        let written: Value = serde_json::from_str(&fs_err::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(written["event"], "post_build");
+        assert_eq!(written["tag"], "v1.0.0");
+
+        assert!(hooks.run(HookEvent::PreUpload, Map::new()).is_err());
+        assert!(hooks.run(HookEvent::OnFailure, Map::new()).is_ok());
+        assert!(hooks.run(HookEvent::PreBuild, Map::new()).is_ok());
+    }
+}
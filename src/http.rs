@@ -0,0 +1,123 @@
+//! The networking core: requests to forges and release downloads all go through one
+//! tokio runtime and one connection pool, with the same timeouts and retries, and
+//! concurrency bounded in one place. Code that isn't async enters it with [`block_on`].
+
+use futures_util::{StreamExt, TryStreamExt, stream};
+use log::*;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::{future::Future, sync::OnceLock, time::Duration};
+
+use crate::{
+    USER_AGENT,
+    progress::Progress,
+    retry::{Failure, RetryPolicy},
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a response may go without sending anything. Not a limit on whole requests,
+/// which can take minutes for big uploads.
+const READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .thread_name("beardist-http")
+            .enable_all()
+            .build()
+            .expect("Failed to start the async runtime")
+    })
+}
+
+/// The shared client. Cloning it is cheap and shares its connection pool.
+pub(crate) fn client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .expect("Failed to build the HTTP client")
+    })
+}
+
+/// Runs `future` on the shared runtime, blocking until it's done. For sync code only:
+/// calling this from async code panics.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+/// Whether a response is worth retrying: rate limiting and server errors
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Sends the request `build` makes, retrying connection errors, timeouts, 429s and 5xx
+/// responses. `what` describes the request in logs. Other responses are returned as they
+/// are, for callers to interpret.
+pub(crate) async fn send(what: &str, build: impl Fn() -> RequestBuilder) -> eyre::Result<Response> {
+    RetryPolicy::default()
+        .run_async(what, || async {
+            let response = build()
+                .send()
+                .await
+                .map_err(|e| Failure::Transient(eyre::eyre!("Request error: {}", e)))?;
+            let status = response.status();
+            if is_transient(status) {
+                let body = response.text().await.unwrap_or_default();
+                debug!("Error response: {}", body);
+                return Err(Failure::Transient(eyre::eyre!("HTTP status {}", status)));
+            }
+            Ok(response)
+        })
+        .await
+}
+
+/// Runs `futures` with at most `limit` of them in flight at once, returning their
+/// outputs in order
+pub(crate) async fn bounded<T>(
+    limit: usize,
+    futures: impl IntoIterator<Item = impl Future<Output = T>>,
+) -> Vec<T> {
+    stream::iter(futures).buffered(limit.max(1)).collect().await
+}
+
+/// Streams a response's body to `on_chunk`, with progress labelled `label`. Returns the
+/// number of bytes read.
+pub(crate) async fn read_body(
+    response: Response,
+    label: String,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> eyre::Result<u64> {
+    let progress = Progress::bytes(label, response.content_length());
+    let mut body = response.bytes_stream();
+    let mut size = 0;
+    while let Some(chunk) = body.try_next().await? {
+        on_chunk(&chunk);
+        progress.inc(chunk.len() as u64);
+        size += chunk.len() as u64;
+    }
+    progress.finish();
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_keeps_order() {
+        let outputs = block_on(bounded(
+            2,
+            [30u64, 0, 10].map(|ms| async move {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                ms
+            }),
+        ));
+        assert_eq!(outputs, [30, 0, 10]);
+        assert!(is_transient(StatusCode::BAD_GATEWAY));
+        assert!(!is_transient(StatusCode::NOT_FOUND));
+    }
+}
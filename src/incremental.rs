@@ -0,0 +1,498 @@
+//! Making incremental builds work in CI, and telling whether they did. Fresh checkouts
+//! get fresh mtimes, which makes cargo rebuild everything: timelord puts back the
+//! timestamps of files whose contents haven't changed since the last build. Target
+//! directories are keyed by toolchain and lockfile, so a toolchain bump starts from a
+//! clean slate instead of a corrupted one.
+
+use camino::{Utf8Path, Utf8PathBuf};
+use ignore::WalkBuilder;
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::SystemTime};
+
+use crate::{checksums, environment::Environment};
+
+/// Touched whenever a keyed target directory is used, so pruning knows which are stale
+const LAST_USED: &str = ".beardist-last-used";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TimelordConfig {
+    /// Whether to restore timestamps. Defaults to doing it in CI only. `$SKIP_TIMELORD`
+    /// turns it off regardless.
+    #[serde(default)]
+    pub(crate) enabled: Option<bool>,
+
+    /// More directories to restore timestamps in, relative to the source directory,
+    /// e.g. path dependencies that live outside of it
+    #[serde(default)]
+    pub(crate) extra_paths: Vec<String>,
+
+    /// Only report which timestamps would be restored
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+}
+
+impl TimelordConfig {
+    pub(crate) fn is_enabled(&self, env: &dyn Environment) -> bool {
+        if env.var("SKIP_TIMELORD").is_some() {
+            info!("Skipping timelord ($SKIP_TIMELORD is set)");
+            return false;
+        }
+        match self.enabled {
+            Some(enabled) => enabled,
+            None if env.is_ci() => true,
+            None => {
+                info!("🏠 Not in CI environment, skipping Timelord sync");
+                false
+            }
+        }
+    }
+}
+
+/// How `cargo sweep` keeps target directories in check after builds
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SweepConfig {
+    /// Remove artifacts that weren't used in this many days. Default: 30
+    #[serde(default = "default_sweep_days")]
+    pub(crate) days: u64,
+
+    /// Don't sweep while the target directory is smaller than this. Default: 1 GB
+    #[serde(default = "default_sweep_min_bytes")]
+    pub(crate) min_bytes: u64,
+
+    /// Then remove the oldest artifacts until the target directory is smaller than this
+    #[serde(default)]
+    pub(crate) max_bytes: Option<u64>,
+}
+
+fn default_sweep_days() -> u64 {
+    30
+}
+
+fn default_sweep_min_bytes() -> u64 {
+    1_000_000_000
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            days: default_sweep_days(),
+            min_bytes: default_sweep_min_bytes(),
+            max_bytes: None,
+        }
+    }
+}
+
+/// Arguments to the `cargo sweep`s a target directory of `size` bytes needs. After a
+/// toolchain change, that includes getting rid of other toolchains' artifacts.
+pub(crate) fn sweep_commands(
+    config: &SweepConfig,
+    size: u64,
+    toolchain_changed: bool,
+) -> Vec<Vec<String>> {
+    let mut commands = Vec::new();
+    if toolchain_changed {
+        commands.push(vec!["sweep".to_string(), "--installed".to_string()]);
+    }
+    if size < config.min_bytes {
+        return commands;
+    }
+    commands.push(vec![
+        "sweep".to_string(),
+        "--time".to_string(),
+        config.days.to_string(),
+    ]);
+    if let Some(max_bytes) = config.max_bytes {
+        commands.push(vec![
+            "sweep".to_string(),
+            "--maxsize".to_string(),
+            format!("{}MB", (max_bytes / 1_000_000).max(1)),
+        ]);
+    }
+    commands
+}
+
+/// Total size of the files in `dir`
+pub(crate) fn dir_size(dir: &Utf8Path) -> u64 {
+    WalkBuilder::new(dir)
+        .standard_filters(false)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// What timelord did to a directory
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct TimelordStats {
+    /// Files it looked at
+    pub(crate) files: usize,
+    /// Files whose timestamp it restored (or would have, in a dry run)
+    pub(crate) restored: usize,
+    pub(crate) dry_run: bool,
+}
+
+impl TimelordStats {
+    fn add(&mut self, other: TimelordStats) {
+        self.files += other.files;
+        self.restored += other.restored;
+        self.dry_run |= other.dry_run;
+    }
+}
+
+/// How much of the last build came from cargo's incremental cache, going by which
+/// units' fingerprints it rewrote
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct FingerprintStats {
+    pub(crate) fresh: usize,
+    pub(crate) rebuilt: usize,
+    /// Crates none of whose units were rebuilt
+    pub(crate) crates_reused: usize,
+    /// Crates with at least one unit rebuilt (its build script, say)
+    pub(crate) crates_rebuilt: usize,
+}
+
+/// Evidence that incremental builds work, for reports and the job summary
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct CacheStats {
+    /// `None` when timelord didn't run
+    pub(crate) timelord: Option<TimelordStats>,
+    pub(crate) units: FingerprintStats,
+    /// Whether the target directory held a previous build to reuse
+    pub(crate) warm: bool,
+    /// How long cargo took, which is what rebuilding cost us
+    pub(crate) build_ms: u64,
+}
+
+impl CacheStats {
+    /// Whether there was a previous build, and yet every crate got rebuilt: timelord
+    /// or the cache mount is broken, or the toolchain changed
+    pub(crate) fn is_ineffective(&self) -> bool {
+        self.warm && self.units.crates_reused == 0 && self.units.crates_rebuilt > 0
+    }
+
+    /// e.g. "🕰️ restored 120 of 130 timestamps | ♻️ 3 crates rebuilt, 95 reused (210 of
+    /// 214 units fresh) | ⏱️ 42.1s building"
+    pub(crate) fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(timelord) = &self.timelord {
+            parts.push(format!(
+                "🕰️ {} {} of {} timestamps",
+                if timelord.dry_run {
+                    "would restore"
+                } else {
+                    "restored"
+                },
+                timelord.restored,
+                timelord.files
+            ));
+        }
+        let units = &self.units;
+        parts.push(format!(
+            "♻️ {} crates rebuilt, {} reused ({} of {} units fresh)",
+            units.crates_rebuilt,
+            units.crates_reused,
+            units.fresh,
+            units.fresh + units.rebuilt
+        ));
+        parts.push(format!("⏱️ {:.1}s building", self.build_ms as f64 / 1000.0));
+        if self.is_ineffective() {
+            parts.push("⚠️ nothing reused from the previous build".to_string());
+        }
+        parts.join(" | ")
+    }
+}
+
+/// Key of the target directory for a toolchain (`rustc -vV`) and lockfile
+pub(crate) fn target_dir_key(rustc_version: &str, lockfile: &[u8]) -> String {
+    let mut input = rustc_version.as_bytes().to_vec();
+    input.push(b'\n');
+    input.extend_from_slice(lockfile);
+    checksums::sha256_hex(&input)[..16].to_string()
+}
+
+/// Marks `parent/key` as used, and deletes everything else in `parent` but the target
+/// directories of the `keep - 1` keys used most recently before it. That includes
+/// target directories from before they were keyed.
+pub(crate) fn prune_target_dirs(parent: &Utf8Path, key: &str, keep: usize) -> eyre::Result<()> {
+    let current = parent.join(key);
+    fs_err::create_dir_all(&current)?;
+    fs_err::write(current.join(LAST_USED), "")?;
+
+    let mut others = Vec::new();
+    for entry in fs_err::read_dir(parent)? {
+        let path = Utf8PathBuf::try_from(entry?.path())?;
+        if path != current {
+            let last_used = fs_err::metadata(path.join(LAST_USED))
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            others.push((last_used, path));
+        }
+    }
+    // Most recently used first, and unmarked ones (not ours) last
+    others.sort_by(|a, b| b.0.cmp(&a.0));
+    for (index, (last_used, path)) in others.into_iter().enumerate() {
+        if last_used.is_some() && index + 1 < keep {
+            continue;
+        }
+        info!("🧹 Removing stale target directory {}", path.cyan());
+        let removed = if path.is_dir() {
+            fs_err::remove_dir_all(&path)
+        } else {
+            fs_err::remove_file(&path)
+        };
+        if let Err(e) = removed {
+            warn!("Couldn't remove {}: {}", path, e);
+        }
+    }
+    Ok(())
+}
+
+/// Restores timestamps in `source_dir` and `config.extra_paths`, keeping its cache
+/// (`timelord.db`) in `cache_dir`
+pub(crate) fn run_timelord(
+    config: &TimelordConfig,
+    source_dir: &Utf8Path,
+    cache_dir: &Utf8Path,
+) -> TimelordStats {
+    let mut stats = sync_dir(config, source_dir.to_owned(), cache_dir.to_owned());
+    for extra in &config.extra_paths {
+        let dir = source_dir.join(extra);
+        // each directory needs its own cache
+        let cache_dir = cache_dir
+            .join("timelord")
+            .join(extra.replace(['/', '\\', '.'], "_"));
+        stats.add(sync_dir(config, dir, cache_dir));
+    }
+    info!(
+        "🕰️ Timelord {} {} of {} timestamps",
+        if stats.dry_run {
+            "would restore"
+        } else {
+            "restored"
+        },
+        stats.restored.to_string().cyan(),
+        stats.files
+    );
+    stats
+}
+
+fn sync_dir(config: &TimelordConfig, dir: Utf8PathBuf, cache_dir: Utf8PathBuf) -> TimelordStats {
+    if config.dry_run {
+        return plan(dir, &cache_dir);
+    }
+
+    let before = mtimes(&dir);
+    timelord::sync(dir.clone(), cache_dir);
+    let after = mtimes(&dir);
+    TimelordStats {
+        files: after.len(),
+        restored: after
+            .iter()
+            .filter(|(path, mtime)| before.get(*path).is_some_and(|before| before != *mtime))
+            .count(),
+        dry_run: false,
+    }
+}
+
+/// Counts the timestamps timelord would restore, without touching anything
+fn plan(dir: Utf8PathBuf, cache_dir: &Utf8Path) -> TimelordStats {
+    let workspace = timelord::Workspace { source_dir: dir };
+    let current = timelord::walk_source_dir(&workspace);
+    let restored = match timelord::read_cache(&cache_dir.join("timelord.db")) {
+        Some(cache) => current
+            .entries
+            .iter()
+            .filter(|(path, file)| {
+                cache.entries.get(*path).is_some_and(|cached| {
+                    cached.hash == file.hash
+                        && cached.size == file.size
+                        && cached.timestamp != file.timestamp
+                })
+            })
+            .count(),
+        None => 0,
+    };
+    TimelordStats {
+        files: current.entries.len(),
+        restored,
+        dry_run: true,
+    }
+}
+
+/// mtimes of the files in `dir`, skipping the same ones timelord does
+fn mtimes(dir: &Utf8Path) -> BTreeMap<Utf8PathBuf, SystemTime> {
+    WalkBuilder::new(dir)
+        .standard_filters(false)
+        .git_ignore(true)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+        .filter_map(|entry| {
+            let mtime = entry.metadata().ok()?.modified().ok()?;
+            Some((Utf8PathBuf::try_from(entry.into_path()).ok()?, mtime))
+        })
+        .collect()
+}
+
+/// Whether any of `out_dirs` has fingerprints from a previous build
+pub(crate) fn has_fingerprints(out_dirs: &[Utf8PathBuf]) -> bool {
+    out_dirs.iter().any(|out_dir| {
+        fs_err::read_dir(out_dir.join(".fingerprint")).is_ok_and(|mut units| units.next().is_some())
+    })
+}
+
+/// Counts the units in `out_dirs` (e.g. `target/release`) cargo rebuilt since `since`,
+/// and the crates they belong to
+pub(crate) fn fingerprint_stats(out_dirs: &[Utf8PathBuf], since: SystemTime) -> FingerprintStats {
+    let mut stats = FingerprintStats::default();
+    // crate name → whether any of its units was rebuilt
+    let mut crates: BTreeMap<String, bool> = BTreeMap::new();
+    for out_dir in out_dirs {
+        let Ok(units) = fs_err::read_dir(out_dir.join(".fingerprint")) else {
+            continue;
+        };
+        for unit in units.filter_map(|entry| entry.ok()) {
+            // cargo rewrites the files in a unit's fingerprint directory when rebuilding it
+            let newest = fs_err::read_dir(unit.path())
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+                .max();
+            let rebuilt = newest.is_some_and(|mtime| mtime >= since);
+            if rebuilt {
+                stats.rebuilt += 1;
+            } else {
+                stats.fresh += 1;
+            }
+            // units are named "<crate>-<hash>"
+            let name = unit.file_name().to_string_lossy().into_owned();
+            let name = name
+                .rsplit_once('-')
+                .map_or(name.as_str(), |(name, _)| name);
+            *crates.entry(name.to_string()).or_default() |= rebuilt;
+        }
+    }
+    stats.crates_rebuilt = crates.values().filter(|rebuilt| **rebuilt).count();
+    stats.crates_reused = crates.len() - stats.crates_rebuilt;
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fingerprint_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = Utf8PathBuf::try_from(dir.path().to_owned()).unwrap();
+        let fingerprints = out_dir.join(".fingerprint");
+        for unit in ["old-1234", "new-5678", "new-9abc"] {
+            fs_err::create_dir_all(fingerprints.join(unit)).unwrap();
+            fs_err::write(fingerprints.join(unit).join("lib-foo"), "hash").unwrap();
+        }
+        let old = fs_err::File::open(fingerprints.join("old-1234/lib-foo")).unwrap();
+        old.file()
+            .set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+
+        let since = SystemTime::now() - Duration::from_secs(60);
+        assert!(has_fingerprints(std::slice::from_ref(&out_dir)));
+        let stats = fingerprint_stats(&[out_dir], since);
+        assert_eq!((stats.fresh, stats.rebuilt), (1, 2));
+        assert_eq!((stats.crates_reused, stats.crates_rebuilt), (1, 1));
+
+        let mut stats = CacheStats {
+            timelord: Some(TimelordStats {
+                files: 130,
+                restored: 120,
+                dry_run: false,
+            }),
+            units: stats,
+            warm: true,
+            build_ms: 42_130,
+        };
+        assert!(!stats.is_ineffective());
+        assert_eq!(
+            stats.describe(),
+            "🕰️ restored 120 of 130 timestamps | ♻️ 1 crates rebuilt, 1 reused (1 of 3 units fresh) | ⏱️ 42.1s building"
+        );
+
+        stats.units.crates_reused = 0;
+        assert!(stats.is_ineffective());
+        assert!(
+            stats
+                .describe()
+                .ends_with("⚠️ nothing reused from the previous build")
+        );
+    }
+
+    #[test]
+    fn test_sweep_commands() {
+        let config = SweepConfig {
+            max_bytes: Some(20_000_000_000),
+            ..Default::default()
+        };
+        assert!(sweep_commands(&config, 500_000_000, false).is_empty());
+        assert_eq!(
+            sweep_commands(&config, 500_000_000, true),
+            [["sweep", "--installed"]]
+        );
+        assert_eq!(
+            sweep_commands(&config, 30_000_000_000, false),
+            [["sweep", "--time", "30"], ["sweep", "--maxsize", "20000MB"]]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(dir.path().to_owned()).unwrap();
+        fs_err::create_dir_all(dir.join("release/deps")).unwrap();
+        fs_err::write(dir.join("release/deps/libfoo.rlib"), [0; 1000]).unwrap();
+        fs_err::write(dir.join("CACHEDIR.TAG"), [0; 24]).unwrap();
+        assert_eq!(dir_size(&dir), 1024);
+    }
+
+    #[test]
+    fn test_prune_target_dirs() {
+        let lockfile = b"[[package]]\nname = \"beardist\"\n";
+        let key = target_dir_key("rustc 1.88.0", lockfile);
+        assert_eq!(key.len(), 16);
+        assert_eq!(key, target_dir_key("rustc 1.88.0", lockfile));
+        assert_ne!(key, target_dir_key("rustc 1.89.0", lockfile));
+
+        let dir = tempfile::tempdir().unwrap();
+        let parent = Utf8PathBuf::try_from(dir.path().to_owned()).unwrap();
+        // From before target directories were keyed
+        fs_err::create_dir_all(parent.join("release")).unwrap();
+        fs_err::write(parent.join("CACHEDIR.TAG"), "").unwrap();
+        for (key, age) in [("older", 7200), ("old", 3600)] {
+            fs_err::create_dir_all(parent.join(key)).unwrap();
+            let marker = parent.join(key).join(LAST_USED);
+            fs_err::write(&marker, "").unwrap();
+            fs_err::File::options()
+                .write(true)
+                .open(&marker)
+                .unwrap()
+                .file()
+                .set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+        }
+
+        prune_target_dirs(&parent, &key, 2).unwrap();
+        let mut left: Vec<String> = fs_err::read_dir(&parent)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        let mut expected = vec![key.clone(), "old".to_string()];
+        expected.sort();
+        assert_eq!(left, expected);
+        assert!(parent.join(&key).join(LAST_USED).exists());
+    }
+}
@@ -0,0 +1,238 @@
+//! `beardist install <org/repo>`: installs a beardist-built release on this machine, like
+//! the generated `install.sh` does, for CI images and servers without Homebrew.
+
+use log::*;
+use owo_colors::OwoColorize;
+use reqwest::blocking::Client;
+use std::{os::unix::fs::PermissionsExt, path::PathBuf};
+
+use crate::{
+    InstallArgs, cargo::UNIVERSAL_MACOS_ARTIFACT, checksums, command::run_command, download::fetch,
+    format_bytes,
+};
+
+/// The triple our archives are named after, for the machine we're running on
+pub(crate) fn host_triple() -> eyre::Result<String> {
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "x86_64",
+        "aarch64" => "aarch64",
+        other => return Err(eyre::eyre!("Unsupported architecture: {}", other)),
+    };
+    let os = match std::env::consts::OS {
+        "linux" => "unknown-linux-gnu",
+        "macos" => "apple-darwin",
+        other => return Err(eyre::eyre!("Unsupported OS: {}", other)),
+    };
+    Ok(format!("{}-{}", arch, os))
+}
+
+/// The archive to download for `triple`, given which assets the release has. Projects
+/// built with `cargo.universal_macos` only publish `universal-apple-darwin.tar.xz`.
+pub(crate) fn archive_for(triple: &str, published: impl Fn(&str) -> bool) -> String {
+    let archive = format!("{}.tar.xz", triple);
+    let universal = format!("{}.tar.xz", UNIVERSAL_MACOS_ARTIFACT);
+    if triple.ends_with("-apple-darwin") && !published(&archive) && published(&universal) {
+        universal
+    } else {
+        archive
+    }
+}
+
+/// Where a release's assets can be downloaded from
+fn download_base(server_url: &str, repo: &str, tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) => format!("{}/{}/releases/download/{}", server_url, repo, tag),
+        None => format!("{}/{}/releases/latest/download", server_url, repo),
+    }
+}
+
+pub(crate) fn install(args: InstallArgs) -> eyre::Result<()> {
+    if args.repo.split('/').count() != 2 {
+        return Err(eyre::eyre!(
+            "Repository must be in 'org/repo' format, got {}",
+            args.repo
+        ));
+    }
+    let dry_run = std::env::var("DRY_RUN").is_ok();
+    let server_url =
+        std::env::var("GITHUB_SERVER_URL").unwrap_or_else(|_| "https://github.com".to_string());
+    let triple = match &args.target {
+        Some(target) => target.clone(),
+        None => host_triple()?,
+    };
+    let base_url = download_base(&server_url, &args.repo, args.tag.as_deref());
+
+    let client = Client::new();
+    let sums = checksums::parse_sha256sums(&String::from_utf8_lossy(&fetch(
+        &client,
+        &format!("{}/SHA256SUMS", base_url),
+        "Downloading SHA256SUMS",
+    )?));
+    let archive_name = archive_for(&triple, |name| sums.contains_key(name));
+    let archive_url = format!("{}/{}", base_url, archive_name);
+    info!("Downloading {}...", archive_url.cyan());
+    let archive = fetch(
+        &client,
+        &archive_url,
+        &format!("Downloading {}", archive_name),
+    )?;
+    info!("Downloaded {}", format_bytes(archive.len() as u64).blue());
+
+    let expected = sums
+        .get(&archive_name)
+        .ok_or_else(|| eyre::eyre!("No checksum for {} in SHA256SUMS", archive_name))?;
+    let actual = checksums::sha256_hex(&archive);
+    if *expected != actual {
+        return Err(eyre::eyre!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            archive_name,
+            expected,
+            actual
+        ));
+    }
+    info!("✅ Checksum verified: {}", actual.dimmed());
+
+    let tmp = tempfile::tempdir()?;
+    let archive_path = tmp.path().join(&archive_name);
+    fs_err::write(&archive_path, &archive)?;
+    let extract_dir = tmp.path().join("extract");
+    fs_err::create_dir(&extract_dir)?;
+    run_command(
+        "tar",
+        &[
+            "-xJf",
+            archive_path.to_str().unwrap(),
+            "-C",
+            extract_dir.to_str().unwrap(),
+        ],
+        None,
+    )?;
+
+    // Archives are flat: libraries are named `lib*`, binaries are the other executables
+    let mut bins = Vec::new();
+    let mut libs = Vec::new();
+    for entry in fs_err::read_dir(&extract_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if name.starts_with("lib") {
+            libs.push(path);
+        } else if fs_err::metadata(&path)?.permissions().mode() & 0o111 != 0 {
+            bins.push(path);
+        }
+    }
+    if bins.is_empty() {
+        return Err(eyre::eyre!("No binaries found in {}", archive_name));
+    }
+
+    let bin_dir = match args.dir {
+        Some(dir) => dir,
+        None => PathBuf::from(std::env::var("HOME")?).join(".local/bin"),
+    };
+    // Same layout as the Homebrew formula and install.sh
+    let lib_dir = bin_dir.join("../libexec");
+
+    if dry_run {
+        for path in bins.iter().chain(&libs) {
+            let dir = if libs.contains(path) {
+                &lib_dir
+            } else {
+                &bin_dir
+            };
+            info!(
+                "Dry run: Would install {} to {}",
+                path.file_name().unwrap().to_string_lossy().cyan(),
+                dir.display()
+            );
+        }
+        return Ok(());
+    }
+
+    fs_err::create_dir_all(&bin_dir)?;
+    for lib in &libs {
+        let dest = lib_dir.join(lib.file_name().unwrap());
+        fs_err::create_dir_all(&lib_dir)?;
+        fs_err::copy(lib, &dest)?;
+        info!("📚 Installed {}", dest.display().to_string().cyan());
+    }
+    for bin in &bins {
+        let dest = bin_dir.join(bin.file_name().unwrap());
+        fs_err::copy(bin, &dest)?;
+        fs_err::set_permissions(&dest, std::fs::Permissions::from_mode(0o755))?;
+        if !libs.is_empty() {
+            fix_rpath(&dest)?;
+        }
+        info!("📦 Installed {}", dest.display().to_string().bright_green());
+    }
+
+    let on_path = std::env::var("PATH")
+        .unwrap_or_default()
+        .split(':')
+        .any(|dir| std::path::Path::new(dir) == bin_dir);
+    if !on_path {
+        warn!("{} is not in your PATH", bin_dir.display());
+    }
+    Ok(())
+}
+
+/// Points a binary's runtime search path at the `libexec` directory next to it
+fn fix_rpath(bin: &std::path::Path) -> eyre::Result<()> {
+    let bin = bin.to_str().unwrap();
+    if cfg!(target_os = "macos") {
+        // Our libraries are referenced as `@rpath/lib*.dylib`
+        let status = std::process::Command::new("install_name_tool")
+            .args(["-add_rpath", "@executable_path/../libexec", bin])
+            .status()?;
+        if !status.success() {
+            warn!(
+                "Could not add an rpath to {} (it may already have one)",
+                bin
+            );
+        }
+    } else {
+        match std::process::Command::new("patchelf")
+            .args(["--set-rpath", "$ORIGIN/../libexec", bin])
+            .status()
+        {
+            Ok(status) if status.success() => {}
+            _ => warn!(
+                "Could not set the rpath of {} with patchelf: it may not find its libraries",
+                bin
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_download_base() {
+        assert_eq!(
+            download_base("https://github.com", "bearcove/beardist", Some("v1.2.3")),
+            "https://github.com/bearcove/beardist/releases/download/v1.2.3"
+        );
+        assert_eq!(
+            download_base("https://github.com", "bearcove/beardist", None),
+            "https://github.com/bearcove/beardist/releases/latest/download"
+        );
+    }
+
+    #[test]
+    fn test_archive_for() {
+        let universal_only = |name: &str| name == "universal-apple-darwin.tar.xz";
+        assert_eq!(
+            archive_for("aarch64-apple-darwin", universal_only),
+            "universal-apple-darwin.tar.xz"
+        );
+        assert_eq!(
+            archive_for("aarch64-apple-darwin", |_| true),
+            "aarch64-apple-darwin.tar.xz"
+        );
+        assert_eq!(
+            archive_for("x86_64-unknown-linux-gnu", universal_only),
+            "x86_64-unknown-linux-gnu.tar.xz"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+/// Generates a POSIX `install.sh` for a release: it picks the asset matching the host's
+/// OS and architecture, checks it against the release's `SHA256SUMS`, and installs the
+/// binaries into `~/.local/bin` (libraries go to `~/.local/libexec`, like in the Homebrew
+/// formula). `server_url` is the forge's, e.g. "https://github.com". `asset` is the
+/// archive's name without `.tar.xz`, in terms of the shell variable `$target` (e.g.
+/// "$target-nightly" for channel builds).
+pub(crate) fn generate_install_script(
+    server_url: &str,
+    org: &str,
+    name: &str,
+    tag: &str,
+    bins: &[&str],
+    asset: &str,
+) -> String {
+    let bins = bins.join(" ");
+    format!(
+        r#"#!/bin/sh
+# Installer for {org}/{name} {tag}, generated by beardist.
+#
+#   curl -fsSL {server_url}/{org}/{name}/releases/download/{tag}/install.sh | sh
+#
+# Environment: VERSION (a tag, or "latest"), INSTALL_DIR (default: ~/.local/bin)
+set -eu
+
+SERVER_URL="{server_url}"
+REPO="{org}/{name}"
+VERSION="${{VERSION:-{tag}}}"
+INSTALL_DIR="${{INSTALL_DIR:-$HOME/.local/bin}}"
+LIB_DIR="$INSTALL_DIR/../libexec"
+BINS="{bins}"
+
+case "$(uname -s)" in
+  Linux) os="unknown-linux-gnu" ;;
+  Darwin) os="apple-darwin" ;;
+  *) echo "Unsupported OS: $(uname -s)" >&2; exit 1 ;;
+esac
+case "$(uname -m)" in
+  x86_64 | amd64) arch="x86_64" ;;
+  arm64 | aarch64) arch="aarch64" ;;
+  *) echo "Unsupported architecture: $(uname -m)" >&2; exit 1 ;;
+esac
+target="$arch-$os"
+asset="{asset}.tar.xz"
+
+if [ "$VERSION" = "latest" ]; then
+  base_url="$SERVER_URL/$REPO/releases/latest/download"
+else
+  base_url="$SERVER_URL/$REPO/releases/download/$VERSION"
+fi
+
+download() {{
+  if command -v curl >/dev/null 2>&1; then
+    curl -fsSL -o "$2" "$1"
+  else
+    wget -qO "$2" "$1"
+  fi
+}}
+
+sha256() {{
+  if command -v sha256sum >/dev/null 2>&1; then
+    sha256sum "$1" | cut -d' ' -f1
+  else
+    shasum -a 256 "$1" | cut -d' ' -f1
+  fi
+}}
+
+tmp="$(mktemp -d)"
+trap 'rm -rf "$tmp"' EXIT
+
+download "$base_url/SHA256SUMS" "$tmp/SHA256SUMS"
+if [ "$os" = "apple-darwin" ] && ! grep -q " $asset\$" "$tmp/SHA256SUMS"; then
+  # Projects built with `universal_macos` only publish universal binaries
+  target="universal-apple-darwin"
+  asset="{asset}.tar.xz"
+fi
+
+echo "Downloading $asset from $base_url..."
+download "$base_url/$asset" "$tmp/$asset"
+
+expected="$(grep " $asset\$" "$tmp/SHA256SUMS" | cut -d' ' -f1)"
+if [ -z "$expected" ]; then
+  echo "No checksum for $asset in SHA256SUMS" >&2
+  exit 1
+fi
+actual="$(sha256 "$tmp/$asset")"
+if [ "$expected" != "$actual" ]; then
+  echo "Checksum mismatch for $asset: expected $expected, got $actual" >&2
+  exit 1
+fi
+
+mkdir -p "$tmp/extract" "$INSTALL_DIR" "$LIB_DIR"
+tar -xJf "$tmp/$asset" -C "$tmp/extract"
+for lib in "$tmp"/extract/lib*; do
+  [ -e "$lib" ] || continue
+  cp "$lib" "$LIB_DIR/"
+done
+for bin in $BINS; do
+  cp "$tmp/extract/$bin" "$INSTALL_DIR/$bin"
+  chmod 755 "$INSTALL_DIR/$bin"
+  echo "Installed $INSTALL_DIR/$bin"
+done
+
+case ":$PATH:" in
+  *":$INSTALL_DIR:"*) ;;
+  *) echo "Note: $INSTALL_DIR is not in your PATH" ;;
+esac
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_install_script() {
+        let script = generate_install_script(
+            "https://github.com",
+            "bearcove",
+            "beardist",
+            "v1.2.3",
+            &["beardist"],
+            "$target",
+        );
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("REPO=\"bearcove/beardist\"\n"));
+        assert!(script.contains("VERSION=\"${VERSION:-v1.2.3}\"\n"));
+        assert!(script.contains("BINS=\"beardist\"\n"));
+        assert!(script.contains("asset=\"$target.tar.xz\"\n"));
+
+        let script = generate_install_script(
+            "https://github.com",
+            "bearcove",
+            "beardist",
+            "nightly",
+            &["beardist"],
+            "$target-nightly",
+        );
+        assert!(script.contains("asset=\"$target-nightly.tar.xz\"\n"));
+        assert!(
+            script.contains(
+                "  target=\"universal-apple-darwin\"\n  asset=\"$target-nightly.tar.xz\"\n"
+            )
+        );
+        assert!(script.contains("download \"$base_url/$asset\" \"$tmp/$asset\"\n"));
+
+        let script = generate_install_script(
+            "https://code.bearcove.cloud",
+            "bearcove",
+            "home",
+            "v0.4.0",
+            &["home"],
+            "$target",
+        );
+        assert!(script.contains(
+            "#   curl -fsSL https://code.bearcove.cloud/bearcove/home/releases/download/v0.4.0/install.sh | sh\n"
+        ));
+        assert!(script.contains("SERVER_URL=\"https://code.bearcove.cloud\"\n"));
+        assert!(script.contains("  base_url=\"$SERVER_URL/$REPO/releases/download/$VERSION\"\n"));
+        assert!(!script.contains("github.com"));
+    }
+}
@@ -1,18 +1,215 @@
+use eyre::Context;
 use ignore::WalkBuilder;
-use log::info;
+use log::{debug, info, warn};
 use owo_colors::OwoColorize;
 use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-use crate::github::GitHubClient;
+use crate::{
+    credentials, environment::ProcessEnvironment, forgejo::ForgejoClient, github::GitHubClient,
+};
+use source::{Fixed, ForgePackages, RegistryV2, VersionSource};
 
+mod audit;
+mod history;
+mod source;
+mod webhook;
+mod yaml;
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub(crate) struct DeployConfig {
+    /// Images deployed by `beardist k8s --all`, e.g. "bearcove/home"
+    #[serde(default)]
+    images: Vec<String>,
+
+    /// Registry hosts that image references may use, e.g. "ghcr.io". Include
+    /// "docker.io" to also match references without a host.
+    #[serde(default = "default_registries")]
+    registries: Vec<String>,
+
+    /// Helm values files that hold image tags
+    #[serde(default)]
+    helm: Vec<HelmValues>,
+
+    /// Where to look for manifests
+    #[serde(default = "default_manifest_dir")]
+    manifest_dir: PathBuf,
+
+    /// Only search manifests matching these globs (relative to `manifest_dir`), e.g.
+    /// "apps/**"
+    #[serde(default)]
+    include: Vec<String>,
+
+    /// Never touch manifests matching these globs (relative to `manifest_dir`), e.g.
+    /// "*.tmpl.yaml"
+    #[serde(default)]
+    exclude: Vec<String>,
+
+    /// Tags matching any of these regexes are never deployed. Default: "-(amd64|arm64)$",
+    /// the per-architecture tags of multi-arch images
+    #[serde(default = "default_skip_tags")]
+    skip_tags: Vec<String>,
+
+    /// Only deploy tags with this suffix, e.g. "arm64" for "1.2.3-arm64", for clusters
+    /// running a single architecture. `skip_tags` then apply to tags without it.
+    #[serde(default)]
+    arch_suffix: Option<String>,
+
+    /// Where deployments are recorded
+    #[serde(default)]
+    audit: audit::AuditConfig,
+
+    /// Command run after updating manifests. An argument that is exactly `{files}` is
+    /// replaced with the updated files, and `{version}` with the deployed version (of
+    /// the first image, when deploying several).
+    #[serde(default = "default_deploy_command")]
+    deploy_command: Vec<String>,
+}
+
+fn default_registries() -> Vec<String> {
+    vec!["ghcr.io".to_string()]
+}
+
+fn default_skip_tags() -> Vec<String> {
+    vec!["-(amd64|arm64)$".to_string()]
+}
+
+fn default_manifest_dir() -> PathBuf {
+    PathBuf::from("manifests")
+}
+
+fn default_deploy_command() -> Vec<String> {
+    vec!["./deploy".to_string(), "{files}".to_string()]
+}
+
+/// Expands `{files}` and `{version}` in a deploy command
+fn expand_deploy_command(command: &[String], files: &[&Path], version: &str) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for arg in command {
+        if arg == "{files}" {
+            expanded.extend(files.iter().map(|file| file.display().to_string()));
+        } else {
+            expanded.push(arg.replace("{version}", version));
+        }
+    }
+    expanded
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+struct HelmValues {
+    /// e.g. "bearcove/home"
+    image: String,
+
+    /// Path of the values file, e.g. "charts/home/values.yaml"
+    file: PathBuf,
+
+    /// Dotted key path of the tag in the values file
+    #[serde(default = "default_tag_path")]
+    tag_path: String,
+}
+
+fn default_tag_path() -> String {
+    "image.tag".to_string()
+}
+
+/// Loads `.beardist-deploy.json`, if there is one
+fn load_deploy_config() -> eyre::Result<Option<DeployConfig>> {
+    load_deploy_config_in(Path::new("."))
+}
+
+/// Loads the `.beardist-deploy.json` in `dir`, if there is one
+fn load_deploy_config_in(dir: &Path) -> eyre::Result<Option<DeployConfig>> {
+    let config_path = dir.join(".beardist-deploy.json");
+    if !config_path.exists() {
+        return Ok(None);
+    }
+    let config_path = fs_err::canonicalize(config_path)?;
+    let config_str = fs_err::read_to_string(&config_path).wrap_err_with(|| {
+        format!(
+            "Failed to read deploy config file at {}",
+            config_path.display().to_string().cyan()
+        )
+    })?;
+    let config: DeployConfig = serde_json::from_str(&config_str).wrap_err_with(|| {
+        format!(
+            "Failed to parse config file at {}",
+            config_path.display().to_string().cyan()
+        )
+    })?;
+    Ok(Some(config))
+}
+
+/// Full image names the image may be referenced by, e.g. "ghcr.io/bearcove/home"
+fn image_names(registries: &[String], image: &str) -> Vec<String> {
+    let mut names: Vec<String> = registries
+        .iter()
+        .map(|registry| format!("{}/{}", registry, image))
+        .collect();
+    if registries.iter().any(|registry| registry == "docker.io") {
+        names.push(image.to_string());
+    }
+    names
+}
+
+/// Matches `<registry>/<image>:v<version>` image references for any of the given
+/// registries. The first group is the registry prefix (with its trailing slash, empty for Docker Hub
+/// references without a host), the second one the version.
+fn image_regex(registries: &[String], image: &str) -> Regex {
+    let hosts: Vec<String> = registries
+        .iter()
+        .map(|registry| format!("{}/", regex::escape(registry)))
+        .collect();
+    let mut prefix = hosts.join("|");
+    if registries.iter().any(|registry| registry == "docker.io") {
+        prefix.push('|');
+    }
+    Regex::new(&format!(r"^({}){}:v(\S+)$", prefix, regex::escape(image))).unwrap()
+}
+
+/// A version of the image in a file: `start..end` is the version without its `v` prefix,
+/// which is all that gets replaced.
 #[derive(Debug, Clone)]
 struct ImageOccurrence {
     start: usize,
     end: usize,
     current_version: String,
+    /// 1-based line the version is on
+    line: usize,
     context: String,
+    /// Registry host the image is pulled from, when the occurrence tells us
+    registry: Option<String>,
+}
+
+impl ImageOccurrence {
+    fn new(contents: &str, start: usize, end: usize, registry: Option<String>) -> Self {
+        let start = if contents[start..end].starts_with('v') {
+            start + 1
+        } else {
+            start
+        };
+
+        let lines: Vec<&str> = contents.lines().collect();
+        let line_number = contents[..start].lines().count();
+        let context_start = line_number.saturating_sub(2);
+        let context_end = (line_number + 3).min(lines.len());
+        let context = lines[context_start..context_end].join("\n");
+
+        Self {
+            start,
+            end,
+            current_version: contents[start..end].to_string(),
+            line: line_number,
+            context,
+            registry,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -26,83 +223,427 @@ struct Workspace {
     manifests: Vec<Manifest>,
 }
 
-fn collect_workspace(manifest_dir: &Path, image: &str) -> Result<Workspace, std::io::Error> {
-    let search_regex = Regex::new(&format!(r"image:\s*ghcr\.io/{}:v(\S+)", image)).unwrap();
+impl Workspace {
+    /// Keeps the manifests whose path relative to `dir` matches one of the `only` globs
+    /// (if any) and is part of `env` (if given)
+    fn select_manifests(&mut self, dir: &Path, only: &[glob::Pattern], env: Option<&str>) {
+        self.manifests.retain(|manifest| {
+            let relative = manifest.path.strip_prefix(dir).unwrap_or(&manifest.path);
+            (only.is_empty() || only.iter().any(|glob| glob.matches_path(relative)))
+                && env.is_none_or(|env| in_env(relative, env))
+        });
+    }
+
+    /// The versions the manifests are at, each with the manifests at it
+    fn versions(&self) -> BTreeMap<&str, Vec<&Path>> {
+        let mut versions: BTreeMap<&str, Vec<&Path>> = BTreeMap::new();
+        for manifest in &self.manifests {
+            for occurrence in &manifest.occurrences {
+                let paths = versions.entry(&occurrence.current_version).or_default();
+                if !paths.contains(&manifest.path.as_path()) {
+                    paths.push(&manifest.path);
+                }
+            }
+        }
+        versions
+    }
+
+    /// Keeps the occurrences at one of `versions`
+    fn keep_versions(&mut self, versions: &[String]) {
+        for manifest in &mut self.manifests {
+            manifest
+                .occurrences
+                .retain(|occurrence| versions.contains(&occurrence.current_version));
+        }
+        self.manifests
+            .retain(|manifest| !manifest.occurrences.is_empty());
+    }
+}
+
+/// Whether a manifest is part of an environment: it's in a directory named after it
+/// (e.g. "overlays/prod/"), or its name says so (e.g. "home.prod.yaml", "home-prod.yaml")
+fn in_env(relative: &Path, env: &str) -> bool {
+    let in_dir = relative
+        .parent()
+        .is_some_and(|dir| dir.components().any(|c| c.as_os_str() == env));
+    let in_name = relative
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.split(['.', '-', '_']).any(|part| part == env));
+    in_dir || in_name
+}
+
+/// Parses the answer to "which versions?": "" or "all", or numbers like "1,3"
+fn parse_selection(answer: &str, count: usize) -> Option<Vec<usize>> {
+    let answer = answer.trim();
+    if answer.is_empty() || answer == "all" {
+        return Some((0..count).collect());
+    }
+    answer
+        .split([',', ' '])
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            part.parse::<usize>()
+                .ok()
+                .filter(|n| (1..=count).contains(n))
+                .map(|n| n - 1)
+        })
+        .collect()
+}
+
+/// When the manifests of an image are at different versions (say, canary and stable),
+/// asks which ones to bump. Without a terminal, or with `--yes`, all of them are.
+fn pick_versions(image: &str, workspace: &mut Workspace, yes: bool) -> eyre::Result<()> {
+    use std::io::IsTerminal;
+
+    let versions: Vec<(String, Vec<PathBuf>)> = workspace
+        .versions()
+        .into_iter()
+        .map(|(version, paths)| {
+            (
+                version.to_string(),
+                paths.into_iter().map(Path::to_path_buf).collect(),
+            )
+        })
+        .collect();
+    if versions.len() < 2 {
+        return Ok(());
+    }
+    if yes || !std::io::stdin().is_terminal() {
+        warn!(
+            "Manifests reference {} versions of {}, bumping all of them (choose with {} or {})",
+            versions.len(),
+            image.cyan(),
+            "--only".bright_yellow(),
+            "--env".bright_yellow()
+        );
+        return Ok(());
+    }
+
+    eprintln!(
+        "Manifests reference several versions of {}:",
+        image.bright_cyan()
+    );
+    for (i, (version, paths)) in versions.iter().enumerate() {
+        let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        eprintln!(
+            "  {}) {}: {}",
+            i + 1,
+            version.bright_yellow(),
+            paths.join(", ")
+        );
+    }
+    loop {
+        eprint!("{} [all] ", "Which ones should be bumped?".bold());
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        match parse_selection(&answer, versions.len()) {
+            Some(selection) if !selection.is_empty() => {
+                let selected: Vec<String> = selection
+                    .into_iter()
+                    .map(|i| versions[i].0.clone())
+                    .collect();
+                workspace.keep_versions(&selected);
+                return Ok(());
+            }
+            _ => eprintln!("Answer with numbers like \"1,2\", or \"all\""),
+        }
+    }
+}
+
+/// Registry host from an image reference prefix like "ghcr.io/" (empty for Docker Hub)
+fn registry_host(prefix: &str) -> String {
+    match prefix.trim_end_matches('/') {
+        "" => "docker.io".to_string(),
+        host => host.to_string(),
+    }
+}
+
+/// Finds the `image` fields of containers, and `images:` entries in kustomizations
+fn scan_file(
+    path: &Path,
+    contents: &str,
+    image: &str,
+    search_regex: &Regex,
+    names: &[String],
+) -> Vec<ImageOccurrence> {
+    let mut occurrences: Vec<ImageOccurrence> = yaml::find_container_images(contents)
+        .into_iter()
+        .filter_map(|value| {
+            let captures = search_regex.captures(&contents[value.clone()])?;
+            let version = captures.get(2).unwrap();
+            Some(ImageOccurrence::new(
+                contents,
+                value.start + version.start(),
+                value.start + version.end(),
+                Some(registry_host(&captures[1])),
+            ))
+        })
+        .collect();
+
+    let file_name = path.file_name().and_then(|s| s.to_str());
+    if matches!(file_name, Some("kustomization.yaml" | "kustomization.yml")) {
+        for (name, tag) in yaml::find_kustomize_tags(contents, names) {
+            let registry = registry_host(name.strip_suffix(image).unwrap_or_default());
+            occurrences.push(ImageOccurrence::new(
+                contents,
+                tag.start,
+                tag.end,
+                Some(registry),
+            ));
+        }
+    }
+    occurrences
+}
+
+/// Which files under the manifest directory are searched. Files ignored by git (through
+/// `.gitignore`, `.ignore` or `.git/info/exclude`) and hidden files are skipped.
+#[derive(Debug, Clone)]
+struct ManifestSearch {
+    dir: PathBuf,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    follow_symlinks: bool,
+}
+
+impl ManifestSearch {
+    fn walker(&self) -> eyre::Result<WalkBuilder> {
+        let mut overrides = ignore::overrides::OverrideBuilder::new(&self.dir);
+        for glob in &self.include {
+            overrides
+                .add(glob)
+                .wrap_err_with(|| format!("Invalid include glob {:?}", glob))?;
+        }
+        for glob in &self.exclude {
+            overrides
+                .add(&format!("!{}", glob))
+                .wrap_err_with(|| format!("Invalid exclude glob {:?}", glob))?;
+        }
+        let mut walker = WalkBuilder::new(&self.dir);
+        walker
+            .overrides(overrides.build()?)
+            .follow_links(self.follow_symlinks)
+            // Deploy repositories get checked out without `.git` too (e.g. from tarballs)
+            .require_git(false);
+        Ok(walker)
+    }
+}
+
+fn is_yaml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|s| s.to_str()),
+        Some("yaml" | "yml")
+    )
+}
+
+fn collect_workspace(
+    search: &ManifestSearch,
+    registries: &[String],
+    image: &str,
+    helm: &[HelmValues],
+) -> eyre::Result<Workspace> {
+    let search_regex = image_regex(registries, image);
+    let names = image_names(registries, image);
     let manifests = Arc::new(std::sync::Mutex::new(Vec::new()));
 
-    WalkBuilder::new(manifest_dir)
-        .types(
-            ignore::types::TypesBuilder::new()
-                .add_defaults()
-                .build()
-                .unwrap(),
-        )
-        .build_parallel()
-        .run(|| {
-            let search_regex = search_regex.clone();
-            let manifests = Arc::clone(&manifests);
-            Box::new(move |result| {
-                if let Ok(entry) = result {
-                    let path = entry.path();
-                    if path.extension().and_then(|s| s.to_str()) == Some("yaml")
-                        || path.extension().and_then(|s| s.to_str()) == Some("yml")
-                    {
-                        if let Ok(contents) = fs_err::read_to_string(path) {
-                            let mut occurrences = Vec::new();
-                            for captures in search_regex.captures_iter(&contents) {
-                                let full_match = captures.get(0).unwrap();
-                                let version = captures.get(1).unwrap();
-                                let start = full_match.start();
-                                let end = full_match.end();
-
-                                let lines: Vec<&str> = contents.lines().collect();
-                                let line_number = contents[..start].lines().count();
-                                let context_start = line_number.saturating_sub(2);
-                                let context_end = (line_number + 3).min(lines.len());
-                                let context = lines[context_start..context_end].join("\n");
-
-                                occurrences.push(ImageOccurrence {
-                                    start,
-                                    end,
-                                    current_version: version.as_str().to_string(),
-                                    context,
-                                });
-                            }
-                            if !occurrences.is_empty() {
-                                manifests.lock().unwrap().push(Manifest {
-                                    path: path.to_path_buf(),
-                                    occurrences,
-                                });
-                            }
+    search.walker()?.build_parallel().run(|| {
+        let search_regex = search_regex.clone();
+        let names = names.clone();
+        let manifests = Arc::clone(&manifests);
+        Box::new(move |result| {
+            if let Ok(entry) = result {
+                let path = entry.path();
+                if entry.file_type().is_some_and(|t| t.is_file()) && is_yaml(path) {
+                    if let Ok(contents) = fs_err::read_to_string(path) {
+                        let occurrences = scan_file(path, &contents, image, &search_regex, &names);
+                        if !occurrences.is_empty() {
+                            manifests.lock().unwrap().push(Manifest {
+                                path: path.to_path_buf(),
+                                occurrences,
+                            });
                         }
                     }
                 }
-                ignore::WalkState::Continue
-            })
+            }
+            ignore::WalkState::Continue
+        })
+    });
+    let mut manifests = manifests.lock().unwrap().clone();
+
+    for values in helm.iter().filter(|values| values.image == image) {
+        let contents = fs_err::read_to_string(&values.file)?;
+        let tag = yaml::find_value(&contents, &values.tag_path).ok_or_else(|| {
+            eyre::eyre!(
+                "No {} in {}",
+                values.tag_path.cyan(),
+                values.file.display().to_string().cyan()
+            )
+        })?;
+        let occurrence = ImageOccurrence::new(&contents, tag.start, tag.end, None);
+
+        // The values file may be under the manifest directory and already have been found
+        let canonical = fs_err::canonicalize(&values.file)?;
+        let existing = manifests.iter_mut().find(|manifest| {
+            fs_err::canonicalize(&manifest.path).is_ok_and(|path| path == canonical)
         });
+        match existing {
+            Some(manifest) => {
+                if !manifest
+                    .occurrences
+                    .iter()
+                    .any(|o| o.start == occurrence.start)
+                {
+                    manifest.occurrences.push(occurrence);
+                }
+            }
+            None => manifests.push(Manifest {
+                path: values.file.clone(),
+                occurrences: vec![occurrence],
+            }),
+        }
+    }
 
-    Ok(Workspace {
-        manifests: manifests.lock().unwrap().clone(),
-    })
+    Ok(Workspace { manifests })
 }
 
-pub(crate) fn k8s(args: crate::DeployArgs) -> eyre::Result<()> {
-    let manifest_dir = Path::new("manifests");
-    info!(
-        "Searching for manifests in: {}",
-        manifest_dir.display().bright_cyan()
-    );
-    let workspace = collect_workspace(manifest_dir, &args.image)?;
+/// A unified diff of two versions of a file. Versions are replaced within lines, so the
+/// line count never changes and lines can be compared one-to-one.
+fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    const CONTEXT: usize = 3;
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let changed: Vec<usize> = (0..old_lines.len().max(new_lines.len()))
+        .filter(|&i| old_lines.get(i) != new_lines.get(i))
+        .collect();
 
-    let (org, package_name) = match args.image.split_once('/') {
-        Some((org, name)) if !org.is_empty() && !name.is_empty() => (org, name),
-        _ => {
-            return Err(eyre::eyre!("Invalid image format. Expected 'org/name'."));
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let mut i = 0;
+    while i < changed.len() {
+        // Changes closer than twice the context end up in the same hunk
+        let mut j = i;
+        while j + 1 < changed.len() && changed[j + 1] - changed[j] <= 2 * CONTEXT {
+            j += 1;
         }
-    };
+        let start = changed[i].saturating_sub(CONTEXT);
+        let end = (changed[j] + CONTEXT + 1).min(old_lines.len());
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            start + 1,
+            end - start,
+            start + 1,
+            end - start
+        ));
+        for line in start..end {
+            if changed[i..=j].contains(&line) {
+                diff.push_str(&format!("-{}\n", old_lines[line]));
+                diff.push_str(&format!("+{}\n", new_lines[line]));
+            } else {
+                diff.push_str(&format!(" {}\n", old_lines[line]));
+            }
+        }
+        i = j + 1;
+    }
+    diff
+}
+
+/// Asks a yes/no question on the terminal. Refuses if there's no one to ask.
+fn confirm(question: &str) -> eyre::Result<bool> {
+    use std::io::{IsTerminal, Write};
+
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre::eyre!(
+            "stdin is not a terminal, pass {} to deploy without confirmation",
+            "--yes".bright_yellow()
+        ));
+    }
+    eprint!("{} [y/N] ", question.bold());
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// An image we're deploying, and where it's referenced
+struct Target<'a> {
+    image: &'a str,
+    org: &'a str,
+    package_name: &'a str,
+    workspace: Workspace,
+}
+
+impl Target<'_> {
+    /// The registry the manifests pull the image from, if they say
+    fn registry_host(&self) -> Option<&str> {
+        self.workspace
+            .manifests
+            .iter()
+            .flat_map(|manifest| &manifest.occurrences)
+            .find_map(|occurrence| occurrence.registry.as_deref())
+    }
+}
+
+/// Where `beardist k8s` looks up versions of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum SourceKind {
+    /// GHCR, through the GitHub API
+    Github,
+    /// A Forgejo (or Gitea) instance's container packages
+    Forgejo,
+    /// Any registry speaking the Docker Registry v2 API: Docker Hub, Harbor...
+    Registry,
+}
+
+/// Picks the source explicitly asked for, or guesses from the registry host: ghcr.io is
+/// GitHub, docker.io is Docker Hub's registry API, any other host is assumed to be a
+/// Forgejo instance.
+fn version_source(
+    source: Option<SourceKind>,
+    registry_host: Option<&str>,
+) -> eyre::Result<Box<dyn VersionSource>> {
+    let source = source.unwrap_or(match registry_host {
+        None | Some("ghcr.io") => SourceKind::Github,
+        Some("docker.io") => SourceKind::Registry,
+        Some(_) => SourceKind::Forgejo,
+    });
+    match source {
+        SourceKind::Github => {
+            info!("Initializing GitHub client...");
+            Ok(Box::new(ForgePackages(Box::new(GitHubClient::from_env()?))))
+        }
+        SourceKind::Forgejo => {
+            let server_url = match std::env::var("FORGEJO_SERVER_URL") {
+                Ok(url) => url,
+                Err(_) => match registry_host {
+                    Some(host) if host != "ghcr.io" && host != "docker.io" => {
+                        format!("https://{}", host)
+                    }
+                    _ => {
+                        return Err(eyre::eyre!(
+                            "Could not tell which Forgejo instance to use, set FORGEJO_SERVER_URL"
+                        ));
+                    }
+                },
+            };
+            info!("Initializing Forgejo client for {}...", server_url.cyan());
+            Ok(Box::new(ForgePackages(Box::new(ForgejoClient::from_env(
+                server_url,
+            )?))))
+        }
+        SourceKind::Registry => {
+            let host = registry_host.unwrap_or("docker.io");
+            info!("Using the registry API of {}...", host.cyan());
+            Ok(Box::new(RegistryV2::from_env(host)?))
+        }
+    }
+}
 
-    info!("YAML files containing '{}' are:", args.image.bright_cyan());
-    for manifest in &workspace.manifests {
+fn print_workspace(target: &Target) {
+    info!(
+        "YAML files containing '{}' are:",
+        target.image.bright_cyan()
+    );
+    for manifest in &target.workspace.manifests {
         info!("File: {}", manifest.path.display().bright_green());
         for occurrence in &manifest.occurrences {
             info!(
@@ -123,44 +664,152 @@ pub(crate) fn k8s(args: crate::DeployArgs) -> eyre::Result<()> {
             info!("");
         }
     }
+}
+
+/// Which versions may be deployed automatically
+struct VersionPolicy {
+    /// e.g. "^1"
+    constraint: Option<semver::VersionReq>,
+
+    /// How many minor versions we may move forward at once. Setting this also rules
+    /// out major bumps.
+    max_minor_jump: Option<u64>,
+
+    /// Tags never deployed, e.g. architecture-specific ones like "1.2.3-amd64"
+    skip_tags: Vec<Regex>,
+
+    /// Only deploy tags with this suffix, e.g. "arm64"
+    arch_suffix: Option<String>,
+}
+
+impl VersionPolicy {
+    fn allows(&self, version: &semver::Version, current_version: &str) -> bool {
+        let tag = version.to_string();
+        let tag = match &self.arch_suffix {
+            Some(suffix) => match tag.strip_suffix(suffix.as_str()) {
+                Some(tag) if tag.ends_with('-') => &tag[..tag.len() - 1],
+                _ => return false,
+            },
+            None => &tag,
+        };
+        if self.skip_tags.iter().any(|skip| skip.is_match(tag)) {
+            return false;
+        }
+        if let Some(constraint) = &self.constraint {
+            if !constraint.matches(version) {
+                return false;
+            }
+        }
+        if let Some(max_minor_jump) = self.max_minor_jump {
+            let Ok(current) = semver::Version::parse(current_version) else {
+                return false;
+            };
+            if version.major != current.major || version.minor > current.minor + max_minor_jump {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// However many webhook deliveries arrive, checks they trigger are at least this far
+/// apart: deliveries in between are coalesced into the next check
+const WEBHOOK_DEBOUNCE: Duration = Duration::from_secs(10);
 
-    info!("Initializing GitHub client...");
-    let github_client = GitHubClient::from_env()?;
+/// How to wait for new versions
+struct Polling {
+    interval: Duration,
 
-    info!("Checking for new versions...");
+    /// Give up after this long
+    timeout: Option<Duration>,
+
+    /// Webhook deliveries: they trigger a check ahead of the next poll, at most once
+    /// every [`WEBHOOK_DEBOUNCE`]
+    events: Option<mpsc::Receiver<()>>,
+}
+
+/// Polls the registry until every target has a version that differs from its manifests,
+/// then returns those versions (in the same order as `targets`)
+fn wait_for_new_versions(
+    targets: &[Target],
+    sources: &[Box<dyn VersionSource>],
+    policy: &VersionPolicy,
+    polling: &Polling,
+) -> eyre::Result<Vec<String>> {
+    let mut new_versions: Vec<Option<String>> = vec![None; targets.len()];
     let mut spinner = ['|', '/', '-', '\\'].iter().cycle();
-    let mut last_check_time = std::time::Instant::now();
-    let new_version = loop {
-        let latest_version = github_client.get_latest_container_version(org, package_name)?;
-
-        if let Some(version) = latest_version {
-            // Skip versions that end with -amd64 or -arm64
-            if version.ends_with("-amd64") || version.ends_with("-arm64") {
-                info!("Skipping architecture-specific version: {}", version);
-                std::thread::sleep(std::time::Duration::from_secs(1));
-                last_check_time = std::time::Instant::now();
+    let start_time = Instant::now();
+    let mut last_check_time;
+    loop {
+        for (i, (target, new_version)) in targets.iter().zip(new_versions.iter_mut()).enumerate() {
+            if new_version.is_some() {
                 continue;
             }
+            let versions = sources[i].versions(target.org, target.package_name)?;
+            let current_version = &target.workspace.manifests[0].occurrences[0].current_version;
+            let latest_version = versions
+                .iter()
+                .find(|version| policy.allows(version, current_version))
+                .map(|version| version.to_string());
+            if let Some(skipped) = versions
+                .first()
+                .filter(|v| Some(v.to_string()) != latest_version)
+            {
+                debug!(
+                    "Not considering {} {}: excluded by version policy",
+                    target.image, skipped
+                );
+            }
 
-            let is_new_version = workspace.manifests.iter().any(|manifest| {
-                manifest
-                    .occurrences
-                    .iter()
-                    .any(|occurrence| occurrence.current_version != version)
-            });
+            if let Some(version) = latest_version {
+                let is_new_version = target.workspace.manifests.iter().any(|manifest| {
+                    manifest
+                        .occurrences
+                        .iter()
+                        .any(|occurrence| occurrence.current_version != version)
+                });
 
-            if is_new_version {
-                eprintln!("\r\x1B[KNew version detected: {}", version.bright_green());
-                break version;
+                if is_new_version {
+                    eprintln!(
+                        "\r\x1B[KNew version of {} detected: {}",
+                        target.image.bright_cyan(),
+                        version.bright_green()
+                    );
+                    *new_version = Some(version);
+                }
             }
         }
+        last_check_time = Instant::now();
 
+        if new_versions.iter().all(Option::is_some) {
+            return Ok(new_versions.into_iter().map(Option::unwrap).collect());
+        }
+
+        if let Some(timeout) = polling.timeout {
+            if start_time.elapsed() >= timeout {
+                return Err(eyre::eyre!(
+                    "Timed out after {}s waiting for new versions",
+                    timeout.as_secs()
+                ));
+            }
+        }
+
+        let mut webhook_pending = false;
         loop {
-            std::thread::sleep(std::time::Duration::from_millis(100));
+            let tick = Duration::from_millis(100);
+            let notified = match &polling.events {
+                Some(events) => events.recv_timeout(tick).is_ok(),
+                None => {
+                    std::thread::sleep(tick);
+                    false
+                }
+            };
             let elapsed = last_check_time.elapsed();
             eprint!(
-                "\r\x1B[K{} Checking for new versions... Last checked: {}",
+                "\r\x1B[K{} Checking for new versions ({}/{} ready)... Last checked: {}",
                 spinner.next().unwrap().bright_cyan(),
+                new_versions.iter().filter(|v| v.is_some()).count(),
+                targets.len(),
                 format!(
                     "{:02}:{:02} ago",
                     elapsed.as_secs() / 60,
@@ -168,45 +817,956 @@ pub(crate) fn k8s(args: crate::DeployArgs) -> eyre::Result<()> {
                 )
                 .bright_yellow()
             );
-            if elapsed >= std::time::Duration::from_secs(2) {
+            webhook_pending |= notified;
+            let webhook_due = webhook_pending && elapsed >= WEBHOOK_DEBOUNCE;
+            if webhook_due || elapsed >= polling.interval {
+                if webhook_due {
+                    eprintln!("\r\x1B[KWebhook received, checking now");
+                }
                 eprint!(
                     "\r                                                                                      \r"
                 );
                 break;
             }
         }
-        last_check_time = std::time::Instant::now(); // Update last_check_time after each check
-    };
+    }
+}
 
-    info!("Updating manifests...");
+/// A workload whose rollout `kubectl rollout status` can wait for
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Workload {
+    kind: String,
+    name: String,
+    namespace: Option<String>,
+}
 
-    for manifest in &workspace.manifests {
-        let mut contents = fs_err::read_to_string(&manifest.path)?;
-        for occurrence in &manifest.occurrences {
-            let before = &contents[..occurrence.start];
-            let after = &contents[occurrence.end..];
-            let new_image_line = format!("image: ghcr.io/{}:v{}", args.image, new_version);
-            contents = format!("{}{}{}", before, new_image_line, after);
+/// Finds the Deployments, StatefulSets and DaemonSets defined in a (multi-document) manifest
+fn find_workloads(contents: &str) -> Vec<Workload> {
+    let mut workloads = Vec::new();
+    let mut documents = Vec::new();
+    let mut document = String::new();
+    for line in contents.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            documents.push(std::mem::take(&mut document));
+        } else {
+            document.push_str(line);
         }
-        fs_err::write(&manifest.path, contents)?;
-        info!("Updated {}", manifest.path.display().bright_green());
     }
+    documents.push(document);
 
-    info!("Deploying manifests...");
-    let mut deploy_cmd = std::process::Command::new("./deploy");
+    for document in &documents {
+        let value = |path: &str| yaml::find_value(document, path).map(|r| document[r].to_string());
+        let Some(kind) = value("kind") else {
+            continue;
+        };
+        if !matches!(kind.as_str(), "Deployment" | "StatefulSet" | "DaemonSet") {
+            continue;
+        }
+        let Some(name) = value("metadata.name") else {
+            continue;
+        };
+        workloads.push(Workload {
+            kind,
+            name,
+            namespace: value("metadata.namespace"),
+        });
+    }
+    workloads
+}
+
+/// Waits for the workloads in the given manifests to finish rolling out
+fn wait_for_rollout(paths: &[&Path], timeout_secs: u64) -> eyre::Result<()> {
+    let mut workloads = Vec::new();
+    for path in paths {
+        workloads.extend(find_workloads(&fs_err::read_to_string(path)?));
+    }
+    workloads.sort();
+    workloads.dedup();
+    if workloads.is_empty() {
+        warn!("No Deployments, StatefulSets or DaemonSets found, not waiting for a rollout");
+        return Ok(());
+    }
+
+    for workload in &workloads {
+        info!(
+            "Waiting for rollout of {}/{}...",
+            workload.kind.to_lowercase().cyan(),
+            workload.name.cyan()
+        );
+        let mut cmd = std::process::Command::new("kubectl");
+        cmd.arg("rollout")
+            .arg("status")
+            .arg(format!(
+                "{}/{}",
+                workload.kind.to_lowercase(),
+                workload.name
+            ))
+            .arg(format!("--timeout={}s", timeout_secs));
+        if let Some(namespace) = &workload.namespace {
+            cmd.arg("--namespace").arg(namespace);
+        }
+        let status = cmd
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::inherit())
+            .stderr(std::process::Stdio::inherit())
+            .status()?;
+        if !status.success() {
+            return Err(eyre::eyre!(
+                "Rollout of {}/{} did not complete: {}",
+                workload.kind.to_lowercase(),
+                workload.name,
+                status
+            ));
+        }
+    }
+    info!("✅ All {} workloads rolled out", workloads.len());
+    Ok(())
+}
+
+/// Setting every occurrence of an image in a manifest to a version
+struct Bump<'a> {
+    image: &'a str,
+    manifest: &'a Manifest,
+    version: String,
+}
 
-    // Add all updated manifest paths as arguments
-    for manifest in &workspace.manifests {
-        deploy_cmd.arg(manifest.path.as_os_str());
+/// How `beardist k8s` reports what it's about to do
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Show a diff, then write the manifests and deploy
+    #[default]
+    Text,
+    /// Print the planned changes as JSON on stdout, without applying them
+    Json,
+}
+
+/// A version that would be replaced, for `--output json`
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct PlannedChange<'a> {
+    image: &'a str,
+    file: String,
+    line: usize,
+    old_version: &'a str,
+    new_version: &'a str,
+}
+
+fn plan<'a>(bumps: &'a [Bump]) -> Vec<PlannedChange<'a>> {
+    bumps
+        .iter()
+        .flat_map(|bump| {
+            bump.manifest
+                .occurrences
+                .iter()
+                .map(move |occurrence| PlannedChange {
+                    image: bump.image,
+                    file: bump.manifest.path.display().to_string(),
+                    line: occurrence.line,
+                    old_version: &occurrence.current_version,
+                    new_version: &bump.version,
+                })
+        })
+        .collect()
+}
+
+fn print_plan(bumps: &[Bump]) -> eyre::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&plan(bumps))?);
+    Ok(())
+}
+
+/// Shows the changes, asks for confirmation, writes the manifests and runs the deploy
+/// script. Returns how the deploy script exited, if it ran.
+fn apply_and_deploy(
+    bumps: &[Bump],
+    deploy_command: &[String],
+    dry_run: bool,
+    yes: bool,
+) -> eyre::Result<Option<std::process::ExitStatus>> {
+    // A manifest may reference several of our images: collect every replacement for
+    // a file, then apply them back-to-front so the offsets stay valid.
+    let mut replacements: BTreeMap<&Path, Vec<(&ImageOccurrence, &str)>> = BTreeMap::new();
+    for bump in bumps {
+        for occurrence in &bump.manifest.occurrences {
+            replacements
+                .entry(&bump.manifest.path)
+                .or_default()
+                .push((occurrence, &bump.version));
+        }
+    }
+    let mut changes = Vec::new();
+    for (path, file_replacements) in replacements.iter_mut() {
+        let old_contents = fs_err::read_to_string(path)?;
+        let mut contents = old_contents.clone();
+        file_replacements.sort_by(|a, b| b.0.start.cmp(&a.0.start));
+        file_replacements.dedup_by_key(|(occurrence, _)| occurrence.start);
+        let mut previous_start = contents.len();
+        for (occurrence, new_version) in file_replacements.iter() {
+            // We may have waited a long time for a new version: don't write over edits
+            // made meanwhile
+            if occurrence.end > previous_start
+                || old_contents.get(occurrence.start..occurrence.end)
+                    != Some(occurrence.current_version.as_str())
+            {
+                return Err(eyre::eyre!(
+                    "{} changed since it was scanned, run again",
+                    path.display().to_string().cyan()
+                ));
+            }
+            contents.replace_range(occurrence.start..occurrence.end, new_version);
+            previous_start = occurrence.start;
+        }
+        changes.push((*path, old_contents, contents));
+    }
+
+    info!("Changes:");
+    for (path, old_contents, contents) in &changes {
+        let diff = unified_diff(&path.display().to_string(), old_contents, contents);
+        for line in diff.lines() {
+            if line.starts_with("+++") || line.starts_with("---") {
+                eprintln!("{}", line.bold());
+            } else if line.starts_with('+') {
+                eprintln!("{}", line.green());
+            } else if line.starts_with('-') {
+                eprintln!("{}", line.red());
+            } else if line.starts_with("@@") {
+                eprintln!("{}", line.cyan());
+            } else {
+                eprintln!("{}", line);
+            }
+        }
+    }
+
+    if dry_run {
+        warn!("Not writing manifests or deploying (dry run)");
+        return Ok(None);
     }
+    if !yes && !confirm("Write these changes and deploy?")? {
+        warn!("Deployment cancelled");
+        return Ok(None);
+    }
+
+    info!("Updating manifests...");
+    for (path, _, contents) in &changes {
+        fs_err::write(path, contents)?;
+        info!("Updated {}", path.display().bright_green());
+    }
+
+    info!("Deploying manifests...");
+    let files: Vec<&Path> = replacements.keys().copied().collect();
+    let version = bumps
+        .first()
+        .map(|bump| bump.version.as_str())
+        .unwrap_or("");
+    let deploy_command = expand_deploy_command(deploy_command, &files, version);
+    let (program, deploy_args) = deploy_command
+        .split_first()
+        .ok_or_else(|| eyre::eyre!("The deploy command is empty"))?;
+    info!("Running {}", deploy_command.join(" ").cyan());
+    let mut deploy_cmd = std::process::Command::new(program);
+    deploy_cmd.args(deploy_args);
 
-    deploy_cmd
+    let status = deploy_cmd
         .stdin(std::process::Stdio::inherit())
         .stdout(std::process::Stdio::inherit())
         .stderr(std::process::Stdio::inherit())
         .spawn()?
         .wait()?;
+    Ok(Some(status))
+}
+
+/// Versions of `image` the manifests in the deploy repository at `dir` reference, with
+/// the file each is in
+pub(crate) fn image_versions(dir: &Path, image: &str) -> eyre::Result<Vec<(PathBuf, String)>> {
+    let config = load_deploy_config_in(dir)?;
+    let registries = config
+        .as_ref()
+        .map(|config| config.registries.clone())
+        .unwrap_or_else(default_registries);
+    let search = ManifestSearch {
+        dir: dir.join(
+            config
+                .as_ref()
+                .map(|config| config.manifest_dir.clone())
+                .unwrap_or_else(default_manifest_dir),
+        ),
+        include: config
+            .as_ref()
+            .map(|config| config.include.clone())
+            .unwrap_or_default(),
+        exclude: config
+            .as_ref()
+            .map(|config| config.exclude.clone())
+            .unwrap_or_default(),
+        follow_symlinks: true,
+    };
+    // Helm values files are relative to the deploy repository too
+    let helm: Vec<HelmValues> = config
+        .map(|config| config.helm)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|values| HelmValues {
+            file: dir.join(values.file),
+            ..values
+        })
+        .collect();
+    let workspace = collect_workspace(&search, &registries, image, &helm)?;
+    Ok(workspace
+        .manifests
+        .into_iter()
+        .flat_map(|manifest| {
+            manifest
+                .occurrences
+                .into_iter()
+                .map(move |occurrence| (manifest.path.clone(), occurrence.current_version))
+        })
+        .collect())
+}
+
+pub(crate) fn k8s(args: crate::DeployArgs) -> eyre::Result<()> {
+    info!("Loading deploy {}...", "configuration".cyan());
+    let config = load_deploy_config()?;
+    let images = if let Some(image) = &args.rollback {
+        vec![image.clone()]
+    } else if args.all {
+        config
+            .as_ref()
+            .map(|config| config.images.clone())
+            .ok_or_else(|| eyre::eyre!("--all requires a .beardist-deploy.json"))?
+    } else {
+        args.images
+    };
+    let registries = if !args.registries.is_empty() {
+        args.registries
+    } else {
+        config
+            .as_ref()
+            .map(|config| config.registries.clone())
+            .unwrap_or_else(default_registries)
+    };
+    let manifest_dir = args
+        .manifest_dir
+        .clone()
+        .or_else(|| config.as_ref().map(|config| config.manifest_dir.clone()))
+        .unwrap_or_else(default_manifest_dir);
+    let globs = |from_args: Vec<String>, from_config: fn(&DeployConfig) -> &Vec<String>| {
+        if !from_args.is_empty() {
+            from_args
+        } else {
+            config
+                .as_ref()
+                .map(from_config)
+                .cloned()
+                .unwrap_or_default()
+        }
+    };
+    let search = ManifestSearch {
+        include: globs(args.include, |config| &config.include),
+        exclude: globs(args.exclude, |config| &config.exclude),
+        dir: manifest_dir,
+        follow_symlinks: !args.no_follow_symlinks,
+    };
+    let deploy_command = match &args.deploy_command {
+        Some(command) => command.split_whitespace().map(String::from).collect(),
+        None => config
+            .as_ref()
+            .map(|config| config.deploy_command.clone())
+            .unwrap_or_else(default_deploy_command),
+    };
+    let skip_tags = if !args.skip_tags.is_empty() {
+        args.skip_tags.clone()
+    } else {
+        config
+            .as_ref()
+            .map(|config| config.skip_tags.clone())
+            .unwrap_or_else(default_skip_tags)
+    };
+    let arch_suffix = args.arch_suffix.clone().or_else(|| {
+        config
+            .as_ref()
+            .and_then(|config| config.arch_suffix.clone())
+    });
+    let audit = config
+        .as_ref()
+        .map(|config| config.audit.clone())
+        .unwrap_or_default();
+    let helm = config.map(|config| config.helm).unwrap_or_default();
+    if images.is_empty() {
+        return Err(eyre::eyre!("No images to deploy"));
+    }
+
+    info!(
+        "Searching for manifests in: {}",
+        search.dir.display().bright_cyan()
+    );
+    let only = args
+        .only
+        .iter()
+        .map(|glob| {
+            glob::Pattern::new(glob).wrap_err_with(|| format!("Invalid --only glob {:?}", glob))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let mut targets = Vec::new();
+    for image in &images {
+        let (org, package_name) = match image.split_once('/') {
+            Some((org, name)) if !org.is_empty() && !name.is_empty() => (org, name),
+            _ => {
+                return Err(eyre::eyre!(
+                    "Invalid image format for '{}'. Expected 'org/name'.",
+                    image
+                ));
+            }
+        };
+        let mut workspace = collect_workspace(&search, &registries, image, &helm)?;
+        workspace.select_manifests(&search.dir, &only, args.env.as_deref());
+        if workspace.manifests.is_empty() {
+            return Err(eyre::eyre!(
+                "No manifests{} reference image '{}'",
+                if only.is_empty() && args.env.is_none() {
+                    ""
+                } else {
+                    " selected by --only or --env"
+                },
+                image.bright_cyan()
+            ));
+        }
+        if only.is_empty() && args.env.is_none() {
+            pick_versions(image, &mut workspace, args.yes)?;
+        }
+        let target = Target {
+            image,
+            org,
+            package_name,
+            workspace,
+        };
+        print_workspace(&target);
+        targets.push(target);
+    }
+
+    let mut history = history::History::load()?;
+    let mut bumps = Vec::new();
+    if args.rollback.is_some() {
+        let target = &targets[0];
+        let current_version = &target.workspace.manifests[0].occurrences[0].current_version;
+        let deployment = history
+            .last_deployment_of(target.image, current_version)
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "No recorded deployment of {} {} to roll back",
+                    target.image.cyan(),
+                    current_version.bright_yellow()
+                )
+            })?;
+        for manifest in &target.workspace.manifests {
+            if let Some(version) = deployment
+                .previous
+                .get(&manifest.path.display().to_string())
+            {
+                bumps.push(Bump {
+                    image: target.image,
+                    manifest,
+                    version: version.clone(),
+                });
+            }
+        }
+    } else {
+        if let Some(version) = &args.version {
+            let version = version.to_string();
+            targets.retain(|target| {
+                let deployed = target.workspace.manifests.iter().all(|manifest| {
+                    manifest
+                        .occurrences
+                        .iter()
+                        .all(|occurrence| occurrence.current_version == version)
+                });
+                if deployed {
+                    info!(
+                        "{} is already at {}",
+                        target.image.cyan(),
+                        version.bright_green()
+                    );
+                }
+                !deployed
+            });
+            if targets.is_empty() {
+                if args.output == OutputFormat::Json {
+                    print_plan(&[])?;
+                }
+                return Ok(());
+            }
+        }
+        let sources = targets
+            .iter()
+            .map(|target| match &args.version {
+                Some(version) => Ok(Box::new(Fixed(version.clone())) as Box<dyn VersionSource>),
+                None => version_source(args.source, target.registry_host()),
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let polling = Polling {
+            interval: Duration::from_secs(args.poll_interval),
+            timeout: args.timeout.map(Duration::from_secs),
+            events: args
+                .listen
+                .as_deref()
+                .map(|addr| {
+                    let secret = credentials::require("BEARDIST_WEBHOOK_SECRET")
+                        .wrap_err("--listen needs a secret to authenticate deliveries")?;
+                    webhook::listen(addr, secret)
+                })
+                .transpose()?,
+        };
+
+        let policy = VersionPolicy {
+            constraint: args
+                .constraint
+                .as_deref()
+                .map(semver::VersionReq::parse)
+                .transpose()
+                .wrap_err("Invalid --constraint")?,
+            max_minor_jump: args.max_minor_jump,
+            // `--version` is deployed whatever its tag looks like
+            skip_tags: match args.version {
+                Some(_) => Vec::new(),
+                None => skip_tags
+                    .iter()
+                    .map(|skip| {
+                        Regex::new(skip)
+                            .wrap_err_with(|| format!("Invalid skip_tags regex {:?}", skip))
+                    })
+                    .collect::<eyre::Result<_>>()?,
+            },
+            arch_suffix: arch_suffix.filter(|_| args.version.is_none()),
+        };
+
+        info!("Checking for new versions...");
+        let new_versions = wait_for_new_versions(&targets, &sources, &policy, &polling)?;
+        for (target, new_version) in targets.iter().zip(new_versions) {
+            for manifest in &target.workspace.manifests {
+                bumps.push(Bump {
+                    image: target.image,
+                    manifest,
+                    version: new_version.clone(),
+                });
+            }
+        }
+    }
+
+    if args.output == OutputFormat::Json {
+        return print_plan(&bumps);
+    }
+
+    let dry_run = std::env::var("DRY_RUN").is_ok();
+    let Some(status) = apply_and_deploy(&bumps, &deploy_command, dry_run, args.yes)? else {
+        return Ok(());
+    };
+    audit::record(
+        &audit,
+        &audit::Entry {
+            timestamp: history::now(),
+            operator: audit::operator(&ProcessEnvironment),
+            rollback: args.rollback.is_some(),
+            changes: bumps
+                .iter()
+                .flat_map(|bump| {
+                    bump.manifest
+                        .occurrences
+                        .iter()
+                        .map(|occurrence| audit::Change {
+                            image: bump.image.to_string(),
+                            manifest: bump.manifest.path.display().to_string(),
+                            from: occurrence.current_version.clone(),
+                            to: bump.version.clone(),
+                        })
+                })
+                .collect(),
+            exit_code: status.code(),
+        },
+    )?;
+    if !status.success() {
+        return Err(eyre::eyre!("Deploy script failed with {}", status));
+    }
+
+    for target in &targets {
+        let target_bumps: Vec<&Bump> = bumps
+            .iter()
+            .filter(|bump| bump.image == target.image)
+            .collect();
+        let Some(first) = target_bumps.first() else {
+            continue;
+        };
+        history.record(history::Deployment {
+            image: target.image.to_string(),
+            version: first.version.clone(),
+            previous: target_bumps
+                .iter()
+                .map(|bump| {
+                    (
+                        bump.manifest.path.display().to_string(),
+                        bump.manifest.occurrences[0].current_version.clone(),
+                    )
+                })
+                .collect(),
+            rollback: args.rollback.is_some(),
+            timestamp: history::now(),
+        });
+    }
+    history.save()?;
+
+    if args.commit {
+        let verb = if args.rollback.is_some() {
+            "roll back"
+        } else {
+            "bump"
+        };
+        let mut bumped: Vec<String> = Vec::new();
+        for bump in &bumps {
+            let entry = format!("{} to {}", bump.image, bump.version);
+            if !bumped.contains(&entry) {
+                bumped.push(entry);
+            }
+        }
+        let message = format!("deploy: {} {}", verb, bumped.join(", "));
+
+        let mut paths: Vec<String> = bumps
+            .iter()
+            .map(|bump| bump.manifest.path.display().to_string())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths.push(history::HISTORY_PATH.to_string());
+        paths.push(audit.log.display().to_string());
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        crate::git::commit_paths(&paths, &message, args.push)?;
+    }
+
+    if args.wait {
+        let mut paths: Vec<&Path> = bumps
+            .iter()
+            .map(|bump| bump.manifest.path.as_path())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        wait_for_rollout(&paths, args.rollout_timeout)?;
+    }
 
     info!("Deployment process completed successfully.");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_deploy_command() {
+        let command: Vec<String> = ["kubectl", "apply", "-f", "{files}", "--tag=v{version}"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let files = [Path::new("manifests/a.yaml"), Path::new("manifests/b.yaml")];
+        assert_eq!(
+            expand_deploy_command(&command, &files, "1.2.3"),
+            vec![
+                "kubectl",
+                "apply",
+                "-f",
+                "manifests/a.yaml",
+                "manifests/b.yaml",
+                "--tag=v1.2.3"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manifest_search() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("manifests");
+        for file in [
+            "home.yaml",
+            "home.tmpl.yaml",
+            "notes.txt",
+            "apps/web.yml",
+            "generated/out.yaml",
+            "elsewhere/linked.yaml",
+        ] {
+            let path = dir.join(file);
+            fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+            fs_err::write(&path, "").unwrap();
+        }
+        // Not a git checkout: .gitignore still applies
+        fs_err::write(dir.join(".gitignore"), "generated/\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("elsewhere"), dir.join("apps/linked")).unwrap();
+
+        let files = |include: &[&str], exclude: &[&str], follow_symlinks| {
+            let search = ManifestSearch {
+                dir: dir.clone(),
+                include: include.iter().map(|s| s.to_string()).collect(),
+                exclude: exclude.iter().map(|s| s.to_string()).collect(),
+                follow_symlinks,
+            };
+            let mut files: Vec<String> = search
+                .walker()
+                .unwrap()
+                .build()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+                .filter(|entry| is_yaml(entry.path()))
+                .map(|entry| {
+                    let path = entry.path().strip_prefix(&dir).unwrap();
+                    path.to_string_lossy().replace('\\', "/")
+                })
+                .collect();
+            files.sort();
+            files
+        };
+
+        let mut all = vec![
+            "apps/web.yml",
+            "elsewhere/linked.yaml",
+            "home.tmpl.yaml",
+            "home.yaml",
+        ];
+        #[cfg(unix)]
+        all.insert(0, "apps/linked/linked.yaml");
+        assert_eq!(files(&[], &[], true), all);
+        assert_eq!(
+            files(&[], &["*.tmpl.yaml", "elsewhere/**"], false),
+            ["apps/web.yml", "home.yaml"]
+        );
+        assert_eq!(files(&["apps/**"], &[], false), ["apps/web.yml"]);
+    }
+
+    #[test]
+    fn test_select_occurrences() {
+        let manifest = |path: &str, versions: &[&str]| {
+            let contents: String = versions
+                .iter()
+                .map(|version| format!("image: ghcr.io/bearcove/home:v{}\n", version))
+                .collect();
+            Manifest {
+                path: Path::new("manifests").join(path),
+                occurrences: versions
+                    .iter()
+                    .map(|version| {
+                        let start = contents.find(&format!("v{}", version)).unwrap();
+                        ImageOccurrence::new(&contents, start, start + version.len() + 1, None)
+                    })
+                    .collect(),
+            }
+        };
+        let workspace = Workspace {
+            manifests: vec![
+                manifest("overlays/prod/home.yaml", &["1.2.3"]),
+                manifest("overlays/canary/home.yaml", &["1.3.0"]),
+                manifest("home-prod.yaml", &["1.2.3", "1.3.0"]),
+            ],
+        };
+        let paths = |workspace: &Workspace| -> Vec<String> {
+            workspace
+                .manifests
+                .iter()
+                .map(|m| m.path.display().to_string().replace('\\', "/"))
+                .collect()
+        };
+
+        let versions = workspace.versions();
+        assert_eq!(
+            versions.keys().copied().collect::<Vec<_>>(),
+            ["1.2.3", "1.3.0"]
+        );
+        assert_eq!(versions["1.3.0"].len(), 2);
+
+        let mut prod = workspace.clone();
+        prod.select_manifests(Path::new("manifests"), &[], Some("prod"));
+        assert_eq!(
+            paths(&prod),
+            [
+                "manifests/overlays/prod/home.yaml",
+                "manifests/home-prod.yaml"
+            ]
+        );
+
+        let mut canary = workspace.clone();
+        let only = [glob::Pattern::new("overlays/canary/*").unwrap()];
+        canary.select_manifests(Path::new("manifests"), &only, None);
+        assert_eq!(paths(&canary), ["manifests/overlays/canary/home.yaml"]);
+
+        let mut stable = workspace.clone();
+        stable.keep_versions(&["1.2.3".to_string()]);
+        assert_eq!(
+            paths(&stable),
+            [
+                "manifests/overlays/prod/home.yaml",
+                "manifests/home-prod.yaml"
+            ]
+        );
+        assert_eq!(stable.manifests[1].occurrences.len(), 1);
+
+        assert_eq!(parse_selection("\n", 2), Some(vec![0, 1]));
+        assert_eq!(parse_selection("2", 2), Some(vec![1]));
+        assert_eq!(parse_selection("1, 2", 2), Some(vec![0, 1]));
+        assert_eq!(parse_selection("3", 2), None);
+    }
+
+    #[test]
+    fn test_plan() {
+        let contents = "spec:\n  containers:\n    - image: ghcr.io/bearcove/home:v1.2.3\n";
+        let start = contents.find("v1.2.3").unwrap();
+        let manifest = Manifest {
+            path: PathBuf::from("manifests/home.yaml"),
+            occurrences: vec![ImageOccurrence::new(
+                contents,
+                start,
+                start + "v1.2.3".len(),
+                Some("ghcr.io".to_string()),
+            )],
+        };
+        let bumps = [Bump {
+            image: "bearcove/home",
+            manifest: &manifest,
+            version: "1.3.0".to_string(),
+        }];
+        assert_eq!(
+            serde_json::to_value(plan(&bumps)).unwrap(),
+            serde_json::json!([{
+                "image": "bearcove/home",
+                "file": "manifests/home.yaml",
+                "line": 3,
+                "old_version": "1.2.3",
+                "new_version": "1.3.0",
+            }])
+        );
+    }
+
+    #[test]
+    fn test_version_policy() {
+        let v = |s: &str| semver::Version::parse(s).unwrap();
+
+        let skip_tags: Vec<Regex> = default_skip_tags()
+            .iter()
+            .map(|skip| Regex::new(skip).unwrap())
+            .collect();
+        let policy = VersionPolicy {
+            constraint: None,
+            max_minor_jump: None,
+            skip_tags: skip_tags.clone(),
+            arch_suffix: None,
+        };
+        assert!(policy.allows(&v("2.0.0"), "1.2.3"));
+        assert!(!policy.allows(&v("1.3.0-amd64"), "1.2.3"));
+
+        let policy = VersionPolicy {
+            constraint: None,
+            max_minor_jump: None,
+            skip_tags: vec![Regex::new("-rc").unwrap()],
+            arch_suffix: Some("arm64".to_string()),
+        };
+        assert!(policy.allows(&v("1.3.0-arm64"), "1.2.3-arm64"));
+        assert!(!policy.allows(&v("1.3.0"), "1.2.3-arm64"));
+        assert!(!policy.allows(&v("1.3.0-amd64"), "1.2.3-arm64"));
+        assert!(!policy.allows(&v("1.3.0-rc.1-arm64"), "1.2.3-arm64"));
+
+        let policy = VersionPolicy {
+            constraint: Some(semver::VersionReq::parse("^1").unwrap()),
+            max_minor_jump: None,
+            skip_tags: Vec::new(),
+            arch_suffix: None,
+        };
+        assert!(policy.allows(&v("1.9.0"), "1.2.3"));
+        assert!(!policy.allows(&v("2.0.0"), "1.2.3"));
+
+        let policy = VersionPolicy {
+            constraint: None,
+            max_minor_jump: Some(1),
+            skip_tags,
+            arch_suffix: None,
+        };
+        assert!(policy.allows(&v("1.3.5"), "1.2.3"));
+        assert!(!policy.allows(&v("1.4.0"), "1.2.3"));
+        assert!(!policy.allows(&v("2.0.0"), "1.2.3"));
+    }
+
+    #[test]
+    fn test_find_workloads() {
+        let manifest = "\
+apiVersion: v1
+kind: Service
+metadata:
+  name: home
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: home
+  namespace: web
+spec:
+  template:
+    spec:
+      containers:
+        - name: home
+          image: ghcr.io/bearcove/home:v1.2.3
+";
+        assert_eq!(
+            find_workloads(manifest),
+            vec![Workload {
+                kind: "Deployment".to_string(),
+                name: "home".to_string(),
+                namespace: Some("web".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old =
+            "a\nb\nc\nd\nimage: ghcr.io/x/y:v1\ne\nf\ng\nh\ni\nj\nk\nl\nimage: ghcr.io/x/y:v1\n";
+        let new = old.replace(":v1", ":v2");
+        assert_eq!(
+            unified_diff("m.yaml", old, &new),
+            "\
+--- a/m.yaml
++++ b/m.yaml
+@@ -2,7 +2,7 @@
+ b
+ c
+ d
+-image: ghcr.io/x/y:v1
++image: ghcr.io/x/y:v2
+ e
+ f
+ g
+@@ -11,4 +11,4 @@
+ j
+ k
+ l
+-image: ghcr.io/x/y:v1
++image: ghcr.io/x/y:v2
+"
+        );
+    }
+
+    #[test]
+    fn test_image_regex() {
+        let regex = image_regex(&default_registries(), "bearcove/home");
+        let captures = regex.captures("ghcr.io/bearcove/home:v1.2.3").unwrap();
+        assert_eq!(&captures[1], "ghcr.io/");
+        assert_eq!(&captures[2], "1.2.3");
+        assert!(!regex.is_match("bearcove/home:v1.2.3"));
+        assert!(!regex.is_match("ghcr.io/bearcove/homepage:v1.2.3"));
+
+        let registries = vec!["registry.example.com".to_string(), "docker.io".to_string()];
+        let regex = image_regex(&registries, "bearcove/home");
+        assert_eq!(
+            &regex
+                .captures("registry.example.com/bearcove/home:v2")
+                .unwrap()[1],
+            "registry.example.com/"
+        );
+        assert_eq!(
+            &regex.captures("docker.io/bearcove/home:v2").unwrap()[1],
+            "docker.io/"
+        );
+        assert_eq!(&regex.captures("bearcove/home:v2").unwrap()[1], "");
+        assert!(!regex.is_match("ghcr.io/bearcove/home:v2"));
+    }
+}
@@ -0,0 +1,209 @@
+//! An append-only record of every deployment `beardist k8s` performs, one JSON object
+//! per line, so "what changed at 14:32" has an answer. Entries can also be posted to
+//! webhooks.
+
+use log::{info, warn};
+use owo_colors::OwoColorize;
+use reqwest::blocking::Client;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    USER_AGENT,
+    environment::Environment,
+    notify::{Webhook, WebhookKind},
+};
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AuditConfig {
+    /// JSONL file deployments are appended to, relative to the deploy repository
+    #[serde(default = "default_log")]
+    pub(super) log: PathBuf,
+
+    /// Also post each deployment to these webhooks
+    #[serde(default)]
+    webhooks: Vec<Webhook>,
+}
+
+fn default_log() -> PathBuf {
+    PathBuf::from(".beardist-deploy-audit.jsonl")
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            log: default_log(),
+            webhooks: Vec::new(),
+        }
+    }
+}
+
+/// A manifest whose version of an image was changed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(super) struct Change {
+    pub(super) image: String,
+    pub(super) manifest: String,
+    pub(super) from: String,
+    pub(super) to: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(super) struct Entry {
+    /// Seconds since the Unix epoch
+    pub(super) timestamp: u64,
+
+    /// Who deployed, e.g. "amos@builder" or a CI actor
+    pub(super) operator: String,
+
+    pub(super) rollback: bool,
+    pub(super) changes: Vec<Change>,
+
+    /// Of the deploy command, `None` if it was killed by a signal
+    pub(super) exit_code: Option<i32>,
+}
+
+impl Entry {
+    fn text(&self) -> String {
+        let mut images: Vec<String> = Vec::new();
+        for change in &self.changes {
+            let image = format!("{} {} → {}", change.image, change.from, change.to);
+            if !images.contains(&image) {
+                images.push(image);
+            }
+        }
+        format!(
+            "{} {} {} by {}{}",
+            if self.exit_code == Some(0) {
+                "🚀"
+            } else {
+                "❌"
+            },
+            if self.rollback {
+                "Rolled back"
+            } else {
+                "Deployed"
+            },
+            images.join(", "),
+            self.operator,
+            match self.exit_code {
+                Some(0) => String::new(),
+                Some(code) => format!(" (deploy command exited with {})", code),
+                None => " (deploy command was killed)".to_string(),
+            }
+        )
+    }
+
+    fn payload(&self, kind: WebhookKind) -> serde_json::Value {
+        match kind {
+            WebhookKind::Slack => serde_json::json!({ "text": self.text() }),
+            WebhookKind::Discord => serde_json::json!({ "content": self.text() }),
+            WebhookKind::Generic => {
+                let mut payload = serde_json::to_value(self).unwrap();
+                payload["event"] = "deploy".into();
+                payload["text"] = self.text().into();
+                payload
+            }
+        }
+    }
+}
+
+/// Who's deploying: `BEARDIST_OPERATOR` if set, the CI actor in CI, or user@host
+pub(super) fn operator(env: &dyn Environment) -> String {
+    if let Some(operator) = env.var("BEARDIST_OPERATOR") {
+        return operator;
+    }
+    if let Some((_, actor)) = env.ci_var("ACTOR") {
+        return actor;
+    }
+    let user = env
+        .var("USER")
+        .or_else(|| env.var("USERNAME"))
+        .unwrap_or_else(|| "unknown".to_string());
+    match hostname::get().ok().and_then(|h| h.into_string().ok()) {
+        Some(host) => format!("{}@{}", user, host),
+        None => user,
+    }
+}
+
+fn append(log: &Path, entry: &Entry) -> eyre::Result<()> {
+    let mut file = fs_err::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Appends `entry` to the log and posts it to the webhooks. Webhooks failing only gets
+/// logged: the deployment happened either way.
+pub(super) fn record(config: &AuditConfig, entry: &Entry) -> eyre::Result<()> {
+    append(&config.log, entry)?;
+    info!("📝 Recorded in {}", config.log.display().cyan());
+
+    let client = Client::new();
+    for webhook in &config.webhooks {
+        let sent = crate::credentials::require(&webhook.url_env).and_then(|url| {
+            client
+                .post(&url)
+                .header("User-Agent", USER_AGENT)
+                .json(&entry.payload(webhook.kind))
+                .send()?
+                .error_for_status()?;
+            Ok(())
+        });
+        match sent {
+            Ok(()) => info!("🔔 Notified {:?} webhook", webhook.kind),
+            Err(e) => warn!("Failed to notify {:?} webhook: {}", webhook.kind, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::FakeEnvironment;
+
+    #[test]
+    fn test_record() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = AuditConfig {
+            log: tmp.path().join("audit.jsonl"),
+            webhooks: Vec::new(),
+        };
+        let entry = |exit_code| Entry {
+            timestamp: 1_700_000_000,
+            operator: "ci-bot".to_string(),
+            rollback: false,
+            changes: vec![Change {
+                image: "bearcove/home".to_string(),
+                manifest: "manifests/home.yaml".to_string(),
+                from: "1.2.3".to_string(),
+                to: "1.3.0".to_string(),
+            }],
+            exit_code,
+        };
+        record(&config, &entry(Some(0))).unwrap();
+        record(&config, &entry(Some(1))).unwrap();
+
+        let log = fs_err::read_to_string(&config.log).unwrap();
+        let entries: Vec<Entry> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries, [entry(Some(0)), entry(Some(1))]);
+        assert_eq!(
+            entry(Some(1)).text(),
+            "❌ Deployed bearcove/home 1.2.3 → 1.3.0 by ci-bot (deploy command exited with 1)"
+        );
+
+        let env = FakeEnvironment {
+            vars: [("GITHUB_ACTOR".to_string(), "fasterthanlime".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(operator(&env), "fasterthanlime");
+    }
+}
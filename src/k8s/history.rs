@@ -0,0 +1,111 @@
+use eyre::Context;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub(super) const HISTORY_PATH: &str = ".beardist-deploy-history.json";
+
+/// Past deployments, so `beardist k8s --rollback` knows what to go back to
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(super) struct History {
+    deployments: Vec<Deployment>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(super) struct Deployment {
+    /// e.g. "bearcove/home"
+    pub(super) image: String,
+
+    /// The version that was deployed
+    pub(super) version: String,
+
+    /// Manifest path → version it referenced before the deployment
+    pub(super) previous: BTreeMap<String, String>,
+
+    /// Whether this deployment was itself a rollback
+    #[serde(default)]
+    pub(super) rollback: bool,
+
+    /// Seconds since the Unix epoch
+    pub(super) timestamp: u64,
+}
+
+impl History {
+    pub(super) fn load() -> eyre::Result<Self> {
+        let path = PathBuf::from(HISTORY_PATH);
+        if !path.exists() {
+            return Ok(Default::default());
+        }
+        let history_str = fs_err::read_to_string(&path)?;
+        serde_json::from_str(&history_str).wrap_err_with(|| {
+            format!(
+                "Failed to parse deploy history at {}",
+                path.display().to_string().cyan()
+            )
+        })
+    }
+
+    pub(super) fn save(&self) -> eyre::Result<()> {
+        fs_err::write(HISTORY_PATH, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub(super) fn record(&mut self, deployment: Deployment) {
+        self.deployments.push(deployment);
+    }
+
+    /// The deployment that brought `image` to `current_version`. Rollbacks are skipped,
+    /// so rolling back twice goes back two versions.
+    pub(super) fn last_deployment_of(
+        &self,
+        image: &str,
+        current_version: &str,
+    ) -> Option<&Deployment> {
+        self.deployments.iter().rev().find(|deployment| {
+            deployment.image == image
+                && deployment.version == current_version
+                && !deployment.rollback
+        })
+    }
+}
+
+pub(super) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deployment(version: &str, previous: &str, rollback: bool) -> Deployment {
+        Deployment {
+            image: "bearcove/home".to_string(),
+            version: version.to_string(),
+            previous: BTreeMap::from([("manifests/home.yaml".to_string(), previous.to_string())]),
+            rollback,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_last_deployment_of() {
+        let mut history = History::default();
+        history.record(deployment("1.0.1", "1.0.0", false));
+        history.record(deployment("1.0.2", "1.0.1", false));
+        history.record(deployment("1.0.1", "1.0.2", true));
+
+        let last = history
+            .last_deployment_of("bearcove/home", "1.0.1")
+            .unwrap();
+        assert_eq!(last.previous["manifests/home.yaml"], "1.0.0");
+        assert!(
+            history
+                .last_deployment_of("bearcove/other", "1.0.1")
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Where `beardist k8s` learns which versions of an image exist: a forge's container
+//! packages (GHCR, Forgejo), any registry speaking the Docker Registry v2 API (Docker
+//! Hub, Harbor...), or just the version given on the command line.
+
+use log::{debug, info};
+use owo_colors::OwoColorize;
+use reqwest::{Method, RequestBuilder};
+use semver::Version;
+use std::sync::Mutex;
+
+use crate::{credentials, forge::Forge, http, oci::parse_bearer_challenge};
+
+/// A source of image versions
+pub(super) trait VersionSource {
+    /// The semver versions `org/name` is tagged with, newest first
+    fn versions(&self, org: &str, name: &str) -> eyre::Result<Vec<Version>>;
+}
+
+/// The container packages of a forge: GHCR through the GitHub API, or Forgejo's
+pub(super) struct ForgePackages(pub(super) Box<dyn Forge>);
+
+impl VersionSource for ForgePackages {
+    fn versions(&self, org: &str, name: &str) -> eyre::Result<Vec<Version>> {
+        self.0.get_container_versions(org, name)
+    }
+}
+
+/// "Deploy exactly this version", from `--version`
+pub(super) struct Fixed(pub(super) Version);
+
+impl VersionSource for Fixed {
+    fn versions(&self, _org: &str, _name: &str) -> eyre::Result<Vec<Version>> {
+        Ok(vec![self.0.clone()])
+    }
+}
+
+/// A registry's Docker Registry HTTP API v2, anonymously or with the basic auth
+/// credentials in `REGISTRY_USERNAME` and `REGISTRY_PASSWORD`
+pub(super) struct RegistryV2 {
+    /// e.g. "https://registry-1.docker.io"
+    base_url: String,
+
+    credentials: Option<(String, String)>,
+
+    /// Bearer token from the registry's auth service, once challenged
+    token: Mutex<Option<String>>,
+}
+
+impl RegistryV2 {
+    pub(super) fn new(base_url: String, credentials: Option<(String, String)>) -> Self {
+        Self {
+            base_url,
+            credentials,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// The registry at `host` (as found in image references), with credentials from
+    /// the environment
+    pub(super) fn from_env(host: &str) -> eyre::Result<Self> {
+        // Docker Hub's API isn't on the host image references use
+        let api_host = match host {
+            "docker.io" => "registry-1.docker.io",
+            host => host,
+        };
+        let credentials = match credentials::token("REGISTRY_USERNAME") {
+            Some(username) => Some((username, credentials::require("REGISTRY_PASSWORD")?)),
+            None => None,
+        };
+        Ok(Self::new(format!("https://{}", api_host), credentials))
+    }
+
+    fn authed(&self, request: RequestBuilder) -> RequestBuilder {
+        match (self.token.lock().unwrap().as_ref(), &self.credentials) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some((username, password))) => request.basic_auth(username, Some(password)),
+            (None, None) => request,
+        }
+    }
+
+    /// Gets `url`, answering a bearer challenge from the registry if there is one
+    async fn get(&self, url: &str) -> eyre::Result<reqwest::Response> {
+        let response = http::send("Listing image tags", || {
+            self.authed(http::client().request(Method::GET, url))
+        })
+        .await?;
+        if response.status() != 401 {
+            return Ok(response);
+        }
+        let challenge = response
+            .headers()
+            .get("WWW-Authenticate")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_bearer_challenge)
+            .ok_or_else(|| eyre::eyre!("Registry {} rejected our credentials", self.base_url))?;
+        let realm = challenge
+            .iter()
+            .find(|(key, _)| key == "realm")
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| eyre::eyre!("Auth challenge without a realm"))?;
+        let query: Vec<(String, String)> = challenge
+            .into_iter()
+            .filter(|(key, _)| key == "service" || key == "scope")
+            .collect();
+        debug!("Fetching registry token from {}", realm);
+        let token_response = http::send("Fetching registry token", || {
+            let request = http::client().get(&realm).query(&query);
+            match &self.credentials {
+                Some((username, password)) => request.basic_auth(username, Some(password)),
+                None => request,
+            }
+        })
+        .await?;
+        let status = token_response.status();
+        if !status.is_success() {
+            return Err(eyre::eyre!(
+                "Failed to get a token for {}: HTTP status {status}",
+                self.base_url
+            ));
+        }
+        let token_response: serde_json::Value = token_response.json().await?;
+        let token = token_response["token"]
+            .as_str()
+            .or_else(|| token_response["access_token"].as_str())
+            .ok_or_else(|| eyre::eyre!("Registry token response has no token"))?;
+        *self.token.lock().unwrap() = Some(token.to_string());
+
+        http::send("Listing image tags", || {
+            self.authed(http::client().request(Method::GET, url))
+        })
+        .await
+    }
+}
+
+/// The next page's URL from a `Link: </v2/...?last=x&n=100>; rel="next"` header
+fn next_page(link: &str) -> Option<&str> {
+    let (target, params) = link.split_once(';')?;
+    params
+        .contains("rel=\"next\"")
+        .then(|| target.trim().trim_start_matches('<').trim_end_matches('>'))
+}
+
+impl VersionSource for RegistryV2 {
+    fn versions(&self, org: &str, name: &str) -> eyre::Result<Vec<Version>> {
+        http::block_on(async {
+            let mut url = format!("{}/v2/{}/{}/tags/list?n=100", self.base_url, org, name);
+            info!(
+                "Fetching tags of {}/{} from {}",
+                org,
+                name,
+                self.base_url.cyan()
+            );
+            let mut versions = Vec::new();
+            loop {
+                let response = self.get(&url).await?;
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await?;
+                    debug!("Error response: {}", body);
+                    return Err(eyre::eyre!(
+                        "Failed to list tags of {org}/{name}: HTTP status {status}"
+                    ));
+                }
+                let next = response
+                    .headers()
+                    .get("Link")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(next_page)
+                    .map(|next| format!("{}{}", self.base_url, next));
+                let page: serde_json::Value = response.json().await?;
+                versions.extend(
+                    page["tags"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|tag| tag.as_str())
+                        .filter_map(|tag| Version::parse(tag.trim_start_matches('v')).ok()),
+                );
+                match next {
+                    Some(next) => url = next,
+                    None => break,
+                }
+            }
+            versions.sort_by(|a, b| b.cmp(a));
+            Ok(versions)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_forge::{MockForge, Response};
+    use serde_json::json;
+
+    #[test]
+    fn test_registry_v2_versions() {
+        let registry = MockForge::start();
+        let tags = "/v2/bearcove/home/tags/list";
+        registry.mock_sequence(
+            "GET",
+            tags,
+            vec![
+                Response::status(401).header(
+                    "WWW-Authenticate",
+                    &format!(
+                        "Bearer realm=\"{}/token\",service=\"registry\",scope=\"repository:bearcove/home:pull\"",
+                        registry.url()
+                    ),
+                ),
+                Response::json(200, json!({"tags": ["v1.0.0", "latest", "1.2.0"]})).header(
+                    "Link",
+                    "</v2/bearcove/home/tags/list?last=latest&n=100>; rel=\"next\"",
+                ),
+                Response::json(200, json!({"tags": ["1.10.0-rc.1", "sha-abc123"]})),
+            ],
+        );
+        registry.mock(
+            "GET",
+            "/token",
+            Response::json(200, json!({"token": "t0k"})),
+        );
+
+        let source = RegistryV2::new(registry.url().to_string(), None);
+        let versions: Vec<String> = source
+            .versions("bearcove", "home")
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(versions, ["1.10.0-rc.1", "1.2.0", "1.0.0"]);
+
+        let token_requests = registry.requests_to("GET", "/token");
+        assert_eq!(
+            token_requests[0].query.as_deref(),
+            Some("service=registry&scope=repository%3Abearcove%2Fhome%3Apull")
+        );
+        let requests = registry.requests_to("GET", tags);
+        assert_eq!(requests[1].header("authorization"), Some("Bearer t0k"));
+        assert_eq!(requests[2].query.as_deref(), Some("last=latest&n=100"));
+    }
+}
@@ -0,0 +1,203 @@
+//! The `--listen` webhook endpoint: deliveries (e.g. GitHub `registry_package` events)
+//! trigger a version check ahead of the next poll. Only deliveries carrying the shared
+//! secret in `BEARDIST_WEBHOOK_SECRET` count, either signed with it (GitHub's
+//! `X-Hub-Signature-256`, Forgejo's `X-Forgejo-Signature`) or sent with it as a bearer
+//! token.
+
+use eyre::Context;
+use hmac::{Hmac, Mac};
+use log::{debug, info};
+use owo_colors::OwoColorize;
+use sha2::Sha256;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
+
+/// Deliveries bigger than this are rejected
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// How long a client gets to send its whole request
+const REQUEST_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Accepts deliveries on `addr`. Payloads aren't trusted beyond their authenticity: a
+/// delivery only asks for a version check against the registry API.
+pub(super) fn listen(addr: &str, secret: String) -> eyre::Result<mpsc::Receiver<()>> {
+    let listener = std::net::TcpListener::bind(addr)
+        .wrap_err_with(|| format!("while binding webhook listener to {}", addr))?;
+    info!("Listening for webhooks on {}", addr.bright_cyan());
+    let (tx, rx) = mpsc::channel();
+    let secret: Arc<str> = secret.into();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            // One thread per connection, so a slow client can't hold up other deliveries
+            let tx = tx.clone();
+            let secret = secret.clone();
+            std::thread::spawn(move || handle(stream, &secret, &tx));
+        }
+    });
+    Ok(rx)
+}
+
+fn handle(mut stream: TcpStream, secret: &str, tx: &mpsc::Sender<()>) {
+    let authentic = read_request(&mut stream, Instant::now() + REQUEST_DEADLINE)
+        .is_some_and(|request| is_authentic(&request, secret.as_bytes()));
+    let response: &[u8] = if authentic {
+        b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n"
+    } else {
+        debug!("Ignoring webhook delivery without a valid secret");
+        b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    };
+    let _ = stream.write_all(response);
+    if authentic {
+        let _ = tx.send(());
+    }
+}
+
+/// An HTTP request's head (as text) and body
+struct Request {
+    head: String,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+}
+
+/// Reads a request, up to the end of its body as given by `Content-Length`. Gives up
+/// once `deadline` passes, however slowly the client trickles bytes in.
+fn read_request(stream: &mut TcpStream, deadline: Instant) -> Option<Request> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&data[..end]).into_owned();
+            let mut request = Request {
+                head,
+                body: Vec::new(),
+            };
+            let length: usize = request
+                .header("Content-Length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or(0);
+            if end + 4 + length > MAX_REQUEST_BYTES {
+                return None;
+            }
+            while data.len() < end + 4 + length {
+                let n = read_before(stream, &mut buf, deadline)?;
+                data.extend_from_slice(&buf[..n]);
+            }
+            request.body = data[end + 4..end + 4 + length].to_vec();
+            return Some(request);
+        }
+        if data.len() > MAX_REQUEST_BYTES {
+            return None;
+        }
+        let n = read_before(stream, &mut buf, deadline)?;
+        data.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// One `read` that doesn't block past `deadline`; `None` at end of stream or on error
+fn read_before(stream: &mut TcpStream, buf: &mut [u8], deadline: Instant) -> Option<usize> {
+    let remaining = deadline.checked_duration_since(Instant::now())?;
+    stream.set_read_timeout(Some(remaining)).ok()?;
+    stream.read(buf).ok().filter(|n| *n > 0)
+}
+
+/// The hex-encoded HMAC-SHA256 of `body`, as webhook signature headers carry it
+fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Compares without bailing out at the first difference, so timing doesn't tell how
+/// much of a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether `request` was signed with `secret`, or carries it as a bearer token
+fn is_authentic(request: &Request, secret: &[u8]) -> bool {
+    let expected = signature(secret, &request.body);
+    let signed = [
+        ("X-Hub-Signature-256", "sha256="),
+        ("X-Forgejo-Signature", ""),
+        ("X-Gitea-Signature", ""),
+    ]
+    .iter()
+    .any(|(header, prefix)| {
+        request
+            .header(header)
+            .and_then(|value| value.strip_prefix(prefix))
+            .is_some_and(|signature| constant_time_eq(signature.as_bytes(), expected.as_bytes()))
+    });
+    let bearer = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), secret));
+    signed || bearer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)], body: &str) -> Request {
+        let mut head = "POST /hooks HTTP/1.1".to_string();
+        for (key, value) in headers {
+            head.push_str(&format!("\r\n{}: {}", key, value));
+        }
+        Request {
+            head,
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_is_authentic() {
+        // RFC 4231, test case 2
+        let mac = signature(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            mac,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let secret = b"Jefe";
+        let body = "what do ya want for nothing?";
+        assert!(is_authentic(
+            &request(&[("X-Hub-Signature-256", &format!("sha256={}", mac))], body),
+            secret
+        ));
+        assert!(is_authentic(
+            &request(&[("x-forgejo-signature", &mac)], body),
+            secret
+        ));
+        assert!(is_authentic(
+            &request(&[("Authorization", "Bearer Jefe")], "{}"),
+            secret
+        ));
+        assert!(!is_authentic(
+            &request(&[("X-Hub-Signature-256", &format!("sha256={}", mac))], "{}"),
+            secret
+        ));
+        assert!(!is_authentic(
+            &request(&[("Authorization", "Bearer jefe")], body),
+            secret
+        ));
+        assert!(!is_authentic(&request(&[], body), secret));
+    }
+}
@@ -0,0 +1,269 @@
+//! Just enough YAML scanning to find scalar values by key, so they can be replaced in
+//! place without reformatting the rest of the file. Comments, block scalars (`|`, `>`)
+//! and document separators (`---`) are understood, so they're never mistaken for keys.
+
+use std::ops::Range;
+
+/// A `key: value` line, possibly starting a sequence item (`- key: value`)
+struct Line<'a> {
+    /// Column the key starts at
+    indent: usize,
+    /// Whether the line starts with `- `
+    item: bool,
+    key: &'a str,
+    /// Byte range of the value in the document (without quotes), if it's an inline scalar
+    value: Option<Range<usize>>,
+}
+
+fn lines(contents: &str) -> impl Iterator<Item = Line<'_>> {
+    let mut offset = 0;
+    // Indent of the key whose block scalar we're in: more indented lines are its text
+    let mut block: Option<usize> = None;
+    contents.split_inclusive('\n').filter_map(move |raw| {
+        let line_start = offset;
+        offset += raw.len();
+        let line = raw.trim_end_matches(['\n', '\r']);
+
+        let trimmed = line.trim_start_matches(' ');
+        if trimmed == "---" || trimmed.starts_with("--- ") || trimmed == "..." {
+            block = None;
+            return None;
+        }
+        if let Some(block_indent) = block {
+            if trimmed.is_empty() || line.len() - trimmed.len() > block_indent {
+                return None;
+            }
+            block = None;
+        }
+        let (item, rest) = match trimmed.strip_prefix("- ") {
+            Some(rest) => (true, rest.trim_start_matches(' ')),
+            None => (false, trimmed),
+        };
+        if rest.is_empty() || rest.starts_with('#') {
+            return None;
+        }
+        let indent = line.len() - rest.len();
+
+        let colon = match rest.find(": ") {
+            Some(colon) => colon,
+            None if rest.ends_with(':') => rest.len() - 1,
+            None => return None,
+        };
+        let key = rest[..colon].trim_matches(['"', '\'']);
+        let value_start = line_start + indent + colon + 1;
+        let raw_value = &contents[value_start..line_start + line.len()];
+        if raw_value.trim_start().starts_with(['|', '>']) {
+            block = Some(indent);
+        }
+        let value =
+            scalar(raw_value).map(|range| value_start + range.start..value_start + range.end);
+        Some(Line {
+            indent,
+            item,
+            key,
+            value,
+        })
+    })
+}
+
+/// Range of an inline scalar, without quotes, trailing comments or whitespace
+fn scalar(s: &str) -> Option<Range<usize>> {
+    let rest = s.trim_start();
+    let leading = s.len() - rest.len();
+    if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let len = rest[1..].find(quote)?;
+        return Some(leading + 1..leading + 1 + len);
+    }
+    let rest = match rest.find(" #") {
+        Some(comment) => &rest[..comment],
+        None => rest,
+    }
+    .trim_end();
+    if rest.is_empty() || rest.starts_with(['|', '>', '{', '[', '&', '*']) {
+        return None;
+    }
+    Some(leading..leading + rest.len())
+}
+
+/// Finds the value at a dotted key path, like `image.tag` in a Helm `values.yaml`
+pub(super) fn find_value(contents: &str, path: &str) -> Option<Range<usize>> {
+    let path: Vec<&str> = path.split('.').collect();
+    let mut stack: Vec<(usize, &str)> = Vec::new();
+    for line in lines(contents) {
+        while stack
+            .last()
+            .is_some_and(|(indent, _)| *indent >= line.indent)
+        {
+            stack.pop();
+        }
+        stack.push((line.indent, line.key));
+        if line.value.is_some()
+            && stack.len() == path.len()
+            && stack.iter().zip(&path).all(|((_, key), part)| key == part)
+        {
+            return line.value;
+        }
+    }
+    None
+}
+
+/// Finds the `image` values of the items of `containers` and `initContainers` lists, in
+/// any of the documents of a manifest
+pub(super) fn find_container_images(contents: &str) -> Vec<Range<usize>> {
+    let mut images = Vec::new();
+    // Keys leading to the current line, and whether their mapping is a sequence item
+    let mut stack: Vec<(usize, &str, bool)> = Vec::new();
+    for line in lines(contents) {
+        while stack
+            .last()
+            .is_some_and(|(indent, _, _)| *indent > line.indent)
+        {
+            stack.pop();
+        }
+        let mut in_item = line.item;
+        if let Some((_, _, item)) = stack.last().filter(|(indent, _, _)| *indent == line.indent) {
+            // Another key of the same mapping
+            in_item |= *item;
+            stack.pop();
+        }
+        let parent = stack.last().map(|(_, key, _)| *key);
+        if let (true, Some("containers" | "initContainers"), "image", Some(value)) =
+            (in_item, parent, line.key, &line.value)
+        {
+            images.push(value.clone());
+        }
+        stack.push((line.indent, line.key, in_item));
+    }
+    images
+}
+
+/// Finds the `newTag` values of the entries of a kustomization's `images:` list whose
+/// `name` (or `newName`) is one of `names`, along with the name that matched
+pub(super) fn find_kustomize_tags<'a>(
+    contents: &str,
+    names: &'a [String],
+) -> Vec<(&'a str, Range<usize>)> {
+    struct Entry<'a> {
+        names: Vec<&'a str>,
+        tag: Option<Range<usize>>,
+    }
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut in_images = false;
+    for line in lines(contents) {
+        if line.indent == 0 && !line.item {
+            in_images = line.key == "images" && line.value.is_none();
+            continue;
+        }
+        if !in_images {
+            continue;
+        }
+        if line.item {
+            entries.push(Entry {
+                names: Vec::new(),
+                tag: None,
+            });
+        }
+        let Some(entry) = entries.last_mut() else {
+            continue;
+        };
+        match (line.key, line.value) {
+            ("name" | "newName", Some(value)) => entry.names.push(&contents[value]),
+            ("newTag", Some(value)) => entry.tag = Some(value),
+            _ => {}
+        }
+    }
+
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let name = names.iter().find(|n| entry.names.contains(&n.as_str()))?;
+            Some((name.as_str(), entry.tag?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_value() {
+        let values = "\
+replicaCount: 2
+image:
+  repository: ghcr.io/bearcove/home
+  # the tag
+  tag: \"v1.2.3\" # bumped by beardist
+sidecar:
+  image:
+    tag: 0.1.0
+";
+        let range = find_value(values, "image.tag").unwrap();
+        assert_eq!(&values[range], "v1.2.3");
+        let range = find_value(values, "sidecar.image.tag").unwrap();
+        assert_eq!(&values[range], "0.1.0");
+        assert_eq!(find_value(values, "image"), None);
+        assert_eq!(find_value(values, "tag"), None);
+    }
+
+    #[test]
+    fn test_find_container_images() {
+        let manifest = "\
+apiVersion: v1
+kind: ConfigMap
+data:
+  notes: |
+    containers:
+      - image: ghcr.io/bearcove/home:v0.0.1
+---
+# - image: ghcr.io/bearcove/home:v0.0.2
+apiVersion: apps/v1
+kind: Deployment
+spec:
+  template:
+    spec:
+      initContainers:
+      - name: migrate
+        image: ghcr.io/bearcove/home:v1.2.3 # same as the app
+      containers:
+        - name: app
+          env:
+            - name: IMAGE
+              value: ghcr.io/bearcove/home:v0.0.3
+          image: \"ghcr.io/bearcove/home:v1.2.3\"
+        - image: nginx:1.25.0
+  image: ghcr.io/bearcove/home:v0.0.4
+";
+        let images: Vec<&str> = find_container_images(manifest)
+            .into_iter()
+            .map(|range| &manifest[range])
+            .collect();
+        assert_eq!(
+            images,
+            [
+                "ghcr.io/bearcove/home:v1.2.3",
+                "ghcr.io/bearcove/home:v1.2.3",
+                "nginx:1.25.0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_kustomize_tags() {
+        let kustomization = "\
+resources:
+  - deployment.yaml
+images:
+  - name: ghcr.io/bearcove/home
+    newTag: v1.2.3
+  - name: nginx
+    newTag: 1.25.0
+";
+        let names = ["ghcr.io/bearcove/home".to_string()];
+        let tags = find_kustomize_tags(kustomization, &names);
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].0, "ghcr.io/bearcove/home");
+        assert_eq!(&kustomization[tags[0].1.clone()], "v1.2.3");
+    }
+}
@@ -1,40 +1,121 @@
 #![deny(clippy::disallowed_methods)]
 
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use cargo::{CargoBuildContext, CargoConfig};
 use clap::{Parser, Subcommand};
 use command::run_command;
+use environment::{Environment, ProcessEnvironment};
+use error::{Classify, ErrorKind};
 use eyre::{self, Context, Result};
 use homebrew::update_tap;
 use log::*;
 use owo_colors::OwoColorize;
 use rand::seq::IndexedRandom;
+use schemars::JsonSchema;
 use semver::{BuildMetadata, Prerelease, Version};
 use serde::{Deserialize, Serialize};
-use std::{env, os::unix::fs::PermissionsExt, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
 use target_spec::TargetSpec;
 use tempfile::TempDir;
 
 pub(crate) mod github;
 
+mod binstall;
+mod build_info;
 mod cargo;
+mod changelog;
+mod channel;
+mod checksums;
+mod color;
 pub(crate) mod command;
+mod config_file;
+mod credentials;
+mod dispatch;
+mod doctor;
+mod download;
+mod download_cache;
+mod environment;
+mod error;
+mod forge;
+mod forgejo;
+mod gha;
+mod git;
+mod helm;
 mod homebrew;
+mod hooks;
+mod http;
+mod incremental;
 mod system;
 pub(crate) mod target_spec;
+mod throttle;
 
 mod utils;
 pub use utils::*;
 
 mod k8s;
+mod migrate;
+#[cfg(test)]
+mod mock_forge;
+mod nix;
+mod notify;
+mod objfile;
+mod oci;
+mod package_checks;
+mod pe;
+mod progress;
+mod provenance;
+mod prune;
+mod release_body;
+mod remote_cache;
+mod report;
+mod retry;
+mod schema;
+mod scoop;
+mod status;
+mod warm;
+mod winget;
+mod workflow;
+mod workspace;
 
 mod indented_writer;
+mod install;
+mod installer;
 pub(crate) use indented_writer::*;
 
 /// CLI interface for beardist
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Path to the config file. By default, `.beardist.json` (or `.toml`, `.yaml`) is
+    /// looked for in the current directory and its parents.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Only show warnings and errors
+    #[arg(long, short = 'q', global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Show more details: `-v` for beardist's debug logs (including every command it
+    /// runs), `-vv` for its dependencies' too
+    #[arg(long, short = 'v', global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// When to use colors in output, and in the environment of child processes
+    #[arg(long, global = true, value_enum, default_value_t)]
+    color: color::ColorChoice,
+
+    /// Don't upload, push, or write anything outside of temporary directories (also
+    /// enabled by setting `DRY_RUN`)
+    #[arg(long, global = true)]
+    dry_run: bool,
+
     /// The subcommand to execute
     #[command(subcommand)]
     command: Commands,
@@ -44,13 +125,123 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Build the project, create a package, and upload it to github
-    Build,
+    Build(BuildArgs),
     /// Bump the version number and create a new git tag
     Bump(BumpArgs),
-    /// Bump k8s manifests and run `./deploy-manifests`
-    K8s(DeployArgs),
+    /// Bump k8s manifests and run a deploy command (`./deploy` by default)
+    K8s(Box<DeployArgs>),
     /// Update a Homebrew tap containing a `.beardist-tap.json`
-    UpdateTap,
+    UpdateTap(UpdateTapArgs),
+    /// Update a Scoop bucket containing a `.beardist-scoop.json`
+    UpdateScoop(UpdateScoopArgs),
+    /// Add manifests for new releases to a winget-pkgs fork and open a pull request
+    UpdateWinget,
+    /// Update a Nix package repository containing a `.beardist-nix.json`
+    UpdateNix(UpdateNixArgs),
+    /// Print (or add to Cargo.toml) the metadata `cargo binstall` needs to find our assets
+    Binstall(BinstallArgs),
+    /// Check config, tools, environment and GitHub access before attempting a build
+    #[command(alias = "check")]
+    Doctor,
+    /// Print a JSON Schema for one of beardist's config files
+    Schema(SchemaArgs),
+    /// Rewrite the config file for the current config version, keeping a backup
+    MigrateConfig,
+    /// Download, verify and install the binaries of a release for this machine
+    Install(InstallArgs),
+    /// Download (and optionally extract) assets of a release, verifying their checksums
+    Download(DownloadArgs),
+    /// Install the toolchain and fetch dependencies into `$BEARDIST_CACHE_DIR`, without
+    /// building
+    Warm,
+    /// Delete old releases and their assets, as the `prune` section of the config says.
+    /// Use `--dry-run` to preview.
+    PruneReleases,
+    /// Run the build workflow for a tag on every configured runner, wait for it, and
+    /// optionally update the Homebrew tap
+    Dispatch(DispatchArgs),
+    /// Render the changes between two tags as Markdown, e.g. for announcements
+    Changelog(ChangelogArgs),
+    /// Show what a release has (archives, hashes, checksums, signatures) and which
+    /// downstream taps and manifests are on its version
+    Status(StatusArgs),
+    /// Package the chart in `charts/<name>` for a release, sign it, and push it to an OCI
+    /// registry or a chart repository
+    Helm(HelmArgs),
+    /// Write the GitHub Actions workflow that builds and releases the project, from the
+    /// config
+    GenerateWorkflow(GenerateWorkflowArgs),
+}
+
+/// Arguments for the Build command
+#[derive(Parser)]
+struct BuildArgs {
+    /// Write a JSON report of the build (artifact, checksum, upload, timings,
+    /// toolchain) to this path
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Also copy the packaged files and the archive to this directory, to look at them
+    /// after the build. Overrides `inspect_dir` in the config.
+    #[arg(long)]
+    inspect_dir: Option<Utf8PathBuf>,
+
+    /// Build the project in this directory, for repositories with several of them. By
+    /// default, the one whose `tag_prefix` the tag being built starts with.
+    #[arg(long)]
+    project: Option<PathBuf>,
+
+    /// Cap uploads at this rate, e.g. "10MB/s". Overrides `max_upload_rate` in the
+    /// config.
+    #[arg(long, value_name = "RATE", value_parser = throttle::parse_rate)]
+    max_upload_rate: Option<u64>,
+}
+
+/// Arguments for the Install command
+#[derive(Parser)]
+struct InstallArgs {
+    /// The repository to install from, e.g. "bearcove/beardist"
+    repo: String,
+
+    /// Release tag to install (default: the latest release)
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// Where to put binaries. Libraries go to `../libexec` relative to it.
+    /// (default: ~/.local/bin)
+    #[arg(long)]
+    dir: Option<PathBuf>,
+
+    /// Target triple of the archive to install (default: this machine's)
+    #[arg(long)]
+    target: Option<String>,
+}
+
+/// Arguments for the Download command
+#[derive(Parser)]
+struct DownloadArgs {
+    /// The repository to download from, e.g. "bearcove/beardist"
+    repo: String,
+
+    /// Release tag to download from (default: the latest release)
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// Download `<target>.tar.xz` (default: this machine's triple)
+    #[arg(long, conflicts_with = "asset")]
+    target: Option<String>,
+
+    /// Download every asset whose name matches this glob instead, e.g. "*.tar.xz"
+    #[arg(long)]
+    asset: Option<String>,
+
+    /// File or directory to download to (default: the current directory)
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+
+    /// Extract archives next to where they were downloaded, then remove them
+    #[arg(long)]
+    extract: bool,
 }
 
 /// Arguments for the Bump command
@@ -59,6 +250,11 @@ struct BumpArgs {
     /// Type of version bump (major, minor, or patch)
     #[arg(value_enum)]
     bump_type: Option<BumpType>,
+
+    /// Bump the version of the project in this directory, for repositories with several
+    /// of them: its tags are the ones starting with its `tag_prefix`
+    #[arg(long)]
+    project: Option<PathBuf>,
 }
 
 #[derive(clap::ValueEnum, Clone)]
@@ -68,17 +264,278 @@ enum BumpType {
     Patch,
 }
 
+/// Arguments for the Changelog command
+#[derive(Parser)]
+struct ChangelogArgs {
+    /// Tag (or any revision) to start after (default: the tag before `--to`)
+    #[arg(long)]
+    from: Option<String>,
+
+    /// Tag (or any revision) to end at
+    #[arg(long, default_value = "HEAD")]
+    to: String,
+
+    /// Write the changelog to this file instead of stdout
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+}
+
+/// Arguments for the Status command
+#[derive(Parser)]
+struct StatusArgs {
+    /// The release's tag, e.g. "v1.2.3"
+    tag: String,
+
+    /// Checkout of a Homebrew tap to check the formula version in (repeatable)
+    #[arg(long)]
+    tap: Vec<PathBuf>,
+
+    /// Checkout of a deploy repository to check the k8s manifests in (repeatable)
+    #[arg(long)]
+    k8s: Vec<PathBuf>,
+}
+
+/// Arguments for the Helm command
+#[derive(Parser)]
+struct HelmArgs {
+    /// The release's tag, e.g. "v1.2.3": the chart's `appVersion` is set to it (without
+    /// the `tag_prefix`)
+    tag: String,
+
+    /// OCI registry to push to, e.g. "oci://ghcr.io/bearcove/charts". Overrides
+    /// `helm.oci` in the config.
+    #[arg(long)]
+    oci: Option<String>,
+
+    /// Chart repository to upload to. Overrides `helm.repo` in the config.
+    #[arg(long)]
+    repo: Option<String>,
+
+    /// GPG key to sign the chart with. Overrides `helm.sign_key` in the config.
+    #[arg(long)]
+    sign_key: Option<String>,
+
+    /// Also copy the packaged chart (and its provenance file) to this directory
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+/// Arguments for the GenerateWorkflow command
+#[derive(Parser)]
+struct GenerateWorkflowArgs {
+    /// Which CI to generate a pipeline for (default: the config's forge's Actions)
+    #[arg(long, value_enum)]
+    format: Option<workflow::WorkflowFormat>,
+
+    /// Where to write the pipeline (default: `.github/workflows/release.yml`,
+    /// `.forgejo/workflows/release.yml` or `.woodpecker/release.yml`)
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Don't write anything, fail if the workflow isn't what would be generated (e.g. to
+    /// check in CI that it was regenerated after changing the config)
+    #[arg(long)]
+    check: bool,
+}
+
+/// Arguments for the Dispatch command
+#[derive(Parser)]
+struct DispatchArgs {
+    /// The tag to build, e.g. "v1.2.3"
+    tag: String,
+
+    /// Return once the runs are dispatched, without waiting for them
+    #[arg(long)]
+    no_wait: bool,
+
+    /// Give up waiting after this many minutes
+    #[arg(long, default_value_t = 120)]
+    timeout_mins: u64,
+
+    /// Once all runs succeeded, run `update-tap` for this project in this tap checkout
+    #[arg(long, conflicts_with = "no_wait")]
+    update_tap: Option<PathBuf>,
+}
+
+/// Arguments for the UpdateTap command
+#[derive(Parser)]
+struct UpdateTapArgs {
+    /// Push the bump to a new branch and open a pull request instead of pushing to main
+    #[arg(long)]
+    pr: bool,
+
+    /// Only process the formulas with these names (can be repeated)
+    #[arg(long)]
+    only: Vec<String>,
+
+    /// How many formulas to process concurrently
+    #[arg(long, short = 'j', default_value_t = 4)]
+    jobs: usize,
+
+    /// Update the tap in this directory instead of the current one. Repeat it to update
+    /// several taps in one run: each is committed and pushed on its own.
+    #[arg(long = "repo")]
+    repos: Vec<PathBuf>,
+}
+
+/// Arguments for the UpdateScoop command
+#[derive(Parser)]
+struct UpdateScoopArgs {
+    /// Push the bump to a new branch and open a pull request instead of pushing to main
+    #[arg(long)]
+    pr: bool,
+}
+
+/// Arguments for the UpdateNix command
+#[derive(Parser)]
+struct UpdateNixArgs {
+    /// Push the bump to a new branch and open a pull request instead of pushing to main
+    #[arg(long)]
+    pr: bool,
+}
+
+/// Arguments for the Binstall command
+#[derive(Parser)]
+struct BinstallArgs {
+    /// Append the metadata to `Cargo.toml` instead of printing it
+    #[arg(long)]
+    write: bool,
+}
+
+/// Arguments for the Schema command
+#[derive(Parser)]
+struct SchemaArgs {
+    /// Which config file to describe
+    #[arg(value_enum, default_value_t)]
+    kind: schema::SchemaKind,
+}
+
 /// Arguments for the Deploy command
 #[derive(Parser)]
 struct DeployArgs {
-    /// The name of the image to deploy, e.g. "bearcove/home" (`ghcr.io` is implied)
-    image: String,
+    /// The names of the images to deploy, e.g. "bearcove/home"
+    #[arg(required_unless_present_any = ["all", "rollback"])]
+    images: Vec<String>,
+
+    /// Registry host to match in manifests (repeatable). Defaults to the `registries`
+    /// of `.beardist-deploy.json`, or `ghcr.io`
+    #[arg(long = "registry")]
+    registries: Vec<String>,
+
+    /// Deploy every image listed in `.beardist-deploy.json`
+    #[arg(long, conflicts_with = "images")]
+    all: bool,
+
+    /// Directory to search for manifests (default: from `.beardist-deploy.json`, or
+    /// `manifests`)
+    #[arg(long)]
+    manifest_dir: Option<PathBuf>,
+
+    /// Only search manifests matching this glob, relative to the manifest directory
+    /// (repeatable, default: from `.beardist-deploy.json`)
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Skip manifests matching this glob, relative to the manifest directory, e.g.
+    /// "*.tmpl.yaml" (repeatable, default: from `.beardist-deploy.json`)
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Only bump manifests matching this glob, relative to the manifest directory, e.g.
+    /// "overlays/canary/**" (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    only: Vec<String>,
+
+    /// Only bump the manifests of this environment: those in a directory named after it,
+    /// or with it in their name, like "home.prod.yaml"
+    #[arg(long)]
+    env: Option<String>,
+
+    /// Don't follow symbolic links while searching for manifests
+    #[arg(long)]
+    no_follow_symlinks: bool,
+
+    /// Command to run after updating manifests, e.g. "kubectl apply -f {files}"
+    /// (default: from `.beardist-deploy.json`, or "./deploy {files}")
+    #[arg(long)]
+    deploy_command: Option<String>,
+
+    /// Restore the version an image had before its last deployment, and redeploy
+    #[arg(long, value_name = "IMAGE", conflicts_with_all = ["images", "all"])]
+    rollback: Option<String>,
+
+    /// Don't ask for confirmation before writing manifests and deploying
+    #[arg(long, short = 'y')]
+    yes: bool,
+
+    /// Where to look up container versions (default: guessed from the registry host in
+    /// the manifests). `registry` uses the registry's own API, with credentials from
+    /// `REGISTRY_USERNAME` and `REGISTRY_PASSWORD` if set
+    #[arg(long, alias = "forge", value_enum)]
+    source: Option<k8s::SourceKind>,
+
+    /// Deploy exactly this version, rather than waiting for a new one
+    #[arg(long, conflicts_with_all = ["rollback", "constraint", "max_minor_jump"])]
+    version: Option<semver::Version>,
+
+    /// Only deploy versions matching this semver requirement, e.g. "^1"
+    #[arg(long)]
+    constraint: Option<String>,
+
+    /// Only deploy versions at most this many minor versions ahead (and never a new major)
+    #[arg(long)]
+    max_minor_jump: Option<u64>,
+
+    /// Never deploy tags matching this regex (repeatable, default: from
+    /// `.beardist-deploy.json`, or "-(amd64|arm64)$")
+    #[arg(long = "skip-tag", value_name = "REGEX")]
+    skip_tags: Vec<String>,
+
+    /// Only deploy tags with this suffix, e.g. "arm64" for "1.2.3-arm64" (default: from
+    /// `.beardist-deploy.json`)
+    #[arg(long, conflicts_with = "version")]
+    arch_suffix: Option<String>,
+
+    /// Seconds between registry checks while waiting for new versions
+    #[arg(long, default_value_t = 2)]
+    poll_interval: u64,
+
+    /// Give up waiting for new versions after this many seconds
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    /// Listen for webhooks on this address (e.g. "0.0.0.0:8787"): deliveries trigger a
+    /// check ahead of the next poll. They must be signed with (or carry as a bearer
+    /// token) the secret in `BEARDIST_WEBHOOK_SECRET`.
+    #[arg(long, value_name = "ADDR")]
+    listen: Option<String>,
+
+    /// With `json`, print the planned changes (file, line, old and new version) on stdout
+    /// instead of applying them, for other automation to consume
+    #[arg(long, value_enum, default_value_t)]
+    output: k8s::OutputFormat,
+
+    /// After deploying, commit the updated manifests (and deploy history) to git
+    #[arg(long)]
+    commit: bool,
+
+    /// Push the commit made by `--commit`
+    #[arg(long, requires = "commit")]
+    push: bool,
+
+    /// After deploying, wait for `kubectl rollout status` of the updated workloads
+    #[arg(long)]
+    wait: bool,
+
+    /// How long to wait for each rollout, in seconds
+    #[arg(long, default_value_t = 300, requires = "wait")]
+    rollout_timeout: u64,
 }
 
 pub const CONFIG_VERSION: u64 = 3;
 pub const USER_AGENT: &str = "github.com/bearcove/beardist@1.0";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct Config {
     /// Version of beardist required
@@ -90,11 +547,102 @@ struct Config {
     /// Project name
     name: String,
 
+    /// Prefix of this project's tags, for repositories with several projects: e.g. with
+    /// "api-", it's released by tags like "api-v1.2.3", and ignores other tags
+    #[serde(default)]
+    tag_prefix: Option<String>,
+
     cargo: Option<CargoConfig>,
     custom: Option<CustomConfig>,
+
+    /// Where the repository (and its releases) are hosted
+    #[serde(default)]
+    forge: forge::ForgeKind,
+
+    /// Also push archives to an OCI registry
+    #[serde(default)]
+    oci: Option<oci::OciConfig>,
+
+    /// Also upload archives as Forgejo generic packages
+    #[serde(default)]
+    generic_package: Option<forgejo::GenericPackageConfig>,
+
+    /// Attach an `install.sh` to releases, for `curl | sh` installs
+    #[serde(default)]
+    installer: bool,
+
+    /// What the release page shows instead of asset file names, by file name, e.g.
+    /// `{"x86_64-unknown-linux-gnu.tar.xz": "Linux x86_64 (glibc)"}`. Only GitHub shows
+    /// labels.
+    #[serde(default)]
+    asset_labels: BTreeMap<String, String>,
+
+    /// Add install instructions (Homebrew, `install.sh`, a table of downloads) to the
+    /// body of releases
+    #[serde(default)]
+    install_instructions: Option<release_body::InstallInstructionsConfig>,
+
+    /// Post to webhooks when a build succeeds or fails
+    #[serde(default)]
+    notifications: Option<notify::NotificationsConfig>,
+
+    /// Commands to run at points of the build, with a JSON payload on stdin
+    #[serde(default)]
+    hooks: Option<hooks::HooksConfig>,
+
+    /// Kill external commands (`cargo build`, build steps, etc.) that run for longer than
+    /// this many seconds, instead of stalling CI until the runner gives up
+    #[serde(default)]
+    command_timeout_secs: Option<u64>,
+
+    /// Command that prints a token on stdout, for tokens that aren't in the environment or
+    /// in files (`<VAR>_FILE`). It's passed the token's variable name, e.g.
+    /// `["vault-token", "--field"]` gets run as `vault-token --field GH_READWRITE_TOKEN`.
+    #[serde(default)]
+    credential_helper: Option<Vec<String>>,
+
+    /// Copy the packaged files and the archive to this directory, to look at them after
+    /// the build. Its previous contents are removed.
+    #[serde(default)]
+    inspect_dir: Option<String>,
+
+    /// After releasing, set a status on the built commit and/or comment on its pull
+    /// requests, linking to the release (GitHub only)
+    #[serde(default)]
+    provenance: Option<provenance::ProvenanceConfig>,
+
+    /// Cap uploads at this rate, e.g. "10MB/s" or "512KiB/s", so releases don't
+    /// saturate a shared uplink
+    #[serde(default)]
+    max_upload_rate: Option<String>,
+
+    /// Bounds on the archive's size, checked before uploading
+    #[serde(default)]
+    package_checks: package_checks::PackageChecksConfig,
+
+    /// Which releases `beardist prune-releases` keeps
+    #[serde(default)]
+    prune: Option<prune::PruneConfig>,
+
+    /// Publish builds that aren't for a tag (e.g. of the main branch) to a rolling
+    /// release, instead of doing a dry run
+    #[serde(default)]
+    channel: Option<channel::ChannelConfig>,
+
+    /// Which workflow `beardist dispatch` triggers, and on which runners
+    #[serde(default)]
+    dispatch: Option<dispatch::DispatchConfig>,
+
+    /// Where `beardist helm` pushes the project's chart
+    #[serde(default)]
+    helm: Option<helm::HelmConfig>,
+
+    /// What `beardist generate-workflow` builds, and on which runners
+    #[serde(default)]
+    workflow: Option<workflow::WorkflowConfig>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct CustomConfig {
     /// Any custom build steps to run (`bun build` etc.)
@@ -105,6 +653,10 @@ struct CustomConfig {
     /// here. This is for data files.
     #[serde(default)]
     files: Vec<String>,
+    /// Run the steps in a pseudo-terminal, for tools that only use colors and progress
+    /// bars on a terminal
+    #[serde(default)]
+    pty: bool,
 }
 
 /// Context for `build` subcommand
@@ -118,9 +670,12 @@ struct BuildContext {
     /// github read-write API token
     github_rw_token: String,
 
-    /// The git tag we're reacting to (in CI)
+    /// The git tag we're reacting to (in CI), or the channel's name for channel builds
     tag: String,
 
+    /// Set for channel builds: builds that aren't for a tag, published to a rolling release
+    channel: Option<channel::Channel>,
+
     /// Flag indicating whether this is a dry run
     is_dry_run: bool,
 
@@ -135,9 +690,12 @@ struct BuildContext {
 
     /// `$BEARDIST_ARTIFACT_NAME`
     artifact_name: String,
+
+    /// Where the above came from, for anything else that depends on the environment
+    env: Arc<dyn Environment>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum PackagedFileKind {
     /// Mach-O/PE/ELF, etc.
     Bin,
@@ -154,10 +712,89 @@ struct PackagedFile {
     path: Utf8PathBuf,
 }
 
+/// The `.tar.xz` we built, with its hash and size computed while writing it
+struct PackageArchive {
+    path: Utf8PathBuf,
+    size: u64,
+    sha256: String,
+}
+
+impl PackageArchive {
+    fn file_name(&self) -> &str {
+        self.path.file_name().unwrap()
+    }
+
+    fn source(&self) -> forge::UploadSource<'_> {
+        forge::UploadSource::File {
+            path: &self.path,
+            size: self.size,
+            sha256: &self.sha256,
+        }
+    }
+}
+
+impl BuildContext {
+    /// Runs the configured hooks for `event`, with `extra` (an object) added to the
+    /// common payload
+    fn run_hooks(&self, event: hooks::HookEvent, extra: serde_json::Value) -> Result<()> {
+        let Some(hooks) = &self.config.hooks else {
+            return Ok(());
+        };
+        let mut payload = serde_json::Map::new();
+        payload.insert("org".to_string(), self.config.org.clone().into());
+        payload.insert("name".to_string(), self.config.name.clone().into());
+        payload.insert("tag".to_string(), self.tag.clone().into());
+        payload.insert("target".to_string(), self.artifact_name.clone().into());
+        payload.insert("dry_run".to_string(), self.is_dry_run.into());
+        if let serde_json::Value::Object(extra) = extra {
+            payload.extend(extra);
+        }
+        hooks.run(event, payload)
+    }
+}
+
+/// Size of the tarball `tar --create` makes of these files: a 512-byte header per file,
+/// contents padded to 512 bytes, two empty blocks at the end, all padded to 10KiB records
+fn tar_size(files: &[PackagedFile]) -> Result<u64> {
+    let mut size = 1024;
+    for file in files {
+        size += 512 + fs_err::metadata(&file.path)?.len().div_ceil(512) * 512;
+    }
+    Ok(size.div_ceil(10240) * 10240)
+}
+
+/// Sets up the directory `$BEARDIST_CACHE_DIR` points to
+fn cache_dir_from_env(env: &dyn Environment) -> Result<Utf8PathBuf> {
+    // BEARDIST_CACHE_DIR must be set to point to persistent storage
+    // This tool is meant to be run in CI, and we want the cache
+    // to use a persistent location for faster builds
+    // We'll place rustup home, cargo home, target directory, etc. in this cache
+    let cache_dir = env
+        .var("BEARDIST_CACHE_DIR")
+        .map(Utf8PathBuf::from)
+        .ok_or_else(|| {
+            eyre::eyre!(
+                "{} is not set. It should point to persistent storage for CI builds. This is where we'll store rustup home, cargo home, target directory, etc.",
+                "BEARDIST_CACHE_DIR".cyan()
+            )
+        })?;
+
+    if !cache_dir.try_exists().unwrap_or(false) {
+        fs_err::create_dir_all(&cache_dir)?;
+    }
+
+    fs_err::set_permissions(&cache_dir, std::fs::Permissions::from_mode(0o755))?;
+    command::set_log_dir(cache_dir.join("logs").into_std_path_buf());
+    Ok(cache_dir)
+}
+
 impl BuildContext {
     fn new(config: Config) -> Result<Self> {
-        let source_dir =
-            camino::Utf8PathBuf::from_path_buf(env::current_dir()?.canonicalize()?).unwrap();
+        Self::from_environment(config, Arc::new(ProcessEnvironment))
+    }
+
+    fn from_environment(config: Config, env: Arc<dyn Environment>) -> Result<Self> {
+        let source_dir = env.current_dir()?;
         info!(
             "🏗️ Building project from: {}",
             source_dir.to_string().cyan()
@@ -165,24 +802,7 @@ impl BuildContext {
 
         info!("");
 
-        // BEARDIST_CACHE_DIR must be set to point to persistent storage
-        // This tool is meant to be run in CI, and we want the cache
-        // to use a persistent location for faster builds
-        // We'll place rustup home, cargo home, target directory, etc. in this cache
-        let cache_dir = env::var("BEARDIST_CACHE_DIR")
-            .map(Utf8PathBuf::from)
-            .map_err(|_| {
-                eyre::eyre!(
-                    "{} is not set. It should point to persistent storage for CI builds. This is where we'll store rustup home, cargo home, target directory, etc.",
-                    "BEARDIST_CACHE_DIR".cyan()
-                )
-            })?;
-
-        if !cache_dir.try_exists().unwrap_or(false) {
-            fs_err::create_dir_all(&cache_dir)?;
-        }
-
-        fs_err::set_permissions(&cache_dir, std::fs::Permissions::from_mode(0o755))?;
+        let cache_dir = cache_dir_from_env(env.as_ref())?;
 
         let cache_messages = [
             "🍭 that's where we hide the goodies",
@@ -215,30 +835,57 @@ impl BuildContext {
 
         info!("🔍 Cache {} — {}", cache_dir.cyan(), cache_message.yellow());
 
-        let mut is_dry_run = false;
+        let mut is_dry_run = env.var("DRY_RUN").is_some();
 
-        let github_rw_token = match env::var("GH_READWRITE_TOKEN") {
-            Ok(token) => {
-                info!(
-                    "{} is set: {}",
-                    "GH_READWRITE_TOKEN".cyan(),
-                    format_secret(&token)
-                );
+        // Forgejo Actions provides a token that can manage releases of the repository
+        let mut token_vars = vec!["GH_READWRITE_TOKEN"];
+        if config.forge == forge::ForgeKind::Forgejo {
+            token_vars.push("FORGEJO_TOKEN");
+        }
+        let github_rw_token = match token_vars
+            .iter()
+            .find_map(|var| credentials::token_in(env.as_ref(), var).map(|token| (var, token)))
+        {
+            Some((var, token)) => {
+                info!("{} is set: {}", var.cyan(), format_secret(&token));
                 token
             }
-            Err(_) => {
+            None => {
                 is_dry_run = true;
                 "placeholder_token".to_string()
             }
         };
 
-        let maybe_tag = env::var("GITHUB_REF").ok().and_then(|ref_str| {
-            info!("{} is set: {}", "GITHUB_REF".cyan(), ref_str);
+        let maybe_tag = env.ci_var("REF").and_then(|(var, ref_str)| {
+            info!("{} is set: {}", var.cyan(), ref_str);
             ref_str.strip_prefix("refs/tags/").map(String::from)
         });
-        let tag = match maybe_tag {
-            Some(t) => t,
-            None => {
+        if let (Some(tag), Some(prefix)) = (&maybe_tag, &config.tag_prefix) {
+            if !tag.starts_with(prefix.as_str()) {
+                return Err(eyre::eyre!(
+                    "Tag {} isn't one of {}'s, whose tags start with {}",
+                    tag,
+                    config.name,
+                    prefix
+                ))
+                .classify(ErrorKind::Config);
+            }
+        }
+        let channel = match (&maybe_tag, &config.channel, env.ci_var("SHA")) {
+            (None, Some(channel_config), Some((_, sha))) => {
+                info!(
+                    "Not a tag build, publishing to channel {} at {}",
+                    channel_config.name.cyan(),
+                    sha.yellow()
+                );
+                Some(channel::Channel::new(channel_config, sha))
+            }
+            _ => None,
+        };
+        let tag = match (maybe_tag, &channel) {
+            (Some(t), _) => t,
+            (None, Some(channel)) => channel.name.clone(),
+            (None, None) => {
                 is_dry_run = true;
                 warn!(
                     "{} is not set or invalid, falling back to placeholder",
@@ -248,9 +895,9 @@ impl BuildContext {
             }
         };
 
-        let github_server_url = match env::var("GITHUB_SERVER_URL") {
-            Ok(url) => url,
-            Err(_) => {
+        let github_server_url = match env.ci_var("SERVER_URL") {
+            Some((_, url)) => url,
+            None => {
                 warn!(
                     "{} is not set, falling back to default",
                     "GITHUB_SERVER_URL".cyan()
@@ -262,8 +909,9 @@ impl BuildContext {
         let temp_dir = TempDir::new()?;
 
         let artifact_name_var = "BEARDIST_ARTIFACT_NAME";
-        let artifact_name = std::env::var(artifact_name_var).unwrap_or_else(|_| {
-            if std::env::var("CI").is_ok() {
+        let artifact_name = match env.var(artifact_name_var) {
+            Some(artifact_name) => artifact_name,
+            None if env.is_ci() => {
                 error!(
                     "beardist expects ${} to be set to determine the custom package name to upload",
                     artifact_name_var
@@ -281,26 +929,31 @@ impl BuildContext {
                     rustc +stable --print target-libdir | sed -E 's/.*stable-([^/]+).*/\1/'
                 "#
                 );
-                panic!("${artifact_name_var} must be set in CI environment")
-            } else {
+                return Err(eyre::eyre!(
+                    "${artifact_name_var} must be set in CI environment"
+                ))
+                .classify(ErrorKind::Config);
+            }
+            None => {
                 let output = command::get_trimmed_cmd_stdout(
                     "rustc",
                     &["+stable", "--print", "target-libdir"],
                     None,
                 )
-                .expect("Failed to execute rustc command");
+                .wrap_err("Failed to execute rustc command")
+                .classify(ErrorKind::Config)?;
                 let triplet = output
                     .split('/')
                     .find(|s| s.contains("stable-"))
                     .and_then(|s| s.strip_prefix("stable-"))
-                    .expect("Failed to extract triplet from rustc output");
-                info!(
-                    "Automatically determined artifact name: {}",
-                    triplet.cyan()
-                );
+                    .ok_or_else(|| {
+                        eyre::eyre!("Failed to extract triplet from rustc output: {}", output)
+                    })
+                    .classify(ErrorKind::Config)?;
+                info!("Automatically determined artifact name: {}", triplet.cyan());
                 triplet.to_string()
             }
-        });
+        };
 
         let cx = Self {
             artifact_name,
@@ -309,18 +962,20 @@ impl BuildContext {
             github_server_url,
             github_rw_token,
             tag,
+            channel,
             is_dry_run,
             source_dir,
             temp_dir,
+            env,
         };
         Ok(cx)
     }
 
-    fn create_package_archive(
-        &self,
-        files_to_package: &[PackagedFile],
-    ) -> Result<camino::Utf8PathBuf> {
-        let artifact_name = &self.artifact_name;
+    fn create_package_archive(&self, files_to_package: &[PackagedFile]) -> Result<PackageArchive> {
+        let artifact_name = match &self.channel {
+            Some(channel) => channel.artifact_name(&self.artifact_name),
+            None => self.artifact_name.clone(),
+        };
         let package_file = camino::Utf8PathBuf::from_path_buf(
             self.temp_dir.path().join(format!("{artifact_name}.tar.xz")),
         )
@@ -340,90 +995,190 @@ impl BuildContext {
             );
         }
 
-        let tar_args = files_to_package
-            .iter()
-            .flat_map(|f| {
-                vec![
-                    "-C".to_string(),
-                    f.path.parent().unwrap().to_string(),
-                    f.path.file_name().unwrap().to_string(),
-                ]
-            })
-            .collect::<Vec<_>>()
-            .join(" ");
+        let mut tar_args = vec!["--create".to_string(), "--file=-".to_string()];
+        for f in files_to_package {
+            tar_args.push("-C".to_string());
+            tar_args.push(f.path.parent().unwrap().to_string());
+            tar_args.push(f.path.file_name().unwrap().to_string());
+        }
+
+        // tar | xz | file, with us in the middle to measure progress, and at the end to
+        // hash the archive as it's written
+        let mut tar = std::process::Command::new("tar")
+            .args(&tar_args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .wrap_err("Failed to run tar")?;
+        let mut xz = std::process::Command::new("xz")
+            .args(["-2", "--threads=0", "--stdout"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .wrap_err("Failed to run xz")?;
+        let mut xz_stdout = xz.stdout.take().unwrap();
+        let mut archive = checksums::HashingWriter::new(fs_err::File::create(&package_file)?);
+        let writer = std::thread::spawn(move || -> std::io::Result<(String, u64)> {
+            std::io::copy(&mut xz_stdout, &mut archive)?;
+            archive.finish()
+        });
 
-        let archive_command = format!(
-            "tar --create --verbose --file=- {} | xz -2 --threads=0 --stdout > {}",
-            tar_args, package_file
+        let progress = progress::Progress::bytes(
+            format!("Compressing {}", package_file.file_name().unwrap()),
+            Some(tar_size(files_to_package)?),
         );
-        run_command("bash", &["-euo", "pipefail", "-c", &archive_command], None)?;
+        let mut tar_stdout = progress.reader(tar.stdout.take().unwrap());
+        std::io::copy(&mut tar_stdout, &mut xz.stdin.take().unwrap())?;
+        progress.finish();
+
+        let tar_status = tar.wait()?;
+        let xz_status = xz.wait()?;
+        let (sha256, size) = writer.join().unwrap()?;
+        if !tar_status.success() || !xz_status.success() {
+            return Err(eyre::eyre!(
+                "Failed to create {}: tar exited with {}, xz with {}",
+                package_file,
+                tar_status,
+                xz_status
+            ));
+        }
 
-        Ok(package_file)
+        Ok(PackageArchive {
+            path: package_file,
+            size,
+            sha256,
+        })
     }
 
-    fn upload_package(
+    /// Copies the packaged files and the archive to `inspect_dir`, replacing whatever
+    /// a previous build left there
+    fn copy_for_inspection(
         &self,
-        package_file: &camino::Utf8Path,
-        file_content: &[u8],
+        inspect_dir: &Utf8Path,
+        archive: &PackageArchive,
         files_to_package: &[PackagedFile],
     ) -> Result<()> {
+        if inspect_dir.exists() {
+            fs_err::remove_dir_all(inspect_dir)?;
+        }
+        fs_err::create_dir_all(inspect_dir)?;
+        let archive_file = (archive.path.as_path(), archive.file_name());
+        let packaged_files = files_to_package
+            .iter()
+            .map(|file| (file.path.as_path(), file.path.file_name().unwrap()));
+        for (path, file_name) in packaged_files.chain([archive_file]) {
+            let dest_path = inspect_dir.join(file_name);
+            // hard links are free, but the directory may be on another filesystem
+            if fs_err::hard_link(path, &dest_path).is_err() {
+                fs_err::copy(path, &dest_path)?;
+            }
+            debug!("📄 Copied {} to {}", path.cyan(), dest_path);
+        }
+        info!(
+            "📁 Packaged files and archive copied to: {}",
+            inspect_dir.bold().underline()
+        );
+        Ok(())
+    }
+
+    fn upload_package(
+        &self,
+        archive: &PackageArchive,
+        files_to_package: &[PackagedFile],
+    ) -> Result<Option<report::Upload>> {
         let org = &self.config.org;
         let name = &self.config.name;
         let tag = &self.tag;
-        let package_file_name = package_file.file_name().unwrap();
+        let package_file_name = archive.file_name();
         assert!(!package_file_name.contains('/'));
 
-        const INSPECT_OUTPUT_DIR: &str = "/tmp/beardist-output";
-        let _ = fs_err::remove_dir_all(INSPECT_OUTPUT_DIR);
-        fs_err::create_dir_all(INSPECT_OUTPUT_DIR)?;
-        for file in files_to_package {
-            let dest_path = format!("{}/{}", INSPECT_OUTPUT_DIR, file.path.file_name().unwrap());
-            fs_err::copy(&file.path, &dest_path)?;
-            info!(
-                "📄 Copied {} to {}",
-                file.path.to_string().cyan(),
-                dest_path.bold().underline()
-            );
-        }
-        info!(
-            "📁 All files copied to: {}",
-            INSPECT_OUTPUT_DIR.bold().underline()
-        );
-
-        const INSPECT_OUTPUT_PATH: &str = "/tmp/beardist-output.tar.xz";
-        fs_err::write(INSPECT_OUTPUT_PATH, file_content)?;
         info!(
             "📦 {} package written to: {}",
-            format_bytes(file_content.len() as _).blue(),
-            INSPECT_OUTPUT_PATH.bold().underline()
+            format_bytes(archive.size).blue(),
+            archive.path.bold().underline()
         );
-        if file_content.len() < 10 * 1024 {
-            return Err(eyre::eyre!(
-                "Suspiciously small package size ({}). Aborting.",
-                format_bytes(file_content.len() as _)
-            ));
-        }
+        self.config
+            .package_checks
+            .check_size(archive.size)
+            .classify(ErrorKind::Policy)?;
+        let file_names: Vec<&str> = files_to_package
+            .iter()
+            .map(|file| file.path.file_name().unwrap())
+            .collect();
+        package_checks::check_contents(&archive.path, &file_names).classify(ErrorKind::Policy)?;
 
         if self.is_dry_run {
             warn!("Not uploading (dry run)");
-            return Ok(());
+            return Ok(None);
+        }
+
+        // Where the archive went, if it's not going to a release
+        let mut package_upload = None;
+        if let Some(oci_config) = &self.config.oci {
+            self.push_to_registry(oci_config, archive)?;
+            if oci_config.skip_releases {
+                package_upload = Some(report::Upload {
+                    url: format!(
+                        "{}/{}:{}-{}",
+                        oci_config.registry,
+                        oci_config.repository,
+                        tag,
+                        package_file_name.trim_end_matches(".tar.xz")
+                    ),
+                    release_id: None,
+                    asset_url: None,
+                });
+            }
+        }
+        if let Some(generic_config) = &self.config.generic_package {
+            let upload = self.upload_generic_package(generic_config, archive)?;
+            if generic_config.skip_releases {
+                package_upload = Some(upload);
+            }
+        }
+        if package_upload.is_some() {
+            return Ok(package_upload);
         }
 
-        // Create a release if it doesn't exist using the github client
-        let github_client = crate::github::GitHubClient::new(
+        // Create a release if it doesn't exist on whichever forge hosts the repository
+        let forge = forge::client(
+            self.config.forge,
             self.github_server_url.clone(),
             self.github_rw_token.clone(),
         );
 
-        let release_id = github_client
-            .create_release(org, name, tag)
+        if let Some(channel) = &self.channel {
+            forge.move_tag(org, name, tag, &channel.sha)?;
+        }
+        // Channel releases are prereleases, so they never count as the latest release
+        let release_id = forge
+            .create_release(org, name, tag, self.channel.is_some())
             .map_err(|e| eyre::eyre!("Failed to create or get release: {}", e))?;
 
-        // Upload the asset to the release using the GitHub client abstraction
+        // Channel releases get rebuilt: replace our archive from the previous build
+        if self.channel.is_some() {
+            let assets = forge.list_release_assets(org, name, release_id)?;
+            for asset in assets
+                .iter()
+                .filter(|asset| asset.name == package_file_name)
+            {
+                info!("Replacing {} from a previous build", asset.name.cyan());
+                forge.delete_release_asset(org, name, release_id, asset.id)?;
+            }
+        }
+
+        // Upload the asset to the release using the forge abstraction
         let upload_start = std::time::Instant::now();
 
-        github_client
-            .upload_artifact(org, name, release_id, package_file_name, file_content)
+        forge
+            .upload_artifact(
+                org,
+                name,
+                release_id,
+                package_file_name,
+                self.asset_label(package_file_name),
+                archive.source(),
+            )
             .map_err(|e| eyre::eyre!("Failed to upload release artifact: {}", e))?;
 
         let upload_time = upload_start.elapsed().as_millis() as u64;
@@ -431,34 +1186,384 @@ impl BuildContext {
             "✅ Package upload completed ({})",
             format!("{}ms", upload_time).green()
         );
+
+        self.update_sha256sums(
+            forge.as_ref(),
+            release_id,
+            package_file_name,
+            &archive.sha256,
+        )?;
+
+        if self.config.installer {
+            let bins: Vec<&str> = files_to_package
+                .iter()
+                .filter(|file| matches!(file.kind, PackagedFileKind::Bin))
+                .map(|file| file.path.file_name().unwrap())
+                .collect();
+            let assets = forge.list_release_assets(org, name, release_id)?;
+            if assets.iter().any(|asset| asset.name == "install.sh") {
+                info!("{} already attached to release", "install.sh".cyan());
+            } else {
+                // Channel archives are named after the channel too
+                let asset = match &self.channel {
+                    Some(channel) => channel.artifact_name("$target"),
+                    None => "$target".to_string(),
+                };
+                let script = installer::generate_install_script(
+                    &self.github_server_url,
+                    org,
+                    name,
+                    tag,
+                    &bins,
+                    &asset,
+                );
+                forge
+                    .upload_artifact(
+                        org,
+                        name,
+                        release_id,
+                        "install.sh",
+                        self.asset_label("install.sh"),
+                        forge::UploadSource::Bytes(script.as_bytes()),
+                    )
+                    .map_err(|e| eyre::eyre!("Failed to upload install.sh: {}", e))?;
+                info!("✅ Attached {} to release", "install.sh".cyan());
+            }
+        }
+        if let Some(install_instructions) = &self.config.install_instructions {
+            let release = release_body::ReleaseInfo {
+                server_url: &self.github_server_url,
+                org,
+                name,
+                tag,
+                installer: self.config.installer,
+            };
+            release_body::update(forge.as_ref(), release_id, &release, install_instructions)?;
+        }
+        Ok(Some(report::Upload {
+            url: format!(
+                "{}/{}/{}/releases/tag/{}",
+                self.github_server_url, org, name, tag
+            ),
+            release_id: Some(release_id),
+            asset_url: Some(format!(
+                "{}/{}/{}/releases/download/{}/{}",
+                self.github_server_url, org, name, tag, package_file_name
+            )),
+        }))
+    }
+
+    /// Posts the commit status and comments `provenance` asks for. The release is out
+    /// either way, so failing to is only a warning.
+    fn report_provenance(
+        &self,
+        provenance: &provenance::ProvenanceConfig,
+        upload: &report::Upload,
+    ) {
+        let Some(release_id) = upload.release_id else {
+            return;
+        };
+        if self.config.forge != forge::ForgeKind::Github {
+            warn!("Provenance reports are only posted on GitHub");
+            return;
+        }
+        let sha = match &self.channel {
+            Some(channel) => Some(channel.sha.clone()),
+            None => self.env.ci_var("SHA").map(|(_, sha)| sha),
+        };
+        let Some(sha) = sha else {
+            warn!("Don't know which commit was built, not reporting provenance");
+            return;
+        };
+        let client =
+            github::GitHubClient::new(self.github_server_url.clone(), self.github_rw_token.clone());
+        let released = provenance::Released {
+            org: &self.config.org,
+            name: &self.config.name,
+            tag: &self.tag,
+            sha: &sha,
+            artifact_name: &self.artifact_name,
+            release_id,
+            release_url: &upload.url,
+        };
+        if let Err(e) = provenance::report(provenance, &client, &released) {
+            warn!("Couldn't report the release on {}: {:?}", sha, e);
+        }
+    }
+
+    /// The configured label of the asset named `file_name`
+    fn asset_label(&self, file_name: &str) -> Option<&str> {
+        self.config.asset_labels.get(file_name).map(String::as_str)
+    }
+
+    /// Pushes the archive to an OCI registry, tagged `<tag>-<target>`, along with any
+    /// configured referrers
+    fn push_to_registry(
+        &self,
+        oci_config: &oci::OciConfig,
+        archive: &PackageArchive,
+    ) -> Result<()> {
+        let package_file_name = archive.file_name();
+        let client = oci::OciClient::new(oci_config, &self.github_rw_token);
+        let reference = format!(
+            "{}-{}",
+            self.tag,
+            package_file_name.trim_end_matches(".tar.xz")
+        );
+        let subject = client
+            .push_artifact(
+                Some(&reference),
+                "application/vnd.bearcove.beardist.archive",
+                package_file_name,
+                archive.source(),
+                None,
+            )
+            .wrap_err("while pushing archive to OCI registry")?;
+
+        for referrer in &oci_config.referrers {
+            let path = self.source_dir.join(&referrer.path);
+            let content = fs_err::read(&path)?;
+            client
+                .push_artifact(
+                    None,
+                    &referrer.artifact_type,
+                    path.file_name().unwrap_or(&referrer.path),
+                    forge::UploadSource::Bytes(&content),
+                    Some(&subject),
+                )
+                .wrap_err_with(|| format!("while pushing referrer {}", referrer.path))?;
+        }
         Ok(())
     }
+
+    /// Uploads the archive as a file of a Forgejo generic package, versioned by tag
+    fn upload_generic_package(
+        &self,
+        generic_config: &forgejo::GenericPackageConfig,
+        archive: &PackageArchive,
+    ) -> Result<report::Upload> {
+        let package_file_name = archive.file_name();
+        let server_url = generic_config
+            .server_url
+            .clone()
+            .unwrap_or_else(|| self.github_server_url.clone());
+        let token =
+            credentials::token("FORGEJO_TOKEN").unwrap_or_else(|| self.github_rw_token.clone());
+        let client = forgejo::ForgejoClient::new(server_url, token);
+        let owner = generic_config.owner.as_ref().unwrap_or(&self.config.org);
+        let package = generic_config.package.as_ref().unwrap_or(&self.config.name);
+
+        let asset_url = client
+            .upload_generic_package(
+                owner,
+                package,
+                &self.tag,
+                package_file_name,
+                archive.source(),
+            )
+            .wrap_err("while uploading generic package")?;
+        info!(
+            "✅ Uploaded {} to {}",
+            package_file_name.cyan(),
+            asset_url.bright_green()
+        );
+        Ok(report::Upload {
+            url: client.generic_package_page(owner, package, &self.tag),
+            release_id: None,
+            asset_url: Some(asset_url),
+        })
+    }
+
+    /// Adds our package's hash to the release's `SHA256SUMS` asset. Builds for other
+    /// targets may be doing the same thing concurrently, so we check that our entry
+    /// survived, and try again if it didn't.
+    fn update_sha256sums(
+        &self,
+        forge: &dyn forge::Forge,
+        release_id: u64,
+        package_file_name: &str,
+        hash: &str,
+    ) -> Result<()> {
+        const SUMS_FILE_NAME: &str = "SHA256SUMS";
+        let org = &self.config.org;
+        let name = &self.config.name;
+
+        let read_sums = || -> Result<(Option<u64>, checksums::Sha256Sums)> {
+            let assets = forge.list_release_assets(org, name, release_id)?;
+            match assets.iter().find(|asset| asset.name == SUMS_FILE_NAME) {
+                Some(asset) => {
+                    let contents = forge.download_release_asset(org, name, asset)?;
+                    let sums = checksums::parse_sha256sums(&String::from_utf8_lossy(&contents));
+                    Ok((Some(asset.id), sums))
+                }
+                None => Ok((None, Default::default())),
+            }
+        };
+
+        for attempt in 1..=5 {
+            let (asset_id, mut sums) = read_sums()?;
+            if sums.get(package_file_name).map(String::as_str) == Some(hash) {
+                info!(
+                    "✅ {} has the hash for {}",
+                    SUMS_FILE_NAME.cyan(),
+                    package_file_name.cyan()
+                );
+                return Ok(());
+            }
+
+            sums.insert(package_file_name.to_string(), hash.to_string());
+            if let Some(asset_id) = asset_id {
+                forge.delete_release_asset(org, name, release_id, asset_id)?;
+            }
+            if let Err(e) = forge.upload_artifact(
+                org,
+                name,
+                release_id,
+                SUMS_FILE_NAME,
+                self.asset_label(SUMS_FILE_NAME),
+                forge::UploadSource::Bytes(checksums::format_sha256sums(&sums).as_bytes()),
+            ) {
+                warn!(
+                    "Failed to upload {} (attempt {}): {}",
+                    SUMS_FILE_NAME, attempt, e
+                );
+            }
+            std::thread::sleep(std::time::Duration::from_millis(
+                500 + rand::random::<u64>() % 1500,
+            ));
+        }
+        Err(eyre::eyre!(
+            "Could not add {} to {} after several attempts",
+            package_file_name,
+            SUMS_FILE_NAME
+        ))
+    }
 }
 
 fn main() -> Result<()> {
-    if std::env::var("RUST_LOG").is_err() {
-        unsafe { std::env::set_var("RUST_LOG", "info") }
+    let cli = Cli::parse();
+    color::init(cli.color);
+
+    // `RUST_LOG` still works for fine-grained filtering, unless a flag overrides it
+    let filter = match (cli.quiet, cli.verbose) {
+        (true, _) => Some("warn"),
+        (false, 1) => Some("info,beardist=debug"),
+        (false, 2..) => Some("debug,beardist=trace"),
+        (false, 0) if std::env::var("RUST_LOG").is_err() => Some("info"),
+        (false, 0) => None,
+    };
+    if let Some(filter) = filter {
+        unsafe { std::env::set_var("RUST_LOG", filter) }
     }
-    env_logger::builder()
+
+    let mut logger = env_logger::builder();
+    logger
         .format_timestamp(None)
         .format_target(false)
-        .format_level(false) // would be nice for non-info, but shrug
-        .init();
-    color_eyre::install()?;
-
-    let cli = Cli::parse();
+        .format_level(false); // would be nice for non-info, but shrug
+    if gha::enabled() || !color::enabled() {
+        logger.format(|buf, record| {
+            use std::io::Write;
+            let message = record.args().to_string();
+            let message = if color::enabled() {
+                message
+            } else {
+                color::strip_ansi(&message).into_owned()
+            };
+            // Surface warnings and errors as annotations on the workflow run
+            match record.level() {
+                Level::Error if gha::enabled() => {
+                    writeln!(buf, "{}", gha::annotation("error", &message))
+                }
+                Level::Warn if gha::enabled() => {
+                    writeln!(buf, "{}", gha::annotation("warning", &message))
+                }
+                _ => writeln!(buf, "{}", message),
+            }
+        });
+    }
+    logger.init();
+    if color::enabled() {
+        color_eyre::install()?;
+    } else {
+        color_eyre::config::HookBuilder::new()
+            .theme(color_eyre::config::Theme::new())
+            .install()?;
+    }
 
-    match cli.command {
-        Commands::Build => build()?,
-        Commands::Bump(args) => bump(args)?,
-        Commands::UpdateTap => update_tap()?,
-        Commands::K8s(args) => k8s::k8s(args)?,
+    if cli.dry_run {
+        // Commands check `DRY_RUN`, so that it can also be set from CI
+        unsafe { std::env::set_var("DRY_RUN", "1") }
+    }
+    if std::env::var("DRY_RUN").is_ok() {
+        print_dry_run_banner();
     }
 
-    Ok(())
+    let config_path = cli.config.as_deref();
+    let result = match cli.command {
+        Commands::Build(args) => build(args, config_path),
+        Commands::Bump(args) => bump(args, config_path),
+        Commands::UpdateTap(args) => update_tap(args),
+        Commands::UpdateScoop(args) => scoop::update_scoop(args),
+        Commands::UpdateWinget => winget::update_winget(),
+        Commands::UpdateNix(args) => nix::update_nix(args),
+        Commands::Binstall(args) => binstall::binstall(args, config_path),
+        Commands::K8s(args) => k8s::k8s(*args),
+        Commands::Doctor => doctor::doctor(config_path),
+        Commands::Schema(args) => schema::schema(args),
+        Commands::MigrateConfig => migrate::migrate_config(config_path),
+        Commands::Install(args) => install::install(args),
+        Commands::Download(args) => download::download(args),
+        Commands::Warm => warm::warm(config_path),
+        Commands::PruneReleases => prune::prune_releases(config_path),
+        Commands::Dispatch(args) => dispatch::dispatch(args, config_path),
+        Commands::Changelog(args) => changelog::changelog(args, config_path),
+        Commands::Status(args) => status::status(args, config_path),
+        Commands::Helm(args) => helm::helm(args, config_path),
+        Commands::GenerateWorkflow(args) => workflow::generate_workflow(args, config_path),
+    };
+
+    if let Err(e) = &result {
+        if gha::enabled() {
+            eprintln!("{}", gha::annotation("error", &error::message(e)));
+        }
+        // Everything has been cleaned up by now: exit with the code for this class of
+        // failure, see `ErrorKind`
+        let exit_code = ErrorKind::of(e).exit_code();
+        if let Some(command_error) = e.downcast_ref::<command::CommandError>() {
+            error!("{}", command_error);
+            error!(
+                "We really needed that command to work, so we're going to bail out now. Buh-bye."
+            );
+            std::process::exit(exit_code);
+        }
+        if exit_code != ErrorKind::Other.exit_code() {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(exit_code);
+        }
+    }
+    result
 }
 
-fn bump(args: BumpArgs) -> Result<()> {
+fn bump(args: BumpArgs, config_path: Option<&Path>) -> Result<()> {
+    let dry_run = env::var("DRY_RUN").is_ok();
+
+    // Bumping works without a config, but in a workspace, it says which tags are ours
+    let config_path = match &args.project {
+        Some(dir) => Some(workspace::project_config(dir)?),
+        None => find_config(config_path)?,
+    };
+    let tag_prefix = match config_path {
+        Some(path) => {
+            let config: serde_json::Value = config_file::load(&path, "config")?;
+            config["tag_prefix"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string()
+        }
+        None => String::new(),
+    };
+
     // Check for unstaged changes
     let status = command::get_trimmed_cmd_stdout("git", &["status", "--porcelain"], None)?;
     if !status.is_empty() {
@@ -466,12 +1571,16 @@ fn bump(args: BumpArgs) -> Result<()> {
         for line in status.lines() {
             info!("  {}", line);
         }
-        info!("Do you want to stage these changes? (y/n)");
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        if input.trim().to_lowercase() == "y" {
-            run_command("git", &["add", "."], None)?;
-            info!("Changes staged.");
+        if dry_run {
+            info!("Dry run: leaving them alone");
+        } else {
+            info!("Do you want to stage these changes? (y/n)");
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            if input.trim().to_lowercase() == "y" {
+                run_command("git", &["add", "."], None)?;
+                info!("Changes staged.");
+            }
         }
     }
 
@@ -482,15 +1591,19 @@ fn bump(args: BumpArgs) -> Result<()> {
         for line in status.lines() {
             info!("  {}", line);
         }
-        info!("Do you want to commit these changes? (y/n)");
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        if input.trim().to_lowercase() == "y" {
-            info!("Enter commit message:");
-            let mut message = String::new();
-            std::io::stdin().read_line(&mut message)?;
-            run_command("git", &["commit", "-m", message.trim()], None)?;
-            info!("Changes committed.");
+        if dry_run {
+            info!("Dry run: leaving them alone");
+        } else {
+            info!("Do you want to commit these changes? (y/n)");
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            if input.trim().to_lowercase() == "y" {
+                info!("Enter commit message:");
+                let mut message = String::new();
+                std::io::stdin().read_line(&mut message)?;
+                run_command("git", &["commit", "-m", message.trim()], None)?;
+                info!("Changes committed.");
+            }
         }
     }
 
@@ -501,29 +1614,47 @@ fn bump(args: BumpArgs) -> Result<()> {
         for line in unpushed.lines() {
             info!("  {}", line);
         }
-        info!("Do you want to push these commits? (y/n)");
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        if input.trim().to_lowercase() == "y" {
-            run_command("git", &["push"], None)?;
-            info!("Commits pushed.");
+        if dry_run {
+            info!("Dry run: leaving them alone");
+        } else {
+            info!("Do you want to push these commits? (y/n)");
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            if input.trim().to_lowercase() == "y" {
+                command::run_command_with_retry(
+                    "git",
+                    &["push"],
+                    None,
+                    retry::RetryPolicy::default(),
+                )?;
+                info!("Commits pushed.");
+            }
         }
     }
 
     // Fetch all tags
-    run_command("git", &["fetch", "--tags"], None)?;
+    command::run_command_with_retry(
+        "git",
+        &["fetch", "--tags"],
+        None,
+        retry::RetryPolicy::default(),
+    )?;
     info!("Fetched all tags from remote.");
 
     // Get all tags sorted by version (newest to oldest)
     let output = command::get_trimmed_cmd_stdout("git", &["tag", "--sort=-version:refname"], None)?;
-    let tags: Vec<String> = output.lines().map(String::from).collect();
+    let tags: Vec<&str> = output
+        .lines()
+        .filter_map(|tag| tag.strip_prefix(tag_prefix.as_str()))
+        .filter(|version| semver::Version::parse(version.trim_start_matches('v')).is_ok())
+        .collect();
 
     if tags.is_empty() {
         return Err(eyre::eyre!("No tags found"));
     }
 
-    let latest_tag = &tags[0];
-    info!("Latest tag: {}", latest_tag);
+    let latest_tag = tags[0];
+    info!("Latest tag: {}{}", tag_prefix, latest_tag);
 
     // Parse the latest tag
     let latest_version = semver::Version::parse(latest_tag.trim_start_matches('v'))?;
@@ -589,12 +1720,22 @@ fn bump(args: BumpArgs) -> Result<()> {
         }
     };
 
-    let new_tag = format!("v{}", new_version);
+    let new_tag = format!("{}v{}", tag_prefix, new_version);
     info!("Creating new tag: {}", new_tag);
 
+    if dry_run {
+        info!("Dry run: Would create and push tag {}", new_tag.cyan());
+        return Ok(());
+    }
+
     // Create and push the new tag
     run_command("git", &["tag", &new_tag], None)?;
-    run_command("git", &["push", "origin", &new_tag], None)?;
+    command::run_command_with_retry(
+        "git",
+        &["push", "origin", &new_tag],
+        None,
+        retry::RetryPolicy::default(),
+    )?;
 
     info!("Tag {} created and pushed successfully", new_tag);
 
@@ -614,32 +1755,123 @@ fn print_banner() {
     }
 }
 
-fn build() -> Result<()> {
+fn print_dry_run_banner() {
+    warn!(
+        "{}",
+        "╭──────────────────────────────────────────────╮".yellow()
+    );
+    warn!(
+        "{}",
+        "│  DRY RUN: nothing will be uploaded, pushed,  │".yellow()
+    );
+    warn!(
+        "{}",
+        "│  or written outside of temporary directories │".yellow()
+    );
+    warn!(
+        "{}",
+        "╰──────────────────────────────────────────────╯".yellow()
+    );
+}
+
+fn build(args: BuildArgs, config_path: Option<&Path>) -> Result<()> {
     print_banner();
     let start_time = std::time::Instant::now();
-    let config = load_config()?;
+    let config_path = match (&args.project, config_path) {
+        (Some(dir), _) => Some(workspace::project_config(dir)?),
+        (None, Some(path)) => Some(path.to_path_buf()),
+        (None, None) if find_config(None)?.is_some() => None,
+        // Not inside a project: it's somewhere below us, in a workspace
+        (None, None) => {
+            let tag = ProcessEnvironment
+                .ci_var("REF")
+                .and_then(|(_, ref_str)| ref_str.strip_prefix("refs/tags/").map(String::from));
+            Some(workspace::find_project(
+                &env::current_dir()?,
+                tag.as_deref(),
+            )?)
+        }
+    };
+    let config = load_config(config_path.as_deref())?;
+    let max_upload_rate = match (args.max_upload_rate, &config.max_upload_rate) {
+        (Some(rate), _) => Some(rate),
+        (None, Some(rate)) => Some(
+            throttle::parse_rate(rate)
+                .wrap_err("Invalid max_upload_rate in config")
+                .classify(ErrorKind::Config)?,
+        ),
+        (None, None) => None,
+    };
+    throttle::set_max_upload_rate(max_upload_rate);
+    let notifications = config.notifications.clone();
     let mut cx = BuildContext::new(config)?;
 
+    let result = build_and_upload(&mut cx, &args, start_time);
+    if let Err(e) = &result {
+        cx.run_hooks(
+            hooks::HookEvent::OnFailure,
+            serde_json::json!({ "error": error::message(e) }),
+        )?;
+    }
+    if let Some(notifications) = &notifications {
+        if !cx.is_dry_run {
+            notify::build_finished(
+                notifications,
+                &notify::BuildEvent {
+                    project: format!("{}/{}", cx.config.org, cx.config.name),
+                    tag: &cx.tag,
+                    target: &cx.artifact_name,
+                    duration: start_time.elapsed(),
+                    outcome: result.as_ref().map_err(error::message),
+                },
+            );
+        }
+    }
+    result.map(|_| ())
+}
+
+/// Everything `build` does once the build context is set up, returning what to tell
+/// notification webhooks about
+fn build_and_upload(
+    cx: &mut BuildContext,
+    args: &BuildArgs,
+    start_time: std::time::Instant,
+) -> Result<notify::Outcome> {
     info!(
         "📦 Building {}/{}",
         cx.config.org.blue(),
         cx.config.name.green(),
     );
+    cx.run_hooks(hooks::HookEvent::PreBuild, serde_json::json!({}))?;
 
-    system::print_sysinfo();
+    let system_info = {
+        let _group = gha::group("System information");
+        system::print_sysinfo()
+    };
 
-    let cargo = cx
+    if cx
+        .config
+        .cargo
+        .as_ref()
+        .is_some_and(|cargo| cargo.universal_macos)
+        && cfg!(target_os = "macos")
+    {
+        cx.artifact_name = cargo::UNIVERSAL_MACOS_ARTIFACT.to_string();
+    }
+    let mut cargo = cx
         .config
         .cargo
         .take()
-        .map(|cc| CargoBuildContext::new(&cx, cc))
+        .map(|cc| CargoBuildContext::new(cx, cc))
         .transpose()?;
 
     let mut files_to_package: Vec<PackagedFile> = Vec::new();
 
     let build_start = std::time::Instant::now();
-    if let Some(cargo) = cargo.as_ref() {
-        cargo.build(&mut files_to_package)?;
+    if let Some(cargo) = cargo.as_mut() {
+        cargo
+            .build(&mut files_to_package)
+            .classify(ErrorKind::Build)?;
     }
 
     if let Some(custom) = cx.config.custom.as_ref() {
@@ -651,7 +1883,16 @@ fn build() -> Result<()> {
                 index + 1,
                 step.join(" ").cyan()
             );
-            run_command(step[0], &step[1..], None)?;
+            command::run_command_with(
+                step[0],
+                &step[1..],
+                Some(warm::js_cache_env(&cx.cache_dir)),
+                command::RunOptions {
+                    pty: custom.pty,
+                    ..Default::default()
+                },
+            )
+            .classify(ErrorKind::Build)?;
         }
 
         info!("📁 Adding custom files to package");
@@ -659,21 +1900,55 @@ fn build() -> Result<()> {
             let path = cx.source_dir.join(file);
             info!("➕ Adding file: {}", path.to_string().cyan());
             files_to_package.push(PackagedFile {
-                kind: PackagedFileKind::Misc,
+                kind: objfile::kind_of(&path).classify(ErrorKind::Build)?,
                 path,
             });
         }
     }
     let build_time = build_start.elapsed().as_millis() as u64;
     info!("🔨 Built in {}", format!("{}ms", build_time).green());
+    cx.run_hooks(
+        hooks::HookEvent::PostBuild,
+        serde_json::json!({
+            "files": files_to_package.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(),
+        }),
+    )?;
 
     info!("{}", "----------------------------------------".dimmed());
 
-    let package_file = cx.create_package_archive(&files_to_package)?;
-    let archive_time = std::time::Instant::now().elapsed().as_millis() as u64;
-    let file_content = fs_err::read(&package_file)?;
+    let archive_start = std::time::Instant::now();
+    let archive = cx
+        .create_package_archive(&files_to_package)
+        .classify(ErrorKind::Build)?;
+    let archive_time = archive_start.elapsed().as_millis() as u64;
+    let inspect_dir =
+        args.inspect_dir
+            .as_deref()
+            .or(cx.config.inspect_dir.as_deref().map(Utf8Path::new));
+    if let Some(inspect_dir) = inspect_dir {
+        cx.copy_for_inspection(inspect_dir, &archive, &files_to_package)?;
+    }
+    let archive_json = serde_json::json!({
+        "path": archive.path.as_str(),
+        "sha256": archive.sha256,
+        "size": archive.size,
+    });
+    cx.run_hooks(
+        hooks::HookEvent::PreUpload,
+        serde_json::json!({ "archive": archive_json }),
+    )?;
     let upload_start = std::time::Instant::now();
-    cx.upload_package(&package_file, &file_content, &files_to_package)?;
+    let upload = cx
+        .upload_package(&archive, &files_to_package)
+        .classify(ErrorKind::Upload)?;
+    let release_url = upload.as_ref().map(|upload| upload.url.clone());
+    if let (Some(provenance), Some(upload)) = (&cx.config.provenance, &upload) {
+        cx.report_provenance(provenance, upload);
+    }
+    cx.run_hooks(
+        hooks::HookEvent::PostUpload,
+        serde_json::json!({ "archive": archive_json, "upload": upload }),
+    )?;
     let upload_time = upload_start.elapsed().as_millis() as u64;
 
     if let Some(cargo) = cargo.as_ref() {
@@ -693,30 +1968,351 @@ fn build() -> Result<()> {
         total_time.to_string().green()
     );
 
-    Ok(())
+    let mut timings = vec![("Build", build_time), ("Archive", archive_time)];
+    if !cx.is_dry_run {
+        timings.push(("Upload", upload_time));
+    }
+    timings.push(("Total", total_time as u64));
+    gha::BuildSummary {
+        project: format!("{}/{}", cx.config.org, cx.config.name),
+        archive: archive.file_name(),
+        archive_size: archive.size,
+        files: files_to_package
+            .iter()
+            .map(|file| {
+                let size = fs_err::metadata(&file.path).map(|m| m.len()).unwrap_or(0);
+                (file.path.file_name().unwrap(), size)
+            })
+            .collect(),
+        timings,
+        release_url: release_url.clone(),
+        cache_stats: cargo.as_ref().and_then(|cargo| cargo.cache_stats.clone()),
+    }
+    .write()?;
+
+    if let Some(report_path) = &args.report {
+        report::BuildReport {
+            org: cx.config.org.clone(),
+            name: cx.config.name.clone(),
+            tag: cx.tag.clone(),
+            dry_run: cx.is_dry_run,
+            artifact: report::Artifact {
+                path: archive.path.to_string(),
+                file_name: archive.file_name().to_string(),
+                sha256: archive.sha256.clone(),
+                size: archive.size,
+            },
+            upload,
+            timings_ms: report::Timings {
+                build: build_time,
+                archive: archive_time,
+                upload: upload_time,
+                total: total_time as u64,
+            },
+            toolchain: cargo
+                .as_ref()
+                .map(|cargo| cargo.toolchain.clone())
+                .unwrap_or_default(),
+            system: system_info,
+            cache: cargo.as_ref().and_then(|cargo| cargo.cache_stats.clone()),
+        }
+        .write(report_path)?;
+        info!(
+            "📝 Build report written to {}",
+            report_path.display().to_string().cyan()
+        );
+    }
+
+    Ok(notify::Outcome {
+        archive_size: archive.size,
+        release_url: release_url.clone(),
+    })
 }
 
-fn load_config() -> Result<Config> {
-    let config_path = fs_err::canonicalize(PathBuf::from(".beardist.json"))?;
-    let config_str = fs_err::read_to_string(&config_path).wrap_err_with(|| {
-        format!(
-            "Failed to read config file at {}",
-            config_path.display().to_string().cyan()
+/// Finds the config file: `explicit` if given, otherwise `.beardist.{json,toml,yaml}` in
+/// the current directory or the closest parent that has one (like cargo does for
+/// `Cargo.toml`)
+fn find_config(explicit: Option<&Path>) -> Result<Option<PathBuf>> {
+    if let Some(path) = explicit {
+        return Ok(Some(fs_err::canonicalize(path)?));
+    }
+    for dir in env::current_dir()?.ancestors() {
+        if let Some(path) = config_file::find(dir, ".beardist")? {
+            return Ok(Some(fs_err::canonicalize(path)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Loads the config, then moves to the directory holding it: that's the project's root,
+/// which the build (and the paths in the config) are relative to.
+fn load_config(explicit: Option<&Path>) -> Result<Config> {
+    read_config(explicit).classify(ErrorKind::Config)
+}
+
+fn read_config(explicit: Option<&Path>) -> Result<Config> {
+    let config_path = find_config(explicit)?.ok_or_else(|| {
+        eyre::eyre!(
+            "No .beardist.json, .beardist.toml or .beardist.yaml in the current directory or its parents"
         )
     })?;
-    let config: Config = serde_json::from_str(&config_str).wrap_err_with(|| {
+    // Check the version before the shape, so old configs get a helpful error
+    let value: serde_json::Value = config_file::load(&config_path, "config")?;
+    let version = value["version"].as_u64().unwrap_or_default();
+    if version < CONFIG_VERSION {
+        return Err(eyre::eyre!(
+            "Outdated beardist config version: {}. Expected: {} (in file {}). Run `beardist migrate-config` to upgrade it.",
+            version,
+            CONFIG_VERSION,
+            config_path.display().to_string().cyan()
+        ));
+    }
+    if version > CONFIG_VERSION {
+        return Err(eyre::eyre!(
+            "Invalid beardist config version: {}. Expected: {} (in file {}). Is this beardist too old?",
+            version,
+            CONFIG_VERSION,
+            config_path.display().to_string().cyan()
+        ));
+    }
+    let config: Config = serde_json::from_value(value).wrap_err_with(|| {
         format!(
             "Failed to parse config file at {}",
             config_path.display().to_string().cyan()
         )
     })?;
-    if config.version != CONFIG_VERSION {
-        return Err(eyre::eyre!(
-            "Invalid beardist config version: {}. Expected: {} (in file {})",
-            config.version,
-            CONFIG_VERSION,
+
+    credentials::set_helper(config.credential_helper.clone());
+    command::set_default_timeout(
+        config
+            .command_timeout_secs
+            .map(std::time::Duration::from_secs),
+    );
+
+    let project_dir = config_path.parent().unwrap();
+    if project_dir != env::current_dir()? {
+        info!(
+            "Using config at {}",
             config_path.display().to_string().cyan()
-        ));
+        );
+        env::set_current_dir(project_dir)?;
     }
     Ok(config)
 }
+
+/// Makes a [`BuildContext`] out of made-up environment variables, for tests
+#[cfg(test)]
+struct BuildContextBuilder {
+    config: Config,
+    env: environment::FakeEnvironment,
+}
+
+#[cfg(test)]
+impl BuildContextBuilder {
+    /// Starts from a local (non-CI) build of `org/name` in `source_dir`, caching in
+    /// `cache_dir`
+    fn new(source_dir: &Utf8Path, cache_dir: &Utf8Path) -> Self {
+        let config = serde_json::from_value(serde_json::json!({
+            "version": CONFIG_VERSION,
+            "org": "bearcove",
+            "name": "beardist",
+        }))
+        .unwrap();
+        let mut env = environment::FakeEnvironment {
+            current_dir: source_dir.to_owned(),
+            ..Default::default()
+        };
+        env.vars
+            .insert("BEARDIST_CACHE_DIR".to_string(), cache_dir.to_string());
+        env.vars.insert(
+            "BEARDIST_ARTIFACT_NAME".to_string(),
+            "x86_64-unknown-linux-gnu".to_string(),
+        );
+        Self { config, env }
+    }
+
+    fn var(mut self, name: &str, value: &str) -> Self {
+        self.env.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    fn build(self) -> Result<BuildContext> {
+        BuildContext::from_environment(self.config, Arc::new(self.env))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_context_from_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let (source_dir, cache_dir) = (dir.join("src"), dir.join("cache"));
+
+        let cx = BuildContextBuilder::new(&source_dir, &cache_dir)
+            .build()
+            .unwrap();
+        assert!(cx.is_dry_run);
+        assert_eq!(cx.tag, "vX.Y.Z");
+        assert_eq!(cx.source_dir, source_dir);
+        assert!(cache_dir.is_dir());
+
+        let cx = BuildContextBuilder::new(&source_dir, &cache_dir)
+            .var("CI", "true")
+            .var("FORGEJO_REF", "refs/tags/v1.2.3")
+            .var("FORGEJO_SERVER_URL", "https://code.bearcove.cloud")
+            .var("GH_READWRITE_TOKEN", "secret")
+            .var("BEARDIST_ARTIFACT_NAME", "aarch64-apple-darwin")
+            .build()
+            .unwrap();
+        assert!(!cx.is_dry_run);
+        assert_eq!(cx.tag, "v1.2.3");
+        assert_eq!(cx.github_server_url, "https://code.bearcove.cloud");
+        assert_eq!(cx.github_rw_token, "secret");
+        assert_eq!(cx.artifact_name, "aarch64-apple-darwin");
+        assert!(cx.env.is_ci());
+        assert!(cx.channel.is_none());
+    }
+
+    #[test]
+    fn test_channel_builds() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let (source_dir, cache_dir) = (dir.join("src"), dir.join("cache"));
+
+        let mut builder = BuildContextBuilder::new(&source_dir, &cache_dir)
+            .var("CI", "true")
+            .var("GITHUB_REF", "refs/heads/main")
+            .var("GITHUB_SHA", "0123abcd")
+            .var("GH_READWRITE_TOKEN", "secret");
+        builder.config.channel = Some(serde_json::from_str(r#"{"name": "nightly"}"#).unwrap());
+        let cx = builder.build().unwrap();
+        assert!(!cx.is_dry_run);
+        assert_eq!(cx.tag, "nightly");
+        let channel = cx.channel.as_ref().unwrap();
+        assert_eq!(channel.sha, "0123abcd");
+        assert_eq!(
+            channel.artifact_name(&cx.artifact_name),
+            "x86_64-unknown-linux-gnu-nightly"
+        );
+
+        // Tag builds aren't channel builds
+        let mut builder = BuildContextBuilder::new(&source_dir, &cache_dir)
+            .var("CI", "true")
+            .var("GITHUB_REF", "refs/tags/v1.2.3")
+            .var("GITHUB_SHA", "0123abcd");
+        builder.config.channel = Some(serde_json::from_str(r#"{"name": "nightly"}"#).unwrap());
+        let cx = builder.build().unwrap();
+        assert_eq!(cx.tag, "v1.2.3");
+        assert!(cx.channel.is_none());
+    }
+
+    /// The release part of `build`: archive, release lookup and creation, upload (with a
+    /// retried 5xx) and the `SHA256SUMS` update, against a mock forge
+    #[test]
+    fn test_upload_package_against_mock_forge() {
+        use mock_forge::{MockForge, Response};
+        use serde_json::json;
+
+        let forge = MockForge::start();
+        let releases = "/repos/bearcove/beardist/releases";
+        forge.mock(
+            "GET",
+            &format!("{releases}/tags/v1.2.3"),
+            Response::json(404, json!({"message": "Not Found"})),
+        );
+        forge.mock("POST", releases, Response::json(201, json!({"id": 42})));
+
+        // The assets, as uploaded so far
+        let assets = Arc::new(std::sync::Mutex::new(Vec::<(String, Vec<u8>)>::new()));
+        let mut archive_attempts = 0;
+        forge.mock_with("POST", &format!("{releases}/42/assets"), {
+            let assets = assets.clone();
+            move |request| {
+                let name = request
+                    .query
+                    .as_deref()
+                    .unwrap()
+                    .trim_start_matches("name=");
+                if name.ends_with(".tar.xz") {
+                    archive_attempts += 1;
+                    if archive_attempts == 1 {
+                        return Response::status(502);
+                    }
+                }
+                let mut assets = assets.lock().unwrap();
+                assets.retain(|(existing, _)| existing != name);
+                assets.push((name.to_string(), request.body.clone()));
+                Response::json(201, json!({"id": assets.len(), "name": name}))
+            }
+        });
+        forge.mock_with("GET", &format!("{releases}/42/assets"), {
+            let assets = assets.clone();
+            move |_| {
+                let listing: Vec<_> = assets
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (name, _))| {
+                        json!({"id": index + 1, "name": name, "browser_download_url": ""})
+                    })
+                    .collect();
+                Response::json(200, json!(listing))
+            }
+        });
+        forge.mock_with("GET", &format!("{releases}/assets/2"), {
+            let assets = assets.clone();
+            move |_| match assets.lock().unwrap().get(1) {
+                Some((_, body)) => Response::status(200).body(body.clone()),
+                None => Response::status(404),
+            }
+        });
+        forge.mock(
+            "DELETE",
+            &format!("{releases}/assets/2"),
+            Response::status(204),
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let (source_dir, cache_dir) = (dir.join("src"), dir.join("cache"));
+        fs_err::create_dir_all(&source_dir).unwrap();
+        let cx = BuildContextBuilder::new(&source_dir, &cache_dir)
+            .var("CI", "true")
+            .var("GITHUB_REF", "refs/tags/v1.2.3")
+            .var("GITHUB_SERVER_URL", forge.url())
+            .var("GH_READWRITE_TOKEN", "secret")
+            .build()
+            .unwrap();
+
+        // Incompressible, so the archive clears the minimum size
+        let bin = source_dir.join("beardist");
+        let contents: Vec<u8> = (0..32 * 1024).map(|_| rand::random::<u8>()).collect();
+        fs_err::write(&bin, &contents).unwrap();
+        let files = [PackagedFile {
+            kind: PackagedFileKind::Bin,
+            path: bin,
+        }];
+        let archive = cx.create_package_archive(&files).unwrap();
+        let upload = cx.upload_package(&archive, &files).unwrap().unwrap();
+        assert_eq!(upload.release_id, Some(42));
+
+        let assets = assets.lock().unwrap();
+        let names: Vec<&str> = assets.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["x86_64-unknown-linux-gnu.tar.xz", "SHA256SUMS"]);
+        assert_eq!(assets[0].1, fs_err::read(&archive.path).unwrap());
+        let archive_uploads = forge
+            .requests_to("POST", &format!("{releases}/42/assets"))
+            .into_iter()
+            .filter(|request| {
+                request.query.as_deref() == Some("name=x86_64-unknown-linux-gnu.tar.xz")
+            })
+            .count();
+        assert_eq!(archive_uploads, 2, "the 502 should have been retried");
+        let sums = checksums::parse_sha256sums(&String::from_utf8_lossy(&assets[1].1));
+        assert_eq!(sums["x86_64-unknown-linux-gnu.tar.xz"], archive.sha256);
+    }
+}
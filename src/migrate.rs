@@ -0,0 +1,126 @@
+//! `beardist migrate-config`: rewrites a config file written for an older
+//! [`CONFIG_VERSION`] to the current schema.
+
+use log::*;
+use owo_colors::OwoColorize;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use crate::{
+    CONFIG_VERSION,
+    config_file::{self, Format},
+    find_config,
+};
+
+/// A step from `version` to `version + 1`: reshapes the config in place and describes
+/// what it changed. Add one here whenever `CONFIG_VERSION` is bumped for a change that
+/// isn't purely additive; versions without a step only get their number bumped.
+type Migration = fn(&mut Map<String, Value>) -> Vec<String>;
+
+const MIGRATIONS: &[(u64, Migration)] = &[];
+
+/// Migrates a config to [`CONFIG_VERSION`], returning what changed (nothing if it was
+/// already current)
+fn migrate(config: &mut Map<String, Value>) -> eyre::Result<Vec<String>> {
+    let mut version = config
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| eyre::eyre!("Config has no \"version\""))?;
+    if version > CONFIG_VERSION {
+        return Err(eyre::eyre!(
+            "Config version {} is newer than this beardist supports ({}), upgrade beardist instead",
+            version,
+            CONFIG_VERSION
+        ));
+    }
+
+    let mut changes = Vec::new();
+    while version < CONFIG_VERSION {
+        if let Some((_, migration)) = MIGRATIONS.iter().find(|(from, _)| *from == version) {
+            changes.extend(migration(config));
+        }
+        changes.push(format!("version: {} → {}", version, version + 1));
+        version += 1;
+        config.insert("version".to_string(), version.into());
+    }
+    Ok(changes)
+}
+
+pub(crate) fn migrate_config(config_path: Option<&Path>) -> eyre::Result<()> {
+    let path = find_config(config_path)?
+        .ok_or_else(|| eyre::eyre!("No beardist config found to migrate"))?;
+    let format = Format::from_path(&path)
+        .ok_or_else(|| eyre::eyre!("Unsupported config file format: {}", path.display()))?;
+    let contents = fs_err::read_to_string(&path)?;
+    let mut value: Value = config_file::from_str(&contents, format)?;
+    let config = value
+        .as_object_mut()
+        .ok_or_else(|| eyre::eyre!("{} doesn't hold an object", path.display()))?;
+    if !config.contains_key("version") && config.contains_key("extends") {
+        return Err(eyre::eyre!(
+            "{} takes its version from the file it extends: migrate that one instead",
+            path.display()
+        ));
+    }
+
+    let changes = migrate(config)?;
+    if changes.is_empty() {
+        info!(
+            "{} is already at version {}",
+            path.display().to_string().cyan(),
+            CONFIG_VERSION
+        );
+        return Ok(());
+    }
+
+    if std::env::var("DRY_RUN").is_ok() {
+        info!(
+            "Dry run: Would migrate {} to version {}:",
+            path.display().to_string().cyan(),
+            CONFIG_VERSION
+        );
+        for change in &changes {
+            info!("  {}", change.yellow());
+        }
+        return Ok(());
+    }
+
+    let mut backup = path.clone().into_os_string();
+    backup.push(".bak");
+    fs_err::copy(&path, &backup)?;
+    fs_err::write(&path, config_file::to_string(&value, format)?)?;
+
+    info!(
+        "Migrated {} to version {} (backup at {}):",
+        path.display().to_string().cyan(),
+        CONFIG_VERSION,
+        Path::new(&backup).display().to_string().cyan()
+    );
+    for change in &changes {
+        info!("  {}", change.yellow());
+    }
+    if format != Format::Json {
+        warn!("Comments were not preserved, see the backup");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate() {
+        let mut config = serde_json::json!({ "version": CONFIG_VERSION - 1, "org": "bearcove" });
+        let changes = migrate(config.as_object_mut().unwrap()).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(config["version"], CONFIG_VERSION);
+        assert_eq!(config["org"], "bearcove");
+
+        let changes = migrate(config.as_object_mut().unwrap()).unwrap();
+        assert!(changes.is_empty());
+
+        let mut newer = serde_json::json!({ "version": CONFIG_VERSION + 1 });
+        assert!(migrate(newer.as_object_mut().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! A fake forge for tests: a little HTTP server on localhost that answers from scripted
+//! responses and records what it was sent, so release, upload and download flows (and
+//! their retries) can run in `cargo test` without touching a real forge.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// A request the server received
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+
+    /// Without the query string
+    pub(crate) path: String,
+
+    pub(crate) query: Option<String>,
+
+    /// Keyed by lowercase name
+    pub(crate) headers: HashMap<String, String>,
+
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+/// A scripted response
+#[derive(Debug, Clone)]
+pub(crate) struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// An empty response with the given status
+    pub(crate) fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub(crate) fn json(status: u16, value: serde_json::Value) -> Self {
+        Self::status(status)
+            .header("Content-Type", "application/json")
+            .body(value.to_string())
+    }
+
+    pub(crate) fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub(crate) fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+type Handler = Box<dyn FnMut(&Request) -> Response + Send>;
+
+struct Route {
+    method: String,
+    path: String,
+    handler: Handler,
+}
+
+#[derive(Default)]
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<Request>,
+}
+
+/// The server. It stops when dropped.
+pub(crate) struct MockForge {
+    url: String,
+    state: Arc<Mutex<State>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockForge {
+    pub(crate) fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock forge");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let (accept_state, accept_stopped) = (state.clone(), stopped.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let state = accept_state.clone();
+                std::thread::spawn(move || serve_connection(stream, &state));
+            }
+        });
+
+        Self {
+            url,
+            state,
+            stopped,
+        }
+    }
+
+    /// `http://127.0.0.1:<port>`, without a trailing slash
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Answers `method path` with `response`, every time
+    pub(crate) fn mock(&self, method: &str, path: &str, response: Response) {
+        self.mock_with(method, path, move |_| response.clone());
+    }
+
+    /// Answers `method path` with `responses` in order, repeating the last one once they
+    /// run out: e.g. a 502 and then a 201, to exercise retries
+    pub(crate) fn mock_sequence(&self, method: &str, path: &str, responses: Vec<Response>) {
+        assert!(!responses.is_empty());
+        let mut responses = responses.into_iter();
+        let mut last = None;
+        self.mock_with(method, path, move |_| {
+            if let Some(response) = responses.next() {
+                last = Some(response);
+            }
+            last.clone().unwrap()
+        });
+    }
+
+    /// Answers `method path` with whatever `handler` makes of the request. Routes added
+    /// later take precedence, so a test can override an earlier one.
+    pub(crate) fn mock_with(
+        &self,
+        method: &str,
+        path: &str,
+        handler: impl FnMut(&Request) -> Response + Send + 'static,
+    ) {
+        self.state.lock().unwrap().routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: Box::new(handler),
+        });
+    }
+
+    /// Every request received so far, in order
+    pub(crate) fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// The requests received for `method path`
+    pub(crate) fn requests_to(&self, method: &str, path: &str) -> Vec<Request> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.method == method && request.path == path)
+            .collect()
+    }
+}
+
+impl Drop for MockForge {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake the accept loop up so it notices
+        let _ = TcpStream::connect(self.url.trim_start_matches("http://"));
+    }
+}
+
+fn serve_connection(stream: TcpStream, state: &Mutex<State>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    // Keep-alive: serve requests until the client hangs up
+    while let Some(request) = read_request(&mut reader) {
+        let response = {
+            let mut state = state.lock().unwrap();
+            state.requests.push(request.clone());
+            match state
+                .routes
+                .iter_mut()
+                .rev()
+                .find(|route| route.method == request.method && route.path == request.path)
+            {
+                Some(route) => (route.handler)(&request),
+                None => Response::status(404)
+                    .body(format!("No mock for {} {}", request.method, request.path)),
+            }
+        };
+        if write_response(&mut writer, &response).is_err() {
+            return;
+        }
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).ok()? == 0 {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    };
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let mut body = Vec::new();
+    if headers.get("transfer-encoding").map(String::as_str) == Some("chunked") {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).ok()?;
+            let size = usize::from_str_radix(size.trim(), 16).ok()?;
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).ok()?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(length) = headers.get("content-length") {
+        body.resize(length.parse().ok()?, 0);
+        reader.read_exact(&mut body).ok()?;
+    }
+
+    Some(Request {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
+
+fn write_response(writer: &mut impl Write, response: &Response) -> std::io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\n",
+        response.status,
+        response.body.len()
+    )?;
+    for (name, value) in &response.headers {
+        write!(writer, "{}: {}\r\n", name, value)?;
+    }
+    writer.write_all(b"\r\n")?;
+    writer.write_all(&response.body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http;
+
+    #[test]
+    fn test_scripted_responses_and_retries() {
+        let forge = MockForge::start();
+        forge.mock_sequence(
+            "GET",
+            "/flaky",
+            vec![
+                Response::status(503),
+                Response::json(200, serde_json::json!({"ok": true})),
+            ],
+        );
+
+        let url = format!("{}/flaky?attempt=yes", forge.url());
+        let body: serde_json::Value = http::block_on(async {
+            http::send("Fetching flaky", || http::client().get(&url))
+                .await?
+                .json()
+                .await
+                .map_err(eyre::Report::from)
+        })
+        .unwrap();
+        assert_eq!(body["ok"], true);
+
+        let requests = forge.requests_to("GET", "/flaky");
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].query.as_deref(), Some("attempt=yes"));
+
+        let url = format!("{}/missing", forge.url());
+        let status = http::block_on(async { http::client().get(&url).send().await })
+            .unwrap()
+            .status();
+        assert_eq!(status, 404);
+    }
+}
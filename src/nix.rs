@@ -0,0 +1,457 @@
+use camino::Utf8PathBuf;
+use eyre::Context;
+use log::*;
+use owo_colors::OwoColorize;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt::Write, path::PathBuf};
+
+use crate::{
+    Indented, UpdateNixArgs, checksums, forge::Forge, github::GitHubClient, http,
+    install::archive_for,
+};
+
+#[derive(Deserialize, Debug, Clone)]
+struct NixConfig {
+    /// Description of the generated flake
+    #[serde(default = "default_description")]
+    description: String,
+
+    packages: Vec<NixPackage>,
+
+    /// Identity and message of the commits bumping packages
+    #[serde(default)]
+    commit: crate::git::CommitConfig,
+
+    /// Push over "https" (the default) or "ssh"
+    #[serde(default)]
+    push: crate::git::Transport,
+}
+
+fn default_description() -> String {
+    "Packages published by beardist".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct NixPackage {
+    repo: String,
+    homepage: String,
+    desc: String,
+
+    /// Attribute name in `lib.licenses`, e.g. "mit", "asl20"
+    license: String,
+
+    bins: Vec<String>,
+
+    /// Nix system → target triple of the release asset
+    #[serde(default = "default_systems")]
+    systems: BTreeMap<String, String>,
+}
+
+fn default_systems() -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (
+            "aarch64-darwin".to_string(),
+            "aarch64-apple-darwin".to_string(),
+        ),
+        (
+            "x86_64-linux".to_string(),
+            "x86_64-unknown-linux-gnu".to_string(),
+        ),
+        (
+            "aarch64-linux".to_string(),
+            "aarch64-unknown-linux-gnu".to_string(),
+        ),
+    ])
+}
+
+impl NixPackage {
+    fn org(&self) -> &str {
+        self.repo.split('/').next().unwrap()
+    }
+
+    fn name(&self) -> &str {
+        self.repo.split('/').nth(1).unwrap()
+    }
+
+    /// Where the expression is written on disk
+    fn disk_path(&self) -> Utf8PathBuf {
+        Utf8PathBuf::from(format!("pkgs/{}/default.nix", self.name()))
+    }
+
+    fn expression_version(&self) -> Option<String> {
+        let content = fs_err::read_to_string(self.disk_path()).ok()?;
+        let version_line = content
+            .lines()
+            .find(|line| line.trim().starts_with("version ="))?;
+        let version = version_line.split('"').nth(1)?;
+        Some(version.to_string())
+    }
+
+    fn release_file_url(&self, version: &str, file_name: &str) -> String {
+        format!(
+            "https://github.com/{}/{}/releases/download/v{}/{}",
+            self.org(),
+            self.name(),
+            version,
+            file_name
+        )
+    }
+}
+
+/// Quotes a string for Nix, escaping interpolation
+fn nix_str(s: &str) -> String {
+    format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${")
+    )
+}
+
+/// Generates a `callPackage`-able derivation that repackages the prebuilt binaries.
+/// `archives` maps each of the package's triples to its archive's name and hash.
+fn generate_expression(
+    package: &NixPackage,
+    version: &str,
+    archives: &BTreeMap<String, (String, String)>,
+) -> eyre::Result<String> {
+    let mut w = String::new();
+
+    writeln!(w, "# Generated by beardist, do not edit by hand")?;
+    writeln!(w, "{{ lib, stdenv, fetchurl, autoPatchelfHook }}:")?;
+    writeln!(w)?;
+    writeln!(w, "let")?;
+    {
+        let mut w = w.indented();
+        writeln!(w, "version = \"{}\";", version)?;
+        writeln!(w, "sources = {{")?;
+        for (system, triple) in &package.systems {
+            let (file_name, hash) = archives
+                .get(triple)
+                .ok_or_else(|| eyre::eyre!("No archive for {}", triple))?;
+            let mut w = w.indented();
+            writeln!(w, "\"{}\" = fetchurl {{", system)?;
+            {
+                let mut w = w.indented();
+                writeln!(
+                    w,
+                    "url = \"{}\";",
+                    package.release_file_url(version, file_name)
+                )?;
+                writeln!(w, "sha256 = \"{}\";", hash)?;
+            }
+            writeln!(w, "}};")?;
+        }
+        writeln!(w, "}};")?;
+    }
+    writeln!(w, "in")?;
+    writeln!(w, "stdenv.mkDerivation {{")?;
+    {
+        let mut w = w.indented();
+        writeln!(w, "pname = \"{}\";", package.name())?;
+        writeln!(w, "inherit version;")?;
+        writeln!(w)?;
+        writeln!(
+            w,
+            "src = sources.${{stdenv.hostPlatform.system}} or (throw \"{}: unsupported system ${{stdenv.hostPlatform.system}}\");",
+            package.name()
+        )?;
+        writeln!(w, "sourceRoot = \".\";")?;
+        writeln!(w)?;
+        writeln!(
+            w,
+            "nativeBuildInputs = lib.optionals stdenv.isLinux [ autoPatchelfHook ];"
+        )?;
+        writeln!(
+            w,
+            "buildInputs = lib.optionals stdenv.isLinux [ stdenv.cc.cc.lib ];"
+        )?;
+        writeln!(w)?;
+        writeln!(w, "installPhase = ''")?;
+        {
+            let mut w = w.indented();
+            writeln!(w, "runHook preInstall")?;
+            for bin in &package.bins {
+                writeln!(w, "install -Dm755 {} $out/bin/{}", bin, bin)?;
+            }
+            writeln!(
+                w,
+                "for lib in lib*.so lib*.dylib; do [ -e \"$lib\" ] && install -Dm644 \"$lib\" \"$out/libexec/$lib\"; done"
+            )?;
+            writeln!(w, "runHook postInstall")?;
+        }
+        writeln!(w, "'';")?;
+        writeln!(w)?;
+        writeln!(w, "meta = {{")?;
+        {
+            let mut w = w.indented();
+            writeln!(w, "description = {};", nix_str(&package.desc))?;
+            writeln!(w, "homepage = {};", nix_str(&package.homepage))?;
+            writeln!(w, "license = lib.licenses.{};", package.license)?;
+            writeln!(w, "platforms = builtins.attrNames sources;")?;
+            if let Some(bin) = package.bins.first() {
+                writeln!(w, "mainProgram = \"{}\";", bin)?;
+            }
+        }
+        writeln!(w, "}};")?;
+    }
+    writeln!(w, "}}")?;
+
+    Ok(w)
+}
+
+/// Generates a flake exposing every configured package for every system they support
+fn generate_flake(config: &NixConfig) -> eyre::Result<String> {
+    let mut systems: Vec<&str> = config
+        .packages
+        .iter()
+        .flat_map(|p| p.systems.keys().map(|s| s.as_str()))
+        .collect();
+    systems.sort();
+    systems.dedup();
+
+    let mut w = String::new();
+    writeln!(w, "# Generated by beardist, do not edit by hand")?;
+    writeln!(w, "{{")?;
+    {
+        let mut w = w.indented();
+        writeln!(w, "description = {};", nix_str(&config.description))?;
+        writeln!(w)?;
+        writeln!(
+            w,
+            "inputs.nixpkgs.url = \"github:NixOS/nixpkgs/nixos-unstable\";"
+        )?;
+        writeln!(w)?;
+        writeln!(w, "outputs = {{ self, nixpkgs }}:")?;
+        {
+            let mut w = w.indented();
+            writeln!(w, "let")?;
+            {
+                let mut w = w.indented();
+                let systems = systems
+                    .iter()
+                    .map(|s| format!("\"{}\"", s))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(w, "systems = [ {} ];", systems)?;
+                writeln!(
+                    w,
+                    "forAllSystems = f: nixpkgs.lib.genAttrs systems (system: f nixpkgs.legacyPackages.${{system}});"
+                )?;
+            }
+            writeln!(w, "in {{")?;
+            {
+                let mut w = w.indented();
+                writeln!(w, "packages = forAllSystems (pkgs: {{")?;
+                for package in &config.packages {
+                    let mut w = w.indented();
+                    writeln!(
+                        w,
+                        "{} = pkgs.callPackage ./pkgs/{} {{ }};",
+                        package.name(),
+                        package.name()
+                    )?;
+                }
+                writeln!(w, "}});")?;
+            }
+            writeln!(w, "}};")?;
+        }
+    }
+    writeln!(w, "}}")?;
+
+    Ok(w)
+}
+
+/// Updates the expression for one package, returning the new version if it was bumped
+fn process_package(
+    github_client: &GitHubClient,
+    package: &NixPackage,
+    dry_run: bool,
+) -> eyre::Result<Option<String>> {
+    let Some(version) = github_client.get_latest_release_version(package.org(), package.name())?
+    else {
+        info!("No version found for {}, skipping", package.name().cyan());
+        return Ok(None);
+    };
+
+    if package.expression_version().as_deref() == Some(version.as_str()) {
+        info!(
+            "Expression for {} is already at version {}",
+            package.name().cyan(),
+            version.bright_green()
+        );
+        return Ok(None);
+    }
+
+    let sums = http::block_on(checksums::fetch_sha256sums(
+        &package.release_file_url(&version, "SHA256SUMS"),
+        dry_run,
+    ))?;
+    let mut archives = BTreeMap::new();
+    for triple in package.systems.values() {
+        let file_name = match &sums {
+            Some(sums) => archive_for(triple, |name| sums.contains_key(name)),
+            None => format!("{}.tar.xz", triple),
+        };
+        let hash = match sums.as_ref().and_then(|sums| sums.get(&file_name)) {
+            Some(hash) => hash.clone(),
+            None => http::block_on(checksums::fetch_and_hash(
+                &package.release_file_url(&version, &file_name),
+                dry_run,
+            ))?,
+        };
+        archives.insert(triple.clone(), (file_name, hash));
+    }
+
+    let expression = generate_expression(package, &version, &archives)?;
+    write_generated_file(&package.disk_path(), &expression, dry_run)?;
+
+    Ok(Some(version))
+}
+
+fn write_generated_file(path: &Utf8PathBuf, contents: &str, dry_run: bool) -> eyre::Result<()> {
+    if dry_run {
+        info!(
+            "Dry run: Would write {}:\n{}",
+            path.to_string().cyan(),
+            contents
+        );
+    } else {
+        if let Some(parent) = path.parent() {
+            fs_err::create_dir_all(parent)?;
+        }
+        fs_err::write(path, contents)?;
+        info!("Nix file written to {}", path.to_string().bright_green());
+    }
+    Ok(())
+}
+
+fn load_nix_config() -> eyre::Result<NixConfig> {
+    let config_path = fs_err::canonicalize(PathBuf::from(".beardist-nix.json"))?;
+    let config_str = fs_err::read_to_string(&config_path).wrap_err_with(|| {
+        format!(
+            "Failed to read nix config file at {}",
+            config_path.display().to_string().cyan()
+        )
+    })?;
+    let config: NixConfig = serde_json::from_str(&config_str).wrap_err_with(|| {
+        format!(
+            "Failed to parse config file at {}",
+            config_path.display().to_string().cyan()
+        )
+    })?;
+    Ok(config)
+}
+
+pub(crate) fn update_nix(args: UpdateNixArgs) -> eyre::Result<()> {
+    let dry_run = std::env::var("DRY_RUN").is_ok();
+    if dry_run {
+        info!("Dry run {}", "enabled".bright_yellow());
+    }
+    let github_token = crate::credentials::require("GITHUB_TOKEN")?;
+
+    info!("Loading nix {}...", "configuration".cyan());
+    let config = load_nix_config()?;
+
+    let github_client =
+        GitHubClient::new("https://api.github.com".to_string(), github_token.clone());
+
+    let mut bumped_packages = Vec::new();
+    for package in &config.packages {
+        info!("Processing package {}...", package.name().cyan());
+        if let Some(version) = process_package(&github_client, package, dry_run)
+            .wrap_err_with(|| format!("while processing package {}", package.name().cyan()))?
+        {
+            bumped_packages.push((package.name().to_string(), version));
+        }
+    }
+
+    if !bumped_packages.is_empty() {
+        let flake = generate_flake(&config)?;
+        write_generated_file(&Utf8PathBuf::from("flake.nix"), &flake, dry_run)?;
+
+        crate::git::commit_and_push(crate::git::Publish {
+            kind: "nix packages",
+            bumped: &bumped_packages,
+            github_token: &github_token,
+            pr: args.pr,
+            base_branch: "main",
+            upstream: None,
+            dry_run,
+            commit: &config.commit,
+            transport: config.push,
+        })?;
+    } else {
+        info!("No packages were bumped");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_config() -> NixConfig {
+        serde_json::from_str(
+            r#"{
+                "packages": [{
+                    "repo": "testowner/foobar",
+                    "homepage": "https://example.com",
+                    "desc": "Sample \"generated\" by beardist",
+                    "license": "mit",
+                    "bins": ["foobar"]
+                }]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_generate_expression() -> eyre::Result<()> {
+        let config = sample_config();
+        let package = &config.packages[0];
+        let mut archives: BTreeMap<String, (String, String)> = package
+            .systems
+            .values()
+            .map(|triple| {
+                (
+                    triple.clone(),
+                    (format!("{}.tar.xz", triple), "c".repeat(64)),
+                )
+            })
+            .collect();
+        let expression = generate_expression(package, "1.2.3", &archives)?;
+        assert!(expression.contains("  version = \"1.2.3\";\n"));
+        assert!(expression.contains(
+            "    \"x86_64-linux\" = fetchurl {\n      url = \"https://github.com/testowner/foobar/releases/download/v1.2.3/x86_64-unknown-linux-gnu.tar.xz\";\n"
+        ));
+        assert!(expression.contains("    install -Dm755 foobar $out/bin/foobar\n"));
+        assert!(
+            expression.contains("    description = \"Sample \\\"generated\\\" by beardist\";\n")
+        );
+        assert!(expression.contains("    license = lib.licenses.mit;\n"));
+
+        // Universal macOS binaries, as picked by `archive_for`
+        let sums = BTreeMap::from([("universal-apple-darwin.tar.xz".to_string(), ())]);
+        let universal = archive_for("aarch64-apple-darwin", |name| sums.contains_key(name));
+        archives.insert(
+            "aarch64-apple-darwin".to_string(),
+            (universal, "d".repeat(64)),
+        );
+        let expression = generate_expression(package, "1.2.3", &archives)?;
+        assert!(expression.contains(
+            "    \"aarch64-darwin\" = fetchurl {\n      url = \"https://github.com/testowner/foobar/releases/download/v1.2.3/universal-apple-darwin.tar.xz\";\n"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_flake() -> eyre::Result<()> {
+        let flake = generate_flake(&sample_config())?;
+        assert!(
+            flake.contains("systems = [ \"aarch64-darwin\" \"aarch64-linux\" \"x86_64-linux\" ];")
+        );
+        assert!(flake.contains("foobar = pkgs.callPackage ./pkgs/foobar { };"));
+        Ok(())
+    }
+}
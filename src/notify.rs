@@ -0,0 +1,290 @@
+//! Notifications (webhooks, Matrix rooms) when a build finishes or fails, configured in the
+//! `notifications` section of `.beardist.json`.
+
+use log::*;
+use owo_colors::OwoColorize;
+use reqwest::blocking::Client;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use url::Url;
+
+use crate::{USER_AGENT, format_bytes};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NotificationsConfig {
+    #[serde(default)]
+    pub(crate) webhooks: Vec<Webhook>,
+
+    /// Matrix rooms to post to
+    #[serde(default)]
+    pub(crate) matrix: Vec<MatrixRoom>,
+
+    /// Notify about successful builds
+    #[serde(default = "default_true")]
+    pub(crate) on_success: bool,
+
+    /// Notify about failed builds
+    #[serde(default = "default_true")]
+    pub(crate) on_failure: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Webhook {
+    /// How to format the payload
+    pub(crate) kind: WebhookKind,
+
+    /// Environment variable holding the webhook URL (they embed secrets, so they don't
+    /// belong in the config), e.g. "SLACK_WEBHOOK_URL"
+    pub(crate) url_env: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum WebhookKind {
+    /// Slack incoming webhook: `{"text": ...}`
+    Slack,
+    /// Discord webhook: `{"content": ...}`
+    Discord,
+    /// Any endpoint accepting JSON: all the details, plus a `text` summary
+    Generic,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MatrixRoom {
+    /// e.g. "https://matrix.org"
+    pub(crate) homeserver: String,
+
+    /// e.g. "!abcdefg:matrix.org"
+    pub(crate) room_id: String,
+
+    /// Environment variable holding the access token of the account to post as
+    #[serde(default = "default_matrix_token_env")]
+    pub(crate) access_token_env: String,
+}
+
+fn default_matrix_token_env() -> String {
+    "MATRIX_ACCESS_TOKEN".to_string()
+}
+
+/// Somewhere we can post a build event
+trait Notifier {
+    /// For logs, e.g. "Slack webhook"
+    fn describe(&self) -> String;
+
+    fn send(&self, client: &Client, event: &BuildEvent) -> eyre::Result<()>;
+}
+
+impl Notifier for Webhook {
+    fn describe(&self) -> String {
+        format!("{:?} webhook", self.kind)
+    }
+
+    fn send(&self, client: &Client, event: &BuildEvent) -> eyre::Result<()> {
+        let url = crate::credentials::require(&self.url_env)?;
+        client
+            .post(&url)
+            .header("User-Agent", USER_AGENT)
+            .json(&event.payload(self.kind))
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl MatrixRoom {
+    /// `PUT` endpoint for a new `m.room.message` event in the room
+    fn send_url(&self, txn_id: &str) -> eyre::Result<Url> {
+        let mut url = Url::parse(&self.homeserver)?;
+        url.path_segments_mut()
+            .map_err(|_| eyre::eyre!("Invalid homeserver URL: {}", self.homeserver))?
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.room_id,
+                "send",
+                "m.room.message",
+                txn_id,
+            ]);
+        Ok(url)
+    }
+}
+
+impl Notifier for MatrixRoom {
+    fn describe(&self) -> String {
+        format!("Matrix room {}", self.room_id)
+    }
+
+    fn send(&self, client: &Client, event: &BuildEvent) -> eyre::Result<()> {
+        let token = crate::credentials::require(&self.access_token_env)?;
+        // Transaction ids make retries idempotent, they only need to be unique per token
+        let txn_id = format!("beardist-{:016x}", rand::random::<u64>());
+        client
+            .put(self.send_url(&txn_id)?)
+            .header("User-Agent", USER_AGENT)
+            .bearer_auth(token)
+            .json(&serde_json::json!({ "msgtype": "m.text", "body": event.text() }))
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// What a successful build produced
+pub(crate) struct Outcome {
+    pub(crate) archive_size: u64,
+    pub(crate) release_url: Option<String>,
+}
+
+pub(crate) struct BuildEvent<'a> {
+    /// "org/name"
+    pub(crate) project: String,
+    pub(crate) tag: &'a str,
+    pub(crate) target: &'a str,
+    pub(crate) duration: Duration,
+    /// The error message if the build failed
+    pub(crate) outcome: Result<&'a Outcome, String>,
+}
+
+impl BuildEvent<'_> {
+    fn text(&self) -> String {
+        match &self.outcome {
+            Ok(outcome) => {
+                let mut text = format!(
+                    "✅ {} {} ({}) built in {:.0?}: {}",
+                    self.project,
+                    self.tag,
+                    self.target,
+                    self.duration,
+                    format_bytes(outcome.archive_size)
+                );
+                if let Some(url) = &outcome.release_url {
+                    text.push_str(&format!(", released at {}", url));
+                }
+                text
+            }
+            Err(error) => format!(
+                "❌ {} {} ({}) failed after {:.0?}: {}",
+                self.project, self.tag, self.target, self.duration, error
+            ),
+        }
+    }
+
+    fn payload(&self, kind: WebhookKind) -> serde_json::Value {
+        match kind {
+            WebhookKind::Slack => serde_json::json!({ "text": self.text() }),
+            WebhookKind::Discord => serde_json::json!({ "content": self.text() }),
+            WebhookKind::Generic => {
+                let (archive_size, release_url, error) = match &self.outcome {
+                    Ok(outcome) => (
+                        Some(outcome.archive_size),
+                        outcome.release_url.clone(),
+                        None,
+                    ),
+                    Err(error) => (None, None, Some(error.clone())),
+                };
+                serde_json::json!({
+                    "event": if self.outcome.is_ok() { "success" } else { "failure" },
+                    "project": self.project,
+                    "tag": self.tag,
+                    "target": self.target,
+                    "duration_ms": self.duration.as_millis() as u64,
+                    "archive_size": archive_size,
+                    "release_url": release_url,
+                    "error": error,
+                    "text": self.text(),
+                })
+            }
+        }
+    }
+}
+
+/// Posts the event to every configured webhook. Failing to notify doesn't fail the build,
+/// so errors are only logged.
+pub(crate) fn build_finished(config: &NotificationsConfig, event: &BuildEvent) {
+    let wanted = match event.outcome {
+        Ok(_) => config.on_success,
+        Err(_) => config.on_failure,
+    };
+    if !wanted {
+        return;
+    }
+
+    let client = Client::new();
+    let notifiers = config
+        .webhooks
+        .iter()
+        .map(|webhook| webhook as &dyn Notifier)
+        .chain(config.matrix.iter().map(|room| room as &dyn Notifier));
+    for notifier in notifiers {
+        match notifier.send(&client, event) {
+            Ok(()) => info!("🔔 Notified {}", notifier.describe().cyan()),
+            Err(e) => warn!("Failed to notify {}: {}", notifier.describe(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payloads() {
+        let outcome = Outcome {
+            archive_size: 2048,
+            release_url: Some("https://github.com/bearcove/beardist/releases/tag/v1.0.0".into()),
+        };
+        let event = BuildEvent {
+            project: "bearcove/beardist".to_string(),
+            tag: "v1.0.0",
+            target: "x86_64-unknown-linux-gnu",
+            duration: Duration::from_secs(90),
+            outcome: Ok(&outcome),
+        };
+        assert_eq!(
+            event.payload(WebhookKind::Slack)["text"],
+            "✅ bearcove/beardist v1.0.0 (x86_64-unknown-linux-gnu) built in 90s: 2.00 KB, released at https://github.com/bearcove/beardist/releases/tag/v1.0.0"
+        );
+        let generic = event.payload(WebhookKind::Generic);
+        assert_eq!(generic["event"], "success");
+        assert_eq!(generic["archive_size"], 2048);
+
+        let failed = BuildEvent {
+            outcome: Err("cargo build failed".to_string()),
+            ..event
+        };
+        assert!(
+            failed.payload(WebhookKind::Discord)["content"]
+                .as_str()
+                .unwrap()
+                .ends_with("failed after 90s: cargo build failed")
+        );
+        assert_eq!(
+            failed.payload(WebhookKind::Generic)["error"],
+            "cargo build failed"
+        );
+    }
+
+    #[test]
+    fn test_matrix_send_url() {
+        let room = MatrixRoom {
+            homeserver: "https://matrix.example.org/".to_string(),
+            room_id: "!abc:example.org".to_string(),
+            access_token_env: default_matrix_token_env(),
+        };
+        assert_eq!(
+            room.send_url("txn1").unwrap().as_str(),
+            "https://matrix.example.org/_matrix/client/v3/rooms/!abc:example.org/send/m.room.message/txn1"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Reading what we need out of ELF, Mach-O and PE files ourselves, instead of asking
+//! `ldd`, `readelf` or `otool`, whose output differs across distros and Xcode versions.
+
+use camino::Utf8Path;
+use goblin::{Hint, Object, mach::Mach};
+use log::*;
+use owo_colors::OwoColorize;
+use std::io::Read;
+
+use crate::PackagedFileKind;
+
+/// What an object file links against, and how
+#[derive(Debug, Default)]
+pub(crate) struct ObjectInfo {
+    /// `Bin` or `Lib`
+    pub(crate) kind: Option<PackagedFileKind>,
+    /// e.g. "ELF", "Mach-O (fat)", "PE"
+    pub(crate) format: &'static str,
+    /// The library's own name: ELF soname, Mach-O install name, PE export name
+    pub(crate) name: Option<String>,
+    /// ELF interpreter, e.g. "/lib64/ld-linux-x86-64.so.2"
+    pub(crate) interpreter: Option<String>,
+    /// DT_NEEDED, LC_LOAD_DYLIB and friends, or PE imports, without duplicates
+    pub(crate) needed: Vec<String>,
+    /// DT_RUNPATH/DT_RPATH or LC_RPATH
+    pub(crate) rpaths: Vec<String>,
+}
+
+impl ObjectInfo {
+    /// Parses an object file. `None` if `bytes` aren't an executable or shared library.
+    pub(crate) fn parse(bytes: &[u8]) -> eyre::Result<Option<Self>> {
+        let mut info = Self::default();
+        match Object::parse(bytes)? {
+            Object::Elf(elf) => {
+                info.format = "ELF";
+                info.kind = Some(match elf.is_lib {
+                    true => PackagedFileKind::Lib,
+                    false => PackagedFileKind::Bin,
+                });
+                info.name = elf.soname.map(str::to_string);
+                info.interpreter = elf.interpreter.map(str::to_string);
+                info.add_needed(elf.libraries.iter().copied());
+                info.add_rpaths(elf.runpaths.iter().chain(&elf.rpaths).copied());
+            }
+            Object::Mach(Mach::Binary(macho)) => {
+                info.format = "Mach-O";
+                if !info.add_macho(&macho) {
+                    return Ok(None);
+                }
+            }
+            Object::Mach(Mach::Fat(multi)) => {
+                info.format = "Mach-O (fat)";
+                for arch in &multi {
+                    if let goblin::mach::SingleArch::MachO(macho) = arch? {
+                        if !info.add_macho(&macho) {
+                            return Ok(None);
+                        }
+                    }
+                }
+            }
+            Object::PE(pe) => {
+                info.format = "PE";
+                info.kind = Some(match pe.is_lib {
+                    true => PackagedFileKind::Lib,
+                    false => PackagedFileKind::Bin,
+                });
+                info.name = pe.name.map(str::to_string);
+                info.add_needed(pe.libraries.iter().copied());
+            }
+            _ => return Ok(None),
+        }
+        Ok(Some(info))
+    }
+
+    /// Reads and parses the object file at `path`, see [`Self::parse`]
+    pub(crate) fn read(path: &Utf8Path) -> eyre::Result<Option<Self>> {
+        let bytes = fs_err::read(path)?;
+        Self::parse(&bytes).map_err(|e| eyre::eyre!("Couldn't parse {}: {}", path, e))
+    }
+
+    /// Merges a (possibly single-arch slice of a) Mach-O file. Returns false if it's
+    /// neither an executable nor a dylib.
+    fn add_macho(&mut self, macho: &goblin::mach::MachO) -> bool {
+        use goblin::mach::header::{MH_DYLIB, MH_EXECUTE};
+        self.kind = match macho.header.filetype {
+            MH_EXECUTE => Some(PackagedFileKind::Bin),
+            MH_DYLIB => Some(PackagedFileKind::Lib),
+            _ => return false,
+        };
+        if self.name.is_none() {
+            self.name = macho.name.map(str::to_string);
+        }
+        // goblin lists the file itself as "self", first
+        self.add_needed(macho.libs.iter().copied().filter(|lib| *lib != "self"));
+        self.add_rpaths(macho.rpaths.iter().copied());
+        true
+    }
+
+    fn add_needed<'a>(&mut self, libs: impl Iterator<Item = &'a str>) {
+        for lib in libs {
+            if !self.needed.iter().any(|needed| needed == lib) {
+                self.needed.push(lib.to_string());
+            }
+        }
+    }
+
+    fn add_rpaths<'a>(&mut self, rpaths: impl Iterator<Item = &'a str>) {
+        // DT_RUNPATH and DT_RPATH hold colon-separated lists
+        for rpath in rpaths.flat_map(|rpath| rpath.split(':')) {
+            if !rpath.is_empty() && !self.rpaths.iter().any(|known| known == rpath) {
+                self.rpaths.push(rpath.to_string());
+            }
+        }
+    }
+
+    /// Logs what `path` links against. `ours` are names of the libraries we ship.
+    pub(crate) fn print(&self, path: &Utf8Path, ours: &[&str]) {
+        info!(
+            "💅 FYI, {} is a {} {}",
+            path.file_name().unwrap_or(path.as_str()).cyan(),
+            self.format.magenta(),
+            match self.kind {
+                Some(PackagedFileKind::Lib) => "library",
+                _ => "executable",
+            }
+        );
+        if let Some(name) = &self.name {
+            info!("    name: {}", name.blue());
+        }
+        if let Some(interpreter) = &self.interpreter {
+            info!("    interpreter: {}", interpreter.dimmed());
+        }
+        for rpath in &self.rpaths {
+            info!("    rpath: {}", rpath.blue());
+        }
+        for lib in &self.needed {
+            let file_name = lib.rsplit('/').next().unwrap_or(lib);
+            if ours.contains(&file_name) {
+                info!("    needs: {}", lib.green());
+            } else {
+                info!("    needs: {}", lib);
+            }
+        }
+    }
+}
+
+/// Tells executables and shared libraries apart from everything else by their magic
+/// bytes, without reading whole files (which matters for `.rlib`s).
+pub(crate) fn kind_of(path: &Utf8Path) -> eyre::Result<PackagedFileKind> {
+    let mut magic = [0u8; 16];
+    let mut file = fs_err::File::open(path)?;
+    if file.read_exact(&mut magic).is_err() {
+        return Ok(PackagedFileKind::Misc);
+    }
+    match goblin::peek_bytes(&magic) {
+        Ok(Hint::Elf(_) | Hint::Mach(_) | Hint::MachFat(_) | Hint::PE) => {}
+        _ => return Ok(PackagedFileKind::Misc),
+    }
+    Ok(ObjectInfo::read(path)?
+        .and_then(|info| info.kind)
+        .unwrap_or(PackagedFileKind::Misc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_own_executable() {
+        let exe = std::env::current_exe().unwrap();
+        let info = ObjectInfo::parse(&fs_err::read(&exe).unwrap())
+            .unwrap()
+            .expect("the test binary is an object file");
+        assert!(matches!(info.kind, Some(PackagedFileKind::Bin)));
+        if cfg!(target_os = "linux") {
+            assert_eq!(info.format, "ELF");
+            assert!(info.needed.iter().any(|lib| lib.starts_with("libc.so")));
+        }
+
+        assert!(
+            ObjectInfo::parse(b"#!/bin/sh\necho hi\n")
+                .unwrap()
+                .is_none()
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = Utf8Path::from_path(dir.path()).unwrap().join("script.sh");
+        fs_err::write(&script, "#!/bin/sh\n").unwrap();
+        assert!(matches!(kind_of(&script).unwrap(), PackagedFileKind::Misc));
+    }
+}
@@ -0,0 +1,328 @@
+//! Pushes files to an OCI registry as ORAS-style artifacts: an empty config, one layer
+//! per file, and optionally a `subject` so that SBOMs, signatures etc. show up as
+//! referrers of the main artifact.
+
+use eyre::Context;
+use log::{debug, info};
+use owo_colors::OwoColorize;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+use crate::{USER_AGENT, checksums, forge::UploadSource};
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+
+/// Where to push archives, in `.beardist.json`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OciConfig {
+    /// e.g. "ghcr.io"
+    pub(crate) registry: String,
+
+    /// e.g. "bearcove/beardist-artifacts"
+    pub(crate) repository: String,
+
+    /// Username for the registry. The password is read from `OCI_PASSWORD`, falling
+    /// back to the release upload token.
+    #[serde(default)]
+    pub(crate) username: Option<String>,
+
+    /// Files (relative to the source directory) to attach as referrers of the archive,
+    /// e.g. an SBOM generated by a custom build step
+    #[serde(default)]
+    pub(crate) referrers: Vec<OciReferrer>,
+
+    /// Only push to the registry, don't create forge releases
+    #[serde(default)]
+    pub(crate) skip_releases: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OciReferrer {
+    pub(crate) path: String,
+
+    /// e.g. "application/spdx+json"
+    pub(crate) artifact_type: String,
+}
+
+/// An OCI content descriptor
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Descriptor {
+    pub(crate) media_type: String,
+    pub(crate) digest: String,
+    pub(crate) size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) annotations: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl Descriptor {
+    fn for_content(media_type: &str, content: &[u8]) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            digest: format!("sha256:{}", checksums::sha256_hex(content)),
+            size: content.len() as u64,
+            annotations: None,
+        }
+    }
+
+    fn for_source(media_type: &str, source: &UploadSource) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            digest: format!("sha256:{}", source.sha256()),
+            size: source.len(),
+            annotations: None,
+        }
+    }
+}
+
+/// Builds the manifest of an artifact made of a single file
+fn artifact_manifest(
+    artifact_type: &str,
+    layer: &Descriptor,
+    subject: Option<&Descriptor>,
+) -> serde_json::Value {
+    let mut manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": MANIFEST_MEDIA_TYPE,
+        "artifactType": artifact_type,
+        "config": Descriptor::for_content(EMPTY_MEDIA_TYPE, b"{}"),
+        "layers": [layer],
+    });
+    if let Some(subject) = subject {
+        manifest["subject"] = serde_json::to_value(subject).unwrap();
+    }
+    manifest
+}
+
+/// Parses a `WWW-Authenticate: Bearer realm="...",service="...",scope="..."` challenge
+pub(crate) fn parse_bearer_challenge(header: &str) -> Option<Vec<(String, String)>> {
+    let params = header.strip_prefix("Bearer ")?;
+    let mut parsed = Vec::new();
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let (key, after_key) = rest.split_once('=')?;
+        let after_key = after_key.strip_prefix('"')?;
+        let (value, after_value) = after_key.split_once('"')?;
+        parsed.push((key.trim().to_string(), value.to_string()));
+        rest = after_value.trim_start_matches(',').trim();
+    }
+    Some(parsed)
+}
+
+pub(crate) struct OciClient {
+    client: Client,
+    registry: String,
+    repository: String,
+    username: String,
+    password: String,
+    /// Bearer token obtained from the registry's auth service, once challenged
+    token: Mutex<Option<String>>,
+}
+
+impl OciClient {
+    pub(crate) fn new(config: &OciConfig, fallback_password: &str) -> Self {
+        Self {
+            client: Client::new(),
+            registry: config.registry.clone(),
+            repository: config.repository.clone(),
+            username: config
+                .username
+                .clone()
+                .unwrap_or_else(|| "beardist".to_string()),
+            password: crate::credentials::token("OCI_PASSWORD")
+                .unwrap_or_else(|| fallback_password.to_string()),
+            token: Mutex::new(None),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("https://{}/v2/{}{}", self.registry, self.repository, path)
+    }
+
+    /// Sends a request, answering a bearer challenge from the registry if there is one
+    fn send(&self, build: impl Fn() -> RequestBuilder) -> eyre::Result<Response> {
+        let authed = |request: RequestBuilder| match self.token.lock().unwrap().as_ref() {
+            Some(token) => request.bearer_auth(token),
+            None => request.basic_auth(&self.username, Some(&self.password)),
+        };
+        let response = authed(build()).header("User-Agent", USER_AGENT).send()?;
+        if response.status() != 401 {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get("WWW-Authenticate")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_bearer_challenge)
+            .ok_or_else(|| eyre::eyre!("Registry {} rejected our credentials", self.registry))?;
+        let realm = challenge
+            .iter()
+            .find(|(key, _)| key == "realm")
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| eyre::eyre!("Auth challenge without a realm"))?;
+        let mut query: Vec<(String, String)> = challenge
+            .into_iter()
+            .filter(|(key, _)| key == "service" || key == "scope")
+            .collect();
+        if !query.iter().any(|(key, _)| key == "scope") {
+            query.push((
+                "scope".to_string(),
+                format!("repository:{}:pull,push", self.repository),
+            ));
+        }
+        debug!("Fetching registry token from {}", realm);
+        let token_response: serde_json::Value = self
+            .client
+            .get(&realm)
+            .query(&query)
+            .basic_auth(&self.username, Some(&self.password))
+            .header("User-Agent", USER_AGENT)
+            .send()?
+            .error_for_status()
+            .wrap_err("while fetching a registry token")?
+            .json()?;
+        let token = token_response["token"]
+            .as_str()
+            .or_else(|| token_response["access_token"].as_str())
+            .ok_or_else(|| eyre::eyre!("Registry token response has no token"))?;
+        *self.token.lock().unwrap() = Some(token.to_string());
+
+        Ok(authed(build()).header("User-Agent", USER_AGENT).send()?)
+    }
+
+    fn push_blob(&self, media_type: &str, source: UploadSource) -> eyre::Result<Descriptor> {
+        let descriptor = Descriptor::for_source(media_type, &source);
+        let exists = self.send(|| {
+            self.client
+                .head(self.url(&format!("/blobs/{}", descriptor.digest)))
+        })?;
+        if exists.status().is_success() {
+            debug!("Blob {} already exists", descriptor.digest);
+            return Ok(descriptor);
+        }
+
+        let started = self.send(|| self.client.post(self.url("/blobs/uploads/")))?;
+        if started.status() != 202 {
+            return Err(eyre::eyre!(
+                "Failed to start blob upload: HTTP status {}",
+                started.status()
+            ));
+        }
+        let location = started
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| eyre::eyre!("Blob upload response has no Location"))?;
+        let location = if location.starts_with('/') {
+            format!("https://{}{}", self.registry, location)
+        } else {
+            location.to_string()
+        };
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let upload_url = format!("{}{}digest={}", location, separator, descriptor.digest);
+
+        let finished = self.send(|| {
+            self.client
+                .put(&upload_url)
+                .header("Content-Type", "application/octet-stream")
+                .body(reqwest::blocking::Body::sized(
+                    source.reader(),
+                    source.len(),
+                ))
+        })?;
+        if finished.status() != 201 {
+            return Err(eyre::eyre!(
+                "Failed to upload blob {}: HTTP status {}",
+                descriptor.digest,
+                finished.status()
+            ));
+        }
+        Ok(descriptor)
+    }
+
+    /// Pushes a single-file artifact, tagged with `reference` if given (referrers are
+    /// only addressed by digest), and returns the manifest's descriptor
+    pub(crate) fn push_artifact(
+        &self,
+        reference: Option<&str>,
+        artifact_type: &str,
+        file_name: &str,
+        source: UploadSource,
+        subject: Option<&Descriptor>,
+    ) -> eyre::Result<Descriptor> {
+        self.push_blob(EMPTY_MEDIA_TYPE, UploadSource::Bytes(b"{}"))?;
+        let mut layer = self.push_blob("application/octet-stream", source)?;
+        let mut annotations = serde_json::Map::new();
+        annotations.insert(
+            "org.opencontainers.image.title".to_string(),
+            file_name.into(),
+        );
+        layer.annotations = Some(annotations);
+
+        let manifest = serde_json::to_vec(&artifact_manifest(artifact_type, &layer, subject))?;
+        let descriptor = Descriptor::for_content(MANIFEST_MEDIA_TYPE, &manifest);
+        let reference = reference.unwrap_or(&descriptor.digest);
+        let response = self.send(|| {
+            self.client
+                .put(self.url(&format!("/manifests/{}", reference)))
+                .header("Content-Type", MANIFEST_MEDIA_TYPE)
+                .body(manifest.clone())
+        })?;
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "Failed to push manifest: HTTP status {}: {}",
+                response.status(),
+                response.text()?
+            ));
+        }
+        info!(
+            "✅ Pushed {} to {}",
+            file_name.cyan(),
+            format!("{}/{}:{}", self.registry, self.repository, reference).bright_green()
+        );
+        Ok(descriptor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bearer_challenge() {
+        let challenge = parse_bearer_challenge(
+            r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:a/b:pull""#,
+        )
+        .unwrap();
+        assert_eq!(
+            challenge,
+            vec![
+                ("realm".to_string(), "https://ghcr.io/token".to_string()),
+                ("service".to_string(), "ghcr.io".to_string()),
+                ("scope".to_string(), "repository:a/b:pull".to_string()),
+            ]
+        );
+        assert!(parse_bearer_challenge("Basic realm=\"x\"").is_none());
+    }
+
+    #[test]
+    fn test_artifact_manifest() {
+        let layer = Descriptor::for_content("application/octet-stream", b"hello");
+        let subject = Descriptor::for_content(MANIFEST_MEDIA_TYPE, b"{}");
+        let manifest = artifact_manifest("application/spdx+json", &layer, Some(&subject));
+        assert_eq!(manifest["artifactType"], "application/spdx+json");
+        assert_eq!(manifest["config"]["mediaType"], EMPTY_MEDIA_TYPE);
+        assert_eq!(
+            manifest["config"]["digest"],
+            "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+        assert_eq!(manifest["layers"][0]["size"], 5);
+        assert_eq!(manifest["subject"]["mediaType"], MANIFEST_MEDIA_TYPE);
+    }
+}
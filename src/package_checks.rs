@@ -0,0 +1,125 @@
+//! Sanity checks on the archive before it gets uploaded: that its size is plausible for
+//! the project, and that it holds exactly the files we meant to package.
+
+use camino::Utf8Path;
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{command, format_bytes};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PackageChecksConfig {
+    /// Smallest acceptable archive, in bytes. Defaults to 10KiB, which catches empty or
+    /// mostly-empty archives; lower it for tiny tools.
+    #[serde(default = "default_min_bytes")]
+    pub(crate) min_bytes: u64,
+
+    /// Largest acceptable archive, in bytes, e.g. to catch debug info or a whole target
+    /// directory sneaking in. No limit by default.
+    #[serde(default)]
+    pub(crate) max_bytes: Option<u64>,
+}
+
+fn default_min_bytes() -> u64 {
+    10 * 1024
+}
+
+impl Default for PackageChecksConfig {
+    fn default() -> Self {
+        Self {
+            min_bytes: default_min_bytes(),
+            max_bytes: None,
+        }
+    }
+}
+
+impl PackageChecksConfig {
+    pub(crate) fn check_size(&self, size: u64) -> eyre::Result<()> {
+        if size < self.min_bytes {
+            return Err(eyre::eyre!(
+                "Suspiciously small package size ({}, expected at least {}). Aborting.",
+                format_bytes(size),
+                format_bytes(self.min_bytes)
+            ));
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if size > max_bytes {
+                return Err(eyre::eyre!(
+                    "Suspiciously large package size ({}, expected at most {}). Aborting.",
+                    format_bytes(size),
+                    format_bytes(max_bytes)
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lists `archive` (a `.tar.xz`) and makes sure it holds `expected` file names, and
+/// nothing else. A truncated archive fails to list.
+pub(crate) fn check_contents(archive: &Utf8Path, expected: &[&str]) -> eyre::Result<()> {
+    let listing = command::get_cmd_stdout("tar", &["-tJf", archive.as_str()], None)
+        .map_err(|e| eyre::eyre!("Couldn't list {}, is it truncated? {}", archive, e))?;
+    compare_listing(&listing, expected)?;
+    info!(
+        "✅ {} holds the {} packaged files",
+        archive.file_name().unwrap_or(archive.as_str()).cyan(),
+        expected.len().to_string().yellow()
+    );
+    Ok(())
+}
+
+fn compare_listing(listing: &str, expected: &[&str]) -> eyre::Result<()> {
+    let listed: Vec<&str> = listing
+        .lines()
+        .map(|line| line.trim().trim_start_matches("./"))
+        .filter(|line| !line.is_empty())
+        .collect();
+    let missing: Vec<&str> = expected
+        .iter()
+        .copied()
+        .filter(|name| !listed.contains(name))
+        .collect();
+    let unexpected: Vec<&str> = listed
+        .iter()
+        .copied()
+        .filter(|name| !expected.contains(name))
+        .collect();
+    if missing.is_empty() && unexpected.is_empty() {
+        return Ok(());
+    }
+    Err(eyre::eyre!(
+        "Archive contents don't match the packaged files (missing: [{}], unexpected: [{}])",
+        missing.join(", "),
+        unexpected.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_checks() {
+        let config = PackageChecksConfig::default();
+        assert!(config.check_size(512).is_err());
+        assert!(config.check_size(20 * 1024).is_ok());
+
+        let config = PackageChecksConfig {
+            min_bytes: 0,
+            max_bytes: Some(1024),
+        };
+        assert!(config.check_size(512).is_ok());
+        assert!(config.check_size(2048).is_err());
+
+        assert!(compare_listing("beardist\nlibfoo.so\n", &["libfoo.so", "beardist"]).is_ok());
+        let err = compare_listing("beardist\nCargo.toml\n", &["beardist", "libfoo.so"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("missing: [libfoo.so]"));
+        assert!(err.contains("unexpected: [Cargo.toml]"));
+    }
+}
@@ -0,0 +1,155 @@
+//! Checking that Windows (PE) binaries only import DLLs users will have.
+
+use camino::Utf8Path;
+use log::*;
+use owo_colors::OwoColorize;
+
+use crate::objfile::ObjectInfo;
+
+/// DLLs that ship with every supported version of Windows (besides `api-ms-win-*` and
+/// `ext-ms-*` API sets, which include the Universal CRT)
+const SYSTEM_DLLS: &[&str] = &[
+    "advapi32.dll",
+    "bcrypt.dll",
+    "bcryptprimitives.dll",
+    "cfgmgr32.dll",
+    "comctl32.dll",
+    "comdlg32.dll",
+    "crypt32.dll",
+    "d3d11.dll",
+    "d3d12.dll",
+    "dbghelp.dll",
+    "dwmapi.dll",
+    "dxgi.dll",
+    "gdi32.dll",
+    "hid.dll",
+    "imm32.dll",
+    "iphlpapi.dll",
+    "kernel32.dll",
+    "msvcrt.dll",
+    "mswsock.dll",
+    "ncrypt.dll",
+    "netapi32.dll",
+    "ntdll.dll",
+    "ole32.dll",
+    "oleaut32.dll",
+    "opengl32.dll",
+    "powrprof.dll",
+    "psapi.dll",
+    "rpcrt4.dll",
+    "secur32.dll",
+    "setupapi.dll",
+    "shell32.dll",
+    "shlwapi.dll",
+    "ucrtbase.dll",
+    "user32.dll",
+    "userenv.dll",
+    "uxtheme.dll",
+    "version.dll",
+    "winhttp.dll",
+    "wininet.dll",
+    "winmm.dll",
+    "ws2_32.dll",
+];
+
+/// Prefixes of the Visual C++ runtime's DLLs, which users may not have installed
+const VC_RUNTIME_PREFIXES: &[&str] = &["vcruntime", "msvcp", "vcomp", "concrt"];
+
+/// Where an imported DLL is going to come from
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DllSource {
+    /// Part of Windows itself
+    System,
+    /// The Visual C++ runtime, a separate install
+    VcRuntime,
+    /// Shipped in the archive, next to the binary
+    Bundled,
+    /// Nowhere we know of
+    Missing,
+}
+
+/// Decides where `dll` comes from, given the names of the files we're shipping
+pub(crate) fn classify(dll: &str, bundled: &[&str]) -> DllSource {
+    let dll = dll.to_ascii_lowercase();
+    if dll.starts_with("api-ms-win-")
+        || dll.starts_with("ext-ms-")
+        || SYSTEM_DLLS.contains(&dll.as_str())
+    {
+        DllSource::System
+    } else if VC_RUNTIME_PREFIXES
+        .iter()
+        .any(|prefix| dll.starts_with(prefix))
+    {
+        DllSource::VcRuntime
+    } else if bundled.iter().any(|name| name.eq_ignore_ascii_case(&dll)) {
+        DllSource::Bundled
+    } else {
+        DllSource::Missing
+    }
+}
+
+/// Warns about the DLLs users might not have. `arch` is the Rust target arch, used to
+/// point at the right redistributable.
+pub(crate) fn check_imports(files: &[(&Utf8Path, ObjectInfo)], arch: &str) {
+    let bundled: Vec<&str> = files
+        .iter()
+        .filter_map(|(path, _)| path.file_name())
+        .collect();
+    let mut needs_vc_runtime: Vec<&str> = Vec::new();
+
+    for (path, info) in files {
+        for dll in &info.needed {
+            match classify(dll, &bundled) {
+                DllSource::System | DllSource::Bundled => {}
+                DllSource::VcRuntime => {
+                    if !needs_vc_runtime.contains(&dll.as_str()) {
+                        needs_vc_runtime.push(dll);
+                    }
+                }
+                DllSource::Missing => warn!(
+                    "⚠️ {} imports {}, which is neither part of Windows nor shipped with it",
+                    path.file_name().unwrap_or(path.as_str()),
+                    dll.red()
+                ),
+            }
+        }
+    }
+
+    if !needs_vc_runtime.is_empty() {
+        let redist_arch = match arch {
+            "aarch64" => "arm64",
+            "x86" => "x86",
+            _ => "x64",
+        };
+        warn!(
+            "⚠️ These binaries need the Visual C++ runtime ({}): users who don't have it get \"{} was not found\" errors. \
+            Link it statically with {}, or have users install https://aka.ms/vs/17/release/vc_redist.{}.exe",
+            needs_vc_runtime.join(", "),
+            needs_vc_runtime[0],
+            "RUSTFLAGS=\"-C target-feature=+crt-static\"".cyan(),
+            redist_arch
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_imports() {
+        let bundled = ["foo.exe", "foo_plugin.dll"];
+        assert_eq!(classify("KERNEL32.dll", &bundled), DllSource::System);
+        assert_eq!(
+            classify("api-ms-win-crt-heap-l1-1-0.dll", &bundled),
+            DllSource::System
+        );
+        assert_eq!(
+            classify("VCRUNTIME140_1.dll", &bundled),
+            DllSource::VcRuntime
+        );
+        assert_eq!(classify("MSVCP140.dll", &bundled), DllSource::VcRuntime);
+        assert_eq!(classify("FOO_PLUGIN.DLL", &bundled), DllSource::Bundled);
+        assert_eq!(classify("libssl-3-x64.dll", &bundled), DllSource::Missing);
+    }
+}
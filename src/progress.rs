@@ -0,0 +1,176 @@
+//! Progress for long-running phases (downloads, compression, uploads): a progress bar on
+//! a terminal, periodic log lines otherwise, e.g. in CI logs.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{Level, info, log_enabled};
+use std::{
+    io::{IsTerminal, Read},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::format_bytes;
+
+/// How often to log progress when there's no terminal to draw a bar on
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub(crate) struct Progress {
+    inner: Inner,
+}
+
+#[derive(Clone)]
+enum Inner {
+    Bar(ProgressBar),
+    Log(Arc<Mutex<LogState>>),
+}
+
+struct LogState {
+    label: String,
+    total: Option<u64>,
+    done: u64,
+    start: Instant,
+    last_log: Instant,
+    finished: bool,
+}
+
+impl LogState {
+    fn line(&self) -> String {
+        match self.total {
+            Some(total) if total > 0 => format!(
+                "{}: {}% ({} / {})",
+                self.label,
+                self.done * 100 / total,
+                format_bytes(self.done),
+                format_bytes(total)
+            ),
+            _ => format!("{}: {}", self.label, format_bytes(self.done)),
+        }
+    }
+}
+
+impl Progress {
+    /// Progress in bytes, out of `total` if it's known
+    pub(crate) fn bytes(label: impl Into<String>, total: Option<u64>) -> Self {
+        let label = label.into();
+        let inner = if !log_enabled!(Level::Info) {
+            Inner::Bar(ProgressBar::hidden())
+        } else if std::io::stderr().is_terminal() {
+            let bar = match total {
+                Some(total) => ProgressBar::new(total).with_style(
+                    ProgressStyle::with_template(
+                        "{msg} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+                    )
+                    .unwrap()
+                    .progress_chars("=> "),
+                ),
+                None => ProgressBar::new_spinner().with_style(
+                    ProgressStyle::with_template("{spinner} {msg} {bytes} ({bytes_per_sec})")
+                        .unwrap(),
+                ),
+            };
+            bar.set_message(label);
+            bar.enable_steady_tick(Duration::from_millis(100));
+            Inner::Bar(bar)
+        } else {
+            let now = Instant::now();
+            Inner::Log(Arc::new(Mutex::new(LogState {
+                label,
+                total,
+                done: 0,
+                start: now,
+                last_log: now,
+                finished: false,
+            })))
+        };
+        Self { inner }
+    }
+
+    pub(crate) fn inc(&self, n: u64) {
+        match &self.inner {
+            Inner::Bar(bar) => bar.inc(n),
+            Inner::Log(state) => {
+                let mut state = state.lock().unwrap();
+                state.done += n;
+                if state.last_log.elapsed() >= LOG_INTERVAL {
+                    state.last_log = Instant::now();
+                    info!("{}", state.line());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(&self) {
+        match &self.inner {
+            Inner::Bar(bar) => bar.finish(),
+            Inner::Log(state) => {
+                let mut state = state.lock().unwrap();
+                if !state.finished {
+                    state.finished = true;
+                    info!("{} in {:.1?}", state.line(), state.start.elapsed());
+                }
+            }
+        }
+    }
+
+    /// Wraps a reader so that reading from it advances this progress, which is finished
+    /// when the reader is exhausted
+    pub(crate) fn reader<R: Read>(&self, inner: R) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            progress: self.clone(),
+        }
+    }
+
+    /// Reads everything from `reader`, advancing this progress along the way
+    pub(crate) fn read_to_end(&self, reader: impl Read) -> std::io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.reader(reader).read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+}
+
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.progress.finish();
+        } else {
+            self.progress.inc(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_counts_bytes() {
+        let progress = Progress {
+            inner: Inner::Log(Arc::new(Mutex::new(LogState {
+                label: "test".to_string(),
+                total: Some(10_000),
+                done: 0,
+                start: Instant::now(),
+                last_log: Instant::now(),
+                finished: false,
+            }))),
+        };
+        let contents = progress.read_to_end(&[7u8; 10_000][..]).unwrap();
+        assert_eq!(contents.len(), 10_000);
+        let Inner::Log(state) = &progress.inner else {
+            unreachable!()
+        };
+        let state = state.lock().unwrap();
+        assert_eq!(state.done, 10_000);
+        assert!(state.finished);
+        assert_eq!(state.line(), "test: 100% (9.77 KB / 9.77 KB)");
+    }
+}
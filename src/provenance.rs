@@ -0,0 +1,203 @@
+//! Tells developers about a release where they'll see it, rather than in the Actions
+//! logs: a status on the commit that was built, and a comment on its pull requests (or
+//! on the commit itself) linking to the release and listing its assets.
+
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    forge::{Forge, ReleaseAsset},
+    github::{CommentTarget, CommitStatus, GitHubClient},
+    utils::format_bytes,
+};
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProvenanceConfig {
+    /// Set a status on the built commit for each target, linking to the release
+    #[serde(default)]
+    status: bool,
+
+    /// Comment on the pull requests the built commit is part of (or on the commit, if
+    /// there are none), listing the release's assets. Builds for every target edit the
+    /// same comment.
+    #[serde(default)]
+    comment: bool,
+}
+
+/// What was just released
+pub(crate) struct Released<'a> {
+    pub(crate) org: &'a str,
+    pub(crate) name: &'a str,
+    pub(crate) tag: &'a str,
+
+    /// The commit that was built
+    pub(crate) sha: &'a str,
+
+    /// e.g. "x86_64-unknown-linux-gnu"
+    pub(crate) artifact_name: &'a str,
+
+    pub(crate) release_id: u64,
+    pub(crate) release_url: &'a str,
+}
+
+/// Lets our comment about a release be found again
+fn marker(tag: &str) -> String {
+    format!("<!-- beardist:provenance {} -->", tag)
+}
+
+fn render_comment(released: &Released, assets: &[ReleaseAsset]) -> String {
+    let short_sha = &released.sha[..released.sha.len().min(7)];
+    let mut comment = format!(
+        "{}\n📦 **[{} {}]({})** was released from {}.\n",
+        marker(released.tag),
+        released.name,
+        released.tag,
+        released.release_url,
+        short_sha
+    );
+    if !assets.is_empty() {
+        comment.push_str("\n| Asset | Size |\n| --- | --- |\n");
+        for asset in assets {
+            comment.push_str(&format!(
+                "| [{}]({}) | {} |\n",
+                asset.name,
+                asset.browser_download_url,
+                format_bytes(asset.size)
+            ));
+        }
+    }
+    comment
+}
+
+/// Posts what `config` asks for about a release on GitHub
+pub(crate) fn report(
+    config: &ProvenanceConfig,
+    client: &GitHubClient,
+    released: &Released,
+) -> eyre::Result<()> {
+    let (org, name) = (released.org, released.name);
+    if config.status {
+        client.create_commit_status(
+            org,
+            name,
+            released.sha,
+            &CommitStatus {
+                state: "success",
+                target_url: released.release_url,
+                description: &format!("Released {} in {}", released.artifact_name, released.tag),
+                context: &format!("beardist/{}", released.artifact_name),
+            },
+        )?;
+        info!(
+            "✅ Set the {} status on {}",
+            released.artifact_name.cyan(),
+            released.sha.cyan()
+        );
+    }
+    if config.comment {
+        let mut assets = client.list_release_assets(org, name, released.release_id)?;
+        assets.sort_by(|a, b| a.name.cmp(&b.name));
+        let comment = render_comment(released, &assets);
+        let pulls = client.pull_requests_for_commit(org, name, released.sha)?;
+        let targets = if pulls.is_empty() {
+            vec![CommentTarget::Commit(released.sha.to_string())]
+        } else {
+            pulls.into_iter().map(CommentTarget::PullRequest).collect()
+        };
+        for target in &targets {
+            client.upsert_comment(org, name, target, &marker(released.tag), &comment)?;
+        }
+        info!("✅ Commented on the release of {}", released.tag.cyan());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_forge::{MockForge, Response};
+    use serde_json::json;
+
+    #[test]
+    fn test_report_edits_previous_comment() {
+        let forge = MockForge::start();
+        let repo = "/repos/bearcove/beardist";
+        let sha = "0123456789abcdef";
+        forge.mock(
+            "POST",
+            &format!("{repo}/statuses/{sha}"),
+            Response::json(201, json!({})),
+        );
+        forge.mock(
+            "GET",
+            &format!("{repo}/releases/42/assets"),
+            Response::json(
+                200,
+                json!([{
+                    "id": 1,
+                    "name": "x86_64-unknown-linux-gnu.tar.xz",
+                    "browser_download_url": "https://example.com/x86_64-unknown-linux-gnu.tar.xz",
+                    "size": 2048,
+                }]),
+            ),
+        );
+        forge.mock(
+            "GET",
+            &format!("{repo}/commits/{sha}/pulls"),
+            Response::json(200, json!([{"number": 12}])),
+        );
+        forge.mock(
+            "GET",
+            &format!("{repo}/issues/12/comments"),
+            Response::json(
+                200,
+                json!([
+                    {"id": 5, "body": "Looks good!"},
+                    {"id": 6, "body": "<!-- beardist:provenance v1.2.3 -->\nolder"},
+                ]),
+            ),
+        );
+        forge.mock(
+            "PATCH",
+            &format!("{repo}/issues/comments/6"),
+            Response::json(200, json!({})),
+        );
+
+        let client = GitHubClient::new(forge.url().to_string(), "secret".to_string());
+        let released = Released {
+            org: "bearcove",
+            name: "beardist",
+            tag: "v1.2.3",
+            sha,
+            artifact_name: "x86_64-unknown-linux-gnu",
+            release_id: 42,
+            release_url: "https://github.com/bearcove/beardist/releases/tag/v1.2.3",
+        };
+        let config = ProvenanceConfig {
+            status: true,
+            comment: true,
+        };
+        report(&config, &client, &released).unwrap();
+
+        let statuses = forge.requests_to("POST", &format!("{repo}/statuses/{sha}"));
+        let status: serde_json::Value = serde_json::from_slice(&statuses[0].body).unwrap();
+        assert_eq!(status["context"], "beardist/x86_64-unknown-linux-gnu");
+        assert_eq!(status["state"], "success");
+
+        let edits = forge.requests_to("PATCH", &format!("{repo}/issues/comments/6"));
+        let edit: serde_json::Value = serde_json::from_slice(&edits[0].body).unwrap();
+        assert_eq!(
+            edit["body"],
+            "<!-- beardist:provenance v1.2.3 -->
+📦 **[beardist v1.2.3](https://github.com/bearcove/beardist/releases/tag/v1.2.3)** was released from 0123456.
+
+| Asset | Size |
+| --- | --- |
+| [x86_64-unknown-linux-gnu.tar.xz](https://example.com/x86_64-unknown-linux-gnu.tar.xz) | 2.00 KB |
+"
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! `beardist prune-releases`: deletes old releases, and with them their assets, as the
+//! project's retention policy says, so nightlies don't pile up forever.
+
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{
+    error::{Classify, ErrorKind},
+    forge::{self, Release},
+    format_bytes, load_config,
+};
+
+/// Which releases `beardist prune-releases` keeps. Drafts are never deleted.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PruneConfig {
+    /// How many of the newest stable releases to keep
+    #[serde(default = "default_keep_last")]
+    pub(crate) keep_last: usize,
+
+    /// Also keep every release of a new major version (tagged `vN.0.0`), however old
+    #[serde(default)]
+    pub(crate) keep_majors: bool,
+
+    /// Delete prereleases older than this many days. Otherwise prereleases are kept.
+    /// Either way, they don't count towards `keep_last`.
+    #[serde(default)]
+    pub(crate) prerelease_max_age_days: Option<u64>,
+}
+
+fn default_keep_last() -> usize {
+    10
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    Keep(String),
+    Delete(String),
+}
+
+/// Whether a release is a prerelease: flagged as one, or tagged with a semver
+/// prerelease version like `v1.2.0-rc.1`
+fn is_prerelease(release: &Release, tag_prefix: &str) -> bool {
+    release.prerelease
+        || version_of(release, tag_prefix).is_some_and(|version| !version.pre.is_empty())
+}
+
+/// The version in a release's tag, after the project's tag prefix (e.g. "api-")
+fn version_of(release: &Release, tag_prefix: &str) -> Option<Version> {
+    let tag = release.tag_name.strip_prefix(tag_prefix)?;
+    Version::parse(tag.trim_start_matches('v')).ok()
+}
+
+/// Days since the Unix epoch of an RFC 3339 timestamp's date
+fn days_since_epoch(timestamp: &str) -> Option<i64> {
+    let date = timestamp.get(..10)?;
+    let mut parts = date.split('-').map(str::parse::<i64>);
+    let (year, month, day) = (
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+
+    // Howard Hinnant's `days_from_civil`
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+fn today() -> i64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_secs() / 86400) as i64
+}
+
+/// Decides what happens to each of the project's releases (those tagged with
+/// `tag_prefix`: other projects may share the repository), newest first. `today` is in
+/// days since the Unix epoch. The channel's rolling release, if any, is reused in place
+/// (so its creation date is its first build's), and is always kept.
+fn plan<'a>(
+    policy: &PruneConfig,
+    tag_prefix: &str,
+    channel: Option<&str>,
+    releases: &'a [Release],
+    today: i64,
+) -> Vec<(&'a Release, Verdict)> {
+    let mut releases: Vec<&Release> = releases
+        .iter()
+        .filter(|release| {
+            release.tag_name.starts_with(tag_prefix) || channel == Some(release.tag_name.as_str())
+        })
+        .collect();
+    releases.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+    let mut stable_seen = 0;
+    releases
+        .into_iter()
+        .map(|release| {
+            let verdict = if release.draft {
+                Verdict::Keep("draft".to_string())
+            } else if channel == Some(release.tag_name.as_str()) {
+                Verdict::Keep("channel".to_string())
+            } else if is_prerelease(release, tag_prefix) {
+                let age = days_since_epoch(&release.created_at).map(|day| today - day);
+                match (policy.prerelease_max_age_days, age) {
+                    (Some(max_age), Some(age)) if age > max_age as i64 => {
+                        Verdict::Delete(format!("prerelease, {} days old", age))
+                    }
+                    _ => Verdict::Keep("prerelease".to_string()),
+                }
+            } else {
+                stable_seen += 1;
+                if stable_seen <= policy.keep_last {
+                    Verdict::Keep(format!("one of the {} newest", policy.keep_last))
+                } else if policy.keep_majors
+                    && version_of(release, tag_prefix).is_some_and(|v| v.minor == 0 && v.patch == 0)
+                {
+                    Verdict::Keep("major release".to_string())
+                } else {
+                    Verdict::Delete(format!("older than the {} newest", policy.keep_last))
+                }
+            };
+            (release, verdict)
+        })
+        .collect()
+}
+
+pub(crate) fn prune_releases(config_path: Option<&Path>) -> eyre::Result<()> {
+    let config = load_config(config_path)?;
+    let policy = config
+        .prune
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("No `prune` section in the config, not deleting anything"))
+        .classify(ErrorKind::Config)?;
+    let dry_run = std::env::var("DRY_RUN").is_ok();
+
+    let forge = forge::client_from_env(config.forge)?;
+
+    let releases = forge.list_releases(&config.org, &config.name)?;
+    let tag_prefix = config.tag_prefix.as_deref().unwrap_or_default();
+    let channel = config.channel.as_ref().map(|channel| channel.name.as_str());
+    let plan = plan(policy, tag_prefix, channel, &releases, today());
+
+    let mut doomed = Vec::new();
+    for (release, verdict) in &plan {
+        let size: u64 = release.assets.iter().map(|asset| asset.size).sum();
+        match verdict {
+            Verdict::Keep(reason) => info!(
+                "  keep    {} {}",
+                release.tag_name.green(),
+                format!("({})", reason).dimmed()
+            ),
+            Verdict::Delete(reason) => {
+                info!(
+                    "  delete  {} {} {}",
+                    release.tag_name.red(),
+                    format_bytes(size).yellow(),
+                    format!("({})", reason).dimmed()
+                );
+                doomed.push(*release);
+            }
+        }
+    }
+    let freed: u64 = doomed
+        .iter()
+        .flat_map(|release| &release.assets)
+        .map(|asset| asset.size)
+        .sum();
+    info!(
+        "🧹 {} of {} releases to delete, freeing {}",
+        doomed.len().to_string().cyan(),
+        plan.len().to_string().cyan(),
+        format_bytes(freed).yellow()
+    );
+
+    if dry_run {
+        info!("Dry run: not deleting anything");
+        return Ok(());
+    }
+    for release in doomed {
+        forge.delete_release(&config.org, &config.name, release.id)?;
+        info!("🗑️ Deleted {}", release.tag_name.red());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(tag_name: &str, created_at: &str, prerelease: bool) -> Release {
+        Release {
+            id: 0,
+            tag_name: tag_name.to_string(),
+            draft: false,
+            prerelease,
+            created_at: created_at.to_string(),
+            assets: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        assert_eq!(days_since_epoch("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(days_since_epoch("2025-03-01T12:34:56+02:00"), Some(20148));
+
+        let releases = [
+            release("v1.0.0", "2024-01-01T00:00:00Z", false),
+            release("v1.1.0", "2024-06-01T00:00:00Z", false),
+            release("v2.0.0-rc.1", "2024-12-01T00:00:00Z", false),
+            release("nightly-old", "2025-01-01T00:00:00Z", true),
+            release("v2.0.0", "2025-02-01T00:00:00Z", false),
+            release("nightly-new", "2025-02-28T00:00:00Z", true),
+        ];
+        let policy = PruneConfig {
+            keep_last: 1,
+            keep_majors: true,
+            prerelease_max_age_days: Some(30),
+        };
+        let plan = plan(
+            &policy,
+            "",
+            None,
+            &releases,
+            days_since_epoch("2025-03-01").unwrap(),
+        );
+        let deleted: Vec<&str> = plan
+            .iter()
+            .filter(|(_, verdict)| matches!(verdict, Verdict::Delete(_)))
+            .map(|(release, _)| release.tag_name.as_str())
+            .collect();
+        assert_eq!(deleted, ["nightly-old", "v2.0.0-rc.1", "v1.1.0"]);
+        assert_eq!(plan[0].0.tag_name, "nightly-new");
+        assert_eq!(plan[5].1, Verdict::Keep("major release".to_string()));
+    }
+
+    #[test]
+    fn test_plan_only_touches_the_projects_tags() {
+        let releases = [
+            release("api-v1.0.0", "2024-01-01T00:00:00Z", false),
+            release("web-v3.0.0", "2024-02-01T00:00:00Z", false),
+            release("api-v1.1.0", "2024-03-01T00:00:00Z", false),
+            release("web-v3.1.0", "2024-04-01T00:00:00Z", false),
+            release("api-v2.0.0-rc.1", "2024-05-01T00:00:00Z", false),
+            release("api-v2.0.0", "2024-06-01T00:00:00Z", false),
+        ];
+        let policy = PruneConfig {
+            keep_last: 1,
+            keep_majors: true,
+            prerelease_max_age_days: None,
+        };
+        let plan = plan(&policy, "api-", None, &releases, 0);
+        let verdicts: Vec<(&str, bool)> = plan
+            .iter()
+            .map(|(release, verdict)| {
+                (
+                    release.tag_name.as_str(),
+                    matches!(verdict, Verdict::Delete(_)),
+                )
+            })
+            .collect();
+        assert_eq!(
+            verdicts,
+            [
+                ("api-v2.0.0", false),
+                ("api-v2.0.0-rc.1", false),
+                ("api-v1.1.0", true),
+                ("api-v1.0.0", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_keeps_the_channel() {
+        let releases = [
+            release("nightly", "2024-01-01T00:00:00Z", true),
+            release("v1.0.0-rc.1", "2024-01-02T00:00:00Z", true),
+        ];
+        let policy = PruneConfig {
+            keep_last: 1,
+            keep_majors: false,
+            prerelease_max_age_days: Some(30),
+        };
+        let today = days_since_epoch("2025-03-01").unwrap();
+        let plan = plan(&policy, "", Some("nightly"), &releases, today);
+        assert_eq!(
+            plan[0].1,
+            Verdict::Delete("prerelease, 424 days old".to_string())
+        );
+        assert_eq!(plan[1].0.tag_name, "nightly");
+        assert_eq!(plan[1].1, Verdict::Keep("channel".to_string()));
+    }
+}
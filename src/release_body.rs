@@ -0,0 +1,243 @@
+//! Install instructions in the release's description: `brew` commands, the `install.sh`
+//! one-liner and a table of downloads. Every build regenerates the section once its
+//! asset is uploaded, so the build that finishes last leaves the complete table.
+
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{checksums, forge::Forge, utils::format_bytes};
+
+/// Where our section of the release body starts and ends: what's outside is left alone
+const START_MARKER: &str = "<!-- beardist:install -->";
+const END_MARKER: &str = "<!-- /beardist:install -->";
+
+const DEFAULT_TEMPLATE: &str = "## Install\n\n{brew}{installer}{downloads}";
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct InstallInstructionsConfig {
+    /// Homebrew tap the project's formula is in, e.g. "bearcove/tap". Without one, there
+    /// are no Homebrew instructions.
+    #[serde(default)]
+    homebrew_tap: Option<String>,
+
+    /// Name of the formula, if it's not the project's name
+    #[serde(default)]
+    formula: Option<String>,
+
+    /// Markdown of the section. `{brew}`, `{installer}` and `{downloads}` are replaced
+    /// by the Homebrew instructions, the `install.sh` one-liner and the table of
+    /// downloads (each empty when it doesn't apply), and `{tag}` by the tag. Default:
+    /// "## Install\n\n{brew}{installer}{downloads}"
+    #[serde(default)]
+    template: Option<String>,
+}
+
+/// The release the instructions are for
+pub(crate) struct ReleaseInfo<'a> {
+    /// e.g. "https://github.com"
+    pub(crate) server_url: &'a str,
+    pub(crate) org: &'a str,
+    pub(crate) name: &'a str,
+    pub(crate) tag: &'a str,
+
+    /// Whether an `install.sh` is attached
+    pub(crate) installer: bool,
+}
+
+/// A row of the downloads table
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Download {
+    name: String,
+    url: String,
+    size: u64,
+    sha256: Option<String>,
+}
+
+/// Our section, markers included
+fn render(
+    config: &InstallInstructionsConfig,
+    release: &ReleaseInfo,
+    downloads: &[Download],
+) -> String {
+    let brew = match &config.homebrew_tap {
+        Some(tap) => format!(
+            "### Homebrew\n\n```sh\nbrew tap {}\nbrew install {}\n```\n\n",
+            tap,
+            config.formula.as_deref().unwrap_or(release.name)
+        ),
+        None => String::new(),
+    };
+    let installer = if release.installer {
+        format!(
+            "### Install script\n\n```sh\ncurl -fsSL {}/{}/{}/releases/download/{}/install.sh | sh\n```\n\n",
+            release.server_url, release.org, release.name, release.tag
+        )
+    } else {
+        String::new()
+    };
+    let downloads = if downloads.is_empty() {
+        String::new()
+    } else {
+        let mut table =
+            "### Downloads\n\n| File | Size | SHA-256 |\n| --- | --- | --- |\n".to_string();
+        for download in downloads {
+            table.push_str(&format!(
+                "| [{}]({}) | {} | {} |\n",
+                download.name,
+                download.url,
+                format_bytes(download.size),
+                download
+                    .sha256
+                    .as_ref()
+                    .map_or_else(|| "—".to_string(), |sha256| format!("`{}`", sha256))
+            ));
+        }
+        table.push('\n');
+        table
+    };
+    let section = config
+        .template
+        .as_deref()
+        .unwrap_or(DEFAULT_TEMPLATE)
+        .replace("{brew}", &brew)
+        .replace("{installer}", &installer)
+        .replace("{downloads}", &downloads)
+        .replace("{tag}", release.tag);
+    format!("{}\n{}\n{}", START_MARKER, section.trim_end(), END_MARKER)
+}
+
+/// `body` with our section replaced by `section`, or appended if it isn't there yet
+fn splice(body: &str, section: &str) -> String {
+    if let Some(start) = body.find(START_MARKER) {
+        if let Some(end) = body[start..].find(END_MARKER) {
+            let end = start + end + END_MARKER.len();
+            return format!("{}{}{}", &body[..start], section, &body[end..]);
+        }
+    }
+    let body = body.trim_end();
+    if body.is_empty() {
+        section.to_string()
+    } else {
+        format!("{}\n\n{}", body, section)
+    }
+}
+
+/// Regenerates the install instructions in the body of release `release_id`. Builds for
+/// other targets may be uploading concurrently: if the assets changed while we were at
+/// it, we go again, so the body doesn't miss any.
+pub(crate) fn update(
+    forge: &dyn Forge,
+    release_id: u64,
+    release: &ReleaseInfo,
+    config: &InstallInstructionsConfig,
+) -> eyre::Result<()> {
+    let (org, name) = (release.org, release.name);
+    let list_downloads = || -> eyre::Result<Vec<Download>> {
+        let assets = forge.list_release_assets(org, name, release_id)?;
+        let sums = match assets.iter().find(|asset| asset.name == "SHA256SUMS") {
+            Some(asset) => checksums::parse_sha256sums(&String::from_utf8_lossy(
+                &forge.download_release_asset(org, name, asset)?,
+            )),
+            None => Default::default(),
+        };
+        let mut downloads: Vec<Download> = assets
+            .into_iter()
+            .filter(|asset| asset.name != "SHA256SUMS" && asset.name != "install.sh")
+            .map(|asset| Download {
+                sha256: sums.get(&asset.name).cloned(),
+                url: asset.browser_download_url,
+                name: asset.name,
+                size: asset.size,
+            })
+            .collect();
+        downloads.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(downloads)
+    };
+
+    let mut downloads = list_downloads()?;
+    for _ in 0..5 {
+        let body = forge.release_body(org, name, release_id)?;
+        let new_body = splice(&body, &render(config, release, &downloads));
+        if new_body != body {
+            forge.set_release_body(org, name, release_id, &new_body)?;
+        }
+        let now = list_downloads()?;
+        if now == downloads {
+            info!(
+                "✅ Updated the install instructions of release {}",
+                release.tag.cyan()
+            );
+            return Ok(());
+        }
+        downloads = now;
+    }
+    Err(eyre::eyre!(
+        "The assets of release {} kept changing, its install instructions may be out of date",
+        release.tag
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_and_splice() {
+        let config = InstallInstructionsConfig {
+            homebrew_tap: Some("bearcove/tap".to_string()),
+            ..Default::default()
+        };
+        let release = ReleaseInfo {
+            server_url: "https://github.com",
+            org: "bearcove",
+            name: "beardist",
+            tag: "v1.2.3",
+            installer: true,
+        };
+        let downloads = [Download {
+            name: "x86_64-unknown-linux-gnu.tar.xz".to_string(),
+            url: "https://example.com/x86_64-unknown-linux-gnu.tar.xz".to_string(),
+            size: 2048,
+            sha256: Some("ab".repeat(32)),
+        }];
+        let section = render(&config, &release, &downloads);
+        assert_eq!(
+            section,
+            format!(
+                "<!-- beardist:install -->
+## Install
+
+### Homebrew
+
+```sh
+brew tap bearcove/tap
+brew install beardist
+```
+
+### Install script
+
+```sh
+curl -fsSL https://github.com/bearcove/beardist/releases/download/v1.2.3/install.sh | sh
+```
+
+### Downloads
+
+| File | Size | SHA-256 |
+| --- | --- | --- |
+| [x86_64-unknown-linux-gnu.tar.xz](https://example.com/x86_64-unknown-linux-gnu.tar.xz) | 2.00 KB | `{}` |
+<!-- /beardist:install -->",
+                "ab".repeat(32)
+            )
+        );
+
+        let body = splice("Release notes\n", &section);
+        assert_eq!(body, format!("Release notes\n\n{}", section));
+        // Regenerating replaces our section, and only it
+        let body = splice(&format!("{}\n\nThanks!", body), "new section");
+        assert_eq!(body, "Release notes\n\nnew section\n\nThanks!");
+        assert_eq!(splice("", "new section"), "new section");
+    }
+}
@@ -0,0 +1,244 @@
+//! Keeping the target directory in a remote cache, for runners that don't have
+//! persistent storage for `$BEARDIST_CACHE_DIR` (e.g. ephemeral GitHub-hosted runners).
+//! Archives are keyed by target triple and a hash of the lockfile, with a per-triple
+//! "latest" archive to fall back on when the lockfile changed.
+//!
+//! Failing to restore or save the cache only costs time, so it never fails the build.
+
+use camino::Utf8Path;
+use log::*;
+use owo_colors::OwoColorize;
+use reqwest::blocking::Client;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{checksums, command};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RemoteCacheConfig {
+    /// Where archives go: `s3://bucket/prefix` (using the AWS CLI and its usual
+    /// credentials), or an HTTP(S) URL that serves GET and accepts PUT, sent with
+    /// `$BEARDIST_CACHE_TOKEN` as a bearer token when set
+    pub(crate) url: String,
+}
+
+/// A target directory's place in the remote cache
+pub(crate) struct RemoteCache {
+    base_url: String,
+    /// e.g. "bearcove/beardist/x86_64-unknown-linux-gnu-0123456789abcdef.tar.zst"
+    key: String,
+    /// e.g. "bearcove/beardist/x86_64-unknown-linux-gnu-latest.tar.zst"
+    fallback_key: String,
+    client: Client,
+}
+
+/// What restoring found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Restored {
+    /// The archive for this exact lockfile
+    Exact,
+    /// The latest archive for this target, built from another lockfile
+    Fallback,
+    Nothing,
+}
+
+impl RemoteCache {
+    /// `lockfile` is the contents of `Cargo.lock`, `project` something like
+    /// "bearcove/beardist"
+    pub(crate) fn new(
+        config: &RemoteCacheConfig,
+        project: &str,
+        triple: &str,
+        lockfile: &[u8],
+    ) -> Self {
+        let hash = checksums::sha256_hex(lockfile);
+        Self {
+            base_url: config.url.trim_end_matches('/').to_string(),
+            key: format!("{}/{}-{}.tar.zst", project, triple, &hash[..16]),
+            fallback_key: format!("{}/{}-latest.tar.zst", project, triple),
+            client: Client::builder().timeout(None).build().unwrap(),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key)
+    }
+
+    /// Fills `target_dir` from the cache, trying the exact key first
+    pub(crate) fn restore(&self, target_dir: &Utf8Path, scratch: &Utf8Path) -> Restored {
+        let archive = scratch.join("target-cache.tar.zst");
+        for (key, outcome) in [
+            (&self.key, Restored::Exact),
+            (&self.fallback_key, Restored::Fallback),
+        ] {
+            let url = self.url(key);
+            match self.download(&url, &archive) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("🗄️ No cached target directory at {}", url.cyan());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Couldn't download cached target directory: {}", e);
+                    return Restored::Nothing;
+                }
+            }
+            let extracted = fs_err::create_dir_all(target_dir)
+                .map_err(eyre::Report::from)
+                .and_then(|_| {
+                    command::run_command(
+                        "tar",
+                        &["--zstd", "-xf", archive.as_str(), "-C", target_dir.as_str()],
+                        None,
+                    )
+                });
+            let _ = fs_err::remove_file(&archive);
+            return match extracted {
+                Ok(()) => {
+                    info!("🗄️ Restored target directory from {}", url.cyan());
+                    outcome
+                }
+                Err(e) => {
+                    warn!("Couldn't extract cached target directory: {}", e);
+                    Restored::Nothing
+                }
+            };
+        }
+        Restored::Nothing
+    }
+
+    /// Uploads `target_dir` under the exact key, and as the latest for this target
+    pub(crate) fn save(&self, target_dir: &Utf8Path, scratch: &Utf8Path) {
+        let archive = scratch.join("target-cache.tar.zst");
+        let result = command::run_command(
+            "tar",
+            &[
+                "--zstd",
+                "-cf",
+                archive.as_str(),
+                "-C",
+                target_dir.as_str(),
+                ".",
+            ],
+            None,
+        )
+        .and_then(|_| {
+            let size = fs_err::metadata(&archive)?.len();
+            for key in [&self.key, &self.fallback_key] {
+                let url = self.url(key);
+                info!(
+                    "🗄️ Saving target directory ({}) to {}",
+                    crate::format_bytes(size).green(),
+                    url.cyan()
+                );
+                self.upload(&url, &archive)?;
+            }
+            Ok(())
+        });
+        let _ = fs_err::remove_file(&archive);
+        if let Err(e) = result {
+            warn!("Couldn't save target directory to the remote cache: {}", e);
+        }
+    }
+
+    /// Downloads `url` to `dest`. `false` if there's nothing there.
+    fn download(&self, url: &str, dest: &Utf8Path) -> eyre::Result<bool> {
+        if url.starts_with("s3://") {
+            // `aws s3 cp` fails the same way for missing objects and real errors
+            let exists = command::get_cmd_stdout("aws", &["s3", "ls", url], None)
+                .is_ok_and(|listing| !listing.trim().is_empty());
+            if !exists {
+                return Ok(false);
+            }
+            command::run_command(
+                "aws",
+                &["s3", "cp", "--no-progress", url, dest.as_str()],
+                None,
+            )?;
+            return Ok(true);
+        }
+
+        let mut response = self.authorize(self.client.get(url)).send()?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "GET {}: HTTP status {}",
+                url,
+                response.status()
+            ));
+        }
+        response.copy_to(&mut fs_err::File::create(dest)?)?;
+        Ok(true)
+    }
+
+    fn upload(&self, url: &str, archive: &Utf8Path) -> eyre::Result<()> {
+        if url.starts_with("s3://") {
+            command::run_command(
+                "aws",
+                &["s3", "cp", "--no-progress", archive.as_str(), url],
+                None,
+            )?;
+            return Ok(());
+        }
+
+        let file = fs_err::File::open(archive)?.into_parts().0;
+        let response = self.authorize(self.client.put(url)).body(file).send()?;
+        if !response.status().is_success() {
+            return Err(eyre::eyre!(
+                "PUT {}: HTTP status {}",
+                url,
+                response.status()
+            ));
+        }
+        Ok(())
+    }
+
+    fn authorize(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::blocking::RequestBuilder {
+        match crate::credentials::token("BEARDIST_CACHE_TOKEN") {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_keys() {
+        let config = RemoteCacheConfig {
+            url: "https://cache.example.com/beardist/".to_string(),
+        };
+        let cache = RemoteCache::new(
+            &config,
+            "bearcove/beardist",
+            "x86_64-unknown-linux-gnu",
+            b"# This file is automatically @generated by Cargo.\n",
+        );
+        assert!(
+            cache
+                .key
+                .starts_with("bearcove/beardist/x86_64-unknown-linux-gnu-")
+        );
+        assert_eq!(cache.key.len(), cache.fallback_key.len() + 10);
+        assert_eq!(
+            cache.url(&cache.fallback_key),
+            "https://cache.example.com/beardist/bearcove/beardist/x86_64-unknown-linux-gnu-latest.tar.zst"
+        );
+
+        let other = RemoteCache::new(
+            &config,
+            "bearcove/beardist",
+            "x86_64-unknown-linux-gnu",
+            b"# This file is automatically @generated by Cargo.\n# changed\n",
+        );
+        assert_ne!(cache.key, other.key);
+    }
+}
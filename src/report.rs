@@ -0,0 +1,99 @@
+//! `beardist build --report <path>`: a JSON summary of the build, for downstream jobs
+//! that would otherwise have to scrape the logs.
+
+use serde::Serialize;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{incremental::CacheStats, system::SystemInfo};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BuildReport {
+    pub(crate) org: String,
+    pub(crate) name: String,
+    pub(crate) tag: String,
+    pub(crate) dry_run: bool,
+    pub(crate) artifact: Artifact,
+    /// Where the artifact was uploaded, unless this was a dry run
+    pub(crate) upload: Option<Upload>,
+    pub(crate) timings_ms: Timings,
+    /// e.g. "rustc" → "rustc 1.88.0-nightly (...)"
+    pub(crate) toolchain: BTreeMap<String, String>,
+    /// What the build ran on
+    pub(crate) system: SystemInfo,
+    /// How much of the build was incremental, for cargo builds
+    pub(crate) cache: Option<CacheStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Artifact {
+    pub(crate) path: String,
+    pub(crate) file_name: String,
+    pub(crate) sha256: String,
+    pub(crate) size: u64,
+}
+
+/// Where a build's archive ended up
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Upload {
+    /// Release page, or OCI reference when only pushing to a registry
+    pub(crate) url: String,
+    pub(crate) release_id: Option<u64>,
+    /// Direct download URL of the archive
+    pub(crate) asset_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Timings {
+    pub(crate) build: u64,
+    pub(crate) archive: u64,
+    pub(crate) upload: u64,
+    pub(crate) total: u64,
+}
+
+impl BuildReport {
+    pub(crate) fn write(&self, path: &Path) -> eyre::Result<()> {
+        fs_err::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_shape() {
+        let report = BuildReport {
+            org: "bearcove".to_string(),
+            name: "beardist".to_string(),
+            tag: "v1.0.0".to_string(),
+            dry_run: false,
+            artifact: Artifact {
+                path: "/tmp/x86_64-unknown-linux-gnu.tar.xz".to_string(),
+                file_name: "x86_64-unknown-linux-gnu.tar.xz".to_string(),
+                sha256: "abc123".to_string(),
+                size: 2048,
+            },
+            upload: Some(Upload {
+                url: "https://github.com/bearcove/beardist/releases/tag/v1.0.0".to_string(),
+                release_id: Some(42),
+                asset_url: None,
+            }),
+            timings_ms: Timings {
+                build: 1,
+                archive: 2,
+                upload: 3,
+                total: 6,
+            },
+            toolchain: BTreeMap::from([("rustc".to_string(), "rustc 1.88.0".to_string())]),
+            system: SystemInfo::collect(),
+            cache: None,
+        };
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["artifact"]["sha256"], "abc123");
+        assert_eq!(value["upload"]["release_id"], 42);
+        assert_eq!(value["timings_ms"]["total"], 6);
+        assert_eq!(value["toolchain"]["rustc"], "rustc 1.88.0");
+        assert!(value["system"]["in_container"].is_boolean());
+    }
+}
@@ -0,0 +1,154 @@
+//! Retrying operations that fail for transient reasons: network blips, overloaded
+//! servers, etc.
+
+use log::*;
+use std::{future::Future, time::Duration};
+
+/// How many times to try an operation, and how long to wait in between
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_attempts: usize,
+
+    /// Delay before the first retry, doubled for every retry after that
+    pub(crate) base_delay: Duration,
+
+    /// Random extra delay, up to this, so concurrent jobs don't retry in lockstep
+    pub(crate) max_jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(2),
+            max_jitter: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Why an attempt failed
+pub(crate) enum Failure {
+    /// Worth another try
+    Transient(eyre::Report),
+    /// Trying again would fail the same way
+    Permanent(eyre::Report),
+}
+
+impl RetryPolicy {
+    /// How long to wait before the given attempt (starting at 1 for the first one)
+    fn delay(&self, attempt: usize) -> Duration {
+        let backoff = self.base_delay * 2u32.saturating_pow(attempt.saturating_sub(2) as u32);
+        let jitter_ms = self.max_jitter.as_millis() as u64;
+        let jitter = match jitter_ms {
+            0 => 0,
+            _ => rand::random::<u64>() % jitter_ms,
+        };
+        backoff + Duration::from_millis(jitter)
+    }
+
+    /// Runs `op` until it succeeds, fails permanently, or runs out of attempts. `what`
+    /// describes the operation in logs, e.g. "git push".
+    pub(crate) fn run<T>(
+        &self,
+        what: &str,
+        mut op: impl FnMut() -> Result<T, Failure>,
+    ) -> eyre::Result<T> {
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(failure) => {
+                    std::thread::sleep(self.retry_delay(what, &mut attempt, failure)?)
+                }
+            }
+        }
+    }
+
+    /// Like [`Self::run`], but for async operations, without blocking a runtime thread
+    /// while waiting
+    pub(crate) async fn run_async<T, F: Future<Output = Result<T, Failure>>>(
+        &self,
+        what: &str,
+        mut op: impl FnMut() -> F,
+    ) -> eyre::Result<T> {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Ok(value) => return Ok(value),
+                Err(failure) => {
+                    tokio::time::sleep(self.retry_delay(what, &mut attempt, failure)?).await
+                }
+            }
+        }
+    }
+
+    /// How long to wait before trying again after `failure`, or the error to give up with
+    fn retry_delay(
+        &self,
+        what: &str,
+        attempt: &mut usize,
+        failure: Failure,
+    ) -> eyre::Result<Duration> {
+        match failure {
+            Failure::Permanent(e) => Err(e),
+            Failure::Transient(e) if *attempt >= self.max_attempts => {
+                Err(e.wrap_err(format!("{} failed after {} attempts", what, attempt)))
+            }
+            Failure::Transient(e) => {
+                *attempt += 1;
+                let delay = self.delay(*attempt);
+                warn!(
+                    "📶 {} failed ({}), retrying in {:.1?} (attempt {} of {})...",
+                    what, e, delay, attempt, self.max_attempts
+                );
+                Ok(delay)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retries() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::ZERO,
+            max_jitter: Duration::ZERO,
+        };
+
+        let mut calls = 0;
+        let value = policy
+            .run("flaky", || {
+                calls += 1;
+                match calls {
+                    1 | 2 => Err(Failure::Transient(eyre::eyre!("blip"))),
+                    _ => Ok(calls),
+                }
+            })
+            .unwrap();
+        assert_eq!(value, 3);
+
+        let mut calls = 0;
+        let err = policy
+            .run("down", || -> Result<(), _> {
+                calls += 1;
+                Err(Failure::Transient(eyre::eyre!("blip")))
+            })
+            .unwrap_err();
+        assert_eq!(calls, 3);
+        assert_eq!(err.to_string(), "down failed after 3 attempts");
+
+        let mut calls = 0;
+        let err = policy
+            .run("broken", || -> Result<(), _> {
+                calls += 1;
+                Err(Failure::Permanent(eyre::eyre!("404")))
+            })
+            .unwrap_err();
+        assert_eq!(calls, 1);
+        assert_eq!(err.to_string(), "404");
+    }
+}
@@ -0,0 +1,64 @@
+use schemars::{Schema, schema_for};
+use serde_json::json;
+
+use crate::{Config, SchemaArgs, homebrew::TapConfig, k8s::DeployConfig};
+
+/// Which config file to print a schema for
+#[derive(clap::ValueEnum, Clone, Copy, Default)]
+pub(crate) enum SchemaKind {
+    /// `.beardist.json` (or `.toml`, `.yaml`)
+    #[default]
+    Beardist,
+    /// `.beardist-tap.json`
+    Tap,
+    /// `.beardist-deploy.json`
+    Deploy,
+}
+
+/// `extends` is resolved by the loader before the config is deserialized, so it's not a
+/// field of [`Config`]: add it by hand
+fn config_schema() -> Schema {
+    let mut schema = schema_for!(Config);
+    if let Some(properties) = schema.get_mut("properties").and_then(|p| p.as_object_mut()) {
+        properties.insert(
+            "extends".to_string(),
+            json!({
+                "description": "Another config file (relative to this one) whose settings this one overrides, e.g. \"../.beardist-base.json\". Objects are merged, everything else is replaced.",
+                "type": "string",
+            }),
+        );
+    }
+    schema
+}
+
+/// `beardist schema`: prints a JSON Schema for one of our config files, so editors can
+/// validate and autocomplete them (point `$schema` at the output)
+pub(crate) fn schema(args: SchemaArgs) -> eyre::Result<()> {
+    let schema = match args.kind {
+        SchemaKind::Beardist => config_schema(),
+        SchemaKind::Tap => schema_for!(TapConfig),
+        SchemaKind::Deploy => schema_for!(DeployConfig),
+    };
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_schema() {
+        let schema = serde_json::to_value(config_schema()).unwrap();
+        assert_eq!(schema["title"], "Config");
+        assert_eq!(schema["additionalProperties"], false);
+        let required = schema["required"].as_array().unwrap();
+        assert!(required.contains(&"org".into()));
+        assert!(!required.contains(&"forge".into()));
+        assert_eq!(schema["properties"]["extends"]["type"], "string");
+        assert_eq!(
+            schema["properties"]["org"]["description"],
+            "Organization or user name"
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use camino::Utf8PathBuf;
+use eyre::Context;
+use log::*;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::{UpdateScoopArgs, checksums, forge::Forge, github::GitHubClient, http};
+
+#[derive(Deserialize, Debug, Clone)]
+struct BucketConfig {
+    apps: Vec<App>,
+
+    /// Identity and message of the commits bumping manifests
+    #[serde(default)]
+    commit: crate::git::CommitConfig,
+
+    /// Push over "https" (the default) or "ssh"
+    #[serde(default)]
+    push: crate::git::Transport,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct App {
+    repo: String,
+    homepage: String,
+    desc: String,
+    license: String,
+
+    /// Binary names, without the `.exe` suffix
+    bins: Vec<String>,
+
+    /// Scoop architecture name → target triple of the release asset
+    #[serde(default = "default_architectures")]
+    architectures: BTreeMap<String, String>,
+}
+
+fn default_architectures() -> BTreeMap<String, String> {
+    BTreeMap::from([("64bit".to_string(), "x86_64-pc-windows-msvc".to_string())])
+}
+
+impl App {
+    fn org(&self) -> &str {
+        self.repo.split('/').next().unwrap()
+    }
+
+    fn name(&self) -> &str {
+        self.repo.split('/').nth(1).unwrap()
+    }
+
+    /// Where the manifest is written on disk
+    fn disk_path(&self) -> Utf8PathBuf {
+        Utf8PathBuf::from(format!("bucket/{}.json", self.name()))
+    }
+
+    fn manifest_version(&self) -> Option<String> {
+        let content = fs_err::read_to_string(self.disk_path()).ok()?;
+        let manifest: serde_json::Value = serde_json::from_str(&content).ok()?;
+        manifest["version"].as_str().map(String::from)
+    }
+
+    /// Download URL for a release file, with `version` possibly being Scoop's `$version`
+    fn release_file_url(&self, version: &str, file_name: &str) -> String {
+        format!(
+            "https://github.com/{}/{}/releases/download/v{}/{}",
+            self.org(),
+            self.name(),
+            version,
+            file_name
+        )
+    }
+}
+
+/// A Scoop app manifest, see <https://github.com/ScoopInstaller/Scoop/wiki/App-Manifests>
+#[derive(Serialize, Debug)]
+struct ScoopManifest {
+    version: String,
+    description: String,
+    homepage: String,
+    license: String,
+    architecture: BTreeMap<String, ScoopArchitecture>,
+    bin: Vec<String>,
+    checkver: ScoopCheckver,
+    autoupdate: ScoopAutoupdate,
+}
+
+#[derive(Serialize, Debug)]
+struct ScoopArchitecture {
+    url: String,
+    hash: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ScoopCheckver {
+    github: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ScoopAutoupdate {
+    architecture: BTreeMap<String, ScoopAutoupdateArchitecture>,
+}
+
+#[derive(Serialize, Debug)]
+struct ScoopAutoupdateArchitecture {
+    url: String,
+}
+
+fn generate_manifest(
+    app: &App,
+    version: &str,
+    hashes: &BTreeMap<String, String>,
+) -> eyre::Result<ScoopManifest> {
+    let mut architecture = BTreeMap::new();
+    let mut autoupdate = BTreeMap::new();
+    for (arch, triple) in &app.architectures {
+        let file_name = format!("{}.tar.xz", triple);
+        let hash = hashes
+            .get(&file_name)
+            .ok_or_else(|| eyre::eyre!("No hash for {}", file_name))?;
+        architecture.insert(
+            arch.clone(),
+            ScoopArchitecture {
+                url: app.release_file_url(version, &file_name),
+                hash: hash.clone(),
+            },
+        );
+        autoupdate.insert(
+            arch.clone(),
+            ScoopAutoupdateArchitecture {
+                url: app.release_file_url("$version", &file_name),
+            },
+        );
+    }
+
+    Ok(ScoopManifest {
+        version: version.to_string(),
+        description: app.desc.clone(),
+        homepage: app.homepage.clone(),
+        license: app.license.clone(),
+        architecture,
+        bin: app.bins.iter().map(|bin| format!("{}.exe", bin)).collect(),
+        checkver: ScoopCheckver {
+            github: format!("https://github.com/{}", app.repo),
+        },
+        autoupdate: ScoopAutoupdate {
+            architecture: autoupdate,
+        },
+    })
+}
+
+/// Updates the manifest for one app, returning the new version if it was bumped
+fn process_app(
+    github_client: &GitHubClient,
+    app: &App,
+    dry_run: bool,
+) -> eyre::Result<Option<String>> {
+    let Some(version) = github_client.get_latest_release_version(app.org(), app.name())? else {
+        info!("No version found for {}, skipping", app.name().cyan());
+        return Ok(None);
+    };
+
+    if app.manifest_version().as_deref() == Some(version.as_str()) {
+        info!(
+            "Manifest for {} is already at version {}",
+            app.name().cyan(),
+            version.bright_green()
+        );
+        return Ok(None);
+    }
+
+    let sums = http::block_on(checksums::fetch_sha256sums(
+        &app.release_file_url(&version, "SHA256SUMS"),
+        dry_run,
+    ))?;
+    let mut hashes = BTreeMap::new();
+    for triple in app.architectures.values() {
+        let file_name = format!("{}.tar.xz", triple);
+        let hash = match sums.as_ref().and_then(|sums| sums.get(&file_name)) {
+            Some(hash) => hash.clone(),
+            None => http::block_on(checksums::fetch_and_hash(
+                &app.release_file_url(&version, &file_name),
+                dry_run,
+            ))?,
+        };
+        hashes.insert(file_name, hash);
+    }
+
+    let manifest = generate_manifest(app, &version, &hashes)?;
+    let manifest = serde_json::to_string_pretty(&manifest)? + "\n";
+    let manifest_path = app.disk_path();
+
+    if dry_run {
+        info!(
+            "Dry run: Would write manifest to {}",
+            manifest_path.to_string().cyan()
+        );
+        info!("Manifest content:\n{}", manifest);
+    } else {
+        if let Some(parent) = manifest_path.parent() {
+            fs_err::create_dir_all(parent)?;
+        }
+        fs_err::write(&manifest_path, manifest)?;
+        info!(
+            "Scoop manifest written to {}",
+            manifest_path.to_string().bright_green()
+        );
+    }
+
+    Ok(Some(version))
+}
+
+fn load_bucket_config() -> eyre::Result<BucketConfig> {
+    let config_path = fs_err::canonicalize(PathBuf::from(".beardist-scoop.json"))?;
+    let config_str = fs_err::read_to_string(&config_path).wrap_err_with(|| {
+        format!(
+            "Failed to read bucket config file at {}",
+            config_path.display().to_string().cyan()
+        )
+    })?;
+    let config: BucketConfig = serde_json::from_str(&config_str).wrap_err_with(|| {
+        format!(
+            "Failed to parse config file at {}",
+            config_path.display().to_string().cyan()
+        )
+    })?;
+    Ok(config)
+}
+
+pub(crate) fn update_scoop(args: UpdateScoopArgs) -> eyre::Result<()> {
+    let dry_run = std::env::var("DRY_RUN").is_ok();
+    if dry_run {
+        info!("Dry run {}", "enabled".bright_yellow());
+    }
+    let github_token = crate::credentials::require("GITHUB_TOKEN")?;
+
+    info!("Loading bucket {}...", "configuration".cyan());
+    let config = load_bucket_config()?;
+
+    let github_client =
+        GitHubClient::new("https://api.github.com".to_string(), github_token.clone());
+
+    let mut bumped_apps = Vec::new();
+    for app in &config.apps {
+        info!("Processing app {}...", app.name().cyan());
+        if let Some(version) = process_app(&github_client, app, dry_run)
+            .wrap_err_with(|| format!("while processing app {}", app.name().cyan()))?
+        {
+            bumped_apps.push((app.name().to_string(), version));
+        }
+    }
+    info!("All apps {}", "processed".bright_green());
+
+    if !bumped_apps.is_empty() {
+        crate::git::commit_and_push(crate::git::Publish {
+            kind: "scoop manifests",
+            bumped: &bumped_apps,
+            github_token: &github_token,
+            pr: args.pr,
+            base_branch: "main",
+            upstream: None,
+            dry_run,
+            commit: &config.commit,
+            transport: config.push,
+        })?;
+    } else {
+        info!("No apps were bumped");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_manifest() -> eyre::Result<()> {
+        let app: App = serde_json::from_str(
+            r#"{
+                "repo": "testowner/foobar",
+                "homepage": "https://example.com",
+                "desc": "Sample generated by beardist",
+                "license": "MIT",
+                "bins": ["foobar"]
+            }"#,
+        )?;
+        let hashes =
+            BTreeMap::from([("x86_64-pc-windows-msvc.tar.xz".to_string(), "a".repeat(64))]);
+        let manifest = serde_json::to_value(generate_manifest(&app, "1.2.3", &hashes)?)?;
+        assert_eq!(manifest["version"], "1.2.3");
+        assert_eq!(manifest["bin"][0], "foobar.exe");
+        assert_eq!(
+            manifest["architecture"]["64bit"]["url"],
+            "https://github.com/testowner/foobar/releases/download/v1.2.3/x86_64-pc-windows-msvc.tar.xz"
+        );
+        assert_eq!(manifest["architecture"]["64bit"]["hash"], "a".repeat(64));
+        assert_eq!(
+            manifest["autoupdate"]["architecture"]["64bit"]["url"],
+            "https://github.com/testowner/foobar/releases/download/v$version/x86_64-pc-windows-msvc.tar.xz"
+        );
+        assert_eq!(
+            manifest["checkver"]["github"],
+            "https://github.com/testowner/foobar"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+//! `beardist status <tag>`: what a release has (archives, their sizes and hashes,
+//! checksums, signatures) and which downstream consumers (Homebrew taps, k8s manifests)
+//! are on its version, so checking a release is complete doesn't take clicking around.
+
+use log::*;
+use owo_colors::OwoColorize;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    StatusArgs, checksums,
+    forge::{self, Release},
+    homebrew, k8s, load_config,
+    utils::format_bytes,
+};
+
+/// Extensions of detached signatures, e.g. "x86_64-unknown-linux-gnu.tar.xz.sig"
+const SIGNATURE_EXTENSIONS: &[&str] = &["sig", "asc", "minisig"];
+
+/// An archive attached to the release
+#[derive(Debug, PartialEq, Eq)]
+struct Archive {
+    name: String,
+    size: u64,
+
+    /// From `SHA256SUMS`, if it has the archive
+    sha256: Option<String>,
+
+    signed: bool,
+}
+
+/// What the release has
+#[derive(Debug, PartialEq, Eq)]
+struct Overview {
+    /// One per target, sorted by name
+    archives: Vec<Archive>,
+
+    has_sha256sums: bool,
+
+    /// `SHA256SUMS` entries for files the release doesn't have
+    stray_sums: Vec<String>,
+
+    has_installer: bool,
+}
+
+fn overview(release: &Release, sums: &checksums::Sha256Sums) -> Overview {
+    let has_asset = |name: &str| release.assets.iter().any(|asset| asset.name == name);
+    let signed = |name: &str| {
+        SIGNATURE_EXTENSIONS
+            .iter()
+            .any(|extension| has_asset(&format!("{}.{}", name, extension)))
+    };
+    let mut archives: Vec<Archive> = release
+        .assets
+        .iter()
+        .filter(|asset| asset.name.ends_with(".tar.xz"))
+        .map(|asset| Archive {
+            name: asset.name.clone(),
+            size: asset.size,
+            sha256: sums.get(&asset.name).cloned(),
+            signed: signed(&asset.name),
+        })
+        .collect();
+    archives.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut stray_sums: Vec<String> = sums
+        .keys()
+        .filter(|name| !has_asset(name))
+        .cloned()
+        .collect();
+    stray_sums.sort();
+    Overview {
+        archives,
+        has_sha256sums: has_asset("SHA256SUMS"),
+        stray_sums,
+        has_installer: has_asset("install.sh"),
+    }
+}
+
+fn print_overview(release: &Release, overview: &Overview) {
+    info!(
+        "📦 Release {}{}{}",
+        release.tag_name.cyan(),
+        if release.draft { " (draft)" } else { "" },
+        if release.prerelease {
+            " (prerelease)"
+        } else {
+            ""
+        }
+    );
+    if overview.archives.is_empty() {
+        warn!("  No archives");
+    }
+    for archive in &overview.archives {
+        info!(
+            "  {} {} {} {}",
+            archive.name.cyan(),
+            format_bytes(archive.size).yellow(),
+            match &archive.sha256 {
+                Some(sha256) => sha256.dimmed().to_string(),
+                None => "not in SHA256SUMS".red().to_string(),
+            },
+            if archive.signed {
+                "signed".green().to_string()
+            } else {
+                "unsigned".dimmed().to_string()
+            }
+        );
+    }
+
+    let hashed = overview
+        .archives
+        .iter()
+        .filter(|archive| archive.sha256.is_some())
+        .count();
+    if overview.has_sha256sums {
+        info!(
+            "  SHA256SUMS: {} of {} archives",
+            hashed,
+            overview.archives.len()
+        );
+    } else {
+        warn!("  No SHA256SUMS");
+    }
+    for name in &overview.stray_sums {
+        warn!("  SHA256SUMS has {}, which isn't attached", name.cyan());
+    }
+    let signed = overview
+        .archives
+        .iter()
+        .filter(|archive| archive.signed)
+        .count();
+    info!(
+        "  Signatures: {} of {} archives",
+        signed,
+        overview.archives.len()
+    );
+    info!(
+        "  install.sh: {}",
+        if overview.has_installer {
+            "attached"
+        } else {
+            "not attached"
+        }
+    );
+}
+
+/// Logs whether `found` is `version`, for a consumer described by `what`
+fn print_consumer(what: &str, found: Option<&str>, version: &str) {
+    match found {
+        Some(found) if found.trim_start_matches('v') == version => {
+            info!("  ✅ {}: {}", what, found.green())
+        }
+        Some(found) => info!("  ⏳ {}: {}", what, found.yellow()),
+        None => info!("  ➖ {}: {}", what, "not there".dimmed()),
+    }
+}
+
+/// Makes `dirs` absolute, before loading the config moves us to the project's root
+fn absolute(dirs: &[PathBuf]) -> eyre::Result<Vec<PathBuf>> {
+    dirs.iter()
+        .map(|dir| Ok(fs_err::canonicalize(dir)?))
+        .collect()
+}
+
+pub(crate) fn status(args: StatusArgs, config_path: Option<&Path>) -> eyre::Result<()> {
+    let taps = absolute(&args.tap)?;
+    let deploys = absolute(&args.k8s)?;
+    let config = load_config(config_path)?;
+    let (org, name) = (&config.org, &config.name);
+    let version = args
+        .tag
+        .strip_prefix(config.tag_prefix.as_deref().unwrap_or_default())
+        .unwrap_or(&args.tag)
+        .trim_start_matches('v');
+
+    let forge = forge::client_from_env(config.forge)?;
+    let releases = forge.list_releases(org, name)?;
+    let release = releases
+        .iter()
+        .find(|release| release.tag_name == args.tag)
+        .ok_or_else(|| eyre::eyre!("{}/{} has no release for {}", org, name, args.tag))?;
+    let sums = match release
+        .assets
+        .iter()
+        .find(|asset| asset.name == "SHA256SUMS")
+    {
+        Some(asset) => checksums::parse_sha256sums(&String::from_utf8_lossy(
+            &forge.download_release_asset(org, name, asset)?,
+        )),
+        None => Default::default(),
+    };
+    print_overview(release, &overview(release, &sums));
+
+    if taps.is_empty() && deploys.is_empty() {
+        return Ok(());
+    }
+    info!("🔗 Downstream (at {})", version.cyan());
+    let repo = format!("{}/{}", org, name);
+    for tap in &taps {
+        let found = homebrew::tap_version(tap, &repo)?;
+        print_consumer(&format!("tap {}", tap.display()), found.as_deref(), version);
+    }
+    for deploy in &deploys {
+        let found = k8s::image_versions(deploy, &repo)?;
+        if found.is_empty() {
+            print_consumer(&format!("k8s {}", deploy.display()), None, version);
+        }
+        for (path, found) in &found {
+            print_consumer(&format!("k8s {}", path.display()), Some(found), version);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forge::ReleaseAsset;
+
+    #[test]
+    fn test_overview() {
+        let asset = |name: &str, size| ReleaseAsset {
+            id: 0,
+            name: name.to_string(),
+            browser_download_url: String::new(),
+            size,
+        };
+        let release = Release {
+            id: 1,
+            tag_name: "v1.2.3".to_string(),
+            draft: false,
+            prerelease: false,
+            created_at: "2025-01-31T12:00:00Z".to_string(),
+            assets: vec![
+                asset("x86_64-unknown-linux-gnu.tar.xz", 2048),
+                asset("x86_64-unknown-linux-gnu.tar.xz.sig", 64),
+                asset("aarch64-apple-darwin.tar.xz", 1024),
+                asset("SHA256SUMS", 100),
+            ],
+        };
+        let sums: checksums::Sha256Sums = [
+            ("x86_64-unknown-linux-gnu.tar.xz", "aa"),
+            ("x86_64-pc-windows-msvc.tar.xz", "bb"),
+        ]
+        .into_iter()
+        .map(|(name, sha256)| (name.to_string(), sha256.to_string()))
+        .collect();
+
+        assert_eq!(
+            overview(&release, &sums),
+            Overview {
+                archives: vec![
+                    Archive {
+                        name: "aarch64-apple-darwin.tar.xz".to_string(),
+                        size: 1024,
+                        sha256: None,
+                        signed: false,
+                    },
+                    Archive {
+                        name: "x86_64-unknown-linux-gnu.tar.xz".to_string(),
+                        size: 2048,
+                        sha256: Some("aa".to_string()),
+                        signed: true,
+                    },
+                ],
+                has_sha256sums: true,
+                stray_sums: vec!["x86_64-pc-windows-msvc.tar.xz".to_string()],
+                has_installer: false,
+            }
+        );
+    }
+}
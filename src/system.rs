@@ -1,148 +1,368 @@
 use log::info;
 use owo_colors::OwoColorize;
+use serde::Serialize;
+use std::{collections::BTreeMap, path::Path};
 
-pub(crate) fn print_sysinfo() {
-    info!("{}", "🖥️ System Information:".yellow());
-
-    // Detect if we're in a container
-    let in_container = match fs_err::read_to_string("/etc/mtab") {
-        Ok(content) => {
-            if content.starts_with("overlay /") {
-                info!("root fs is overlay, we're probably in a container");
-                true
-            } else {
-                false
-            }
-        }
-        Err(_) => false,
+/// Which cgroup hierarchy resource limits are read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CgroupVersion {
+    V1,
+    V2,
+}
+
+/// Resource limits imposed on us by cgroups, e.g. those of a container
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct CgroupLimits {
+    pub(crate) version: CgroupVersion,
+    /// In CPUs, e.g. 1.5
+    pub(crate) cpu_quota: Option<f64>,
+    /// In bytes
+    pub(crate) memory_limit: Option<u64>,
+}
+
+/// Reads our cgroup limits, if cgroups are mounted where we expect them
+pub(crate) fn cgroup_limits() -> Option<CgroupLimits> {
+    read_cgroup_limits(Path::new("/sys/fs/cgroup"))
+}
+
+fn read_cgroup_limits(root: &Path) -> Option<CgroupLimits> {
+    let read = |file: &str| {
+        fs_err::read_to_string(root.join(file))
+            .ok()
+            .map(|s| s.trim().to_string())
     };
 
-    let mut sys_info = Vec::new();
+    if root.join("cgroup.controllers").exists() {
+        // cpu.max is "<quota> <period>", with a quota of "max" when unlimited
+        let cpu_quota = read("cpu.max").and_then(|cpu_max| {
+            let (quota, period) = cpu_max.split_once(' ')?;
+            Some(quota.parse::<f64>().ok()? / period.parse::<f64>().ok()?)
+        });
+        let memory_limit = read("memory.max").and_then(|max| max.parse().ok());
+        return Some(CgroupLimits {
+            version: CgroupVersion::V2,
+            cpu_quota,
+            memory_limit,
+        });
+    }
 
-    if in_container {
-        sys_info.push(format!(
-            "{} {}",
-            "Environment".dimmed(),
-            "Container".cyan().underline()
-        ));
+    // v1 has one hierarchy per controller. The cpu one is often mounted as "cpu,cpuacct",
+    // with "cpu" a symlink to it.
+    let cpu_dir = ["cpu", "cpu,cpuacct"]
+        .into_iter()
+        .find(|dir| root.join(dir).is_dir())?;
+    // A quota of -1 means unlimited
+    let cpu_quota = read(&format!("{}/cpu.cfs_quota_us", cpu_dir))
+        .and_then(|quota| quota.parse::<f64>().ok())
+        .filter(|quota| *quota > 0.0)
+        .and_then(|quota| {
+            let period = read(&format!("{}/cpu.cfs_period_us", cpu_dir))?;
+            Some(quota / period.parse::<f64>().ok()?)
+        });
+    // Unlimited is the largest page-aligned i64, anything close to that isn't a real limit
+    let memory_limit = read("memory/memory.limit_in_bytes")
+        .and_then(|limit| limit.parse::<u64>().ok())
+        .filter(|limit| *limit < 1 << 60);
+    Some(CgroupLimits {
+        version: CgroupVersion::V1,
+        cpu_quota,
+        memory_limit,
+    })
+}
+
+/// Memory a build job can take at its peak: rustc on a big crate, or the linker
+const MEMORY_PER_JOB: u64 = 1536 << 20;
+
+/// Below this much memory, fewer codegen units keep LLVM's peak memory usage down
+const LOW_MEMORY: u64 = 4 << 30;
+
+/// Codegen units for low memory builds, instead of cargo's default of 16 for release
+const LOW_MEMORY_CODEGEN_UNITS: usize = 4;
+
+/// Build settings that fit the resources we're given, when they're less than the host's
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct BuildTuning {
+    /// For `CARGO_BUILD_JOBS`: cargo defaults to the host's core count, and getting
+    /// throttled or OOM-killed is worse than running fewer jobs
+    pub(crate) jobs: Option<usize>,
+    pub(crate) codegen_units: Option<usize>,
+}
+
+pub(crate) fn build_tuning() -> BuildTuning {
+    let cores = sys_info::cpu_num().map_or(1, |cores| cores as usize);
+    tune_build(cgroup_limits(), cores)
+}
+
+fn tune_build(limits: Option<CgroupLimits>, cores: usize) -> BuildTuning {
+    let Some(limits) = limits else {
+        return BuildTuning::default();
+    };
+    let cpu_jobs = limits.cpu_quota.map(|quota| quota.ceil() as usize);
+    let memory_jobs = limits
+        .memory_limit
+        .map(|limit| (limit / MEMORY_PER_JOB) as usize);
+    let jobs = [cpu_jobs, memory_jobs]
+        .into_iter()
+        .flatten()
+        .min()
+        .map(|jobs| jobs.max(1))
+        .filter(|jobs| *jobs < cores);
+    let codegen_units = limits
+        .memory_limit
+        .filter(|limit| *limit < LOW_MEMORY)
+        .map(|_| LOW_MEMORY_CODEGEN_UNITS);
+    BuildTuning {
+        jobs,
+        codegen_units,
+    }
+}
+
+/// What we're building on, for logs and the build report
+#[derive(Debug, Serialize)]
+pub(crate) struct SystemInfo {
+    /// Whether the root filesystem is an overlay, which usually means a container
+    pub(crate) in_container: bool,
+    pub(crate) cgroup: Option<CgroupLimits>,
+    pub(crate) cpu_cores: Option<u32>,
+    pub(crate) hostname: Option<String>,
+    pub(crate) memory: Option<Usage>,
+    pub(crate) disk: Option<Usage>,
+    /// e.g. "Linux"
+    pub(crate) os: Option<String>,
+    /// Kernel release, e.g. "6.8.0-1021-azure"
+    pub(crate) os_release: Option<String>,
+    /// `NAME`, `VERSION` and `ID` from `/etc/os-release`
+    pub(crate) os_release_details: BTreeMap<String, String>,
+}
+
+/// In bytes
+#[derive(Debug, Clone, Copy, Serialize)]
+pub(crate) struct Usage {
+    pub(crate) used: u64,
+    pub(crate) total: u64,
+}
+
+impl Usage {
+    fn percentage(&self) -> f64 {
+        (self.used as f64 / self.total as f64) * 100.0
+    }
+}
 
-        // Check CPU quota
-        if let Ok(cpu_max) = fs_err::read_to_string("/sys/fs/cgroup/cpu.max") {
-            let parts: Vec<&str> = cpu_max.split_whitespace().collect();
-            if parts.len() == 2 && parts[0] != "max" {
-                if let (Ok(quota), Ok(period)) = (parts[0].parse::<f64>(), parts[1].parse::<f64>())
-                {
-                    let cpu_limit = quota / period;
-                    sys_info.push(format!(
-                        "{} {}",
-                        "CPU Quota".dimmed(),
-                        format!("{:.2} CPUs", cpu_limit).cyan().underline()
-                    ));
+impl SystemInfo {
+    pub(crate) fn collect() -> Self {
+        let in_container = fs_err::read_to_string("/etc/mtab")
+            .is_ok_and(|content| content.starts_with("overlay /"));
+
+        // sys_info reports kilobytes
+        let memory = sys_info::mem_info().ok().map(|mem_info| Usage {
+            used: (mem_info.total - mem_info.free) * 1024,
+            total: mem_info.total * 1024,
+        });
+        let disk = sys_info::disk_info().ok().map(|disk_info| Usage {
+            used: (disk_info.total - disk_info.free) * 1024,
+            total: disk_info.total * 1024,
+        });
+
+        let mut os_release_details = BTreeMap::new();
+        if let Ok(os_release_content) = fs_err::read_to_string("/etc/os-release") {
+            for line in os_release_content.lines() {
+                if let Some((key, value)) = line.split_once('=') {
+                    if matches!(key, "NAME" | "VERSION" | "ID") {
+                        os_release_details
+                            .insert(key.to_string(), value.trim_matches('"').to_string());
+                    }
                 }
             }
         }
 
-        // Check Memory limit
-        if let Ok(memory_max) = fs_err::read_to_string("/sys/fs/cgroup/memory.max") {
-            let memory_max = memory_max.trim();
-            if memory_max != "max" {
-                if let Ok(memory_limit) = memory_max.parse::<u64>() {
-                    sys_info.push(format!(
-                        "{} {}",
-                        "Memory Limit".dimmed(),
-                        crate::format_bytes(memory_limit).cyan().underline()
-                    ));
-                }
-            }
+        Self {
+            in_container,
+            cgroup: cgroup_limits(),
+            cpu_cores: sys_info::cpu_num().ok(),
+            hostname: hostname::get()
+                .ok()
+                .and_then(|hostname| hostname.to_str().map(str::to_string)),
+            memory,
+            disk,
+            os: sys_info::os_type().ok(),
+            os_release: sys_info::os_release().ok(),
+            os_release_details,
         }
     }
 
-    // Always show host resources
-    if let Ok(cpu_count) = sys_info::cpu_num() {
-        sys_info.push(format!(
-            "{} {}",
-            "CPU Cores".dimmed(),
-            cpu_count.to_string().cyan().underline()
-        ));
-    }
+    pub(crate) fn print(&self) {
+        info!("{}", "🖥️ System Information:".yellow());
+
+        let mut sys_info = Vec::new();
+
+        if self.in_container {
+            info!("root fs is overlay, we're probably in a container");
+            sys_info.push(format!(
+                "{} {}",
+                "Environment".dimmed(),
+                "Container".cyan().underline()
+            ));
+        }
+
+        // Limits apply whether or not we're in a container (systemd slices, etc.)
+        if let Some(limits) = &self.cgroup {
+            sys_info.push(format!(
+                "{} {}",
+                "cgroup".dimmed(),
+                format!("{:?}", limits.version).cyan().underline()
+            ));
+            if let Some(cpu_quota) = limits.cpu_quota {
+                sys_info.push(format!(
+                    "{} {}",
+                    "CPU Quota".dimmed(),
+                    format!("{:.2} CPUs", cpu_quota).cyan().underline()
+                ));
+            }
+            if let Some(memory_limit) = limits.memory_limit {
+                sys_info.push(format!(
+                    "{} {}",
+                    "Memory Limit".dimmed(),
+                    crate::format_bytes(memory_limit).cyan().underline()
+                ));
+            }
+        }
 
-    // Hostname
-    if let Ok(hostname) = hostname::get() {
-        if let Some(hostname_str) = hostname.to_str() {
+        if let Some(cpu_cores) = self.cpu_cores {
+            sys_info.push(format!(
+                "{} {}",
+                "CPU Cores".dimmed(),
+                cpu_cores.to_string().cyan().underline()
+            ));
+        }
+
+        if let Some(hostname) = &self.hostname {
             sys_info.push(format!(
                 "{} {}",
                 "Hostname".dimmed(),
-                hostname_str.cyan().underline()
+                hostname.cyan().underline()
             ));
         }
-    }
 
-    // Memory information
-    if let Ok(mem_info) = sys_info::mem_info() {
-        let used_memory = mem_info.total - mem_info.free;
-        let usage_percentage = (used_memory as f64 / mem_info.total as f64) * 100.0;
-        sys_info.push(format!(
-            "{} {} out of {} total ({:.1}% used)",
-            "Memory".dimmed(),
-            crate::format_bytes(used_memory * 1024).cyan().underline(),
-            crate::format_bytes(mem_info.total * 1024)
-                .cyan()
-                .underline(),
-            usage_percentage
-        ));
-    }
+        for (label, usage) in [("Memory", &self.memory), ("Disk Space", &self.disk)] {
+            if let Some(usage) = usage {
+                sys_info.push(format!(
+                    "{} {} out of {} total ({:.1}% used)",
+                    label.dimmed(),
+                    crate::format_bytes(usage.used).cyan().underline(),
+                    crate::format_bytes(usage.total).cyan().underline(),
+                    usage.percentage()
+                ));
+            }
+        }
 
-    // Disk space
-    if let Ok(disk_info) = sys_info::disk_info() {
-        let used_space = disk_info.total - disk_info.free;
-        let usage_percentage = (used_space as f64 / disk_info.total as f64) * 100.0;
         sys_info.push(format!(
-            "{} {} out of {} total ({:.1}% used)",
-            "Disk Space".dimmed(),
-            crate::format_bytes(used_space * 1024).cyan().underline(),
-            crate::format_bytes(disk_info.total * 1024)
+            "{} {} {}",
+            "OS".dimmed(),
+            self.os.as_deref().unwrap_or("Unknown").cyan().underline(),
+            self.os_release
+                .as_deref()
+                .unwrap_or("Unknown")
                 .cyan()
-                .underline(),
-            usage_percentage
+                .underline()
         ));
-    }
 
-    // OS information
-    let os_info = sys_info::os_type().unwrap_or_else(|_| "Unknown".to_string());
-    let os_release = sys_info::os_release().unwrap_or_else(|_| "Unknown".to_string());
-    sys_info.push(format!(
-        "{} {} {}",
-        "OS".dimmed(),
-        os_info.cyan().underline(),
-        os_release.cyan().underline()
-    ));
-
-    // Print system information
-    info!("{}", sys_info.join(&" :: ".dimmed().to_string()));
-
-    // Read and print /etc/os-release if it exists
-    if let Ok(os_release_content) = fs_err::read_to_string("/etc/os-release") {
-        let mut os_release_info = Vec::new();
-        for line in os_release_content.lines() {
-            if line.starts_with("NAME=") || line.starts_with("VERSION=") || line.starts_with("ID=")
-            {
-                let parts: Vec<&str> = line.splitn(2, '=').collect();
-                if parts.len() == 2 {
-                    let value = parts[1].trim_matches('"');
-                    os_release_info.push(format!(
-                        "{} {}",
-                        parts[0].dimmed(),
-                        value.cyan().underline()
-                    ));
-                }
-            }
-        }
-        if !os_release_info.is_empty() {
+        info!("{}", sys_info.join(&" :: ".dimmed().to_string()));
+
+        if !self.os_release_details.is_empty() {
+            let details = self
+                .os_release_details
+                .iter()
+                .map(|(key, value)| format!("{} {}", key.dimmed(), value.cyan().underline()))
+                .collect::<Vec<_>>();
             info!(
                 "{}: {}",
                 "OS Release Details".dimmed(),
-                os_release_info.join(&" :: ".dimmed().to_string())
+                details.join(&" :: ".dimmed().to_string())
             );
         }
     }
 }
+
+/// Logs what we're building on, and returns it
+pub(crate) fn print_sysinfo() -> SystemInfo {
+    let info = SystemInfo::collect();
+    info.print();
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_cgroup_limits() {
+        let v2 = tempfile::tempdir().unwrap();
+        fs_err::write(v2.path().join("cgroup.controllers"), "cpu memory").unwrap();
+        fs_err::write(v2.path().join("cpu.max"), "150000 100000\n").unwrap();
+        fs_err::write(v2.path().join("memory.max"), "max\n").unwrap();
+        assert_eq!(
+            read_cgroup_limits(v2.path()),
+            Some(CgroupLimits {
+                version: CgroupVersion::V2,
+                cpu_quota: Some(1.5),
+                memory_limit: None,
+            })
+        );
+
+        let v1 = tempfile::tempdir().unwrap();
+        let cpu = v1.path().join("cpu,cpuacct");
+        fs_err::create_dir_all(&cpu).unwrap();
+        fs_err::write(cpu.join("cpu.cfs_quota_us"), "-1\n").unwrap();
+        fs_err::write(cpu.join("cpu.cfs_period_us"), "100000\n").unwrap();
+        let memory = v1.path().join("memory");
+        fs_err::create_dir_all(&memory).unwrap();
+        fs_err::write(memory.join("memory.limit_in_bytes"), "2147483648\n").unwrap();
+        assert_eq!(
+            read_cgroup_limits(v1.path()),
+            Some(CgroupLimits {
+                version: CgroupVersion::V1,
+                cpu_quota: None,
+                memory_limit: Some(2 << 30),
+            })
+        );
+
+        fs_err::write(cpu.join("cpu.cfs_quota_us"), "200000\n").unwrap();
+        assert_eq!(read_cgroup_limits(v1.path()).unwrap().cpu_quota, Some(2.0));
+
+        assert_eq!(read_cgroup_limits(Path::new("/nonexistent")), None);
+    }
+
+    #[test]
+    fn test_tune_build() {
+        let limits = |cpu_quota, memory_limit| {
+            Some(CgroupLimits {
+                version: CgroupVersion::V2,
+                cpu_quota,
+                memory_limit,
+            })
+        };
+        assert_eq!(tune_build(None, 8), BuildTuning::default());
+        assert_eq!(tune_build(limits(None, None), 8), BuildTuning::default());
+        assert_eq!(
+            tune_build(limits(Some(2.5), None), 8),
+            BuildTuning {
+                jobs: Some(3),
+                codegen_units: None,
+            }
+        );
+        // A 2GB container can only afford one job, and fewer codegen units
+        assert_eq!(
+            tune_build(limits(Some(4.0), Some(2 << 30)), 8),
+            BuildTuning {
+                jobs: Some(1),
+                codegen_units: Some(LOW_MEMORY_CODEGEN_UNITS),
+            }
+        );
+        // Limits above what the host has anyway change nothing
+        assert_eq!(
+            tune_build(limits(Some(16.0), Some(64 << 30)), 8),
+            BuildTuning::default()
+        );
+    }
+}
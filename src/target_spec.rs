@@ -3,9 +3,15 @@ use log::info;
 use owo_colors::OwoColorize;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 #[allow(dead_code)]
 pub(crate) struct TargetSpec {
+    /// The Rust target triple, as reported by rustc, e.g. "x86_64-unknown-linux-gnu".
+    /// It's not part of the spec, and can't always be rebuilt from its parts.
+    #[serde(skip)]
+    pub(crate) triple: Option<String>,
+    /// Examples: "eabihf", None
+    pub(crate) abi: Option<String>,
     /// Examples: true, None
     #[serde(rename = "abi-return-struct-as-int")]
     pub(crate) abi_return_struct_as_int: Option<bool>,
@@ -125,7 +131,7 @@ pub(crate) struct TargetSpec {
     pub(crate) vendor: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 #[allow(dead_code)]
 pub(crate) struct Metadata {
     /// Examples: "ARM64 Apple macOS (11.0+, Big Sur+)", "64-bit Linux (kernel 3.2+, glibc 2.17+)"
@@ -138,14 +144,14 @@ pub(crate) struct Metadata {
     pub(crate) tier: u8,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[allow(dead_code)]
 pub(crate) struct StackProbes {
     /// Examples: "inline"
     pub(crate) kind: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[allow(dead_code)]
 pub(crate) struct PreLinkArgs {
     /// Examples: ["-m64"]
@@ -161,20 +167,94 @@ impl TargetSpec {
         serde_json::from_str(json_output).wrap_err("could not deserialize target spec from JSON payload. '--print target-spec-json' is an unstable Rust flag for a reason, y'know.")
     }
 
+    /// Derives what we can from the stable `rustc --print cfg` output and the target
+    /// triple, for when `--print target-spec-json` isn't available
+    pub(crate) fn from_cfg(cfg_output: &str, triple: &str) -> eyre::Result<Self> {
+        let mut cfg = std::collections::HashMap::new();
+        let mut atomic_widths = Vec::new();
+        for line in cfg_output.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim_matches('"');
+            if key == "target_has_atomic" {
+                atomic_widths.extend(value.parse::<u32>().ok());
+            } else {
+                // Keys like target_family can repeat ("unix", "wasm"), the first one is fine
+                cfg.entry(key).or_insert(value);
+            }
+        }
+        let get = |key: &str| {
+            cfg.get(key)
+                .map(|value| value.to_string())
+                .ok_or_else(|| eyre::eyre!("`rustc --print cfg` output has no {}", key))
+        };
+        let non_empty = |key: &str| get(key).ok().filter(|value| !value.is_empty());
+
+        let os = get("target_os")?;
+        let dll_suffix = match os.as_str() {
+            "macos" | "ios" | "tvos" | "watchos" | "visionos" => ".dylib",
+            "windows" => ".dll",
+            _ => ".so",
+        };
+        Ok(Self {
+            arch: get("target_arch")?,
+            dll_suffix: dll_suffix.to_string(),
+            env: non_empty("target_env"),
+            vendor: non_empty("target_vendor"),
+            abi: non_empty("target_abi"),
+            target_family: non_empty("target_family").map(|family| vec![family]),
+            target_pointer_width: get("target_pointer_width")?,
+            max_atomic_width: atomic_widths.into_iter().max(),
+            triple: Some(triple.to_string()),
+            llvm_target: triple.to_string(),
+            metadata: Metadata {
+                description: format!("{} (derived from `rustc --print cfg`)", triple),
+                ..Default::default()
+            },
+            os,
+            ..Default::default()
+        })
+    }
+
+    /// The Rust target triple, e.g. "aarch64-apple-darwin"
     pub(crate) fn full_name(&self) -> String {
-        let os = if self.os == "macos" {
-            "darwin"
-        } else {
-            self.os.as_str()
+        match &self.triple {
+            Some(triple) => triple.clone(),
+            None => self.triple_from_parts(),
+        }
+    }
+
+    /// Rebuilds the triple from the spec's parts, which don't map one-to-one: the arch
+    /// may be more specific in the triple ("x86" is "i686"), and the env and ABI are
+    /// glued together ("gnu" + "eabihf")
+    fn triple_from_parts(&self) -> String {
+        let llvm_arch = self.llvm_target.split('-').next().unwrap_or_default();
+        let arch = match self.arch.as_str() {
+            // "i586"/"i686", "arm"/"armv7"
+            "x86" | "arm" if !llvm_arch.is_empty() => llvm_arch,
+            "riscv64" => "riscv64gc",
+            "powerpc64" if llvm_arch == "powerpc64le" => llvm_arch,
+            arch => arch,
         };
-        let arch = self.arch.as_str();
-        let vendor = self.vendor.as_deref().unwrap_or("unknown");
-        let env = self.env.as_deref().unwrap_or("");
+        let env = self.env.as_deref().unwrap_or_default();
+        let abi = self.abi.as_deref().unwrap_or_default();
+        let suffix = format!("{}{}", env, abi);
 
-        if !env.is_empty() {
-            format!("{}-{}-{}-{}", arch, vendor, os, env)
-        } else {
-            format!("{}-{}-{}", arch, vendor, os)
+        match self.os.as_str() {
+            // wasm32-wasip1, wasm32-wasip2
+            "wasi" => format!("{}-wasi{}", arch, env),
+            // aarch64-linux-android, armv7-linux-androideabi
+            "android" => format!("{}-linux-android{}", arch, abi),
+            os => {
+                let os = if os == "macos" { "darwin" } else { os };
+                let vendor = self.vendor.as_deref().unwrap_or("unknown");
+                if suffix.is_empty() {
+                    format!("{}-{}-{}", arch, vendor, os)
+                } else {
+                    format!("{}-{}-{}-{}", arch, vendor, os, suffix)
+                }
+            }
         }
     }
 
@@ -272,6 +352,280 @@ fn default_dll_suffix() -> String {
     ".so".into()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_cfg() {
+        let cfg = r#"debug_assertions
+panic="unwind"
+target_abi=""
+target_arch="aarch64"
+target_endian="little"
+target_env=""
+target_family="unix"
+target_has_atomic="128"
+target_has_atomic="16"
+target_has_atomic="32"
+target_has_atomic="64"
+target_has_atomic="8"
+target_has_atomic="ptr"
+target_os="macos"
+target_pointer_width="64"
+target_vendor="apple"
+unix"#;
+        let spec = TargetSpec::from_cfg(cfg, "aarch64-apple-darwin").unwrap();
+        assert_eq!(spec.full_name(), "aarch64-apple-darwin");
+        assert_eq!(spec.dll_suffix, ".dylib");
+        assert_eq!(spec.env, None);
+        assert_eq!(spec.max_atomic_width, Some(128));
+
+        let cfg = cfg
+            .replace(r#"target_os="macos""#, r#"target_os="linux""#)
+            .replace(r#"target_env="""#, r#"target_env="gnu""#)
+            .replace(r#"target_vendor="apple""#, r#"target_vendor="unknown""#);
+        let spec = TargetSpec::from_cfg(&cfg, "aarch64-unknown-linux-gnu").unwrap();
+        assert_eq!(spec.full_name(), "aarch64-unknown-linux-gnu");
+        assert_eq!(spec.dll_suffix, ".so");
+
+        assert!(TargetSpec::from_cfg("unix", "x").is_err());
+    }
+
+    #[test]
+    fn test_triple_from_parts() {
+        // (triple, arch, vendor, os, env, abi, llvm-target)
+        let targets = [
+            (
+                "aarch64-apple-darwin",
+                "aarch64",
+                Some("apple"),
+                "macos",
+                None,
+                None,
+                "arm64-apple-macosx",
+            ),
+            (
+                "x86_64-apple-darwin",
+                "x86_64",
+                Some("apple"),
+                "macos",
+                None,
+                None,
+                "x86_64-apple-macosx",
+            ),
+            (
+                "aarch64-apple-ios-sim",
+                "aarch64",
+                Some("apple"),
+                "ios",
+                None,
+                Some("sim"),
+                "arm64-apple-ios14.0-simulator",
+            ),
+            (
+                "x86_64-unknown-linux-gnu",
+                "x86_64",
+                None,
+                "linux",
+                Some("gnu"),
+                None,
+                "x86_64-unknown-linux-gnu",
+            ),
+            (
+                "x86_64-unknown-linux-musl",
+                "x86_64",
+                None,
+                "linux",
+                Some("musl"),
+                None,
+                "x86_64-unknown-linux-musl",
+            ),
+            (
+                "aarch64-unknown-linux-gnu",
+                "aarch64",
+                None,
+                "linux",
+                Some("gnu"),
+                None,
+                "aarch64-unknown-linux-gnu",
+            ),
+            (
+                "aarch64-unknown-linux-musl",
+                "aarch64",
+                None,
+                "linux",
+                Some("musl"),
+                None,
+                "aarch64-unknown-linux-musl",
+            ),
+            (
+                "i686-unknown-linux-gnu",
+                "x86",
+                None,
+                "linux",
+                Some("gnu"),
+                None,
+                "i686-unknown-linux-gnu",
+            ),
+            (
+                "armv7-unknown-linux-gnueabihf",
+                "arm",
+                None,
+                "linux",
+                Some("gnu"),
+                Some("eabihf"),
+                "armv7-unknown-linux-gnueabihf",
+            ),
+            (
+                "arm-unknown-linux-musleabihf",
+                "arm",
+                None,
+                "linux",
+                Some("musl"),
+                Some("eabihf"),
+                "arm-unknown-linux-musleabihf",
+            ),
+            (
+                "riscv64gc-unknown-linux-gnu",
+                "riscv64",
+                None,
+                "linux",
+                Some("gnu"),
+                None,
+                "riscv64-unknown-linux-gnu",
+            ),
+            (
+                "powerpc64le-unknown-linux-gnu",
+                "powerpc64",
+                None,
+                "linux",
+                Some("gnu"),
+                None,
+                "powerpc64le-unknown-linux-gnu",
+            ),
+            (
+                "s390x-unknown-linux-gnu",
+                "s390x",
+                None,
+                "linux",
+                Some("gnu"),
+                None,
+                "s390x-unknown-linux-gnu",
+            ),
+            (
+                "x86_64-pc-windows-msvc",
+                "x86_64",
+                Some("pc"),
+                "windows",
+                Some("msvc"),
+                None,
+                "x86_64-pc-windows-msvc",
+            ),
+            (
+                "i686-pc-windows-msvc",
+                "x86",
+                Some("pc"),
+                "windows",
+                Some("msvc"),
+                None,
+                "i686-pc-windows-msvc",
+            ),
+            (
+                "aarch64-pc-windows-msvc",
+                "aarch64",
+                Some("pc"),
+                "windows",
+                Some("msvc"),
+                None,
+                "aarch64-pc-windows-msvc",
+            ),
+            (
+                "x86_64-pc-windows-gnu",
+                "x86_64",
+                Some("pc"),
+                "windows",
+                Some("gnu"),
+                None,
+                "x86_64-pc-windows-gnu",
+            ),
+            (
+                "x86_64-unknown-freebsd",
+                "x86_64",
+                None,
+                "freebsd",
+                None,
+                None,
+                "x86_64-unknown-freebsd",
+            ),
+            (
+                "aarch64-linux-android",
+                "aarch64",
+                None,
+                "android",
+                None,
+                None,
+                "aarch64-linux-android",
+            ),
+            (
+                "armv7-linux-androideabi",
+                "arm",
+                None,
+                "android",
+                None,
+                Some("eabi"),
+                "armv7-none-linux-android",
+            ),
+            (
+                "wasm32-unknown-unknown",
+                "wasm32",
+                None,
+                "unknown",
+                None,
+                None,
+                "wasm32-unknown-unknown",
+            ),
+            (
+                "wasm32-wasip1",
+                "wasm32",
+                None,
+                "wasi",
+                Some("p1"),
+                None,
+                "wasm32-wasip1",
+            ),
+            (
+                "wasm32-wasip2",
+                "wasm32",
+                None,
+                "wasi",
+                Some("p2"),
+                None,
+                "wasm32-wasip2",
+            ),
+        ];
+        for (triple, arch, vendor, os, env, abi, llvm_target) in targets {
+            let spec = TargetSpec {
+                arch: arch.to_string(),
+                vendor: vendor.map(str::to_string),
+                os: os.to_string(),
+                env: env.map(str::to_string),
+                abi: abi.map(str::to_string),
+                llvm_target: llvm_target.to_string(),
+                ..Default::default()
+            };
+            assert_eq!(spec.full_name(), triple);
+        }
+
+        // What rustc says wins
+        let spec = TargetSpec {
+            triple: Some("x86_64-unknown-linux-gnux32".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(spec.full_name(), "x86_64-unknown-linux-gnux32");
+    }
+}
+
 /* Sample outputs:
 
 ## arm64 macOS
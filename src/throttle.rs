@@ -0,0 +1,104 @@
+//! Caps the rate of uploads, so releases from runners on a shared uplink don't saturate
+//! it. The cap is for the whole process: concurrent uploads share it.
+
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Bytes per second, 0 for no cap
+static MAX_UPLOAD_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// When the bytes sent so far will have been paid for, at the capped rate
+static NEXT_SEND: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Parses a rate like "10MB/s", "512KiB/s" or "1000000" (bytes per second). KB, MB and
+/// GB are powers of 1000, KiB, MiB and GiB powers of 1024.
+pub(crate) fn parse_rate(rate: &str) -> eyre::Result<u64> {
+    let trimmed = rate.trim();
+    let trimmed = trimmed.strip_suffix("/s").unwrap_or(trimmed);
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| eyre::eyre!("Invalid rate {:?}, expected e.g. \"10MB/s\"", rate))?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "kib" => 1024,
+        "m" | "mb" => 1000 * 1000,
+        "mib" => 1024 * 1024,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "gib" => 1024 * 1024 * 1024,
+        _ => {
+            return Err(eyre::eyre!(
+                "Invalid unit {:?} in rate {:?}, expected B, KB, MB, GB, KiB, MiB or GiB",
+                unit,
+                rate
+            ));
+        }
+    };
+    let bytes_per_sec = (number * multiplier as f64) as u64;
+    if bytes_per_sec == 0 {
+        return Err(eyre::eyre!(
+            "Rate {:?} is zero, uploads would never finish",
+            rate
+        ));
+    }
+    Ok(bytes_per_sec)
+}
+
+/// Caps uploads at `bytes_per_sec`, or lifts the cap with `None`
+pub(crate) fn set_max_upload_rate(bytes_per_sec: Option<u64>) {
+    MAX_UPLOAD_RATE.store(bytes_per_sec.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Accounts for sending `bytes`, returning how long to wait before sending them
+fn reserve(bytes: usize) -> Option<Duration> {
+    let rate = MAX_UPLOAD_RATE.load(Ordering::Relaxed);
+    if rate == 0 {
+        return None;
+    }
+    let cost = Duration::from_secs_f64(bytes as f64 / rate as f64);
+    let now = Instant::now();
+    let mut next_send = NEXT_SEND.lock().unwrap();
+    // Time not spent uploading doesn't build up credit for a burst later
+    let start = next_send.filter(|next| *next > now).unwrap_or(now);
+    *next_send = Some(start + cost);
+    Some(start - now).filter(|wait| !wait.is_zero())
+}
+
+/// Waits until `bytes` more can be uploaded
+pub(crate) async fn upload(bytes: usize) {
+    if let Some(wait) = reserve(bytes) {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Like [`upload`], for blocking uploads
+pub(crate) fn upload_blocking(bytes: usize) {
+    if let Some(wait) = reserve(bytes) {
+        std::thread::sleep(wait);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("10MB/s").unwrap(), 10_000_000);
+        assert_eq!(parse_rate("512KiB/s").unwrap(), 512 * 1024);
+        assert_eq!(parse_rate("1.5 GB/s").unwrap(), 1_500_000_000);
+        assert_eq!(parse_rate("4096").unwrap(), 4096);
+        assert!(parse_rate("10 furlongs/s").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("0MB/s").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! `beardist warm`: filling `$BEARDIST_CACHE_DIR` with toolchains and dependencies
+//! without building, so runner images can be pre-baked, or caches kept hot on a
+//! schedule.
+
+use camino::Utf8Path;
+use indexmap::IndexMap;
+use log::*;
+use owo_colors::OwoColorize;
+use std::path::Path;
+
+use crate::{command, environment::ProcessEnvironment, retry::RetryPolicy};
+
+/// Runs a command that fills the cache (with retries), or under `DRY_RUN`, only says
+/// it would
+pub(crate) fn fill(
+    program: &str,
+    args: &[&str],
+    env: &IndexMap<String, String>,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    if dry_run {
+        info!(
+            "Dry run: Would run {}",
+            format!("{} {}", program, args.join(" ")).cyan()
+        );
+        return Ok(());
+    }
+    command::run_command_with_retry(program, args, Some(env.clone()), RetryPolicy::default())?;
+    Ok(())
+}
+
+/// Points pnpm and bun at stores in the cache, for anything that installs JS packages
+pub(crate) fn js_cache_env(cache_dir: &Utf8Path) -> IndexMap<String, String> {
+    IndexMap::from([
+        (
+            "npm_config_store_dir".to_string(),
+            cache_dir.join("pnpm").join("store").to_string(),
+        ),
+        (
+            "BUN_INSTALL_CACHE_DIR".to_string(),
+            cache_dir.join("bun").to_string(),
+        ),
+    ])
+}
+
+pub(crate) fn warm(config_path: Option<&Path>) -> eyre::Result<()> {
+    let config = crate::load_config(config_path)?;
+    let cache_dir = crate::cache_dir_from_env(&ProcessEnvironment)?;
+    let source_dir = Utf8Path::from_path(&std::env::current_dir()?)
+        .ok_or_else(|| eyre::eyre!("The current directory isn't valid UTF-8"))?
+        .to_owned();
+    info!("🔥 Warming {} for {}", cache_dir.cyan(), source_dir.cyan());
+    let dry_run = std::env::var("DRY_RUN").is_ok();
+
+    if let Some(cargo) = &config.cargo {
+        crate::cargo::warm(&cache_dir, &source_dir, cargo, dry_run)?;
+    }
+
+    let env = js_cache_env(&cache_dir);
+    if source_dir.join("pnpm-lock.yaml").exists() {
+        info!("📦 Fetching pnpm packages");
+        fill("pnpm", &["fetch"], &env, dry_run)?;
+    }
+    if ["bun.lock", "bun.lockb"]
+        .iter()
+        .any(|lockfile| source_dir.join(lockfile).exists())
+    {
+        // bun has no fetch-only command: installing is how its cache gets filled
+        info!("📦 Installing bun packages");
+        fill("bun", &["install", "--frozen-lockfile"], &env, dry_run)?;
+    }
+
+    if dry_run {
+        info!("Dry run: left the cache alone");
+    } else {
+        info!("✅ Cache is warm");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_js_cache_env() {
+        let env = js_cache_env(Utf8Path::new("/cache"));
+        assert_eq!(env["npm_config_store_dir"], "/cache/pnpm/store");
+        assert_eq!(env["BUN_INSTALL_CACHE_DIR"], "/cache/bun");
+        // Dry runs don't run anything
+        fill("beardist-no-such-command", &["fetch"], &env, true).unwrap();
+    }
+}
@@ -0,0 +1,366 @@
+use camino::Utf8PathBuf;
+use eyre::Context;
+use log::*;
+use owo_colors::OwoColorize;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt::Write, path::PathBuf};
+
+use crate::{checksums, forge::Forge, github::GitHubClient, http};
+
+const MANIFEST_VERSION: &str = "1.6.0";
+
+#[derive(Deserialize, Debug, Clone)]
+struct WingetConfig {
+    /// Repository the pull requests are opened against
+    #[serde(default = "default_upstream")]
+    upstream: String,
+
+    /// Default branch of the upstream repository
+    #[serde(default = "default_base_branch")]
+    base_branch: String,
+
+    packages: Vec<Package>,
+
+    /// Identity and message of the commits adding manifests
+    #[serde(default)]
+    commit: crate::git::CommitConfig,
+
+    /// Push over "https" (the default) or "ssh"
+    #[serde(default)]
+    push: crate::git::Transport,
+}
+
+fn default_upstream() -> String {
+    "microsoft/winget-pkgs".to_string()
+}
+
+fn default_base_branch() -> String {
+    "master".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Package {
+    repo: String,
+
+    /// e.g. "Bearcove.Beardist"
+    identifier: String,
+
+    publisher: String,
+    homepage: String,
+    desc: String,
+    license: String,
+
+    /// Binary names, without the `.exe` suffix
+    bins: Vec<String>,
+
+    /// winget architecture name → release asset file name, e.g. "x64" →
+    /// "foobar-windows.zip". winget can't extract the `.tar.xz` archives beardist
+    /// publishes, so these need to be zip archives published alongside them.
+    #[serde(default)]
+    installers: BTreeMap<String, String>,
+}
+
+impl Package {
+    /// Fails for packages winget couldn't install
+    fn check(&self) -> eyre::Result<()> {
+        if self.installers.is_empty() {
+            return Err(eyre::eyre!(
+                "Package {} has no `installers`: winget can't install the .tar.xz archives \
+                 beardist publishes, so publish a zip of the Windows build with the release \
+                 and list it, e.g. {{\"x64\": \"x86_64-pc-windows-msvc.zip\"}}",
+                self.identifier
+            ));
+        }
+        if let Some(file_name) = self.installers.values().find(|f| !f.ends_with(".zip")) {
+            return Err(eyre::eyre!(
+                "Installer {} of package {} isn't a zip archive, which is all winget can \
+                 install from",
+                file_name,
+                self.identifier
+            ));
+        }
+        Ok(())
+    }
+
+    fn org(&self) -> &str {
+        self.repo.split('/').next().unwrap()
+    }
+
+    fn name(&self) -> &str {
+        self.repo.split('/').nth(1).unwrap()
+    }
+
+    /// `manifests/<first letter>/<Publisher>/<Package>/<version>`
+    fn manifest_dir(&self, version: &str) -> Utf8PathBuf {
+        let first_letter = self
+            .identifier
+            .chars()
+            .next()
+            .unwrap_or('_')
+            .to_ascii_lowercase();
+        let mut dir = Utf8PathBuf::from("manifests").join(first_letter.to_string());
+        for part in self.identifier.split('.') {
+            dir.push(part);
+        }
+        dir.join(version)
+    }
+
+    fn release_file_url(&self, version: &str, file_name: &str) -> String {
+        format!(
+            "https://github.com/{}/{}/releases/download/v{}/{}",
+            self.org(),
+            self.name(),
+            version,
+            file_name
+        )
+    }
+}
+
+/// Quotes a string for YAML, so descriptions with colons etc. stay valid
+fn yaml_str(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Generates the version, installer and default locale manifests, keyed by file name
+fn generate_manifests(
+    package: &Package,
+    version: &str,
+    hashes: &BTreeMap<String, String>,
+) -> eyre::Result<BTreeMap<String, String>> {
+    let id = &package.identifier;
+    let mut files = BTreeMap::new();
+
+    let mut w = String::new();
+    writeln!(w, "# Created with beardist")?;
+    writeln!(w, "PackageIdentifier: {}", id)?;
+    writeln!(w, "PackageVersion: {}", version)?;
+    writeln!(w, "DefaultLocale: en-US")?;
+    writeln!(w, "ManifestType: version")?;
+    writeln!(w, "ManifestVersion: {}", MANIFEST_VERSION)?;
+    files.insert(format!("{}.yaml", id), w);
+
+    let mut w = String::new();
+    writeln!(w, "# Created with beardist")?;
+    writeln!(w, "PackageIdentifier: {}", id)?;
+    writeln!(w, "PackageVersion: {}", version)?;
+    writeln!(w, "InstallerType: zip")?;
+    writeln!(w, "NestedInstallerType: portable")?;
+    writeln!(w, "NestedInstallerFiles:")?;
+    for bin in &package.bins {
+        writeln!(w, "- RelativeFilePath: {}.exe", bin)?;
+        writeln!(w, "  PortableCommandAlias: {}", bin)?;
+    }
+    writeln!(w, "Installers:")?;
+    for (arch, file_name) in &package.installers {
+        let hash = hashes
+            .get(file_name)
+            .ok_or_else(|| eyre::eyre!("No hash for {}", file_name))?;
+        writeln!(w, "- Architecture: {}", arch)?;
+        writeln!(
+            w,
+            "  InstallerUrl: {}",
+            package.release_file_url(version, file_name)
+        )?;
+        writeln!(w, "  InstallerSha256: {}", hash.to_ascii_uppercase())?;
+    }
+    writeln!(w, "ManifestType: installer")?;
+    writeln!(w, "ManifestVersion: {}", MANIFEST_VERSION)?;
+    files.insert(format!("{}.installer.yaml", id), w);
+
+    let mut w = String::new();
+    writeln!(w, "# Created with beardist")?;
+    writeln!(w, "PackageIdentifier: {}", id)?;
+    writeln!(w, "PackageVersion: {}", version)?;
+    writeln!(w, "PackageLocale: en-US")?;
+    writeln!(w, "Publisher: {}", yaml_str(&package.publisher))?;
+    writeln!(w, "PackageName: {}", yaml_str(package.name()))?;
+    writeln!(w, "PackageUrl: {}", package.homepage)?;
+    writeln!(w, "License: {}", yaml_str(&package.license))?;
+    writeln!(w, "ShortDescription: {}", yaml_str(&package.desc))?;
+    writeln!(w, "ManifestType: defaultLocale")?;
+    writeln!(w, "ManifestVersion: {}", MANIFEST_VERSION)?;
+    files.insert(format!("{}.locale.en-US.yaml", id), w);
+
+    Ok(files)
+}
+
+/// Writes manifests for the latest release of a package, returning the version if
+/// they didn't exist yet
+fn process_package(
+    github_client: &GitHubClient,
+    package: &Package,
+    dry_run: bool,
+) -> eyre::Result<Option<String>> {
+    let Some(version) = github_client.get_latest_release_version(package.org(), package.name())?
+    else {
+        info!("No version found for {}, skipping", package.name().cyan());
+        return Ok(None);
+    };
+
+    let manifest_dir = package.manifest_dir(&version);
+    if manifest_dir.exists() {
+        info!(
+            "Manifests for {} {} already exist",
+            package.identifier.cyan(),
+            version.bright_green()
+        );
+        return Ok(None);
+    }
+
+    let sums = http::block_on(checksums::fetch_sha256sums(
+        &package.release_file_url(&version, "SHA256SUMS"),
+        dry_run,
+    ))?;
+    let mut hashes = BTreeMap::new();
+    for file_name in package.installers.values() {
+        let hash = match sums.as_ref().and_then(|sums| sums.get(file_name)) {
+            Some(hash) => hash.clone(),
+            None => http::block_on(checksums::fetch_and_hash(
+                &package.release_file_url(&version, file_name),
+                dry_run,
+            ))?,
+        };
+        hashes.insert(file_name.clone(), hash);
+    }
+
+    let files = generate_manifests(package, &version, &hashes)?;
+    for (file_name, contents) in files {
+        let path = manifest_dir.join(file_name);
+        if dry_run {
+            info!(
+                "Dry run: Would write {}:\n{}",
+                path.to_string().cyan(),
+                contents
+            );
+        } else {
+            fs_err::create_dir_all(&manifest_dir)?;
+            fs_err::write(&path, contents)?;
+            info!(
+                "winget manifest written to {}",
+                path.to_string().bright_green()
+            );
+        }
+    }
+
+    Ok(Some(version))
+}
+
+fn load_winget_config() -> eyre::Result<WingetConfig> {
+    let config_path = fs_err::canonicalize(PathBuf::from(".beardist-winget.json"))?;
+    let config_str = fs_err::read_to_string(&config_path).wrap_err_with(|| {
+        format!(
+            "Failed to read winget config file at {}",
+            config_path.display().to_string().cyan()
+        )
+    })?;
+    let config: WingetConfig = serde_json::from_str(&config_str).wrap_err_with(|| {
+        format!(
+            "Failed to parse config file at {}",
+            config_path.display().to_string().cyan()
+        )
+    })?;
+    for package in &config.packages {
+        package.check().wrap_err_with(|| {
+            format!(
+                "Invalid config file at {}",
+                config_path.display().to_string().cyan()
+            )
+        })?;
+    }
+    Ok(config)
+}
+
+/// Run from a checkout of a winget-pkgs fork: writes manifests for new releases,
+/// pushes them to a branch of the fork and opens a pull request upstream.
+pub(crate) fn update_winget() -> eyre::Result<()> {
+    let dry_run = std::env::var("DRY_RUN").is_ok();
+    if dry_run {
+        info!("Dry run {}", "enabled".bright_yellow());
+    }
+    let github_token = crate::credentials::require("GITHUB_TOKEN")?;
+
+    info!("Loading winget {}...", "configuration".cyan());
+    let config = load_winget_config()?;
+
+    let github_client =
+        GitHubClient::new("https://api.github.com".to_string(), github_token.clone());
+
+    let mut bumped_packages = Vec::new();
+    for package in &config.packages {
+        info!("Processing package {}...", package.identifier.cyan());
+        if let Some(version) = process_package(&github_client, package, dry_run)
+            .wrap_err_with(|| format!("while processing package {}", package.identifier.cyan()))?
+        {
+            bumped_packages.push((package.identifier.clone(), version));
+        }
+    }
+
+    if !bumped_packages.is_empty() {
+        crate::git::commit_and_push(crate::git::Publish {
+            kind: "winget manifests",
+            bumped: &bumped_packages,
+            github_token: &github_token,
+            pr: true,
+            base_branch: &config.base_branch,
+            upstream: Some(&config.upstream),
+            dry_run,
+            commit: &config.commit,
+            transport: config.push,
+        })?;
+    } else {
+        info!("No packages were bumped");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_manifests() -> eyre::Result<()> {
+        let package: Package = serde_json::from_str(
+            r#"{
+                "repo": "testowner/foobar",
+                "identifier": "Testowner.Foobar",
+                "publisher": "Test Owner",
+                "homepage": "https://example.com",
+                "desc": "Sample: generated by beardist",
+                "license": "MIT",
+                "bins": ["foobar"],
+                "installers": {"x64": "x86_64-pc-windows-msvc.zip"}
+            }"#,
+        )?;
+        package.check()?;
+        assert_eq!(
+            package.manifest_dir("1.2.3"),
+            "manifests/t/Testowner/Foobar/1.2.3"
+        );
+
+        let hashes = BTreeMap::from([("x86_64-pc-windows-msvc.zip".to_string(), "ab".repeat(32))]);
+        let files = generate_manifests(&package, "1.2.3", &hashes)?;
+        assert_eq!(files.len(), 3);
+
+        let installer = &files["Testowner.Foobar.installer.yaml"];
+        assert!(
+            installer.contains("- RelativeFilePath: foobar.exe\n  PortableCommandAlias: foobar\n")
+        );
+        assert!(installer.contains(
+            "  InstallerUrl: https://github.com/testowner/foobar/releases/download/v1.2.3/x86_64-pc-windows-msvc.zip\n"
+        ));
+        assert!(installer.contains(&format!("  InstallerSha256: {}\n", "AB".repeat(32))));
+
+        let locale = &files["Testowner.Foobar.locale.en-US.yaml"];
+        assert!(locale.contains("ShortDescription: \"Sample: generated by beardist\"\n"));
+
+        let mut package = package;
+        package.installers.clear();
+        assert!(package.check().is_err());
+        package.installers.insert(
+            "x64".to_string(),
+            "x86_64-pc-windows-msvc.tar.xz".to_string(),
+        );
+        assert!(package.check().is_err());
+        Ok(())
+    }
+}
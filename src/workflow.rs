@@ -0,0 +1,402 @@
+//! `beardist generate-workflow`: writes the CI pipeline that builds and releases the
+//! project (GitHub Actions, Forgejo Actions or Woodpecker), from `.beardist.json`, so
+//! adding a target (or a feature that needs a secret) is a config change followed by
+//! regenerating, rather than two edits that can drift apart.
+
+use log::*;
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{Config, GenerateWorkflowArgs, forge::ForgeKind, load_config};
+
+/// Where beardist itself is downloaded from, in the workflow
+const BEARDIST_RELEASES: &str = "https://github.com/bearcove/beardist/releases/download";
+
+/// Image Woodpecker builds run in, unless the config says otherwise
+const DEFAULT_WOODPECKER_IMAGE: &str = "ghcr.io/bearcove/beardist:latest";
+
+const HEADER: &str = "# Generated by `beardist generate-workflow` from .beardist.json: change the config and\n\
+     # regenerate rather than editing this file.\n";
+
+/// Which CI the pipeline is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum WorkflowFormat {
+    /// GitHub Actions
+    Github,
+    /// Forgejo Actions, which mostly speak the same language
+    Forgejo,
+    /// Woodpecker CI
+    Woodpecker,
+}
+
+impl WorkflowFormat {
+    fn default_output(self) -> &'static str {
+        match self {
+            WorkflowFormat::Github => ".github/workflows/release.yml",
+            WorkflowFormat::Forgejo => ".forgejo/workflows/release.yml",
+            WorkflowFormat::Woodpecker => ".woodpecker/release.yml",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct WorkflowConfig {
+    /// What to build, and on which runners. Default: Linux x86_64 and aarch64, and on
+    /// GitHub, macOS aarch64 (or universal, with `cargo.universal_macos`).
+    #[serde(default)]
+    pub(crate) targets: Vec<WorkflowTarget>,
+
+    /// Image Woodpecker builds run in. Default: "ghcr.io/bearcove/beardist:latest"
+    #[serde(default)]
+    pub(crate) image: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct WorkflowTarget {
+    /// Artifact name (`BEARDIST_ARTIFACT_NAME`), e.g. "x86_64-unknown-linux-gnu"
+    pub(crate) artifact: String,
+
+    /// Runner label, e.g. "ubuntu-24.04". For Woodpecker, the agent's platform, e.g.
+    /// "linux/arm64".
+    pub(crate) runs_on: String,
+}
+
+impl WorkflowTarget {
+    fn new(artifact: &str, runs_on: &str) -> Self {
+        Self {
+            artifact: artifact.to_string(),
+            runs_on: runs_on.to_string(),
+        }
+    }
+}
+
+/// Everything the workflow depends on
+#[derive(Debug)]
+struct Workflow {
+    format: WorkflowFormat,
+
+    /// Which forge's variables beardist reads in CI
+    forge: ForgeKind,
+
+    /// e.g. "api-", for the tags that trigger a release
+    tag_prefix: String,
+
+    targets: Vec<WorkflowTarget>,
+
+    /// Environment variables set from the secrets of the same name
+    secrets: Vec<String>,
+
+    /// Whether to install the cargo tools builds use
+    cargo: bool,
+
+    /// Whether builds of the main branch publish to a channel
+    channel: bool,
+
+    /// For Woodpecker
+    image: String,
+}
+
+impl Workflow {
+    fn from_config(config: &Config, format: WorkflowFormat) -> Self {
+        let universal = config
+            .cargo
+            .as_ref()
+            .is_some_and(|cargo| cargo.universal_macos);
+        let targets = match config.workflow.as_ref().map(|w| &w.targets) {
+            Some(targets) if !targets.is_empty() => targets.clone(),
+            _ => match format {
+                WorkflowFormat::Github => vec![
+                    WorkflowTarget::new("x86_64-unknown-linux-gnu", "ubuntu-24.04"),
+                    WorkflowTarget::new("aarch64-unknown-linux-gnu", "ubuntu-24.04-arm"),
+                    if universal {
+                        WorkflowTarget::new("universal-apple-darwin", "macos-15")
+                    } else {
+                        WorkflowTarget::new("aarch64-apple-darwin", "macos-15")
+                    },
+                ],
+                WorkflowFormat::Forgejo => vec![
+                    WorkflowTarget::new("x86_64-unknown-linux-gnu", "docker"),
+                    WorkflowTarget::new("aarch64-unknown-linux-gnu", "docker-arm64"),
+                ],
+                WorkflowFormat::Woodpecker => vec![
+                    WorkflowTarget::new("x86_64-unknown-linux-gnu", "linux/amd64"),
+                    WorkflowTarget::new("aarch64-unknown-linux-gnu", "linux/arm64"),
+                ],
+            },
+        };
+
+        let mut secrets = vec![match config.forge {
+            ForgeKind::Github => "GH_READWRITE_TOKEN".to_string(),
+            ForgeKind::Forgejo => "FORGEJO_TOKEN".to_string(),
+        }];
+        if config.oci.is_some() {
+            secrets.push("OCI_PASSWORD".to_string());
+        }
+        if config
+            .cargo
+            .as_ref()
+            .is_some_and(|cargo| cargo.remote_cache.is_some())
+        {
+            secrets.push("BEARDIST_CACHE_TOKEN".to_string());
+        }
+        if let Some(notifications) = &config.notifications {
+            secrets.extend(notifications.webhooks.iter().map(|w| w.url_env.clone()));
+            secrets.extend(
+                notifications
+                    .matrix
+                    .iter()
+                    .map(|room| room.access_token_env.clone()),
+            );
+        }
+        secrets.sort();
+        secrets.dedup();
+
+        Self {
+            format,
+            forge: config.forge,
+            tag_prefix: config.tag_prefix.clone().unwrap_or_default(),
+            targets,
+            secrets,
+            cargo: config.cargo.is_some(),
+            channel: config.channel.is_some(),
+            image: config
+                .workflow
+                .as_ref()
+                .and_then(|w| w.image.clone())
+                .unwrap_or_else(|| DEFAULT_WOODPECKER_IMAGE.to_string()),
+        }
+    }
+}
+
+/// The beardist archive that runs on the runner building `artifact`
+fn beardist_archive(artifact: &str) -> &'static str {
+    if artifact.contains("apple-darwin") {
+        "aarch64-apple-darwin"
+    } else if artifact.starts_with("aarch64") {
+        "aarch64-unknown-linux-gnu"
+    } else {
+        "x86_64-unknown-linux-gnu"
+    }
+}
+
+fn render(workflow: &Workflow) -> String {
+    match workflow.format {
+        WorkflowFormat::Github | WorkflowFormat::Forgejo => render_actions(workflow),
+        WorkflowFormat::Woodpecker => render_woodpecker(workflow),
+    }
+}
+
+/// GitHub Actions and Forgejo Actions
+fn render_actions(workflow: &Workflow) -> String {
+    let mut yaml = HEADER.to_string();
+    yaml.push_str("name: release\non:\n  push:\n");
+    if workflow.channel {
+        yaml.push_str("    branches: [main]\n");
+    }
+    yaml.push_str(&format!(
+        "    tags:\n      - \"{}*\"\n  workflow_dispatch:\n",
+        workflow.tag_prefix
+    ));
+    yaml.push_str(
+        "jobs:\n  build:\n    strategy:\n      fail-fast: false\n      matrix:\n        include:\n",
+    );
+    for target in &workflow.targets {
+        yaml.push_str(&format!(
+            "          - artifact: {}\n            runs-on: {}\n            beardist: {}\n",
+            target.artifact,
+            target.runs_on,
+            beardist_archive(&target.artifact)
+        ));
+    }
+    yaml.push_str(
+        "    runs-on: ${{ matrix.runs-on }}\n    env:\n      \
+         BEARDIST_CACHE_DIR: ${{ github.workspace }}/../beardist-cache\n      \
+         BEARDIST_ARTIFACT_NAME: ${{ matrix.artifact }}\n      \
+         CLICOLOR_FORCE: 1\n",
+    );
+    for secret in &workflow.secrets {
+        yaml.push_str(&format!(
+            "      {}: ${{{{ secrets.{} }}}}\n",
+            secret, secret
+        ));
+    }
+    yaml.push_str(
+        "    steps:\n      \
+         - uses: actions/checkout@v4\n        with:\n          fetch-depth: 0\n      \
+         - name: Restore cache\n        uses: actions/cache@v4\n        with:\n          \
+         path: ${{ env.BEARDIST_CACHE_DIR }}\n          \
+         key: ${{ runner.os }}-${{ matrix.artifact }}-beardist-${{ hashFiles('**/Cargo.lock') }}\n          \
+         restore-keys: |\n            ${{ runner.os }}-${{ matrix.artifact }}-beardist-\n",
+    );
+    yaml.push_str(&format!(
+        "      - name: Install beardist\n        run: |\n          \
+         curl -fsSL -o beardist.tar.xz {}/v{}/${{{{ matrix.beardist }}}}.tar.xz\n          \
+         tar -xf beardist.tar.xz beardist\n          \
+         mkdir -p \"$HOME/.local/bin\"\n          \
+         mv beardist \"$HOME/.local/bin/\"\n          \
+         echo \"$HOME/.local/bin\" >> \"$GITHUB_PATH\"\n",
+        BEARDIST_RELEASES,
+        env!("CARGO_PKG_VERSION")
+    ));
+    if workflow.cargo {
+        yaml.push_str(
+            "      - name: Install cargo tools\n        run: |\n          \
+             curl -L --proto '=https' --tlsv1.2 -sSf https://raw.githubusercontent.com/cargo-bins/cargo-binstall/main/install-from-binstall-release.sh | bash\n          \
+             cargo binstall -y cargo-sweep\n",
+        );
+    }
+    yaml.push_str("      - name: Build\n        run: beardist build\n");
+    yaml
+}
+
+fn render_woodpecker(workflow: &Workflow) -> String {
+    let mut yaml = HEADER.to_string();
+    yaml.push_str(&format!(
+        "when:\n  - event: tag\n    ref: \"refs/tags/{}*\"\n",
+        workflow.tag_prefix
+    ));
+    if workflow.channel {
+        yaml.push_str("  - event: push\n    branch: main\n");
+    }
+    yaml.push_str("  - event: manual\nmatrix:\n  include:\n");
+    for target in &workflow.targets {
+        yaml.push_str(&format!(
+            "    - ARTIFACT: {}\n      PLATFORM: {}\n",
+            target.artifact, target.runs_on
+        ));
+    }
+    // beardist reads the CI's variables under GitHub's (or Forgejo's) names
+    let prefix = match workflow.forge {
+        ForgeKind::Github => "GITHUB",
+        ForgeKind::Forgejo => "FORGEJO",
+    };
+    yaml.push_str(&format!(
+        "labels:\n  platform: ${{PLATFORM}}\n\
+         steps:\n  - name: build\n    image: {}\n    environment:\n      \
+         BEARDIST_CACHE_DIR: /woodpecker/beardist-cache\n      \
+         BEARDIST_ARTIFACT_NAME: ${{ARTIFACT}}\n      \
+         CLICOLOR_FORCE: \"1\"\n      \
+         {prefix}_REF: ${{CI_COMMIT_REF}}\n      \
+         {prefix}_SHA: ${{CI_COMMIT_SHA}}\n      \
+         {prefix}_SERVER_URL: ${{CI_FORGE_URL}}\n      \
+         {prefix}_ACTOR: ${{CI_COMMIT_AUTHOR}}\n",
+        workflow.image
+    ));
+    for secret in &workflow.secrets {
+        yaml.push_str(&format!(
+            "      {}:\n        from_secret: {}\n",
+            secret, secret
+        ));
+    }
+    yaml.push_str("    commands:\n      - beardist build\n");
+    yaml
+}
+
+pub(crate) fn generate_workflow(
+    args: GenerateWorkflowArgs,
+    config_path: Option<&Path>,
+) -> eyre::Result<()> {
+    // Before loading the config moves us to the project's root
+    let output = args
+        .output
+        .as_deref()
+        .map(std::path::absolute)
+        .transpose()?;
+    let config = load_config(config_path)?;
+    let format = args.format.unwrap_or(match config.forge {
+        ForgeKind::Github => WorkflowFormat::Github,
+        ForgeKind::Forgejo => WorkflowFormat::Forgejo,
+    });
+    let output = output.unwrap_or_else(|| PathBuf::from(format.default_output()));
+    let rendered = render(&Workflow::from_config(&config, format));
+
+    let current = fs_err::read_to_string(&output).ok();
+    if args.check {
+        if current.as_deref() != Some(rendered.as_str()) {
+            return Err(eyre::eyre!(
+                "{} is out of date, run `beardist generate-workflow`",
+                output.display().to_string().cyan()
+            ));
+        }
+        info!("✅ {} is up to date", output.display().cyan());
+        return Ok(());
+    }
+    if current.as_deref() == Some(rendered.as_str()) {
+        info!("{} is already up to date", output.display().cyan());
+        return Ok(());
+    }
+    if std::env::var("DRY_RUN").is_ok() {
+        info!(
+            "Dry run: Would write {}:\n{}",
+            output.display().to_string().cyan(),
+            rendered
+        );
+        return Ok(());
+    }
+    if let Some(parent) = output.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    fs_err::write(&output, &rendered)?;
+    info!("✅ Wrote {}", output.display().cyan());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut workflow = Workflow {
+            format: WorkflowFormat::Github,
+            forge: ForgeKind::Forgejo,
+            tag_prefix: "api-".to_string(),
+            targets: vec![
+                WorkflowTarget::new("x86_64-unknown-linux-gnu", "ubuntu-24.04"),
+                WorkflowTarget::new("universal-apple-darwin", "macos-15"),
+            ],
+            secrets: vec!["GH_READWRITE_TOKEN".to_string(), "OCI_PASSWORD".to_string()],
+            cargo: true,
+            channel: true,
+            image: DEFAULT_WOODPECKER_IMAGE.to_string(),
+        };
+        let rendered = render(&workflow);
+        let parsed: serde_json::Value = serde_yaml_ng::from_str(&rendered).unwrap();
+
+        assert_eq!(parsed["on"]["push"]["tags"][0], "api-*");
+        assert_eq!(parsed["on"]["push"]["branches"][0], "main");
+        let build = &parsed["jobs"]["build"];
+        let include = &build["strategy"]["matrix"]["include"];
+        assert_eq!(include[1]["artifact"], "universal-apple-darwin");
+        assert_eq!(include[1]["runs-on"], "macos-15");
+        assert_eq!(include[1]["beardist"], "aarch64-apple-darwin");
+        assert_eq!(build["env"]["OCI_PASSWORD"], "${{ secrets.OCI_PASSWORD }}");
+        let steps = build["steps"].as_array().unwrap();
+        assert_eq!(steps.last().unwrap()["run"], "beardist build");
+        assert!(
+            steps
+                .iter()
+                .any(|step| step["name"] == "Install cargo tools")
+        );
+
+        workflow.format = WorkflowFormat::Woodpecker;
+        let rendered = render(&workflow);
+        let parsed: serde_json::Value = serde_yaml_ng::from_str(&rendered).unwrap();
+        assert_eq!(parsed["when"][0]["ref"], "refs/tags/api-*");
+        assert_eq!(parsed["when"][1]["branch"], "main");
+        assert_eq!(parsed["matrix"]["include"][1]["PLATFORM"], "macos-15");
+        assert_eq!(parsed["labels"]["platform"], "${PLATFORM}");
+        let build = &parsed["steps"][0];
+        assert_eq!(build["image"], DEFAULT_WOODPECKER_IMAGE);
+        assert_eq!(build["environment"]["FORGEJO_REF"], "${CI_COMMIT_REF}");
+        assert_eq!(
+            build["environment"]["OCI_PASSWORD"]["from_secret"],
+            "OCI_PASSWORD"
+        );
+        assert_eq!(build["commands"][0], "beardist build");
+    }
+}
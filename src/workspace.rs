@@ -0,0 +1,146 @@
+//! Workspaces: repositories holding several projects, each with its own `.beardist.json`
+//! in its directory, and its own tags, told apart by the config's `tag_prefix` (e.g.
+//! `api-v1.2.3` for the project with `"tag_prefix": "api-"`).
+
+use log::*;
+use owo_colors::OwoColorize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config_file,
+    error::{Classify, ErrorKind},
+};
+
+/// A project found in a workspace
+#[derive(Debug)]
+pub(crate) struct Project {
+    /// Path of its config file
+    pub(crate) config_path: PathBuf,
+
+    /// The prefix of its tags, if it has one
+    pub(crate) tag_prefix: Option<String>,
+}
+
+/// Finds every project under `root`, skipping git-ignored directories
+pub(crate) fn discover(root: &Path) -> eyre::Result<Vec<Project>> {
+    let mut projects = Vec::new();
+    let walker = ignore::WalkBuilder::new(root)
+        .standard_filters(false)
+        .git_ignore(true)
+        .filter_entry(|entry| entry.file_name() != ".git" && entry.file_name() != "target")
+        .build();
+    for entry in walker.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let is_config = path.file_stem().is_some_and(|stem| stem == ".beardist")
+            && config_file::Format::from_path(path).is_some();
+        if !is_config || !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            continue;
+        }
+        let value: Value = config_file::load(path, "config")?;
+        projects.push(Project {
+            config_path: path.to_path_buf(),
+            tag_prefix: value["tag_prefix"].as_str().map(String::from),
+        });
+    }
+    projects.sort_by(|a, b| a.config_path.cmp(&b.config_path));
+    Ok(projects)
+}
+
+/// Picks the project a build is for: the only one there is, or the one whose tag prefix
+/// `tag` starts with (the longest, if several do)
+fn select<'a>(projects: &'a [Project], tag: Option<&str>) -> eyre::Result<&'a Project> {
+    if let [project] = projects {
+        return Ok(project);
+    }
+    let by_tag = tag.and_then(|tag| {
+        projects
+            .iter()
+            .filter(|project| {
+                project
+                    .tag_prefix
+                    .as_deref()
+                    .is_some_and(|prefix| tag.starts_with(prefix))
+            })
+            .max_by_key(|project| project.tag_prefix.as_deref().map_or(0, str::len))
+    });
+    by_tag.ok_or_else(|| {
+        eyre::eyre!(
+            "Found {} projects, pick one with --project: {}",
+            projects.len(),
+            projects
+                .iter()
+                .map(|project| project.config_path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })
+}
+
+/// Finds the config of the project to build under `root`, going by the tag being built
+/// when there are several
+pub(crate) fn find_project(root: &Path, tag: Option<&str>) -> eyre::Result<PathBuf> {
+    let projects = discover(root)?;
+    if projects.is_empty() {
+        return Err(eyre::eyre!(
+            "No .beardist.json, .beardist.toml or .beardist.yaml in {} or below",
+            root.display()
+        ))
+        .classify(ErrorKind::Config);
+    }
+    let project = select(&projects, tag).classify(ErrorKind::Config)?;
+    info!(
+        "🗂️ Workspace project: {}",
+        project.config_path.display().to_string().cyan()
+    );
+    Ok(project.config_path.clone())
+}
+
+/// The config of the project in `dir`, for `--project`
+pub(crate) fn project_config(dir: &Path) -> eyre::Result<PathBuf> {
+    config_file::find(dir, ".beardist")?
+        .ok_or_else(|| {
+            eyre::eyre!(
+                "No .beardist.json, .beardist.toml or .beardist.yaml in {}",
+                dir.display()
+            )
+        })
+        .classify(ErrorKind::Config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for (project, prefix) in [("api", "api-"), ("api/admin", "api-admin-"), ("cli", "")] {
+            fs_err::create_dir_all(root.join(project)).unwrap();
+            let config = serde_json::json!({ "tag_prefix": prefix });
+            fs_err::write(
+                root.join(project).join(".beardist.json"),
+                config.to_string(),
+            )
+            .unwrap();
+        }
+        fs_err::create_dir_all(root.join("target/package")).unwrap();
+        fs_err::write(root.join("target/package/.beardist.json"), "{}").unwrap();
+
+        let projects = discover(root).unwrap();
+        assert_eq!(projects.len(), 3);
+
+        let api = find_project(root, Some("api-v1.2.3")).unwrap();
+        assert_eq!(api, root.join("api/.beardist.json"));
+        let admin = find_project(root, Some("api-admin-v0.1.0")).unwrap();
+        assert_eq!(admin, root.join("api/admin/.beardist.json"));
+
+        // An empty prefix matches any tag, but loses to longer ones
+        let cli = find_project(root, Some("v2.0.0")).unwrap();
+        assert_eq!(cli, root.join("cli/.beardist.json"));
+
+        let error = find_project(root, None).unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::Config);
+    }
+}